@@ -1,4 +1,5 @@
-use crate::comms::{OmapComms, messages::*};
+use crate::comms::{OmapComms, is_cancellation, messages::*};
+use crate::drawable::DrawableTerrain;
 use crate::map_gen;
 use crate::map_gen::pipeline::PreparedTile;
 use crate::parameters::MapParameters;
@@ -52,6 +53,13 @@ impl Backend {
     fn run(&mut self) {
         while let Ok(task) = self.comms.recv() {
             match task {
+                // the cancelled job has returned by the time this is received
+                BackendTask::Cancel => {
+                    self.comms.cancel_token().clear();
+                    let _ = self
+                        .comms
+                        .send(FrontendTask::TaskComplete(TaskDone::Cancel));
+                }
                 BackendTask::ClearParams => {
                     self.map_params = None;
                 }
@@ -75,6 +83,7 @@ impl Backend {
                         bounds,
                         polygon,
                         write_single_copc,
+                        convert_to_copc,
                         budget_gb,
                     } = *task;
 
@@ -87,6 +96,7 @@ impl Backend {
                         bounds,
                         polygon,
                         write_single_copc,
+                        convert_to_copc,
                         budget_gb,
                     );
                 }
@@ -96,6 +106,9 @@ impl Backend {
                         paths,
                         test_area,
                         stats,
+                        ground,
+                        noise,
+                        crs,
                     } = *task;
 
                     match map_gen::egui_map::initialize_map_tile(
@@ -103,11 +116,31 @@ impl Backend {
                         paths,
                         test_area,
                         stats,
+                        ground,
+                        noise,
                     ) {
                         Ok(initialized) => {
                             self.map_tiles = initialized.tiles;
                             self.hull = initialized.hull;
                             self.ref_point = initialized.ref_point;
+
+                            // the preview is still usable without the terrain underneath
+                            match DrawableTerrain::from_tiles(
+                                &self.map_tiles,
+                                self.ref_point,
+                                crs.as_ref(),
+                            ) {
+                                Ok(terrain) => {
+                                    let _ = self.comms.send(FrontendTask::UpdateVariable(
+                                        Variable::Terrain(Box::new(terrain)),
+                                    ));
+                                }
+                                Err(e) => {
+                                    let _ = self.comms.send(FrontendTask::Log(format!(
+                                        "Could not draw the terrain of the test tile: {e}"
+                                    )));
+                                }
+                            }
                         }
                         Err(e) => {
                             let _ = self
@@ -132,7 +165,33 @@ impl Backend {
                         scope,
                     );
 
-                    self.map_params = Some(*params);
+                    // a cancelled regeneration leaves the tile as it was for the old params
+                    if !self.comms.cancel_token().is_cancelled() {
+                        self.map_params = Some(*params);
+                    }
+                }
+
+                BackendTask::SuggestContourInterval(job_id, params) => {
+                    match map_gen::egui_map::suggest_contour_interval(
+                        &self.comms,
+                        &self.thread_pool,
+                        &self.map_tiles,
+                        &params,
+                    ) {
+                        Ok(suggestion) => {
+                            let _ = self.comms.send(FrontendTask::UpdateVariable(
+                                Variable::ContourSuggestion(job_id, suggestion),
+                            ));
+                        }
+                        // the frontend is told when the Cancel task is received
+                        Err(e) if is_cancellation(&e) => (),
+                        Err(e) => {
+                            let _ = self.comms.send(FrontendTask::Error(e.to_string(), false));
+                        }
+                    }
+                    let _ = self.comms.send(FrontendTask::TaskComplete(
+                        TaskDone::SuggestContourInterval(job_id),
+                    ));
                 }
 
                 BackendTask::MakeMap(task) => {
@@ -169,6 +228,8 @@ impl Backend {
                         Ok(_) => self
                             .comms
                             .send(FrontendTask::TaskComplete(TaskDone::MakeMap)),
+                        // the frontend is told when the Cancel task is received
+                        Err(e) if is_cancellation(&e) => Ok(()),
                         Err(e) => self.comms.send(FrontendTask::Error(e.to_string(), true)),
                     };
                 }
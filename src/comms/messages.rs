@@ -1,9 +1,10 @@
 use proj_core::CrsDef;
 
 use crate::{
-    drawable::DrawableOmap,
+    drawable::{DrawableOmap, DrawableTerrain},
     gui::modals::OmapModal,
-    parameters::{FileParameters, MapParameters},
+    map_gen::egui_map::{ContourConflict, ContourSuggestion},
+    parameters::{FileParameters, GroundParameters, MapParameters, NoiseParameters},
     statistics::LidarStats,
 };
 use std::path::PathBuf;
@@ -23,6 +24,7 @@ pub enum FrontendTask {
 }
 
 pub enum BackendTask {
+    Cancel,
     ClearParams,
     SetWorkerThreads(usize),
     InitializeMapTile(Box<InitializeMapTileTask>),
@@ -30,6 +32,7 @@ pub enum BackendTask {
     MapSpatialLidarRelations(Vec<PathBuf>, Option<Vec<Option<CrsDef>>>),
     ConvertCopc(Box<ConvertCopcTask>),
     RegenerateMap(JobId, Box<MapParameters>, RegenerationScope),
+    SuggestContourInterval(JobId, Box<MapParameters>),
     Reset,
     MakeMap(Box<MakeMapTask>),
 }
@@ -38,6 +41,9 @@ pub struct InitializeMapTileTask {
     pub paths: Vec<PathBuf>,
     pub test_area: geo::Rect,
     pub stats: LidarStats,
+    pub ground: GroundParameters,
+    pub noise: NoiseParameters,
+    pub crs: Option<CrsDef>,
 }
 
 pub struct ConvertCopcTask {
@@ -48,6 +54,7 @@ pub struct ConvertCopcTask {
     pub bounds: Vec<[walkers::Position; 4]>,
     pub polygon: geo::LineString,
     pub write_single_copc: bool,
+    pub convert_to_copc: bool,
     pub budget_gb: u8,
 }
 
@@ -59,7 +66,9 @@ pub struct MakeMapTask {
 }
 
 pub enum Task {
+    Cancel,
     RegenerateMap,
+    SuggestContourInterval,
     Reset,
     SetCrs(SetCrs),
     ShowComponents,
@@ -78,17 +87,26 @@ pub enum ProgressBar {
 pub enum RegenerationScope {
     Changed,
     Section(MapPreviewSection),
+    // every section up to and including the given one, in the order they are adjusted
+    Through(MapPreviewSection),
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MapPreviewSection {
     Openness,
     Vegetation,
     Cliffs,
     Water,
+    Streams,
+    Marsh,
+    Buildings,
+    Boulders,
+    Roads,
     Intensity,
 }
 
 pub enum TaskDone {
+    Cancel,
     InitializeMapTile,
     ParseCrs(SetCrs),
     MapSpatialLidarRelations,
@@ -96,6 +114,7 @@ pub enum TaskDone {
     ConvertCopc,
     OutputCrs,
     RegenerateMap(JobId),
+    SuggestContourInterval(JobId),
     Reset,
     MakeMap,
 }
@@ -111,6 +130,7 @@ pub enum SetCrs {
 
 pub enum Variable {
     MapTile(JobId, Box<DrawableOmap>),
+    Terrain(Box<DrawableTerrain>),
     Paths(Vec<PathBuf>),
     Boundaries(Vec<[walkers::Position; 4]>),
     BoundaryAreas(Vec<f64>),
@@ -120,6 +140,8 @@ pub enum Variable {
     CrsLessCheckBox(usize),
     ConnectedComponents(Vec<Vec<usize>>),
     ContourScore(JobId, (f32, f32)),
+    ContourConflicts(JobId, Vec<ContourConflict>),
+    ContourSuggestion(JobId, ContourSuggestion),
     Stats(Box<LidarStats>),
     SingleCopcPath(PathBuf),
 }
@@ -1,4 +1,6 @@
+mod cancel_token;
 pub mod messages;
 mod omap_comms;
 
+pub use cancel_token::{CancelToken, is_cancellation};
 pub use omap_comms::{FrontendSender, OmapComms};
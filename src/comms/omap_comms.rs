@@ -2,7 +2,10 @@ use std::sync::mpsc;
 
 use eframe::egui;
 
-use super::messages::{BackendTask, FrontendTask};
+use super::{
+    CancelToken,
+    messages::{BackendTask, FrontendTask},
+};
 
 // Multiple Producer Single Consumer, i.e. a sender is cloneable but the receiver not
 // A dual message passing channel for the frontend and backend
@@ -12,6 +15,7 @@ pub struct OmapComms<T, S> {
     sender: mpsc::Sender<T>,
     receiver: mpsc::Receiver<S>,
     ctx: egui::Context,
+    cancel: CancelToken,
 }
 
 impl OmapComms<BackendTask, FrontendTask> {
@@ -22,6 +26,17 @@ impl OmapComms<BackendTask, FrontendTask> {
     pub fn try_recv(&self) -> Result<FrontendTask, mpsc::TryRecvError> {
         self.receiver.try_recv()
     }
+
+    pub fn recv(&self) -> Result<FrontendTask, mpsc::RecvError> {
+        self.receiver.recv()
+    }
+
+    /// Stops the running backend job as soon as it checks the cancel token.
+    /// The backend answers with `TaskDone::Cancel` once it is ready for new tasks
+    pub fn cancel(&self) -> Result<(), mpsc::SendError<BackendTask>> {
+        self.cancel.cancel();
+        self.sender.send(BackendTask::Cancel)
+    }
 }
 
 impl OmapComms<FrontendTask, BackendTask> {
@@ -29,9 +44,14 @@ impl OmapComms<FrontendTask, BackendTask> {
         FrontendSender {
             sender: self.sender.clone(),
             ctx: self.ctx.clone(),
+            cancel: self.cancel.clone(),
         }
     }
 
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
     pub fn send(&self, t: FrontendTask) -> Result<(), mpsc::SendError<FrontendTask>> {
         let result = self.sender.send(t);
         self.ctx.request_repaint();
@@ -52,16 +72,19 @@ impl OmapComms<FrontendTask, BackendTask> {
     ) {
         let (to_frontend, from_backend) = mpsc::channel();
         let (to_backend, from_frontend) = mpsc::channel();
+        let cancel = CancelToken::default();
 
         let backend_comms = OmapComms {
             sender: to_frontend,
             receiver: from_frontend,
             ctx: ctx.clone(),
+            cancel: cancel.clone(),
         };
         let frontend_comms = OmapComms {
             sender: to_backend,
             receiver: from_backend,
             ctx: ctx.clone(),
+            cancel,
         };
 
         (frontend_comms, backend_comms)
@@ -72,6 +95,7 @@ impl OmapComms<FrontendTask, BackendTask> {
 pub struct FrontendSender {
     sender: mpsc::Sender<FrontendTask>,
     ctx: egui::Context,
+    cancel: CancelToken,
 }
 
 impl FrontendSender {
@@ -80,4 +104,8 @@ impl FrontendSender {
         self.ctx.request_repaint();
         result
     }
+
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
 }
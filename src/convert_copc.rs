@@ -1,6 +1,6 @@
 use crate::{
     Result,
-    comms::{FrontendSender, messages::*},
+    comms::{FrontendSender, is_cancellation, messages::*},
     statistics::LidarStats,
 };
 use anyhow::{Context, bail};
@@ -12,7 +12,19 @@ use std::{
 use copc_converter::{NodeStorage, Pipeline, PipelineConfig, TempCompression};
 use copc_rs::CopcReader;
 use geo::Intersects;
-use proj_core::CrsDef;
+use proj_core::{CrsDef, Transform};
+
+// the LAS specification's user id and record ids of the CRS VLRs
+const CRS_VLR_USER_ID: &str = "LASF_Projection";
+const WKT_RECORD_ID: u16 = 2112;
+const GEO_KEY_DIRECTORY_RECORD_ID: u16 = 34735;
+// the GeoTIFF keys for a projected CRS given by its EPSG code
+const GT_MODEL_TYPE_GEO_KEY: u16 = 1024;
+const GT_RASTER_TYPE_GEO_KEY: u16 = 1025;
+const PROJECTED_CS_TYPE_GEO_KEY: u16 = 3072;
+const MODEL_TYPE_PROJECTED: u16 = 1;
+const RASTER_PIXEL_IS_AREA: u16 = 1;
+const CANCEL_CHECK_INTERVAL: usize = 1 << 20;
 
 #[allow(clippy::too_many_arguments)]
 pub fn convert_copc(
@@ -24,6 +36,7 @@ pub fn convert_copc(
     boundaries: Vec<[walkers::Position; 4]>,
     polygon_filter: geo::LineString,
     write_single_copc: bool,
+    convert_to_copc: bool,
     memory_budget: u8,
 ) {
     if let Err(e) = try_convert_copc(
@@ -35,10 +48,13 @@ pub fn convert_copc(
         boundaries,
         polygon_filter,
         write_single_copc,
+        convert_to_copc,
         memory_budget,
     ) {
         let _ = sender.send(FrontendTask::ProgressBar(ProgressBar::Finish));
-        let _ = sender.send(FrontendTask::Error(e.to_string(), true));
+        if !is_cancellation(&e) {
+            let _ = sender.send(FrontendTask::Error(e.to_string(), true));
+        }
     }
 }
 
@@ -52,6 +68,7 @@ fn try_convert_copc(
     boundaries: Vec<[walkers::Position; 4]>,
     polygon_filter: geo::LineString,
     write_single_copc: bool,
+    convert_to_copc: bool,
     memory_budget: u8,
 ) -> Result<()> {
     let mut new_paths = paths.clone();
@@ -66,56 +83,63 @@ fn try_convert_copc(
 
     let polygon = geo::Polygon::new(polygon_filter, vec![]);
 
+    // reprojected files are written next to the map, the input files may be read-only or shared
+    let output_dir = save_location
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(std::env::temp_dir);
+
     let mut stats = Vec::new();
     let inc_size = 1. / paths.len() as f32;
     for (pi, path) in paths.iter().cloned().enumerate() {
-        // first check if the file is relevant i.e overlaps with the polygon
-        let bounds = boundaries[pi];
+        sender.cancel_token().check()?;
 
-        let relevant =
-            polygon.exterior().0.is_empty() || polygon.intersects(&boundary_polygon(bounds));
+        // first check if the file is relevant i.e overlaps with the polygon
+        // without a polygon filter every file is relevant and no boundaries are needed
+        let relevant = polygon.exterior().0.is_empty()
+            || polygon.intersects(&boundary_polygon(boundaries[pi]));
 
         if relevant {
-            stats.push(
-                LidarStats::calculate_statistics(&path)
-                    .with_context(|| format!("Failed to calculate statistics for {path:?}"))?,
-            );
-
             let transform_needed =
                 if let (Some(input), Some(output)) = (&input_crs[pi], &output_crs) {
-                    input.epsg() != output.epsg()
+                    !same_crs(input, output)?
                 } else {
                     false
                 };
 
-            let conversion_needed = CopcReader::from_path(&path).is_err();
+            // plain files are read directly if the conversion is turned off
+            let conversion_needed = convert_to_copc && CopcReader::from_path(&path).is_err();
 
-            new_paths[pi] = if !conversion_needed && !transform_needed {
-                // the lidar file is both a COPC and in the correct CRS
-                path
-            } else if transform_needed && !conversion_needed {
-                // the lidar file needs to be transformed into another CRS but is already a copc
+            new_paths[pi] = if transform_needed {
+                // the lidar file is reprojected into the output CRS and written to COPC,
+                // also if it already is a COPC
                 transform_file(
                     path,
                     input_crs[pi].clone(),
                     output_crs
                         .clone()
-                        .context("Output CRS is required when transforming COPC files")?,
+                        .context("Output CRS is required when transforming files")?,
+                    &output_dir,
+                    sender.clone(),
+                    convert_to_copc,
+                    memory_budget,
                 )?
-            } else if conversion_needed && !transform_needed {
+            } else if conversion_needed {
                 // the lidar file needs to be converted to copc
                 convert_file(path, input_crs[pi].clone(), sender.clone(), memory_budget)?
             } else {
-                // the lidar file needs both to be transformed into another CRS and written to COPC
-                convert_and_transform_file(
-                    path,
-                    input_crs[pi].clone(),
-                    output_crs
-                        .clone()
-                        .context("Output CRS is required when converting and transforming files")?,
-                )?
+                // the lidar file is in the correct CRS and either a COPC or read as it is
+                path
             };
 
+            // the statistics are calculated in the output CRS
+            stats.push(
+                LidarStats::calculate_statistics(&new_paths[pi]).with_context(|| {
+                    format!("Failed to calculate statistics for {:?}", new_paths[pi])
+                })?,
+            );
+
             if write_single_copc {
                 relevant_paths.push(new_paths[pi].clone());
             }
@@ -134,6 +158,8 @@ fn try_convert_copc(
     }
 
     if write_single_copc {
+        sender.cancel_token().check()?;
+
         let mut merged_path = save_location;
         merged_path.set_extension("copc.laz");
 
@@ -182,12 +208,197 @@ fn boundary_polygon(bounds: [walkers::Position; 4]) -> geo::Polygon {
     )
 }
 
+// The reprojected file is written to the output directory, or to the temp
+// directory if it is only the input of the COPC conversion
 fn transform_file(
-    _path: PathBuf,
-    _current_crs: Option<CrsDef>,
-    _out_crs: CrsDef,
+    path: PathBuf,
+    current_crs: Option<CrsDef>,
+    out_crs: CrsDef,
+    output_dir: &Path,
+    sender: FrontendSender,
+    convert_to_copc: bool,
+    memory_budget: u64,
 ) -> Result<PathBuf> {
-    bail!("Transforming CRS is not yet supported");
+    let current_crs =
+        current_crs.context("The CRS of a lidar file must be known to transform it")?;
+
+    let file_name = reprojected_file_name(&path, &out_crs);
+    let reprojected = if convert_to_copc {
+        std::env::temp_dir().join(format!("omapmaker_{}_{file_name}", std::process::id()))
+    } else {
+        output_dir.join(&file_name)
+    };
+
+    let result = reproject_file(&path, &reprojected, &current_crs, &out_crs, &sender)
+        .with_context(|| format!("Failed to transform {path:?} to EPSG:{}", out_crs.epsg()));
+    if result.is_err() || !convert_to_copc {
+        if result.is_err() {
+            let _ = std::fs::remove_file(&reprojected);
+        }
+        return result.map(|_| reprojected);
+    }
+
+    let mut copc_path = output_dir.join(file_name);
+    copc_path.set_extension("copc.laz");
+    let result = run_copc_converter(
+        std::slice::from_ref(&reprojected),
+        &copc_path,
+        memory_budget,
+    )
+    .with_context(|| format!("Failed to convert lidar file to COPC at {copc_path:?}"));
+    // the intermediate file is only needed as input to the COPC conversion
+    let _ = std::fs::remove_file(&reprojected);
+    result.map(|_| copc_path)
+}
+
+// Writes the points of the file in the output CRS to a LAZ file.
+// The header gets a scale keeping the precision of the input, an offset in
+// the middle of the reprojected bounds and the output CRS as its only CRS VLR
+fn reproject_file(
+    path: &Path,
+    out_path: &Path,
+    current_crs: &CrsDef,
+    out_crs: &CrsDef,
+    sender: &FrontendSender,
+) -> Result<()> {
+    let transform = Transform::from_crs_defs(current_crs, out_crs)?;
+
+    let mut reader = las::Reader::from_path(path)?;
+    let header = reader.header().clone();
+
+    let bounds = header.bounds();
+    let corners = [
+        (bounds.min.x, bounds.min.y),
+        (bounds.min.x, bounds.max.y),
+        (bounds.max.x, bounds.min.y),
+        (bounds.max.x, bounds.max.y),
+    ]
+    .into_iter()
+    .map(|c| transform.convert(c))
+    .collect::<std::result::Result<Vec<_>, _>>()?;
+    let center = (
+        corners.iter().map(|c| c.0).sum::<f64>() / 4.,
+        corners.iter().map(|c| c.1).sum::<f64>() / 4.,
+    );
+
+    let in_center = (
+        (bounds.min.x + bounds.max.x) / 2.,
+        (bounds.min.y + bounds.max.y) / 2.,
+    );
+    let scale = output_scale(&transform, in_center, header.transforms().x.scale)?;
+
+    let mut builder = las::Builder::from(header);
+    builder.transforms.x = las::Transform {
+        scale,
+        offset: center.0.round(),
+    };
+    builder.transforms.y = las::Transform {
+        scale,
+        offset: center.1.round(),
+    };
+    builder.vlrs.retain(|vlr| !is_crs_vlr(vlr));
+    builder.evlrs.retain(|vlr| !is_crs_vlr(vlr));
+    // the extended point formats of LAS 1.4 require the CRS as WKT, the
+    // legacy formats the GeoTIFF keys
+    if builder.point_format.is_extended {
+        builder.vlrs.push(wkt_vlr(out_crs)?);
+        builder.has_wkt_crs = true;
+    } else {
+        builder.vlrs.push(geo_key_directory_vlr(out_crs)?);
+        builder.has_wkt_crs = false;
+    }
+    let header = builder.into_header()?;
+
+    let mut writer = las::Writer::from_path(out_path, header)?;
+    for (i, point) in reader.points().enumerate() {
+        if i % CANCEL_CHECK_INTERVAL == 0 {
+            sender.cancel_token().check()?;
+        }
+
+        let mut point = point?;
+        (point.x, point.y) = transform.convert((point.x, point.y))?;
+        writer.write_point(point)?;
+    }
+    writer.close()?;
+
+    Ok(())
+}
+
+// CRSs without an EPSG code all report code 0, so they are compared by their definitions
+fn same_crs(a: &CrsDef, b: &CrsDef) -> Result<bool> {
+    if a.epsg() != 0 && b.epsg() != 0 {
+        return Ok(a.epsg() == b.epsg());
+    }
+    Ok(crate::parse_crs::crs_wkt(a)? == crate::parse_crs::crs_wkt(b)?)
+}
+
+// the largest power of ten not coarser than one input scale step after the transformation
+fn output_scale(transform: &Transform, at: (f64, f64), in_scale: f64) -> Result<f64> {
+    let origin = transform.convert(at)?;
+    let dx = transform.convert((at.0 + in_scale, at.1))?;
+    let dy = transform.convert((at.0, at.1 + in_scale))?;
+
+    let step =
+        ((dx.0 - origin.0).hypot(dx.1 - origin.1)).min((dy.0 - origin.0).hypot(dy.1 - origin.1));
+    if !step.is_normal() {
+        bail!("The transformation does not preserve the point precision");
+    }
+    Ok(10_f64.powf(step.log10().floor()))
+}
+
+fn is_crs_vlr(vlr: &las::Vlr) -> bool {
+    vlr.user_id == CRS_VLR_USER_ID
+}
+
+fn wkt_vlr(crs: &CrsDef) -> Result<las::Vlr> {
+    let mut data = crate::parse_crs::crs_wkt(crs)?.into_bytes();
+    data.push(0);
+    Ok(las::Vlr {
+        user_id: CRS_VLR_USER_ID.to_string(),
+        record_id: WKT_RECORD_ID,
+        description: "OGC coordinate system WKT".to_string(),
+        data,
+    })
+}
+
+// A GeoKey directory of a projected CRS by its EPSG code, the map output CRS
+// is always projected
+fn geo_key_directory_vlr(crs: &CrsDef) -> Result<las::Vlr> {
+    let epsg = u16::try_from(crs.epsg())
+        .ok()
+        .filter(|&epsg| epsg != 0)
+        .with_context(|| format!("EPSG:{} can not be written as a GeoTIFF key", crs.epsg()))?;
+
+    let keys = [
+        (GT_MODEL_TYPE_GEO_KEY, MODEL_TYPE_PROJECTED),
+        (GT_RASTER_TYPE_GEO_KEY, RASTER_PIXEL_IS_AREA),
+        (PROJECTED_CS_TYPE_GEO_KEY, epsg),
+    ];
+    // the directory header is the version 1.1.0 and the number of keys, every key
+    // is its id, no tag location as the value is inline, a count of 1 and the value
+    let mut directory = vec![1, 1, 0, keys.len() as u16];
+    for (key, value) in keys {
+        directory.extend([key, 0, 1, value]);
+    }
+
+    Ok(las::Vlr {
+        user_id: CRS_VLR_USER_ID.to_string(),
+        record_id: GEO_KEY_DIRECTORY_RECORD_ID,
+        description: "GeoTiff GeoKeyDirectoryTag".to_string(),
+        data: directory.iter().flat_map(|v| v.to_le_bytes()).collect(),
+    })
+}
+
+fn reprojected_file_name(path: &Path, out_crs: &CrsDef) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = name
+        .trim_end_matches(".laz")
+        .trim_end_matches(".las")
+        .trim_end_matches(".copc");
+    format!("{stem}_epsg{}.laz", out_crs.epsg())
 }
 
 fn convert_file(
@@ -226,10 +437,58 @@ fn run_copc_converter(
         .write(output_path)
 }
 
-fn convert_and_transform_file(
-    _path: PathBuf,
-    _current_crs: Option<CrsDef>,
-    _out_crs: CrsDef,
-) -> Result<PathBuf> {
-    bail!("Transforming CRS is not yet supported");
+#[cfg(test)]
+mod tests {
+    use proj_core::Transform;
+
+    use super::{geo_key_directory_vlr, output_scale, same_crs};
+
+    #[test]
+    fn crs_without_epsg_codes_are_compared_by_definition() {
+        let sweref = proj_wkt::parse_crs("EPSG:3006").unwrap();
+        let custom = |lon_0: f64| {
+            proj_wkt::parse_crs(&format!(
+                "+proj=tmerc +lat_0=0 +lon_0={lon_0} +k=0.9996 +x_0=500000 +y_0=0 +datum=WGS84 +units=m"
+            ))
+            .unwrap()
+        };
+
+        assert!(same_crs(&sweref, &sweref).unwrap());
+        assert!(same_crs(&custom(10.5), &custom(10.5)).unwrap());
+        assert!(!same_crs(&custom(10.5), &custom(11.5)).unwrap());
+        assert!(!same_crs(&sweref, &custom(10.5)).unwrap());
+    }
+
+    #[test]
+    fn geo_key_directory_holds_the_projected_epsg_code() {
+        let sweref = proj_wkt::parse_crs("EPSG:3006").unwrap();
+        let vlr = geo_key_directory_vlr(&sweref).unwrap();
+
+        let directory = vlr
+            .data
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            directory,
+            [1, 1, 0, 3, 1024, 0, 1, 1, 1025, 0, 1, 1, 3072, 0, 1, 3006]
+        );
+
+        let custom = proj_wkt::parse_crs(
+            "+proj=tmerc +lat_0=0 +lon_0=10.5 +k=0.9996 +x_0=500000 +y_0=0 +datum=WGS84 +units=m",
+        )
+        .unwrap();
+        assert!(geo_key_directory_vlr(&custom).is_err());
+    }
+
+    #[test]
+    fn output_scale_keeps_the_point_precision() {
+        // web mercator stretches distances by about 2 at 60 degrees north
+        let transform = Transform::new("EPSG:3006", "EPSG:3857").unwrap();
+        let scale = output_scale(&transform, (500_000., 6_650_000.), 0.01).unwrap();
+        assert!((scale - 0.01).abs() < 1e-12);
+
+        let scale = output_scale(&transform, (500_000., 6_650_000.), 0.001).unwrap();
+        assert!((scale - 0.001).abs() < 1e-12);
+    }
 }
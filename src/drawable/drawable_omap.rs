@@ -10,10 +10,13 @@ use proj_core::{CrsDef, Transform};
 
 use super::*;
 use crate::{
-    map_gen::egui_map::{AreaSymbol, LineSymbol, MapObject, Symbol, TempMap},
+    map_gen::egui_map::{AreaSymbol, ContourConflict, LineSymbol, MapObject, Symbol, TempMap},
     parameters::GeometryParameters,
 };
 
+// in screen pixels
+const CONFLICT_MARKER_RADIUS: f32 = 10.;
+
 trait Drawable {
     /// converting a symbol to something drawable to screen
     /// needs to know what crs to unproject to lat/lon
@@ -71,6 +74,8 @@ impl Drawable for MapObject {
 pub struct DrawableOmap {
     hull: Vec<walkers::Position>,
     map_objects: HashMap<Symbol, Vec<DrawableGeometry>>,
+    // only set when the contours are regenerated
+    contour_conflicts: Option<Vec<walkers::Position>>,
 }
 
 impl DrawableOmap {
@@ -110,9 +115,37 @@ impl DrawableOmap {
         Ok(DrawableOmap {
             hull: global_hull,
             map_objects: Self::into_drawable(tmap.objects, ref_point, tmap.crs, geometry),
+            contour_conflicts: None,
         })
     }
 
+    /// Highlights the contour conflicts, given relative to the ref point, on top of the map
+    pub fn set_contour_conflicts(
+        &mut self,
+        conflicts: &[ContourConflict],
+        ref_point: geo::Coord,
+        crs: Option<&CrsDef>,
+    ) -> Result<()> {
+        let points: Vec<(f64, f64)> = conflicts
+            .iter()
+            .map(|c| (c.location.x + ref_point.x, c.location.y + ref_point.y))
+            .collect();
+
+        let points = if let Some(crs) = crs {
+            Transform::from_epsg(crs.epsg(), 4326)?.convert_batch(&points)?
+        } else {
+            points
+        };
+
+        self.contour_conflicts = Some(
+            points
+                .into_iter()
+                .map(|(x, y)| walkers::lon_lat(x, y))
+                .collect(),
+        );
+        Ok(())
+    }
+
     fn into_drawable(
         mut omap_objs: HashMap<Symbol, Vec<MapObject>>,
         ref_point: geo::Coord,
@@ -157,12 +190,16 @@ impl DrawableOmap {
                 let _ = self.map_objects.insert(key, objs);
             }
         }
+        if other.contour_conflicts.is_some() {
+            self.contour_conflicts = other.contour_conflicts;
+        }
     }
 
     pub fn draw(
         &self,
         ui: &mut egui::Ui,
         projector: &walkers::ScreenProjector,
+        terrain: Option<&DrawableTerrain>,
         visibilities: &HashMap<Symbol, bool>,
         opacity: f32,
     ) {
@@ -191,6 +228,10 @@ impl DrawableOmap {
             Stroke::new(2., Color32::RED),
         ));
 
+        if let Some(terrain) = terrain {
+            terrain.draw(ui, projector, opacity);
+        }
+
         for symbol in Symbol::draw_order() {
             if let Some(vis) = visibilities.get(&symbol) {
                 if !vis {
@@ -212,5 +253,13 @@ impl DrawableOmap {
                 }
             }
         }
+
+        for conflict in self.contour_conflicts.iter().flatten() {
+            ui.painter().circle_stroke(
+                projector.project(*conflict),
+                CONFLICT_MARKER_RADIUS,
+                Stroke::new(2., Color32::RED),
+            );
+        }
     }
 }
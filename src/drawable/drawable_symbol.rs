@@ -6,6 +6,7 @@ const ROUGH_YELLOW: Color32 = Color32::from_rgba_premultiplied(255, 220, 155, 25
 const BROWN: Color32 = Color32::from_rgba_premultiplied(180, 50, 0, 255);
 const MEDIUM_BROWN: Color32 = Color32::from_rgba_premultiplied(200, 80, 0, 255);
 const LIGHT_BROWN: Color32 = Color32::from_rgba_premultiplied(220, 110, 0, 255);
+const ROAD_BROWN: Color32 = Color32::from_rgba_premultiplied(230, 160, 80, 255);
 const OLIVE: Color32 = Color32::from_rgba_premultiplied(134, 141, 7, 255);
 
 const SCALE_FACTOR: f32 = 0.25;
@@ -41,8 +42,15 @@ impl DrawOrder for Symbol {
             Symbol::Line(LineSymbol::IndexContour),
             Symbol::Point(PointSymbol::SlopeLineFormLine),
             Symbol::Point(PointSymbol::SlopeLineContour),
+            Symbol::Point(PointSymbol::ContourValue),
             Symbol::Line(LineSymbol::NegBasemapContour),
             Symbol::Line(LineSymbol::SmallCrossableWatercourse),
+            Symbol::Line(LineSymbol::LessDistinctSmallPath),
+            Symbol::Line(LineSymbol::SmallPath),
+            Symbol::Line(LineSymbol::Footpath),
+            Symbol::Line(LineSymbol::VehicleTrack),
+            Symbol::Line(LineSymbol::Road),
+            Symbol::Line(LineSymbol::WideRoad),
             Symbol::Point(PointSymbol::DotKnoll),
             Symbol::Point(PointSymbol::ElongatedDotKnoll),
             Symbol::Point(PointSymbol::UDepression),
@@ -141,6 +149,16 @@ impl DrawableSymbol for LineSymbol {
             LineSymbol::SmallCrossableWatercourse => {
                 Some((false, Stroke::new(4. * scale_factor, Color32::BLUE)))
             }
+            LineSymbol::WideRoad => Some((false, Stroke::new(12. * scale_factor, ROAD_BROWN))),
+            LineSymbol::Road => Some((false, Stroke::new(8. * scale_factor, Color32::BLACK))),
+            LineSymbol::VehicleTrack => {
+                Some((false, Stroke::new(7. * scale_factor, Color32::BLACK)))
+            }
+            LineSymbol::Footpath => Some((false, Stroke::new(5. * scale_factor, Color32::BLACK))),
+            LineSymbol::SmallPath => Some((false, Stroke::new(4. * scale_factor, Color32::BLACK))),
+            LineSymbol::LessDistinctSmallPath => {
+                Some((false, Stroke::new(3. * scale_factor, Color32::DARK_GRAY)))
+            }
         }
     }
 }
@@ -173,6 +191,7 @@ impl DrawableSymbol for PointSymbol {
             PointSymbol::LargeBoulder => {
                 Some((false, Stroke::new(12. * scale_factor, Color32::BLACK)))
             }
+            PointSymbol::ContourValue => Some((false, Stroke::new(6. * scale_factor, BROWN))),
         }
     }
 }
@@ -0,0 +1,127 @@
+use crate::Result;
+
+use eframe::egui::{self, Color32, epaint::Vertex};
+use proj_core::{CrsDef, Transform};
+
+use crate::{CELL_SIZE_METERS, map_gen::pipeline::PreparedTile};
+
+// the same sun as the hillshade in the final map
+const SUN_ANGLE: f64 = 3. * std::f64::consts::FRAC_PI_4;
+
+struct TerrainTile {
+    // moved into the texture when first drawn on the frontend
+    image: Option<egui::ColorImage>,
+    texture: Option<egui::TextureHandle>,
+    // top left, top right, bottom right, bottom left
+    corners: [walkers::Position; 4],
+}
+
+/// The hillshaded terrain model of the test tiles, drawn under the map preview
+pub struct DrawableTerrain {
+    tiles: Vec<TerrainTile>,
+}
+
+impl DrawableTerrain {
+    pub fn from_tiles(
+        tiles: &[PreparedTile],
+        ref_point: geo::Coord,
+        crs: Option<&CrsDef>,
+    ) -> Result<Self> {
+        let transform = match crs {
+            Some(crs) => Some(Transform::from_epsg(crs.epsg(), 4326)?),
+            None => None,
+        };
+
+        let mut terrain_tiles = Vec::with_capacity(tiles.len());
+        for tile in tiles {
+            let dem = &tile.rasters.dem;
+            let hillshade = dem.hillshade(SUN_ANGLE);
+            let bounds = &dem.inner;
+
+            let width = bounds.right - bounds.left;
+            let height = bounds.bottom - bounds.top;
+            let mut pixels = Vec::with_capacity(width * height);
+            for yi in bounds.top..bounds.bottom {
+                for xi in bounds.left..bounds.right {
+                    pixels.push((hillshade[(yi, xi)].clamp(0., 1.) * 255.) as u8);
+                }
+            }
+
+            // the raster values sit at the cell centres
+            let half_cell = CELL_SIZE_METERS / 2.;
+            let tl = dem.index2coord(bounds.top, bounds.left);
+            let br = dem.index2coord(bounds.bottom - 1, bounds.right - 1);
+            let (min_x, max_x) = (
+                tl.x - half_cell + ref_point.x,
+                br.x + half_cell + ref_point.x,
+            );
+            let (min_y, max_y) = (
+                br.y - half_cell + ref_point.y,
+                tl.y + half_cell + ref_point.y,
+            );
+            let points = [
+                (min_x, max_y),
+                (max_x, max_y),
+                (max_x, min_y),
+                (min_x, min_y),
+            ];
+
+            let points = match &transform {
+                Some(transform) => transform.convert_batch(&points)?,
+                None => points.to_vec(),
+            };
+
+            terrain_tiles.push(TerrainTile {
+                image: Some(egui::ColorImage::from_gray([width, height], &pixels)),
+                texture: None,
+                corners: std::array::from_fn(|i| walkers::lon_lat(points[i].0, points[i].1)),
+            });
+        }
+
+        Ok(DrawableTerrain {
+            tiles: terrain_tiles,
+        })
+    }
+
+    /// Uploads the hillshades to the GPU, must be called on the frontend before drawing
+    pub fn load_textures(&mut self, ctx: &egui::Context) {
+        for (i, tile) in self.tiles.iter_mut().enumerate() {
+            if let Some(image) = tile.image.take() {
+                tile.texture = Some(ctx.load_texture(
+                    format!("terrain_preview_{i}"),
+                    image,
+                    egui::TextureOptions::LINEAR,
+                ));
+            }
+        }
+    }
+
+    pub fn draw(&self, ui: &mut egui::Ui, projector: &walkers::ScreenProjector, opacity: f32) {
+        let color = Color32::WHITE.gamma_multiply(opacity);
+        let uvs = [
+            egui::pos2(0., 0.),
+            egui::pos2(1., 0.),
+            egui::pos2(1., 1.),
+            egui::pos2(0., 1.),
+        ];
+
+        for tile in self.tiles.iter() {
+            let Some(texture) = &tile.texture else {
+                continue;
+            };
+
+            let mut mesh = egui::Mesh::with_texture(texture.id());
+            for (corner, uv) in tile.corners.iter().zip(uvs) {
+                mesh.vertices.push(Vertex {
+                    pos: projector.project(*corner),
+                    uv,
+                    color,
+                });
+            }
+            mesh.add_triangle(0, 1, 2);
+            mesh.add_triangle(0, 2, 3);
+
+            ui.painter().add(egui::Shape::mesh(mesh));
+        }
+    }
+}
@@ -1,8 +1,10 @@
 mod drawable_object;
 mod drawable_omap;
 mod drawable_symbol;
+mod drawable_terrain;
 
 pub(crate) use drawable_object::*;
 pub use drawable_omap::DrawableOmap;
 pub use drawable_symbol::DrawOrder;
 pub(crate) use drawable_symbol::DrawableSymbol;
+pub use drawable_terrain::DrawableTerrain;
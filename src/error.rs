@@ -13,8 +13,6 @@ pub enum Error {
     ProjError(#[from] proj_core::Error),
     #[error("The chosen polygon filter does not intersect the lidar files")]
     MapAreaDistinctFromLidarArea,
-    #[error("Cannot create a neighborhood without a center")]
-    NeighborhoodError,
     #[error(transparent)]
     CopcError(#[from] copc_converter::Error),
     #[error(transparent)]
@@ -23,4 +21,6 @@ pub enum Error {
     LasError(#[from] las::Error),
     #[error("The area contains no ground points")]
     NoGroundPoints,
+    #[error("The task was cancelled")]
+    Cancelled,
 }
@@ -1,7 +1,12 @@
 use crate::backend::Backend;
 use crate::comms::{OmapComms, messages::*};
-use crate::gui::{GuiVariables, ProcessStage, modals::OmapModal};
+use crate::gui::{
+    GuiVariables, ProcessStage,
+    modals::OmapModal,
+    project_file::{ProjectFile, ResumePoint},
+};
 use eframe::egui;
+use std::path::Path;
 use walkers::{HttpTiles, MapMemory, MercatorProjection, sources};
 
 pub const HOME_LON_LAT: (f64, f64) = (10.6134, 59.9594);
@@ -25,6 +30,11 @@ pub struct OmapMaker {
 
     // app state
     pub state: ProcessStage,
+    // set while working back to a stage, i.e. after opening a saved project
+    // or cancelling the final map
+    resume: Option<ResumePoint>,
+    // set from a cancel request until the backend has confirmed it
+    cancelling: bool,
 
     // app context
     ctx: egui::Context,
@@ -32,6 +42,7 @@ pub struct OmapMaker {
     // backend communication
     comms: OmapComms<BackendTask, FrontendTask>,
     active_preview_job_id: Option<JobId>,
+    active_suggestion_job_id: Option<JobId>,
     next_preview_job_id: JobId,
 }
 
@@ -40,7 +51,11 @@ impl eframe::App for OmapMaker {
         // register all events that has occurred and monitor for backend panic
         loop {
             match self.comms.try_recv() {
-                Ok(event) => self.on_frontend_task(event),
+                Ok(event) => {
+                    if !self.is_from_cancelled_job(&event) {
+                        self.on_frontend_task(event)
+                    }
+                }
                 Err(recv_err) => match recv_err {
                     // message buffer empty i.e. all pending messages have been dealt with
                     std::sync::mpsc::TryRecvError::Empty => break,
@@ -73,6 +88,11 @@ impl eframe::App for OmapMaker {
                     | ProcessStage::AdjustVegetation
                     | ProcessStage::AdjustCliffs
                     | ProcessStage::AdjustWater
+                    | ProcessStage::AdjustStreams
+                    | ProcessStage::AdjustMarsh
+                    | ProcessStage::AdjustBuildings
+                    | ProcessStage::AdjustBoulders
+                    | ProcessStage::AdjustRoads
                     | ProcessStage::AdjustIntensity => self.render_adjust_slider_panel(ui),
                     ProcessStage::MakeMap => self.render_generating_map_panel(ui),
                     ProcessStage::ExportDone => self.render_done_panel(ui),
@@ -92,6 +112,11 @@ impl eframe::App for OmapMaker {
                 | ProcessStage::AdjustVegetation
                 | ProcessStage::AdjustCliffs
                 | ProcessStage::AdjustWater
+                | ProcessStage::AdjustStreams
+                | ProcessStage::AdjustMarsh
+                | ProcessStage::AdjustBuildings
+                | ProcessStage::AdjustBoulders
+                | ProcessStage::AdjustRoads
                 | ProcessStage::AdjustIntensity
                 | ProcessStage::ShowComponents => {
                     self.render_map(ui);
@@ -167,9 +192,12 @@ impl OmapMaker {
             http_tiles,
             map_memory: Default::default(),
             state: ProcessStage::Welcome,
+            resume: None,
+            cancelling: false,
             ctx,
             comms: frontend_comms,
             active_preview_job_id: None,
+            active_suggestion_job_id: None,
             next_preview_job_id: 0,
             open_modal: OmapModal::None,
             home: walkers::lon_lat(HOME_LON_LAT.0, HOME_LON_LAT.1),
@@ -202,6 +230,51 @@ impl OmapMaker {
             },
         }
     }
+
+    /// Loads a saved project and replays the backend steps needed to get back
+    /// to the stage it was saved at
+    pub fn open_project(&mut self, path: &Path) {
+        let mut gui_variables = GuiVariables::default();
+        let resume = match ProjectFile::from_file(path)
+            .and_then(|project_file| project_file.apply(&mut gui_variables))
+        {
+            Ok(resume) => resume,
+            Err(e) => {
+                self.on_frontend_task(FrontendTask::Error(format!("{e:#}"), false));
+                return;
+            }
+        };
+
+        self.reset();
+        self.gui_variables = gui_variables;
+        self.gui_variables
+            .log_terminal
+            .println(format!("Opened project {}", path.display()).as_str());
+
+        if resume.stage == ProcessStage::Welcome {
+            return;
+        }
+
+        // the lidar boundaries are not saved, so they are read again before jumping ahead
+        self.state = ProcessStage::CheckLidar;
+        self.resume = Some(resume);
+        let _ = self.comms.send(BackendTask::SetWorkerThreads(
+            self.gui_variables.project.worker_threads,
+        ));
+        self.start_task(Task::DoConnectedComponentAnalysis);
+    }
+
+    pub fn save_project(&mut self, path: &Path) {
+        match ProjectFile::new(&self.gui_variables, self.state)
+            .and_then(|project_file| project_file.to_file(path))
+        {
+            Ok(()) => self
+                .gui_variables
+                .log_terminal
+                .println(format!("Saved project to {}", path.display()).as_str()),
+            Err(e) => self.on_frontend_task(FrontendTask::Error(format!("{e:#}"), false)),
+        }
+    }
 }
 
 // private functions
@@ -230,11 +303,25 @@ impl OmapMaker {
                     self.gui_variables.update_map(*drawable_omap);
                 }
             }
+            Variable::Terrain(mut terrain) => {
+                terrain.load_textures(&self.ctx);
+                self.gui_variables.preview.terrain = Some(*terrain);
+            }
             Variable::ContourScore(job_id, score) => {
                 if self.active_preview_job_id == Some(job_id) {
                     self.gui_variables.preview.contour_score = score;
                 }
             }
+            Variable::ContourConflicts(job_id, conflicts) => {
+                if self.active_preview_job_id == Some(job_id) {
+                    self.gui_variables.preview.contour_conflicts = conflicts;
+                }
+            }
+            Variable::ContourSuggestion(job_id, suggestion) => {
+                if self.active_suggestion_job_id == Some(job_id) {
+                    self.gui_variables.preview.contour_suggestion = Some(suggestion);
+                }
+            }
             Variable::Stats(lidar_stats) => self.gui_variables.lidar.stats = Some(*lidar_stats),
             Variable::SingleCopcPath(path) => {
                 self.gui_variables.project.single_copc_path = Some(path)
@@ -247,6 +334,18 @@ impl OmapMaker {
             Task::RegenerateMap => {
                 self.regenerate_map(RegenerationScope::Changed);
             }
+            Task::SuggestContourInterval => {
+                // shares the counter with the map previews, so the ids never repeat
+                self.next_preview_job_id = self.next_preview_job_id.wrapping_add(1);
+                let job_id = self.next_preview_job_id;
+                self.active_suggestion_job_id = Some(job_id);
+                self.gui_variables.preview.suggesting_contours = true;
+                let _ = self.comms.send(BackendTask::SuggestContourInterval(
+                    job_id,
+                    Box::new(self.gui_variables.generation.params.clone()),
+                ));
+            }
+            Task::Cancel => self.cancel(),
             Task::Reset => self.reset(),
             Task::SetCrs(s) => self.update_crs(s),
             Task::ShowComponents => self.state = ProcessStage::ShowComponents,
@@ -311,7 +410,10 @@ impl OmapMaker {
                         .println("The remaining lidar files are all connected.");
                 }
             }
-            TaskDone::DropComponents => self.next_state(),
+            TaskDone::DropComponents => {
+                self.next_state();
+                self.continue_resume();
+            }
             TaskDone::OutputCrs => {
                 self.on_frontend_task(FrontendTask::DelegateTask(
                     Task::DoConnectedComponentAnalysis,
@@ -326,12 +428,36 @@ impl OmapMaker {
                     self.active_preview_job_id = None;
                 }
             }
+            TaskDone::SuggestContourInterval(job_id) => {
+                if self.active_suggestion_job_id == Some(job_id) {
+                    self.gui_variables.preview.suggesting_contours = false;
+                    self.active_suggestion_job_id = None;
+                }
+            }
             TaskDone::MakeMap => self.next_state(),
             TaskDone::Reset => (),
+            TaskDone::Cancel => {
+                self.cancelling = false;
+                self.gui_variables.log_terminal.println("Cancelled");
+            }
             TaskDone::InitializeMapTile => {
                 self.gui_variables.preview.generating_map_tile = false;
-                self.state.next();
-                self.start_task(Task::RegenerateMap);
+                match self.resume.take() {
+                    // an opened project needs every section up to the saved stage
+                    Some(resume) => {
+                        self.state = resume.stage;
+                        match preview_section(resume.stage) {
+                            Some(section) => {
+                                self.regenerate_map(RegenerationScope::Through(section))
+                            }
+                            None => self.start_task(Task::RegenerateMap),
+                        }
+                    }
+                    None => {
+                        self.state.next();
+                        self.start_task(Task::RegenerateMap);
+                    }
+                }
             }
         }
     }
@@ -384,6 +510,7 @@ impl OmapMaker {
                         bounds: ready.boundaries,
                         polygon: ready.polygon_filter,
                         write_single_copc: ready.write_single_copc,
+                        convert_to_copc: ready.convert_to_copc,
                         budget_gb: ready.memory_budget_gb,
                     })));
             }
@@ -406,6 +533,9 @@ impl OmapMaker {
                         paths: ready.paths,
                         test_area: ready.test_area,
                         stats: ready.stats,
+                        ground: ready.ground,
+                        noise: ready.noise,
+                        crs: ready.crs,
                     },
                 )));
             }
@@ -444,6 +574,7 @@ impl OmapMaker {
         match self.state {
             ProcessStage::AdjustContours => {
                 self.gui_variables.preview.map_tile = None;
+                self.gui_variables.preview.terrain = None;
                 self.gui_variables.tile.selected_square = None;
                 self.gui_variables.tile.selected_square_boundary = None;
                 let _ = self.comms.send(BackendTask::ClearParams);
@@ -584,24 +715,51 @@ impl OmapMaker {
     }
 
     fn regenerate_current_adjustment_section(&mut self) {
-        let section = match self.state {
-            ProcessStage::AdjustOpenness => MapPreviewSection::Openness,
-            ProcessStage::AdjustVegetation => MapPreviewSection::Vegetation,
-            ProcessStage::AdjustCliffs => MapPreviewSection::Cliffs,
-            ProcessStage::AdjustWater => MapPreviewSection::Water,
-            ProcessStage::AdjustIntensity => MapPreviewSection::Intensity,
-            _ => return,
+        if let Some(section) = preview_section(self.state) {
+            self.regenerate_map(RegenerationScope::Section(section));
+        }
+    }
+
+    // the lidar files of an opened project are mapped and we are at DrawPolygon,
+    // skip ahead if the project was saved at a later stage
+    fn continue_resume(&mut self) {
+        let Some(resume) = self.resume.take() else {
+            return;
         };
+        if resume.stage == ProcessStage::DrawPolygon {
+            return;
+        }
+
+        if let Err(error) = self.gui_variables.prepare_test_area() {
+            self.on_frontend_task(FrontendTask::Error(format!("{error:#}"), false));
+            return;
+        }
+        self.state = ProcessStage::ChooseSquare;
+        self.map_memory.follow_my_position();
 
-        self.regenerate_map(RegenerationScope::Section(section));
+        if let Some((square, boundary)) = resume.test_square {
+            self.gui_variables.tile.selected_square = Some(square);
+            self.gui_variables.tile.selected_square_boundary = Some(boundary);
+        }
+
+        if resume.stage.is_adjustment() {
+            self.resume = Some(resume);
+            self.next_state();
+            // the saved test square was rejected, stay at ChooseSquare
+            if self.state != ProcessStage::PrepareMapPreview {
+                self.resume = None;
+            }
+        }
     }
 
     fn reset(&mut self) {
         self.home = walkers::lon_lat(HOME_LON_LAT.0, HOME_LON_LAT.1);
         self.gui_variables = Default::default();
         self.active_preview_job_id = None;
+        self.active_suggestion_job_id = None;
         self.next_preview_job_id = 0;
         self.open_modal = OmapModal::None;
+        self.resume = None;
         self.home_zoom = 16.;
         let _ = self.map_memory.set_zoom(self.home_zoom);
         self.map_memory.follow_my_position();
@@ -614,6 +772,53 @@ impl OmapMaker {
         self.state = ProcessStage::Welcome;
     }
 
+    fn cancel(&mut self) {
+        if self.cancelling {
+            return;
+        }
+        self.cancelling = true;
+        let _ = self.comms.cancel();
+        self.gui_variables.log_terminal.println("Cancelling...");
+
+        match self.state {
+            ProcessStage::ConvertingCOPC => self.state = ProcessStage::DrawPolygon,
+            ProcessStage::MakeMap => {
+                // the backend dropped the preview tiles when the final map was started,
+                // so they are read again and every section is regenerated
+                self.gui_variables.preview.map_tile = None;
+                self.gui_variables.preview.terrain = None;
+                self.state = ProcessStage::ChooseSquare;
+                self.resume = Some(ResumePoint {
+                    stage: ProcessStage::AdjustIntensity,
+                    test_square: None,
+                });
+                self.next_state();
+                if self.state != ProcessStage::PrepareMapPreview {
+                    self.resume = None;
+                }
+            }
+            state if state.is_adjustment() => {
+                self.active_preview_job_id = None;
+                self.active_suggestion_job_id = None;
+                self.gui_variables.preview.generating_map_tile = false;
+                self.gui_variables.preview.suggesting_contours = false;
+            }
+            _ => (),
+        }
+    }
+
+    // everything the backend sends between a cancel request and its confirmation
+    // belongs to the cancelled job, except the log output
+    fn is_from_cancelled_job(&self, event: &FrontendTask) -> bool {
+        self.cancelling
+            && !matches!(
+                event,
+                FrontendTask::Log(_)
+                    | FrontendTask::ProgressBar(_)
+                    | FrontendTask::TaskComplete(TaskDone::Cancel)
+            )
+    }
+
     fn restart_backend(&mut self) {
         // start backend thread
         let (frontend_comms, backend_comms) = OmapComms::new(&self.ctx);
@@ -621,5 +826,22 @@ impl OmapMaker {
         // starts the backend on its own thread
         Backend::boot(backend_comms).expect("Could not restart the background threads");
         self.comms = frontend_comms;
+        self.cancelling = false;
+    }
+}
+
+fn preview_section(stage: ProcessStage) -> Option<MapPreviewSection> {
+    match stage {
+        ProcessStage::AdjustOpenness => Some(MapPreviewSection::Openness),
+        ProcessStage::AdjustVegetation => Some(MapPreviewSection::Vegetation),
+        ProcessStage::AdjustCliffs => Some(MapPreviewSection::Cliffs),
+        ProcessStage::AdjustWater => Some(MapPreviewSection::Water),
+        ProcessStage::AdjustStreams => Some(MapPreviewSection::Streams),
+        ProcessStage::AdjustMarsh => Some(MapPreviewSection::Marsh),
+        ProcessStage::AdjustBuildings => Some(MapPreviewSection::Buildings),
+        ProcessStage::AdjustBoulders => Some(MapPreviewSection::Boulders),
+        ProcessStage::AdjustRoads => Some(MapPreviewSection::Roads),
+        ProcessStage::AdjustIntensity => Some(MapPreviewSection::Intensity),
+        _ => None,
     }
 }
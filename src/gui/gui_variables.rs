@@ -5,9 +5,12 @@ use proj_core::{CrsDef, Transform};
 
 use super::terminal_like::TerminalLike;
 use crate::{
-    drawable::{DrawOrder, DrawableOmap},
-    map_gen::egui_map::{AreaSymbol, Symbol},
-    parameters::{FileParameters, MapParameters},
+    drawable::{DrawOrder, DrawableOmap, DrawableTerrain},
+    map_gen::egui_map::{AreaSymbol, ContourConflict, ContourSuggestion, Symbol},
+    parameters::{
+        FileParameters, GroundParameters, MapParameters, NoiseParameters, OutputFormat,
+        RasterFormat,
+    },
     statistics::LidarStats,
 };
 
@@ -15,7 +18,7 @@ use crate::{
 pub enum StageValidationError {
     #[error("Choose at least one lidar file before continuing")]
     MissingLidarFiles,
-    #[error("Choose an output .omap save location before continuing")]
+    #[error("Choose where to save the map before continuing")]
     MissingSaveLocation,
     #[error("Select a test square before continuing")]
     MissingSelectedSquare,
@@ -41,9 +44,12 @@ pub enum TileProvider {
 pub struct ProjectFiles {
     pub paths: Vec<std::path::PathBuf>,
     pub save_location: std::path::PathBuf,
+    pub output_format: OutputFormat,
+    pub symbol_template: Option<std::path::PathBuf>,
     pub selected_file: Option<usize>,
     pub crs_epsg: Vec<Option<CrsDef>>,
     pub write_single_copc: bool,
+    pub convert_to_copc: bool,
     pub memory_budget_gb: u8,
     pub single_copc_path: Option<std::path::PathBuf>,
     pub worker_threads: usize,
@@ -54,6 +60,15 @@ pub struct ProjectFiles {
     pub save_canopy_height_raster: bool,
     pub save_surface_objects_raster: bool,
     pub save_ndvd_raster: bool,
+    pub save_flow_accumulation_raster: bool,
+    pub save_dem_raster: bool,
+    pub save_intensity_raster: bool,
+    pub save_return_number_raster: bool,
+    pub save_vegetation_density_rasters: bool,
+    pub save_water_raster: bool,
+    pub raster_format: RasterFormat,
+    pub save_geopackage: bool,
+    pub save_geojson: bool,
 }
 
 impl Default for ProjectFiles {
@@ -61,9 +76,12 @@ impl Default for ProjectFiles {
         Self {
             paths: Default::default(),
             save_location: Default::default(),
+            output_format: Default::default(),
+            symbol_template: Default::default(),
             selected_file: Default::default(),
             crs_epsg: Default::default(),
             write_single_copc: Default::default(),
+            convert_to_copc: true,
             single_copc_path: Default::default(),
             memory_budget_gb: 8,
             worker_threads: std::thread::available_parallelism()
@@ -77,6 +95,15 @@ impl Default for ProjectFiles {
             save_canopy_height_raster: Default::default(),
             save_surface_objects_raster: Default::default(),
             save_ndvd_raster: Default::default(),
+            save_flow_accumulation_raster: Default::default(),
+            save_dem_raster: Default::default(),
+            save_intensity_raster: Default::default(),
+            save_return_number_raster: Default::default(),
+            save_vegetation_density_rasters: Default::default(),
+            save_water_raster: Default::default(),
+            raster_format: Default::default(),
+            save_geopackage: Default::default(),
+            save_geojson: Default::default(),
         }
     }
 }
@@ -100,11 +127,24 @@ impl ProjectFiles {
             return FileParameters {
                 paths: vec![single_copc_path.clone()],
                 save_location: self.save_location.clone(),
+                output_format: self.output_format,
+                symbol_template: self.symbol_template.clone(),
                 save_slope_raster: self.save_rasters && self.save_slope_raster,
                 save_hillshade_raster: self.save_rasters && self.save_hillshade_raster,
                 save_last_return_raster: self.save_rasters && self.save_last_return_raster,
                 save_surface_objects_raster: self.save_rasters && self.save_surface_objects_raster,
                 save_ndvd_raster: self.save_rasters && self.save_ndvd_raster,
+                save_flow_accumulation_raster: self.save_rasters
+                    && self.save_flow_accumulation_raster,
+                save_dem_raster: self.save_rasters && self.save_dem_raster,
+                save_intensity_raster: self.save_rasters && self.save_intensity_raster,
+                save_return_number_raster: self.save_rasters && self.save_return_number_raster,
+                save_vegetation_density_rasters: self.save_rasters
+                    && self.save_vegetation_density_rasters,
+                save_water_raster: self.save_rasters && self.save_water_raster,
+                raster_format: self.raster_format,
+                save_geopackage: self.save_geopackage,
+                save_geojson: self.save_geojson,
                 crs_epsg: vec![],
                 save_canopy_height_raster: self.save_rasters && self.save_canopy_height_raster,
             };
@@ -113,11 +153,23 @@ impl ProjectFiles {
         FileParameters {
             paths: self.paths.clone(),
             save_location: self.save_location.clone(),
+            output_format: self.output_format,
+            symbol_template: self.symbol_template.clone(),
             save_slope_raster: self.save_rasters && self.save_slope_raster,
             save_hillshade_raster: self.save_rasters && self.save_hillshade_raster,
             save_last_return_raster: self.save_rasters && self.save_last_return_raster,
             save_surface_objects_raster: self.save_rasters && self.save_surface_objects_raster,
             save_ndvd_raster: self.save_rasters && self.save_ndvd_raster,
+            save_flow_accumulation_raster: self.save_rasters && self.save_flow_accumulation_raster,
+            save_dem_raster: self.save_rasters && self.save_dem_raster,
+            save_intensity_raster: self.save_rasters && self.save_intensity_raster,
+            save_return_number_raster: self.save_rasters && self.save_return_number_raster,
+            save_vegetation_density_rasters: self.save_rasters
+                && self.save_vegetation_density_rasters,
+            save_water_raster: self.save_rasters && self.save_water_raster,
+            raster_format: self.raster_format,
+            save_geopackage: self.save_geopackage,
+            save_geojson: self.save_geojson,
             crs_epsg: self.crs_epsg.clone(),
             save_canopy_height_raster: self.save_rasters && self.save_canopy_height_raster,
         }
@@ -193,8 +245,13 @@ pub struct MapPreviewState {
     pub visibility_checkboxes: HashMap<Symbol, bool>,
     pub generating_map_tile: bool,
     pub map_tile: Option<DrawableOmap>,
+    pub terrain: Option<DrawableTerrain>,
+    pub show_terrain: bool,
     pub map_opacity: f32,
     pub contour_score: (f32, f32),
+    pub contour_conflicts: Vec<ContourConflict>,
+    pub suggesting_contours: bool,
+    pub contour_suggestion: Option<ContourSuggestion>,
 }
 
 impl Default for MapPreviewState {
@@ -209,8 +266,13 @@ impl Default for MapPreviewState {
             visibility_checkboxes,
             generating_map_tile: false,
             map_tile: None,
+            terrain: None,
+            show_terrain: true,
             map_opacity: 1.0,
             contour_score: (0.0, 0.0),
+            contour_conflicts: Vec::new(),
+            suggesting_contours: false,
+            contour_suggestion: None,
         }
     }
 }
@@ -232,6 +294,7 @@ pub struct ReadyForCopcConversion {
     pub boundaries: Vec<[walkers::Position; 4]>,
     pub polygon_filter: geo::LineString,
     pub write_single_copc: bool,
+    pub convert_to_copc: bool,
     pub memory_budget_gb: u8,
 }
 
@@ -239,6 +302,9 @@ pub struct ReadyForMapPreview {
     pub paths: Vec<std::path::PathBuf>,
     pub test_area: geo::Rect,
     pub stats: LidarStats,
+    pub ground: GroundParameters,
+    pub noise: NoiseParameters,
+    pub crs: Option<CrsDef>,
 }
 
 pub struct ReadyForFinalMap {
@@ -262,19 +328,7 @@ pub struct GuiVariables {
 
 impl GuiVariables {
     pub fn get_most_popular_crs(&self) -> Option<CrsDef> {
-        let mut crs_tally: Vec<(u32, u16, CrsDef)> = Vec::new();
-        for crs in self.project.crs_epsg.iter().flatten() {
-            let epsg = crs.epsg();
-            if let Some((_, count, _)) = crs_tally.iter_mut().find(|(code, _, _)| *code == epsg) {
-                *count += 1;
-            } else {
-                crs_tally.push((epsg, 1, crs.clone()));
-            }
-        }
-        crs_tally
-            .into_iter()
-            .max_by(|(_, v1, _), (_, v2, _)| v1.cmp(v2))
-            .map(|(_, _, crs)| crs)
+        crate::parse_crs::most_popular_crs(&self.project.crs_epsg)
     }
 
     pub fn update_unique_crs(&mut self) {
@@ -361,11 +415,22 @@ impl GuiVariables {
             file_params: FileParameters {
                 paths: self.project.paths.clone(),
                 save_location: self.project.save_location.clone(),
+                output_format: self.project.output_format,
+                symbol_template: None,
                 save_slope_raster: false,
                 save_hillshade_raster: false,
                 save_last_return_raster: false,
                 save_surface_objects_raster: false,
                 save_ndvd_raster: false,
+                save_flow_accumulation_raster: false,
+                save_dem_raster: false,
+                save_intensity_raster: false,
+                save_return_number_raster: false,
+                save_vegetation_density_rasters: false,
+                save_water_raster: false,
+                raster_format: self.project.raster_format,
+                save_geopackage: false,
+                save_geojson: false,
                 crs_epsg: self.project.crs_epsg.clone(),
                 save_canopy_height_raster: false,
             },
@@ -374,6 +439,7 @@ impl GuiVariables {
             boundaries: self.lidar.boundaries.clone(),
             polygon_filter: self.area.polygon_filter.clone(),
             write_single_copc: self.project.write_single_copc,
+            convert_to_copc: self.project.convert_to_copc,
             memory_budget_gb: self.project.memory_budget_gb,
         })
     }
@@ -453,6 +519,9 @@ impl GuiVariables {
             paths: self.project.to_file_parameters().paths,
             test_area,
             stats,
+            ground: self.generation.params.ground.clone(),
+            noise: self.generation.params.noise.clone(),
+            crs: self.generation.params.output.crs.clone(),
         })
     }
 
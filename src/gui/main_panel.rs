@@ -122,6 +122,11 @@ impl OmapMaker {
             }
             state if state.is_adjustment() => map.with_plugin(map_plugins::OmapDrawer::new(
                 &self.gui_variables.preview.map_tile,
+                self.gui_variables
+                    .preview
+                    .terrain
+                    .as_ref()
+                    .filter(|_| self.gui_variables.preview.show_terrain),
                 &self.gui_variables.preview.visibility_checkboxes,
                 self.gui_variables.preview.map_opacity,
             )),
@@ -154,6 +159,7 @@ impl OmapMaker {
             map_controls::render_map_opacity_slider(
                 ui,
                 &mut self.gui_variables.preview.map_opacity,
+                &mut self.gui_variables.preview.show_terrain,
                 rect,
             );
             map_controls::render_symbol_toggles(
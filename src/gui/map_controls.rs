@@ -226,7 +226,12 @@ pub fn render_symbol_toggles(
         });
 }
 
-pub fn render_map_opacity_slider(ui: &mut egui::Ui, slider_val: &mut f32, rect: egui::Rect) {
+pub fn render_map_opacity_slider(
+    ui: &mut egui::Ui,
+    slider_val: &mut f32,
+    show_terrain: &mut bool,
+    rect: egui::Rect,
+) {
     egui::Window::new("Map opacity slider")
         .collapsible(false)
         .resizable(false)
@@ -235,6 +240,8 @@ pub fn render_map_opacity_slider(ui: &mut egui::Ui, slider_val: &mut f32, rect:
         .show(ui.ctx(), |ui| {
             ui.label("Map opacity");
             ui.add(egui::Slider::new(slider_val, 0.0..=1.0));
+            ui.checkbox(show_terrain, "Terrain model")
+                .on_hover_text("Shade the terrain model of the test tile under the map.");
         });
 }
 
@@ -243,7 +250,7 @@ pub fn render_contour_scores(ui: &mut egui::Ui, score: (f32, f32), weight: f32,
         .collapsible(false)
         .resizable(false)
         .title_bar(false)
-        .anchor(egui::Align2::LEFT_TOP, [rect.min.x + 10., 70.])
+        .anchor(egui::Align2::LEFT_TOP, [rect.min.x + 10., 95.])
         .show(ui.ctx(), |ui| {
             ui.label(format!("Contour Score: {:.4}", score.0 + weight * score.1));
             ui.label(format!(
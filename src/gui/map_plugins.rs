@@ -5,7 +5,10 @@ use geo::{Area, BooleanOps, Contains, TriangulateEarcut, Validation};
 use proj_core::{CrsDef, Transform};
 use walkers::{Plugin, ScreenProjector};
 
-use crate::{drawable::DrawableOmap, map_gen::egui_map::Symbol};
+use crate::{
+    drawable::{DrawableOmap, DrawableTerrain},
+    map_gen::egui_map::Symbol,
+};
 
 const COLOR_LIST: [egui::Color32; 9] = [
     egui::Color32::ORANGE,
@@ -422,6 +425,7 @@ fn draw_polygon(
 
 pub struct OmapDrawer<'a> {
     map: &'a Option<DrawableOmap>,
+    terrain: Option<&'a DrawableTerrain>,
     visibilities: &'a HashMap<Symbol, bool>,
     opacity: f32,
 }
@@ -429,11 +433,13 @@ pub struct OmapDrawer<'a> {
 impl<'a> OmapDrawer<'a> {
     pub fn new(
         map: &'a Option<DrawableOmap>,
+        terrain: Option<&'a DrawableTerrain>,
         visibilities: &'a HashMap<Symbol, bool>,
         opacity: f32,
     ) -> Self {
         Self {
             map,
+            terrain,
             visibilities,
             opacity,
         }
@@ -443,7 +449,7 @@ impl<'a> OmapDrawer<'a> {
 impl Plugin for OmapDrawer<'_> {
     fn run(self: Box<Self>, ui: &mut Ui, _response: &Response, projector: &ScreenProjector) {
         if let Some(map) = self.map.as_ref() {
-            map.draw(ui, projector, self.visibilities, self.opacity);
+            map.draw(ui, projector, self.terrain, self.visibilities, self.opacity);
         }
     }
 }
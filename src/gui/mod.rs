@@ -3,18 +3,26 @@ pub mod main_panel;
 pub mod map_controls;
 pub mod map_plugins;
 pub mod modals;
+pub mod project_file;
 pub mod side_panel;
 pub mod terminal_like;
 
+use serde::{Deserialize, Serialize};
+
 pub use gui_variables::GuiVariables;
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum ProcessStage {
     AdjustContours,
     AdjustOpenness,
     AdjustVegetation,
     AdjustCliffs,
     AdjustWater,
+    AdjustStreams,
+    AdjustMarsh,
+    AdjustBuildings,
+    AdjustBoulders,
+    AdjustRoads,
     AdjustIntensity,
     CheckLidar,
     ShowComponents,
@@ -40,7 +48,12 @@ impl ProcessStage {
             ProcessStage::AdjustOpenness => *self = ProcessStage::AdjustVegetation,
             ProcessStage::AdjustVegetation => *self = ProcessStage::AdjustCliffs,
             ProcessStage::AdjustCliffs => *self = ProcessStage::AdjustWater,
-            ProcessStage::AdjustWater => *self = ProcessStage::AdjustIntensity,
+            ProcessStage::AdjustWater => *self = ProcessStage::AdjustStreams,
+            ProcessStage::AdjustStreams => *self = ProcessStage::AdjustMarsh,
+            ProcessStage::AdjustMarsh => *self = ProcessStage::AdjustBuildings,
+            ProcessStage::AdjustBuildings => *self = ProcessStage::AdjustBoulders,
+            ProcessStage::AdjustBoulders => *self = ProcessStage::AdjustRoads,
+            ProcessStage::AdjustRoads => *self = ProcessStage::AdjustIntensity,
             ProcessStage::AdjustIntensity => *self = ProcessStage::MakeMap,
             ProcessStage::MakeMap => *self = ProcessStage::ExportDone,
             _ => unreachable!("Should not call next on state for {:?} variant.", self),
@@ -53,7 +66,12 @@ impl ProcessStage {
             ProcessStage::AdjustOpenness => *self = ProcessStage::AdjustContours,
             ProcessStage::AdjustVegetation => *self = ProcessStage::AdjustOpenness,
             ProcessStage::AdjustCliffs => *self = ProcessStage::AdjustVegetation,
-            ProcessStage::AdjustIntensity => *self = ProcessStage::AdjustWater,
+            ProcessStage::AdjustIntensity => *self = ProcessStage::AdjustRoads,
+            ProcessStage::AdjustRoads => *self = ProcessStage::AdjustBoulders,
+            ProcessStage::AdjustBoulders => *self = ProcessStage::AdjustBuildings,
+            ProcessStage::AdjustBuildings => *self = ProcessStage::AdjustMarsh,
+            ProcessStage::AdjustMarsh => *self = ProcessStage::AdjustStreams,
+            ProcessStage::AdjustStreams => *self = ProcessStage::AdjustWater,
             ProcessStage::AdjustWater => *self = ProcessStage::AdjustCliffs,
             ProcessStage::ShowComponents => *self = ProcessStage::CheckLidar,
             _ => unreachable!("Should not call prev on state for {:?} variant.", self),
@@ -68,6 +86,11 @@ impl ProcessStage {
                 | ProcessStage::AdjustVegetation
                 | ProcessStage::AdjustCliffs
                 | ProcessStage::AdjustWater
+                | ProcessStage::AdjustStreams
+                | ProcessStage::AdjustMarsh
+                | ProcessStage::AdjustBuildings
+                | ProcessStage::AdjustBoulders
+                | ProcessStage::AdjustRoads
                 | ProcessStage::AdjustIntensity
         )
     }
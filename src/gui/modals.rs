@@ -1,6 +1,6 @@
 use crate::comms::messages::*;
 
-use crate::OmapMaker;
+use crate::{OmapMaker, parameters::OutputFormat};
 use eframe::egui;
 use egui::Modal;
 use proj_core::CrsDef;
@@ -339,6 +339,66 @@ impl OmapMaker {
             ui.separator();
             ui.label("The next step is the map generation, which may take a little while. There is no way of going back once the generation starts. \
             Are you happy with your parameter settings and want to continue?");
+            ui.add_space(10.);
+            let project = &mut self.gui_variables.project;
+            ui.horizontal(|ui| {
+                ui.label("Save the map as");
+                egui::ComboBox::from_id_salt("Output format")
+                    .selected_text(format!("{}", project.output_format))
+                    .show_ui(ui, |ui| {
+                        for format in [OutputFormat::Omap, OutputFormat::Ocd] {
+                            ui.selectable_value(
+                                &mut project.output_format,
+                                format,
+                                format!("{}", format),
+                            );
+                        }
+                    });
+            });
+            project
+                .save_location
+                .set_extension(project.output_format.extension());
+            if project.output_format == OutputFormat::Ocd {
+                ui.label("The OCAD file only holds simplified symbols, \
+                load the ISOM symbol set in OCAD to replace them by symbol number.");
+            } else {
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Symbol set template")
+                        .on_hover_text(
+                            "Use the symbols of an existing .omap or .xmap file, matched by their ISOM number.",
+                        )
+                        .clicked()
+                        && let Some(path) = rfd::FileDialog::new()
+                            .add_filter(
+                                "OpenOrienteering Mapper (*.omap, *.xmap)",
+                                &["omap", "xmap"],
+                            )
+                            .pick_file()
+                    {
+                        project.symbol_template = Some(path);
+                    }
+                    match &project.symbol_template {
+                        Some(path) => {
+                            ui.label(
+                                path.file_name()
+                                    .map(|name| name.to_string_lossy())
+                                    .unwrap_or_default(),
+                            );
+                            if ui
+                                .small_button("x")
+                                .on_hover_text("Use the default symbol set")
+                                .clicked()
+                            {
+                                project.symbol_template = None;
+                            }
+                        }
+                        None => {
+                            ui.label("Default symbol set of the scale");
+                        }
+                    }
+                });
+            }
             ui.separator();
             egui::Sides::new().show(
                 ui,
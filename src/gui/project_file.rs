@@ -0,0 +1,378 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use proj_core::CrsDef;
+use serde::{Deserialize, Serialize};
+
+use super::{GuiVariables, ProcessStage};
+use crate::{
+    parameters::{MapParameters, OutputFormat, RasterFormat},
+    statistics::LidarStats,
+};
+
+/// Version of the project file format.
+/// Bump it when a change means old files can no longer be read as they were written
+pub const PROJECT_FILE_VERSION: u32 = 2;
+
+// TOML has no null value, so files without a CRS are stored as an empty WKT string,
+// or as EPSG code 0 in version 1 files
+const NO_CRS_WKT: &str = "";
+const NO_CRS_EPSG: u32 = 0;
+
+/// A saved OmapMaker session.
+///
+/// CRSs are stored as WKT and everything in map display coordinates
+/// (lon/lat or local) is stored as `[x, y]` pairs
+#[derive(Serialize, Deserialize)]
+pub struct ProjectFile {
+    version: u32,
+    stage: ProcessStage,
+    #[serde(default)]
+    polygon_filter: Vec<[f64; 2]>,
+    files: SavedFiles,
+    test_square: Option<SavedTestSquare>,
+    stats: Option<LidarStats>,
+    #[serde(default)]
+    parameters: MapParameters,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedFiles {
+    paths: Vec<PathBuf>,
+    save_location: PathBuf,
+    #[serde(default)]
+    output_format: OutputFormat,
+    #[serde(default)]
+    symbol_template: Option<PathBuf>,
+    #[serde(default)]
+    crs_wkt: Vec<String>,
+    #[serde(default)]
+    output_crs_wkt: Option<String>,
+    // version 1 stored the CRSs by their EPSG code, which loses the CRSs without one
+    #[serde(default, skip_serializing)]
+    crs_epsg: Vec<u32>,
+    #[serde(default, skip_serializing)]
+    output_crs: Option<u32>,
+    write_single_copc: bool,
+    #[serde(default = "default_convert_to_copc")]
+    convert_to_copc: bool,
+    memory_budget_gb: u8,
+    single_copc_path: Option<PathBuf>,
+    worker_threads: usize,
+    save_rasters: bool,
+    save_slope_raster: bool,
+    save_hillshade_raster: bool,
+    save_last_return_raster: bool,
+    save_canopy_height_raster: bool,
+    save_surface_objects_raster: bool,
+    save_ndvd_raster: bool,
+    #[serde(default)]
+    save_flow_accumulation_raster: bool,
+    #[serde(default)]
+    save_dem_raster: bool,
+    #[serde(default)]
+    save_intensity_raster: bool,
+    #[serde(default)]
+    save_return_number_raster: bool,
+    #[serde(default)]
+    save_vegetation_density_rasters: bool,
+    #[serde(default)]
+    save_water_raster: bool,
+    #[serde(default)]
+    raster_format: RasterFormat,
+    #[serde(default)]
+    save_geopackage: bool,
+    #[serde(default)]
+    save_geojson: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTestSquare {
+    min: [f64; 2],
+    max: [f64; 2],
+    boundary: [[f64; 2]; 4],
+}
+
+/// The stage to jump back to once the lidar files are read again
+pub struct ResumePoint {
+    pub stage: ProcessStage,
+    pub test_square: Option<(geo::Rect, [walkers::Position; 4])>,
+}
+
+impl ProjectFile {
+    pub fn new(gui_variables: &GuiVariables, stage: ProcessStage) -> crate::Result<ProjectFile> {
+        let project = &gui_variables.project;
+        let output_crs = gui_variables.generation.params.output.crs.as_ref();
+
+        // after the COPC conversion the paths point to the converted files,
+        // which are all in the output CRS
+        let crs_wkt = match output_crs {
+            Some(crs) if gui_variables.lidar.stats.is_some() => {
+                vec![write_crs(crs)?; project.paths.len()]
+            }
+            _ => project
+                .crs_epsg
+                .iter()
+                .map(|crs| match crs {
+                    Some(crs) => write_crs(crs),
+                    None => Ok(NO_CRS_WKT.to_string()),
+                })
+                .collect::<crate::Result<Vec<_>>>()?,
+        };
+
+        let test_square = match (
+            gui_variables.tile.selected_square,
+            gui_variables.tile.selected_square_boundary,
+        ) {
+            (Some(rect), Some(boundary)) => Some(SavedTestSquare {
+                min: [rect.min().x, rect.min().y],
+                max: [rect.max().x, rect.max().y],
+                boundary: boundary.map(|p| [p.x(), p.y()]),
+            }),
+            _ => None,
+        };
+
+        Ok(ProjectFile {
+            version: PROJECT_FILE_VERSION,
+            stage,
+            polygon_filter: gui_variables
+                .area
+                .polygon_filter
+                .coords()
+                .map(|c| [c.x, c.y])
+                .collect(),
+            files: SavedFiles {
+                paths: project.paths.clone(),
+                save_location: project.save_location.clone(),
+                output_format: project.output_format,
+                symbol_template: project.symbol_template.clone(),
+                crs_wkt,
+                output_crs_wkt: output_crs.map(write_crs).transpose()?,
+                crs_epsg: Vec::new(),
+                output_crs: None,
+                write_single_copc: project.write_single_copc,
+                convert_to_copc: project.convert_to_copc,
+                memory_budget_gb: project.memory_budget_gb,
+                single_copc_path: project.single_copc_path.clone(),
+                worker_threads: project.worker_threads,
+                save_rasters: project.save_rasters,
+                save_slope_raster: project.save_slope_raster,
+                save_hillshade_raster: project.save_hillshade_raster,
+                save_last_return_raster: project.save_last_return_raster,
+                save_canopy_height_raster: project.save_canopy_height_raster,
+                save_surface_objects_raster: project.save_surface_objects_raster,
+                save_ndvd_raster: project.save_ndvd_raster,
+                save_flow_accumulation_raster: project.save_flow_accumulation_raster,
+                save_dem_raster: project.save_dem_raster,
+                save_intensity_raster: project.save_intensity_raster,
+                save_return_number_raster: project.save_return_number_raster,
+                save_vegetation_density_rasters: project.save_vegetation_density_rasters,
+                save_water_raster: project.save_water_raster,
+                raster_format: project.raster_format,
+                save_geopackage: project.save_geopackage,
+                save_geojson: project.save_geojson,
+            },
+            test_square,
+            stats: gui_variables.lidar.stats.clone(),
+            parameters: gui_variables.generation.params.clone(),
+        })
+    }
+
+    pub fn from_file(path: &Path) -> crate::Result<ProjectFile> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read project file {path:?}"))?;
+
+        let mut file: ProjectFile = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse project file {path:?}"))?;
+
+        if file.version > PROJECT_FILE_VERSION {
+            bail!(
+                "The project file has version {}, but only versions up to {PROJECT_FILE_VERSION} are supported. \
+                It was probably written by a newer version of OmapMaker",
+                file.version
+            );
+        }
+        if file.version < 2 {
+            file.files.upgrade_crs();
+        }
+        if file.files.paths.len() != file.files.crs_wkt.len() {
+            bail!("The project file has a different number of lidar files and CRSs");
+        }
+
+        Ok(file)
+    }
+
+    pub fn to_file(&self, path: &Path) -> crate::Result<()> {
+        let contents = toml::to_string_pretty(self)?;
+        std::fs::write(path, contents)
+            .with_context(|| format!("Failed to write project file {path:?}"))
+    }
+
+    /// Moves the saved state into the (freshly reset) GUI variables
+    pub fn apply(self, gui_variables: &mut GuiVariables) -> crate::Result<ResumePoint> {
+        let stage = self.resume_stage();
+
+        let files = self.files;
+        let crs_epsg = files
+            .crs_wkt
+            .iter()
+            .map(|wkt| read_crs(wkt))
+            .collect::<crate::Result<Vec<_>>>()?;
+        let output_crs = files
+            .output_crs_wkt
+            .as_deref()
+            .map(read_crs)
+            .transpose()?
+            .flatten();
+
+        let project = &mut gui_variables.project;
+        project.paths = files.paths;
+        project.save_location = files.save_location;
+        project.output_format = files.output_format;
+        project.symbol_template = files.symbol_template;
+        project.crs_epsg = crs_epsg;
+        project.write_single_copc = files.write_single_copc;
+        project.convert_to_copc = files.convert_to_copc;
+        project.memory_budget_gb = files.memory_budget_gb;
+        project.single_copc_path = files.single_copc_path;
+        project.worker_threads = files.worker_threads.max(1);
+        project.save_rasters = files.save_rasters;
+        project.save_slope_raster = files.save_slope_raster;
+        project.save_hillshade_raster = files.save_hillshade_raster;
+        project.save_last_return_raster = files.save_last_return_raster;
+        project.save_canopy_height_raster = files.save_canopy_height_raster;
+        project.save_surface_objects_raster = files.save_surface_objects_raster;
+        project.save_ndvd_raster = files.save_ndvd_raster;
+        project.save_flow_accumulation_raster = files.save_flow_accumulation_raster;
+        project.save_dem_raster = files.save_dem_raster;
+        project.save_intensity_raster = files.save_intensity_raster;
+        project.save_return_number_raster = files.save_return_number_raster;
+        project.save_vegetation_density_rasters = files.save_vegetation_density_rasters;
+        project.save_water_raster = files.save_water_raster;
+        project.raster_format = files.raster_format;
+        project.save_geopackage = files.save_geopackage;
+        project.save_geojson = files.save_geojson;
+
+        gui_variables.area.polygon_filter = geo::LineString::new(
+            self.polygon_filter
+                .into_iter()
+                .map(geo::Coord::from)
+                .collect(),
+        );
+        gui_variables.lidar.stats = self.stats;
+        gui_variables.generation.params = self.parameters;
+        gui_variables.generation.params.output.crs = output_crs;
+        gui_variables.update_unique_crs();
+
+        let test_square = self.test_square.map(|square| {
+            (
+                geo::Rect::new(geo::Coord::from(square.min), geo::Coord::from(square.max)),
+                square.boundary.map(|[x, y]| walkers::lon_lat(x, y)),
+            )
+        });
+
+        Ok(ResumePoint { stage, test_square })
+    }
+
+    // only stable stages can be resumed, the transient stages fall back
+    // to the last stage the saved state is complete for
+    fn resume_stage(&self) -> ProcessStage {
+        let converted = self.stats.is_some();
+        let has_test_square = converted && self.test_square.is_some();
+
+        match self.stage {
+            ProcessStage::Welcome | ProcessStage::CheckLidar | ProcessStage::ShowComponents => {
+                ProcessStage::Welcome
+            }
+            ProcessStage::DrawPolygon | ProcessStage::ConvertingCOPC => ProcessStage::DrawPolygon,
+            stage if stage.is_adjustment() && has_test_square => stage,
+            ProcessStage::MakeMap | ProcessStage::ExportDone if has_test_square => {
+                ProcessStage::AdjustIntensity
+            }
+            _ if converted => ProcessStage::ChooseSquare,
+            _ => ProcessStage::DrawPolygon,
+        }
+    }
+}
+
+// projects saved before the conversion was optional always converted
+fn default_convert_to_copc() -> bool {
+    true
+}
+
+impl SavedFiles {
+    // the CRS parser reads EPSG codes as well as WKT
+    fn upgrade_crs(&mut self) {
+        let definition = |epsg: u32| match epsg {
+            NO_CRS_EPSG => NO_CRS_WKT.to_string(),
+            epsg => format!("EPSG:{epsg}"),
+        };
+        self.crs_wkt = self.crs_epsg.drain(..).map(definition).collect();
+        self.output_crs_wkt = self
+            .output_crs
+            .take()
+            .filter(|&epsg| epsg != NO_CRS_EPSG)
+            .map(definition);
+    }
+}
+
+fn write_crs(crs: &CrsDef) -> crate::Result<String> {
+    crate::parse_crs::crs_wkt(crs).context("The project can not be saved with this CRS")
+}
+
+fn read_crs(wkt: &str) -> crate::Result<Option<CrsDef>> {
+    if wkt == NO_CRS_WKT {
+        return Ok(None);
+    }
+
+    proj_wkt::parse_crs(wkt)
+        .with_context(|| format!("Could not create a CRS from the project file: {wkt}"))
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::ProjectFile;
+    use crate::gui::{GuiVariables, ProcessStage};
+
+    #[test]
+    fn a_saved_project_is_opened_as_it_was() {
+        // a CRS without an EPSG code
+        let crs = proj_wkt::parse_crs(
+            "+proj=tmerc +lat_0=0 +lon_0=10.5 +k=0.9996 +x_0=500000 +y_0=0 +datum=WGS84 +units=m",
+        )
+        .unwrap();
+        assert_eq!(crs.epsg(), 0);
+
+        let mut saved = GuiVariables::default();
+        saved.project.paths = vec![PathBuf::from("a.laz"), PathBuf::from("b.laz")];
+        saved.project.crs_epsg = vec![Some(crs.clone()), None];
+        saved.generation.params.output.crs = Some(crs.clone());
+
+        let path = std::env::temp_dir().join(format!(
+            "omapmaker_{}_project_round_trip.toml",
+            std::process::id()
+        ));
+        ProjectFile::new(&saved, ProcessStage::DrawPolygon)
+            .unwrap()
+            .to_file(&path)
+            .unwrap();
+        let mut opened = GuiVariables::default();
+        let resume =
+            ProjectFile::from_file(&path).and_then(|project_file| project_file.apply(&mut opened));
+        let _ = std::fs::remove_file(&path);
+        let resume = resume.unwrap();
+
+        let wkt = crate::parse_crs::crs_wkt(&crs).unwrap();
+        assert_eq!(resume.stage, ProcessStage::DrawPolygon);
+        assert_eq!(opened.project.paths, saved.project.paths);
+        assert_eq!(opened.project.crs_epsg.len(), 2);
+        let file_crs = opened.project.crs_epsg[0].as_ref().unwrap();
+        assert_eq!(crate::parse_crs::crs_wkt(file_crs).unwrap(), wkt);
+        assert!(opened.project.crs_epsg[1].is_none());
+        let output_crs = opened.generation.params.output.crs.as_ref().unwrap();
+        assert_eq!(crate::parse_crs::crs_wkt(output_crs).unwrap(), wkt);
+    }
+}
@@ -3,7 +3,9 @@ use crate::{
     drawable::DrawOrder,
     map_gen::egui_map::AreaSymbol,
     parameters::{
-        BezierParameters, BufferDirection, BufferRule, ContourAlgo, FormlinePruneAlgo, Scale,
+        BezierParameters, BufferDirection, BufferRule, ContourAlgo, FormlinePruneAlgo,
+        GroundClassification, GroundParameters, MapParameters, NoiseParameters, OutputFormat,
+        RasterFormat, Scale,
     },
 };
 
@@ -24,7 +26,8 @@ impl OmapMaker {
         ui.label(
             "Only .las and .laz files are accepted.\n\
         .copc.laz files are strongly recommended.\n\
-        If normal las or laz files are provided, they will be written to copc.laz.",
+        If normal las or laz files are provided, they will be written to copc.laz \
+        unless the conversion is turned off in the advanced settings.",
         );
 
         ui.horizontal(|ui| {
@@ -109,6 +112,15 @@ impl OmapMaker {
                 )
                 .on_hover_text("Number of worker threads used by the backend Rayon thread pool.");
 
+                ui.checkbox(
+                    &mut self.gui_variables.project.convert_to_copc,
+                    "Convert lidar files to COPC",
+                )
+                .on_hover_text(
+                    "COPC files are faster to read tile by tile. Without conversion .las and .laz files are read directly \
+                    through an index stored next to each file, which saves disk space for large projects.",
+                );
+
                 ui.checkbox(
                     &mut self.gui_variables.project.write_single_copc,
                     "Write all relevant lidar files to one COPC file",
@@ -131,7 +143,7 @@ impl OmapMaker {
                     "Save rasters",
                 )
                 .on_hover_text(
-                    "Write selected generated rasters as merged GeoTIFF files next to the .omap output.",
+                    "Write selected generated rasters as merged Cloud Optimized GeoTIFF files next to the .omap output.",
                 );
 
                 if !self.gui_variables.project.save_rasters {
@@ -141,6 +153,12 @@ impl OmapMaker {
                     self.gui_variables.project.save_canopy_height_raster = false;
                     self.gui_variables.project.save_surface_objects_raster = false;
                     self.gui_variables.project.save_ndvd_raster = false;
+                    self.gui_variables.project.save_flow_accumulation_raster = false;
+                    self.gui_variables.project.save_dem_raster = false;
+                    self.gui_variables.project.save_intensity_raster = false;
+                    self.gui_variables.project.save_return_number_raster = false;
+                    self.gui_variables.project.save_vegetation_density_rasters = false;
+                    self.gui_variables.project.save_water_raster = false;
                 }
 
                 ui.indent("indented raster checkboxes", |ui| {
@@ -191,7 +209,98 @@ impl OmapMaker {
                             "Save NDVD raster",
                         ),
                     );
+
+                    ui.add_enabled(
+                        self.gui_variables.project.save_rasters,
+                        egui::Checkbox::new(
+                            &mut self.gui_variables.project.save_flow_accumulation_raster,
+                            "Save flow accumulation raster",
+                        ),
+                    );
+
+                    ui.add_enabled(
+                        self.gui_variables.project.save_rasters,
+                        egui::Checkbox::new(
+                            &mut self.gui_variables.project.save_dem_raster,
+                            "Save DEM raster",
+                        ),
+                    );
+
+                    ui.add_enabled(
+                        self.gui_variables.project.save_rasters,
+                        egui::Checkbox::new(
+                            &mut self.gui_variables.project.save_intensity_raster,
+                            "Save intensity raster",
+                        ),
+                    );
+
+                    ui.add_enabled(
+                        self.gui_variables.project.save_rasters,
+                        egui::Checkbox::new(
+                            &mut self.gui_variables.project.save_return_number_raster,
+                            "Save return number raster",
+                        ),
+                    );
+
+                    ui.add_enabled(
+                        self.gui_variables.project.save_rasters,
+                        egui::Checkbox::new(
+                            &mut self.gui_variables.project.save_vegetation_density_rasters,
+                            "Save vegetation density rasters",
+                        ),
+                    );
+
+                    ui.add_enabled(
+                        self.gui_variables.project.save_rasters,
+                        egui::Checkbox::new(
+                            &mut self.gui_variables.project.save_water_raster,
+                            "Save water raster",
+                        ),
+                    );
+
+                    ui.add_enabled_ui(self.gui_variables.project.save_rasters, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Raster values");
+                            egui::ComboBox::from_id_salt("Raster format")
+                                .selected_text(format!(
+                                    "{}",
+                                    self.gui_variables.project.raster_format
+                                ))
+                                .show_ui(ui, |ui| {
+                                    for format in [RasterFormat::Rendered, RasterFormat::Float32] {
+                                        ui.selectable_value(
+                                            &mut self.gui_variables.project.raster_format,
+                                            format,
+                                            format!("{format}"),
+                                        );
+                                    }
+                                });
+                        })
+                        .response
+                        .on_hover_text(
+                            "8-bit images are stretched for viewing, float rasters keep the true values \
+                            with -9999 as no data, e.g. elevations in meters for the DEM.",
+                        );
+                    });
                 });
+
+                ui.checkbox(
+                    &mut self.gui_variables.project.save_geopackage,
+                    "Save map objects as GeoPackage",
+                )
+                .on_hover_text(
+                    "Write every symbol as a layer of a .gpkg file next to the map, in the output CRS \
+                    with the object tags (e.g. elevation) as attributes.",
+                );
+
+                ui.checkbox(
+                    &mut self.gui_variables.project.save_geojson,
+                    "Save map objects as GeoJSON",
+                )
+                .on_hover_text(
+                    "Write one .geojson file per symbol to a folder next to the map, in WGS 84 longitude and \
+                    latitude with the same attributes as the GeoPackage.",
+                );
             });
 
         ui.add_space(20.);
@@ -199,9 +308,12 @@ impl OmapMaker {
         if ui.button("Choose save location and name").clicked()
             && let Some(mut path) = rfd::FileDialog::new()
                 .add_filter("OpenOrienteering Mapper (*.omap)", &["omap"])
+                .add_filter("OCAD (*.ocd)", &["ocd"])
                 .save_file()
         {
-            path.set_extension("omap");
+            let output_format = OutputFormat::from_path(&path);
+            path.set_extension(output_format.extension());
+            self.gui_variables.project.output_format = output_format;
             self.gui_variables.project.save_location = path;
         };
 
@@ -212,7 +324,7 @@ impl OmapMaker {
             .as_os_str()
             .is_empty()
         {
-            ui.label("Choose where to save the resulting omap-file.");
+            ui.label("Choose where to save the resulting map file.");
         } else {
             ui.label(format!(
                 "{}",
@@ -236,6 +348,21 @@ impl OmapMaker {
             self.on_frontend_task(FrontendTask::NextState);
         }
 
+        ui.add_space(20.);
+        ui.horizontal(|ui| {
+            if ui
+                .button("Open project")
+                .on_hover_text("Continue a saved project from where it was left off.")
+                .clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("OmapMaker project (*.omproj)", &["omproj"])
+                    .pick_file()
+            {
+                self.open_project(&path);
+            }
+            self.render_save_project_button(ui);
+        });
+
         egui::Window::new("text size")
             .anchor(egui::Align2::LEFT_BOTTOM, [10., -10.])
             .collapsible(false)
@@ -268,8 +395,7 @@ impl OmapMaker {
         ui.add_space(10.);
         ui.label(
             "From all the files a graph is constructed where each lidar file \
-        is a node and bordering files are connected by edges. \
-        This assumes that the files belong to a grid-like structure. \
+        is a node and bordering or overlapping files are connected by edges. \
         If the graph has more than one connected component the user gets \
         to choose wether to keep the biggest connected component (by node count) or start over.",
         );
@@ -340,6 +466,13 @@ impl OmapMaker {
 
         ui.add_space(10.);
         ui.label("A file is deemed relevant if it overlaps with the chosen map area.");
+        if !self.gui_variables.project.convert_to_copc {
+            ui.add_space(10.);
+            ui.label(
+                "COPC conversion is turned off. The .las and .laz files are read directly, \
+                only files in another CRS are rewritten.",
+            );
+        }
 
         ui.add_space(10.);
         ui.label(".copc.laz is a .laz file (compressed .las file) where the points internally are structered in an octree. \
@@ -352,10 +485,24 @@ impl OmapMaker {
         If the file is transformed \"_EPSG_*\" is appended to the filename. \
         Where the star is replaced with the code of the CRS.");
 
-        ui.label("The resulting files are stored next to their parent.");
+        ui.label("The resulting files are stored next to their parent, transformed files are stored next to the map.");
 
         ui.add_space(20.);
         ui.label("After conversion you will choose the lidar tile used for adjusting parameters.");
+
+        ui.add_space(20.);
+        Self::render_ground_parameters(ui, &mut self.gui_variables.generation.params.ground);
+        ui.add_space(10.);
+        Self::render_noise_parameters(ui, &mut self.gui_variables.generation.params.noise);
+
+        ui.add_space(20.);
+        if ui
+            .button("Cancel")
+            .on_hover_text("Stop after the file being converted and go back to the map area.")
+            .clicked()
+        {
+            self.on_frontend_task(FrontendTask::DelegateTask(Task::Cancel));
+        }
     }
 
     pub fn render_prepare_map_preview_panel(&mut self, ui: &mut egui::Ui) {
@@ -418,6 +565,7 @@ impl OmapMaker {
             if ui.button("Start over").clicked() {
                 self.open_modal = OmapModal::ConfirmStartOver;
             }
+            self.render_save_project_button(ui);
             let polygon_ready = !self.gui_variables.area.drawing_polygon
                 && (self.gui_variables.area.polygon_filter.0.is_empty()
                     || self.gui_variables.area.polygon_filter.is_closed());
@@ -437,11 +585,21 @@ impl OmapMaker {
             "Select a square test area on the map. At least half of the square must overlap the available lidar area.",
         );
 
+        ui.add_space(20.);
+        Self::render_ground_parameters(ui, &mut self.gui_variables.generation.params.ground);
+        ui.add_space(10.);
+        Self::render_noise_parameters(ui, &mut self.gui_variables.generation.params.noise);
+        ui.label(
+            "The shading and contours of the test tile in the next step show the resulting \
+            terrain model. Go back to this step to change the ground classification.",
+        );
+
         ui.add_space(20.);
         ui.horizontal(|ui| {
             if ui.button("Start over").clicked() {
                 self.open_modal = OmapModal::ConfirmStartOver;
             }
+            self.render_save_project_button(ui);
             if ui
                 .add_enabled(
                     self.gui_variables.tile.selected_square.is_some(),
@@ -476,6 +634,26 @@ impl OmapMaker {
                 "Adjust water settings",
                 "Threshold the water-probability raster and tune the resulting polygon geometry.",
             ),
+            ProcessStage::AdjustStreams => (
+                "Adjust stream settings",
+                "Streams start where the upslope area draining through the DEM exceeds the threshold.",
+            ),
+            ProcessStage::AdjustMarsh => (
+                "Adjust marsh settings",
+                "Threshold the marsh-probability raster and tune the resulting polygon geometry.",
+            ),
+            ProcessStage::AdjustBuildings => (
+                "Adjust building settings",
+                "Threshold the building-probability raster. The outlines are squared up to the dominant direction of each building.",
+            ),
+            ProcessStage::AdjustBoulders => (
+                "Adjust boulder settings",
+                "Tune which compact and isolated bumps in the terrain become boulders.",
+            ),
+            ProcessStage::AdjustRoads => (
+                "Adjust road and path settings",
+                "Threshold the road-probability raster. The centre lines of the corridors get a road or path symbol from their width.",
+            ),
             ProcessStage::AdjustIntensity => (
                 "Adjust lidar intensity settings",
                 "Tune lidar intensity filters and their polygon geometry.",
@@ -486,6 +664,8 @@ impl OmapMaker {
         ui.heading(heading);
         ui.add_space(20.);
         ui.label(help_text);
+        ui.add_space(10.);
+        self.render_parameter_file_buttons(ui);
 
         egui::ScrollArea::both()
             .auto_shrink(false)
@@ -664,6 +844,178 @@ impl OmapMaker {
                             .buffer_rules,
                     );
                 }
+                ProcessStage::AdjustStreams => {
+                    ui.label(egui::RichText::new("Contributing area").strong());
+                    ui.add(
+                        egui::Slider::new(
+                            &mut self.gui_variables.generation.params.streams.min_contributing_area,
+                            500.0..=100_000.0,
+                        )
+                        .logarithmic(true)
+                        .text("Minimum area (m²)")
+                        .show_value(true),
+                    )
+                    .on_hover_text("A larger area gives fewer and shorter streams.");
+                    ui.label(
+                        "The preview only counts the area within the preview tile, \
+                        the final map also counts the water from the tiles upstream.",
+                    );
+                }
+                ProcessStage::AdjustMarsh => {
+                    ui.label(egui::RichText::new("Marsh probability threshold").strong());
+                    ui.add(
+                        egui::Slider::new(
+                            &mut self.gui_variables.generation.params.marsh.threshold,
+                            0.0..=1.0,
+                        )
+                        .text("Marsh 308")
+                        .show_value(true),
+                    )
+                    .on_hover_text(
+                        "The probability is highest on flat ground where water collects, with weak lidar intensity and little tall vegetation.",
+                    );
+                    ui.add_space(20.);
+                    ui.label(egui::RichText::new("Marsh Bezier simplification").strong());
+                    Self::render_bezier_parameters(
+                        ui,
+                        &mut self.gui_variables.generation.params.geometry.marsh.bezier,
+                    );
+                    ui.checkbox(
+                        &mut self
+                            .gui_variables
+                            .generation
+                            .params
+                            .geometry
+                            .marsh
+                            .min_size_filter,
+                        "Filter polygons by minimum symbol size.",
+                    );
+                    ui.add_space(20.);
+                    Self::render_buffer_rules(
+                        ui,
+                        "marsh_buffer_rule",
+                        &mut self
+                            .gui_variables
+                            .generation
+                            .params
+                            .geometry
+                            .marsh
+                            .buffer_rules,
+                    );
+                }
+                ProcessStage::AdjustBuildings => {
+                    ui.label(egui::RichText::new("Building probability threshold").strong());
+                    ui.add(
+                        egui::Slider::new(
+                            &mut self.gui_variables.generation.params.buildings.threshold,
+                            0.0..=1.0,
+                        )
+                        .text("Building 521")
+                        .show_value(true),
+                    )
+                    .on_hover_text(
+                        "Classified lidar gives the share of building returns around each cell. \
+                        Unclassified lidar falls back to tall and planar roof-like surfaces.",
+                    );
+                    ui.add_space(20.);
+                    ui.checkbox(
+                        &mut self
+                            .gui_variables
+                            .generation
+                            .params
+                            .geometry
+                            .buildings
+                            .min_size_filter,
+                        "Filter polygons by minimum symbol size.",
+                    );
+                    ui.add_space(20.);
+                    Self::render_buffer_rules(
+                        ui,
+                        "buildings_buffer_rule",
+                        &mut self
+                            .gui_variables
+                            .generation
+                            .params
+                            .geometry
+                            .buildings
+                            .buffer_rules,
+                    );
+                }
+                ProcessStage::AdjustBoulders => {
+                    let boulders = &mut self.gui_variables.generation.params.boulders;
+                    ui.label(egui::RichText::new("Boulder height").strong());
+                    ui.add(
+                        egui::Slider::new(&mut boulders.min_height, 0.3..=3.0)
+                            .text("Minimum height (m)")
+                            .show_value(true),
+                    )
+                    .on_hover_text("Lower bumps are ignored.");
+                    ui.add(
+                        egui::Slider::new(&mut boulders.large_height, 0.5..=4.0)
+                            .text("Large boulder 205 from (m)")
+                            .show_value(true),
+                    )
+                    .on_hover_text("Lower boulders become small boulders 204.");
+                    ui.add(
+                        egui::Slider::new(&mut boulders.max_height, 1.0..=5.0)
+                            .text("Maximum height (m)")
+                            .show_value(true),
+                    )
+                    .on_hover_text("Higher objects are more likely cars, sheds or cliffs.");
+                    ui.add_space(20.);
+                    ui.label(egui::RichText::new("Boulder footprint").strong());
+                    ui.add(
+                        egui::Slider::new(&mut boulders.max_footprint, 1.0..=50.0)
+                            .text("Maximum footprint (m²)")
+                            .show_value(true),
+                    );
+                }
+                ProcessStage::AdjustRoads => {
+                    let roads = &mut self.gui_variables.generation.params.roads;
+                    ui.label(egui::RichText::new("Road probability threshold").strong());
+                    ui.add(
+                        egui::Slider::new(&mut roads.threshold, 0.0..=1.0)
+                            .text("Threshold")
+                            .show_value(true),
+                    )
+                    .on_hover_text(
+                        "Corridors with more bare ground than their surroundings, \
+                        a smooth cross-section and a different intensity are likely roads.",
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut roads.min_length, 5.0..=100.0)
+                            .text("Minimum length (m)")
+                            .show_value(true),
+                    );
+                    ui.add_space(20.);
+                    ui.label(egui::RichText::new("Minimum corridor widths").strong());
+                    ui.add(
+                        egui::Slider::new(&mut roads.small_path_width, 0.5..=3.0)
+                            .text("Small path 506 (m)")
+                            .show_value(true),
+                    )
+                    .on_hover_text("Narrower corridors become less distinct small paths 507.");
+                    ui.add(
+                        egui::Slider::new(&mut roads.footpath_width, 0.5..=4.0)
+                            .text("Footpath 505 (m)")
+                            .show_value(true),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut roads.vehicle_track_width, 1.0..=6.0)
+                            .text("Vehicle track 504 (m)")
+                            .show_value(true),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut roads.road_width, 2.0..=8.0)
+                            .text("Road 503 (m)")
+                            .show_value(true),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut roads.wide_road_width, 3.0..=12.0)
+                            .text("Wide road 502 (m)")
+                            .show_value(true),
+                    );
+                }
                 ProcessStage::AdjustIntensity => {
                     self.render_intensity_adjustments(ui);
                     ui.add_space(20.);
@@ -711,15 +1063,20 @@ impl OmapMaker {
         } else {
             "Re-generate map"
         };
-        if ui
-            .add_enabled(
-                !self.gui_variables.preview.generating_map_tile,
-                egui::Button::new(button_txt),
-            )
-            .clicked()
-        {
-            self.on_frontend_task(FrontendTask::DelegateTask(Task::RegenerateMap));
-        }
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    !self.gui_variables.preview.generating_map_tile,
+                    egui::Button::new(button_txt),
+                )
+                .clicked()
+            {
+                self.on_frontend_task(FrontendTask::DelegateTask(Task::RegenerateMap));
+            }
+            if self.gui_variables.preview.generating_map_tile && ui.button("Cancel").clicked() {
+                self.on_frontend_task(FrontendTask::DelegateTask(Task::Cancel));
+            }
+        });
 
         ui.add_space(20.);
 
@@ -739,6 +1096,70 @@ impl OmapMaker {
         });
     }
 
+    fn render_parameter_file_buttons(&mut self, ui: &mut egui::Ui) {
+        ui.add_enabled_ui(!self.gui_variables.preview.generating_map_tile, |ui| {
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Import parameters")
+                    .on_hover_text(
+                        "Load all map parameters from a parameter file and regenerate the map.",
+                    )
+                    .clicked()
+                    && let Some(path) = rfd::FileDialog::new()
+                        .add_filter("OmapMaker parameters (*.toml)", &["toml"])
+                        .pick_file()
+                {
+                    match MapParameters::from_file(&path) {
+                        Ok(mut params) => {
+                            // the output CRS is decided by the lidar files, not the parameter file
+                            params.output = self.gui_variables.generation.params.output.clone();
+                            self.gui_variables.generation.params = params;
+                            self.on_frontend_task(FrontendTask::DelegateTask(Task::RegenerateMap));
+                        }
+                        Err(e) => {
+                            self.on_frontend_task(FrontendTask::Error(format!("{e:#}"), false))
+                        }
+                    }
+                }
+
+                if ui
+                    .button("Export parameters")
+                    .on_hover_text(
+                        "Save all map parameters to a file. \
+                        It can be imported for later maps or used for headless map generation.",
+                    )
+                    .clicked()
+                    && let Some(mut path) = rfd::FileDialog::new()
+                        .add_filter("OmapMaker parameters (*.toml)", &["toml"])
+                        .save_file()
+                {
+                    path.set_extension("toml");
+                    if let Err(e) = self.gui_variables.generation.params.to_file(&path) {
+                        self.on_frontend_task(FrontendTask::Error(format!("{e:#}"), false));
+                    }
+                }
+
+                self.render_save_project_button(ui);
+            });
+        });
+    }
+
+    fn render_save_project_button(&mut self, ui: &mut egui::Ui) {
+        if ui
+            .button("Save project")
+            .on_hover_text(
+                "Save the lidar files, map area, test area and parameters to continue later.",
+            )
+            .clicked()
+            && let Some(mut path) = rfd::FileDialog::new()
+                .add_filter("OmapMaker project (*.omproj)", &["omproj"])
+                .save_file()
+        {
+            path.set_extension("omproj");
+            self.save_project(&path);
+        }
+    }
+
     fn render_contour_adjustments(&mut self, ui: &mut egui::Ui) {
         ui.label(egui::RichText::new("Map Scale").strong());
         ui.horizontal(|ui| {
@@ -761,6 +1182,48 @@ impl OmapMaker {
             {
                 self.gui_variables.generation.params.scale = Scale::S10_000;
             };
+            ui.separator();
+            if ui
+                .selectable_label(
+                    self.gui_variables.generation.params.scale == Scale::S5_000,
+                    "1:5 000",
+                )
+                .on_hover_text("Sprint map with ISSprOM 2019 symbols")
+                .clicked()
+            {
+                self.gui_variables.generation.params.scale = Scale::S5_000;
+            };
+            ui.separator();
+            if ui
+                .selectable_label(
+                    self.gui_variables.generation.params.scale == Scale::S4_000,
+                    "1:4 000",
+                )
+                .on_hover_text("Sprint map with ISSprOM 2019 symbols")
+                .clicked()
+            {
+                self.gui_variables.generation.params.scale = Scale::S4_000;
+            };
+            ui.separator();
+            let scale = &mut self.gui_variables.generation.params.scale;
+            if ui
+                .selectable_label(matches!(scale, Scale::Custom(_)), "Custom")
+                .on_hover_text(
+                    "Minimum sizes are scaled from 1:15 000, or from 1:4 000 up to 1:5 000",
+                )
+                .clicked()
+                && !matches!(scale, Scale::Custom(_))
+            {
+                *scale = Scale::Custom(7_500);
+            };
+            if let Scale::Custom(denominator) = scale {
+                ui.add(
+                    egui::widgets::DragValue::new(denominator)
+                        .range(Scale::CUSTOM_DENOMINATORS)
+                        .speed(50)
+                        .prefix("1:"),
+                );
+            }
         });
         ui.add_space(20.);
 
@@ -774,6 +1237,46 @@ impl OmapMaker {
                 .range(1.0..=20.),
             );
         });
+        let interval = self.gui_variables.generation.params.contour.interval;
+        ui.horizontal(|ui| {
+            ui.label("Contour base level: ");
+            ui.add(
+                egui::widgets::DragValue::new(
+                    &mut self.gui_variables.generation.params.contour.base_level,
+                )
+                .fixed_decimals(1)
+                .speed(0.1)
+                .range(0.0..=interval),
+            )
+            .on_hover_text("The contour levels are counted from this elevation.");
+        });
+
+        ui.horizontal(|ui| {
+            let suggesting = self.gui_variables.preview.suggesting_contours;
+            if ui
+                .add_enabled(!suggesting, egui::Button::new("Suggest from terrain"))
+                .on_hover_text(
+                    "Analyses the test area for an interval of 2.5 or 5 m and a base level that \
+                    keeps contours off flat ground and best describes the terrain.",
+                )
+                .clicked()
+            {
+                self.on_frontend_task(FrontendTask::DelegateTask(Task::SuggestContourInterval));
+            }
+            if suggesting {
+                ui.spinner();
+            }
+        });
+        if let Some(suggestion) = self.gui_variables.preview.contour_suggestion {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(format!("Suggested: {suggestion}"));
+                if ui.button("Apply").clicked() {
+                    let contour = &mut self.gui_variables.generation.params.contour;
+                    contour.interval = suggestion.interval;
+                    contour.base_level = suggestion.base_level;
+                }
+            });
+        }
 
         ui.add_space(10.);
 
@@ -801,10 +1304,29 @@ impl OmapMaker {
                         ContourAlgo::Raw,
                         "Raw contours (fastest)",
                     );
+                    ui.selectable_value(
+                        &mut self.gui_variables.generation.params.contour.algorithm,
+                        ContourAlgo::Generalisation,
+                        "Cartographic generalisation of the raw contours",
+                    );
                 });
         });
 
-        if self.gui_variables.generation.params.contour.algorithm != ContourAlgo::Raw {
+        if self.gui_variables.generation.params.contour.algorithm == ContourAlgo::Generalisation {
+            ui.label("Number of generalisation iterations (usual range 5-15)");
+            ui.add(
+                egui::Slider::new(
+                    &mut self
+                        .gui_variables
+                        .generation
+                        .params
+                        .contour
+                        .generalisation_steps,
+                    1..=20,
+                )
+                .show_value(true),
+            );
+        } else if self.gui_variables.generation.params.contour.algorithm != ContourAlgo::Raw {
             if self.gui_variables.generation.params.contour.algorithm
                 == ContourAlgo::NormalFieldSmoothing
             {
@@ -1005,6 +1527,49 @@ impl OmapMaker {
                 .range(0.0..=225.0),
             );
         });
+
+        ui.add_space(10.);
+        ui.checkbox(
+            &mut self.gui_variables.generation.params.contour.slope_lines,
+            "Add slope lines to depressions and contour ends at the map edge.",
+        );
+        ui.checkbox(
+            &mut self
+                .gui_variables
+                .generation
+                .params
+                .contour
+                .index_contour_labels,
+            "Label index contours with their elevation.",
+        )
+        .on_hover_text(
+            "Contour values are placed along the index contours, readable uphill, where there is room between the contours. They are left out of OCAD files.",
+        );
+
+        ui.add_space(10.);
+        ui.label(egui::RichText::new("Contour topology").strong());
+        ui.checkbox(
+            &mut self.gui_variables.generation.params.contour.repair_topology,
+            "Move crossing contours apart and cut small self-crossing loops.",
+        );
+        let conflicts = &self.gui_variables.preview.contour_conflicts;
+        if conflicts.is_empty() {
+            ui.label("No crossing contours in the preview.");
+        } else {
+            egui::CollapsingHeader::new(format!(
+                "{} contour conflicts, circled in red",
+                conflicts.len()
+            ))
+            .show(ui, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(150.)
+                    .show(ui, |ui| {
+                        for conflict in conflicts {
+                            ui.label(conflict.to_string());
+                        }
+                    });
+            });
+        }
     }
 
     fn render_vegetation_adjustments(&mut self, ui: &mut egui::Ui) {
@@ -1121,6 +1686,107 @@ impl OmapMaker {
         });
     }
 
+    fn render_ground_parameters(ui: &mut egui::Ui, ground: &mut GroundParameters) {
+        ui.label(egui::RichText::new("Ground classification").strong());
+        ui.horizontal(|ui| {
+            ui.label("Classify ground:");
+            egui::ComboBox::from_id_salt("Ground classification")
+                .selected_text(ground.classification.to_string())
+                .show_ui(ui, |ui| {
+                    for classification in [
+                        GroundClassification::Lidar,
+                        GroundClassification::Missing,
+                        GroundClassification::Always,
+                    ] {
+                        ui.selectable_value(
+                            &mut ground.classification,
+                            classification,
+                            classification.to_string(),
+                        );
+                    }
+                });
+        })
+        .response
+        .on_hover_text(
+            "Drone and older lidar files often lack ground points. \
+            These are classified with a progressive morphological filter when the tiles are read.",
+        );
+
+        ui.add_enabled_ui(ground.classification != GroundClassification::Lidar, |ui| {
+            ui.add(
+                egui::Slider::new(&mut ground.cell_size, 0.5..=3.0)
+                    .text("Cell size (m)")
+                    .show_value(true),
+            );
+            ui.add(
+                egui::Slider::new(&mut ground.max_window, 5.0..=60.0)
+                    .text("Maximum window (m)")
+                    .show_value(true),
+            )
+            .on_hover_text("Should be larger than the largest building.");
+            ui.add(
+                egui::Slider::new(&mut ground.slope, 0.05..=2.0)
+                    .text("Terrain slope")
+                    .show_value(true),
+            )
+            .on_hover_text("The steepest terrain slope in meters per meter.");
+            ui.add(
+                egui::Slider::new(&mut ground.initial_threshold, 0.1..=1.0)
+                    .text("Initial height threshold (m)")
+                    .show_value(true),
+            );
+            ui.add(
+                egui::Slider::new(&mut ground.max_threshold, 0.5..=10.0)
+                    .text("Maximum height threshold (m)")
+                    .show_value(true),
+            );
+        });
+    }
+
+    fn render_noise_parameters(ui: &mut egui::Ui, noise: &mut NoiseParameters) {
+        ui.label(egui::RichText::new("Noise filter").strong());
+        ui.checkbox(
+            &mut noise.remove_noise_classes,
+            "Remove points classified as noise (class 7 and 18)",
+        );
+
+        ui.checkbox(
+            &mut noise.remove_isolated,
+            "Remove points far above or below the local ground",
+        );
+        ui.add_enabled_ui(noise.remove_isolated, |ui| {
+            ui.add(
+                egui::Slider::new(&mut noise.max_below_ground, 0.5..=10.0)
+                    .text("Max below ground (m)")
+                    .show_value(true),
+            );
+            ui.add(
+                egui::Slider::new(&mut noise.max_above_ground, 20.0..=150.0)
+                    .text("Max above ground (m)")
+                    .show_value(true),
+            )
+            .on_hover_text("Should be above the tallest trees and buildings.");
+        });
+
+        ui.checkbox(&mut noise.remove_outliers, "Remove statistical outliers");
+        ui.add_enabled_ui(noise.remove_outliers, |ui| {
+            ui.add(
+                egui::Slider::new(&mut noise.outlier_neighbors, 4..=32)
+                    .text("Neighbours")
+                    .show_value(true),
+            );
+            ui.add(
+                egui::Slider::new(&mut noise.outlier_std_ratio, 1.0..=6.0)
+                    .text("Standard deviations")
+                    .show_value(true),
+            )
+            .on_hover_text(
+                "Points further from their neighbours than the mean distance \
+                plus this many standard deviations are removed.",
+            );
+        });
+    }
+
     fn render_bezier_parameters(ui: &mut egui::Ui, bezier: &mut BezierParameters) {
         ui.checkbox(&mut bezier.enabled, "Output this process in Bezier curves.");
         ui.add_enabled_ui(bezier.enabled, |ui| {
@@ -1172,6 +1838,16 @@ impl OmapMaker {
         ui.heading("Generating the rest of the map.");
         ui.add_space(20.);
         ui.label("This might take some time.");
+
+        ui.add_space(20.);
+        if ui
+            .button("Cancel")
+            .on_hover_text("Stop the map generation and go back to adjusting the parameters. \
+                Finished tiles are kept and reused if the map is generated again with the same parameters.")
+            .clicked()
+        {
+            self.on_frontend_task(FrontendTask::DelegateTask(Task::Cancel));
+        }
     }
 
     pub fn render_done_panel(&mut self, ui: &mut egui::Ui) {
@@ -1181,7 +1857,15 @@ impl OmapMaker {
             "{}.",
             self.gui_variables.project.save_location.display()
         ));
-        ui.label("The map can be opened in OpenOrienteering Mapper for editing.");
+        match self.gui_variables.project.output_format {
+            OutputFormat::Omap => {
+                ui.label("The map can be opened in OpenOrienteering Mapper for editing.")
+            }
+            OutputFormat::Ocd => ui.label(
+                "The map can be opened in OCAD for editing. \
+                Load the ISOM symbol set to get the full symbol definitions.",
+            ),
+        };
 
         ui.add_space(20.);
         ui.label("If you like this application. Please star the project on Github:)");
@@ -1189,8 +1873,11 @@ impl OmapMaker {
             .on_hover_text("https://github.com/yvind/");
 
         ui.add_space(20.);
-        if ui.button("Start a new map").clicked() {
-            self.on_frontend_task(FrontendTask::DelegateTask(Task::Reset));
-        }
+        ui.horizontal(|ui| {
+            if ui.button("Start a new map").clicked() {
+                self.on_frontend_task(FrontendTask::DelegateTask(Task::Reset));
+            }
+            self.render_save_project_button(ui);
+        });
     }
 }
@@ -0,0 +1,379 @@
+use std::{ffi::OsString, path::PathBuf, str::FromStr};
+
+use anyhow::{Context, anyhow, bail};
+use eframe::egui;
+use proj_core::CrsDef;
+
+use crate::{
+    backend::Backend,
+    comms::{OmapComms, messages::*},
+    gui::modals::OmapModal,
+    parameters::{FileParameters, MapParameters, OutputFormat},
+    statistics::LidarStats,
+};
+
+const USAGE: &str = "\
+Usage: OmapMaker make-map [OPTIONS] --output <FILE> <LIDAR>...
+
+Generates an omap or OCAD file from the given lidar files without opening the GUI.
+
+Options:
+    --params <FILE>         Map parameter file (.toml) [default: the default parameters]
+    --output <FILE>         Where to save the resulting map, an .ocd extension writes an OCAD file
+    --template <FILE>       An .omap/.xmap whose symbols are used instead of the default symbol set
+    --crs <EPSG>            CRS for lidar files without one, also used as the output CRS
+    --threads <N>           Number of backend worker threads
+    --memory-budget <GB>    Max memory used when converting lidar files to COPC [default: 8]
+    --no-copc               Read .las/.laz files directly instead of converting them to COPC
+    --geopackage            Also save the map objects as a GeoPackage next to the map
+    --geojson               Also save the map objects as GeoJSON files next to the map
+    -h, --help              Print this help";
+
+const PROGRESS_BAR_WIDTH: u32 = 40;
+// print the progress bar every 10%
+const PROGRESS_PRINT_STEP: f32 = 0.1;
+
+pub struct HeadlessArgs {
+    pub paths: Vec<PathBuf>,
+    pub save_location: PathBuf,
+    pub output_format: OutputFormat,
+    pub symbol_template: Option<PathBuf>,
+    pub params: Option<PathBuf>,
+    pub crs: Option<CrsDef>,
+    pub worker_threads: Option<usize>,
+    pub memory_budget_gb: u8,
+    pub convert_to_copc: bool,
+    pub save_geopackage: bool,
+    pub save_geojson: bool,
+}
+
+impl HeadlessArgs {
+    /// Returns `None` if the program is not started with the `make-map` subcommand
+    pub fn from_env() -> crate::Result<Option<HeadlessArgs>> {
+        let mut args = std::env::args_os().skip(1);
+
+        match args.next() {
+            Some(command) if command == "make-map" => Self::parse(args).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn parse(mut args: impl Iterator<Item = OsString>) -> crate::Result<HeadlessArgs> {
+        let mut paths = vec![];
+        let mut save_location = None;
+        let mut symbol_template = None;
+        let mut params = None;
+        let mut crs = None;
+        let mut worker_threads = None;
+        let mut memory_budget_gb = 8;
+        let mut convert_to_copc = true;
+        let mut save_geopackage = false;
+        let mut save_geojson = false;
+
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("-h" | "--help") => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                Some("--params") => {
+                    params = Some(PathBuf::from(next_value(&mut args, "--params")?))
+                }
+                Some("--output") => {
+                    save_location = Some(PathBuf::from(next_value(&mut args, "--output")?))
+                }
+                Some("--template") => {
+                    symbol_template = Some(PathBuf::from(next_value(&mut args, "--template")?))
+                }
+                Some("--crs") => {
+                    let code = next_value(&mut args, "--crs")?;
+                    crs = Some(
+                        proj_wkt::parse_crs(&code.to_string_lossy())
+                            .ok()
+                            .with_context(|| format!("Could not create a CRS from {code:?}"))?,
+                    );
+                }
+                Some("--threads") => worker_threads = Some(parse_value(&mut args, "--threads")?),
+                Some("--memory-budget") => {
+                    memory_budget_gb = parse_value(&mut args, "--memory-budget")?
+                }
+                Some("--no-copc") => convert_to_copc = false,
+                Some("--geopackage") => save_geopackage = true,
+                Some("--geojson") => save_geojson = true,
+                Some(flag) if flag.starts_with("--") => bail!("Unknown option {flag}\n\n{USAGE}"),
+                _ => paths.push(PathBuf::from(arg)),
+            }
+        }
+
+        if paths.is_empty() {
+            bail!("No lidar files given\n\n{USAGE}");
+        }
+        let mut save_location =
+            save_location.with_context(|| format!("Missing --output\n\n{USAGE}"))?;
+        let output_format = OutputFormat::from_path(&save_location);
+        save_location.set_extension(output_format.extension());
+
+        Ok(HeadlessArgs {
+            paths,
+            save_location,
+            output_format,
+            symbol_template,
+            params,
+            crs,
+            worker_threads,
+            memory_budget_gb,
+            convert_to_copc,
+            save_geopackage,
+            save_geojson,
+        })
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = OsString>, flag: &str) -> crate::Result<OsString> {
+    args.next()
+        .with_context(|| format!("Missing value for {flag}\n\n{USAGE}"))
+}
+
+fn parse_value<T: FromStr>(
+    args: &mut impl Iterator<Item = OsString>,
+    flag: &str,
+) -> crate::Result<T> {
+    let value = next_value(args, flag)?;
+    value
+        .to_str()
+        .and_then(|v| v.parse().ok())
+        .with_context(|| format!("Invalid value {value:?} for {flag}"))
+}
+
+/// Runs the same backend tasks as the GUI does, in order:
+/// CRS detection -> COPC conversion -> map generation.
+/// Returns an error as soon as the backend reports one.
+pub fn run(args: HeadlessArgs) -> crate::Result<()> {
+    let map_params = match &args.params {
+        Some(path) => MapParameters::from_file(path)?,
+        None => MapParameters::default(),
+    };
+
+    // the context is only used for requesting repaints, which does nothing without a window
+    let ctx = egui::Context::default();
+    let (comms, backend_comms) = OmapComms::new(&ctx);
+    Backend::boot(backend_comms)?;
+
+    let mut runner = HeadlessRunner {
+        comms,
+        paths: args.paths.clone(),
+        crs_epsg: vec![],
+        stats: None,
+        map_params,
+        progress: None,
+        args,
+    };
+    runner.start()?;
+
+    loop {
+        let task = runner
+            .comms
+            .recv()
+            .map_err(|_| anyhow!("The backend panicked"))?;
+
+        if runner.on_frontend_task(task)? {
+            return Ok(());
+        }
+    }
+}
+
+struct HeadlessRunner {
+    comms: OmapComms<BackendTask, FrontendTask>,
+    args: HeadlessArgs,
+    paths: Vec<PathBuf>,
+    crs_epsg: Vec<Option<CrsDef>>,
+    stats: Option<LidarStats>,
+    map_params: MapParameters,
+    progress: Option<StdoutProgress>,
+}
+
+impl HeadlessRunner {
+    fn start(&self) -> crate::Result<()> {
+        if let Some(worker_threads) = self.args.worker_threads {
+            self.send(BackendTask::SetWorkerThreads(worker_threads))?;
+        }
+        self.send(BackendTask::ParseCrs(self.paths.clone()))
+    }
+
+    /// Returns true when the map is written
+    fn on_frontend_task(&mut self, task: FrontendTask) -> crate::Result<bool> {
+        match task {
+            FrontendTask::Log(s) => println!("{s}"),
+            FrontendTask::ProgressBar(p) => match p {
+                ProgressBar::Start => self.progress = Some(StdoutProgress::start()),
+                ProgressBar::Inc(delta) => {
+                    if let Some(progress) = &mut self.progress {
+                        progress.inc(delta);
+                    }
+                }
+                ProgressBar::Finish => {
+                    if let Some(progress) = self.progress.take() {
+                        progress.finish();
+                    }
+                }
+            },
+            FrontendTask::UpdateVariable(variable) => match variable {
+                Variable::Paths(paths) => self.paths = paths,
+                Variable::CrsDefs(crs_epsg) => self.crs_epsg = crs_epsg,
+                Variable::Stats(stats) => self.stats = Some(*stats),
+                _ => (),
+            },
+            // files without a CRS are either given the CRS from the command line or rejected
+            FrontendTask::OpenModal(OmapModal::ManualSetCRS)
+            | FrontendTask::TaskComplete(TaskDone::ParseCrs(_)) => self.convert_copc()?,
+            FrontendTask::TaskComplete(TaskDone::ConvertCopc) => self.make_map()?,
+            FrontendTask::TaskComplete(TaskDone::MakeMap) => {
+                println!("The map is saved at: {}", self.args.save_location.display());
+                return Ok(true);
+            }
+            FrontendTask::Error(s, _) => {
+                if let Some(progress) = self.progress.take() {
+                    progress.finish();
+                }
+                bail!(s);
+            }
+            FrontendTask::TaskComplete(_)
+            | FrontendTask::OpenModal(_)
+            | FrontendTask::DelegateTask(_)
+            | FrontendTask::NextState
+            | FrontendTask::PrevState => (),
+        }
+        Ok(false)
+    }
+
+    fn convert_copc(&mut self) -> crate::Result<()> {
+        if let Some(crs) = &self.args.crs {
+            for file_crs in self.crs_epsg.iter_mut().filter(|c| c.is_none()) {
+                *file_crs = Some(crs.clone());
+            }
+        }
+
+        let output_crs = self
+            .args
+            .crs
+            .clone()
+            .or_else(|| crate::parse_crs::most_popular_crs(&self.crs_epsg));
+
+        match &output_crs {
+            Some(crs) => {
+                if self.crs_epsg.iter().any(Option::is_none) {
+                    bail!("Some lidar files have no CRS, assign one with --crs <EPSG>");
+                }
+                println!("Output CRS: EPSG:{}", crs.epsg());
+            }
+            None => println!("No CRS detected in the lidar files, using local coordinates"),
+        }
+        self.map_params.output.crs = output_crs.clone();
+
+        self.send(BackendTask::ConvertCopc(Box::new(ConvertCopcTask {
+            paths: self.paths.clone(),
+            in_epsg: self.crs_epsg.clone(),
+            out_epsg: output_crs,
+            save_location: self.args.save_location.clone(),
+            bounds: vec![],
+            polygon: geo::LineString::new(vec![]),
+            write_single_copc: false,
+            convert_to_copc: self.args.convert_to_copc,
+            budget_gb: self.args.memory_budget_gb,
+        })))
+    }
+
+    fn make_map(&mut self) -> crate::Result<()> {
+        let stats = self
+            .stats
+            .take()
+            .context("No lidar statistics were produced")?;
+
+        self.send(BackendTask::MakeMap(Box::new(MakeMapTask {
+            map_params: self.map_params.clone(),
+            file_params: FileParameters {
+                paths: self.paths.clone(),
+                save_location: self.args.save_location.clone(),
+                output_format: self.args.output_format,
+                symbol_template: self.args.symbol_template.clone(),
+                save_geopackage: self.args.save_geopackage,
+                save_geojson: self.args.save_geojson,
+                crs_epsg: self.crs_epsg.clone(),
+                ..Default::default()
+            },
+            polygon_filter: geo::LineString::new(vec![]),
+            stats,
+        })))
+    }
+
+    fn send(&self, task: BackendTask) -> crate::Result<()> {
+        self.comms
+            .send(task)
+            .map_err(|_| anyhow!("The backend panicked"))
+    }
+}
+
+// a line based progress bar, as carriage returns make a mess of log files
+struct StdoutProgress {
+    progress: f32,
+    last_printed: f32,
+}
+
+impl StdoutProgress {
+    fn start() -> StdoutProgress {
+        println!();
+        print_progress_bar(0.);
+        StdoutProgress {
+            progress: 0.,
+            last_printed: 0.,
+        }
+    }
+
+    fn inc(&mut self, delta: f32) {
+        self.progress = (self.progress + delta).clamp(0., 1.);
+        if self.progress - self.last_printed >= PROGRESS_PRINT_STEP {
+            print_progress_bar(self.progress);
+            self.last_printed = self.progress;
+        }
+    }
+
+    fn finish(self) {
+        if self.last_printed < 1. {
+            print_progress_bar(1.);
+        }
+        println!();
+    }
+}
+
+fn print_progress_bar(progress: f32) {
+    let num_done = (PROGRESS_BAR_WIDTH as f32 * progress) as u32;
+    let mut bar = String::with_capacity(PROGRESS_BAR_WIDTH as usize);
+
+    for _ in 0..num_done {
+        bar.push('#');
+    }
+    if num_done < PROGRESS_BAR_WIDTH {
+        bar.push('>');
+    }
+    while (bar.len() as u32) < PROGRESS_BAR_WIDTH {
+        bar.push(' ');
+    }
+
+    println!("\t[{bar}] {:>3}%", (progress * 100.).round() as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf};
+
+    use super::HeadlessArgs;
+
+    #[test]
+    fn params_are_optional() {
+        let args = ["--output", "map.omap", "lidar.laz"].map(OsString::from);
+        let args = HeadlessArgs::parse(args.into_iter()).unwrap();
+
+        assert!(args.params.is_none());
+        assert_eq!(args.paths, [PathBuf::from("lidar.laz")]);
+    }
+}
@@ -0,0 +1,258 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use geo::BooleanOps;
+use rstar::{
+    AABB, RTree,
+    primitives::{GeomWithData, Rectangle},
+};
+
+use crate::geometry::MapRect;
+
+// Overlapping files are resolved per cell of this size. Large enough to
+// get a meaningful point count from every file, small enough to follow
+// the edges of the flight strips
+const OVERLAP_CELL_METERS: f64 = 5.;
+
+type FileBounds = GeomWithData<Rectangle<[f64; 2]>, usize>;
+
+/// Spatial index over the bounds of all lidar files,
+/// the files may be laid out in any grid and may overlap
+#[derive(Debug, Clone)]
+pub struct LidarIndex {
+    bounds: Vec<geo::Rect>,
+    tree: RTree<FileBounds>,
+    // files separated by a gap smaller than this are still neighbours
+    margin: f64,
+}
+
+/// The sides of a file where the lidar coverage continues in other files
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CoveredSides {
+    pub top: bool,
+    pub bottom: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl LidarIndex {
+    pub fn new(bounds: Vec<geo::Rect>) -> Self {
+        let tree = RTree::bulk_load(
+            bounds
+                .iter()
+                .enumerate()
+                .map(|(index, b)| {
+                    GeomWithData::new(
+                        Rectangle::from_corners([b.min().x, b.min().y], [b.max().x, b.max().y]),
+                        index,
+                    )
+                })
+                .collect(),
+        );
+
+        let avg_file_size = bounds.iter().map(|r| r.width() + r.height()).sum::<f64>()
+            / (2 * bounds.len().max(1)) as f64;
+
+        Self {
+            bounds,
+            tree,
+            margin: 0.1 * avg_file_size,
+        }
+    }
+
+    pub fn bounds(&self) -> &[geo::Rect] {
+        &self.bounds
+    }
+
+    /// Every file whose bounds intersect the rectangle, in file order
+    pub fn intersecting(&self, rect: geo::Rect) -> Vec<usize> {
+        let mut files = self
+            .tree
+            .locate_in_envelope_intersecting(&envelope(rect))
+            .map(|f| f.data)
+            .collect::<Vec<_>>();
+        files.sort_unstable();
+        files
+    }
+
+    /// The other files touching or overlapping the file
+    pub fn neighbors(&self, index: usize) -> Vec<usize> {
+        let bounds = self.bounds[index];
+        let search = geo::Rect::new(
+            bounds.min() - geo::Coord::from((self.margin, self.margin)),
+            bounds.max() + geo::Coord::from((self.margin, self.margin)),
+        );
+        self.intersecting(search)
+            .into_iter()
+            .filter(|&other| {
+                other != index && bounds.touch_margin(&self.bounds[other], self.margin)
+            })
+            .collect()
+    }
+
+    pub fn covered_sides(&self, index: usize) -> CoveredSides {
+        let b = self.bounds[index];
+        let m = self.margin;
+
+        // a strip just outside each side, reaching around the corners
+        // so diagonal neighbours count for both of their sides
+        let covered = |min: (f64, f64), max: (f64, f64)| {
+            let strip = geo::Rect::new(min, max);
+            self.intersecting(strip)
+                .into_iter()
+                .any(|other| other != index && overlaps(&strip, &self.bounds[other]))
+        };
+
+        let (min, max) = (b.min(), b.max());
+        CoveredSides {
+            top: covered((min.x - m, max.y), (max.x + m, max.y + m)),
+            bottom: covered((min.x - m, min.y - m), (max.x + m, min.y)),
+            left: covered((min.x - m, min.y - m), (min.x, max.y + m)),
+            right: covered((max.x, min.y - m), (max.x + m, max.y + m)),
+        }
+    }
+
+    /// The area of the file already covered by the files before it.
+    ///
+    /// The map is generated file by file, so this part of the file has been
+    /// mapped already and must not be mapped again
+    pub fn claimed_before(&self, index: usize) -> geo::MultiPolygon {
+        let bounds = self.bounds[index];
+        self.intersecting(bounds)
+            .into_iter()
+            .filter(|&other| other < index && overlaps(&bounds, &self.bounds[other]))
+            .fold(geo::MultiPolygon::new(vec![]), |claimed, other| {
+                claimed.union(&self.bounds[other].to_polygon())
+            })
+            .intersection(&bounds.to_polygon())
+    }
+}
+
+/// Merges the points read from several files.
+///
+/// Where the bounds of two or more files cover a whole cell, only the points
+/// of the file with the most points in that cell are kept, so overlapping
+/// flight strips and duplicated buffer areas are not counted twice.
+/// Cells on the seam between adjacent files keep the points of all files
+pub fn merge_overlapping_points(files: Vec<(geo::Rect, Vec<las::Point>)>) -> Vec<las::Point> {
+    if files.len() < 2 {
+        return files.into_iter().flat_map(|(_, points)| points).collect();
+    }
+
+    let cell = |p: &las::Point| {
+        (
+            (p.x / OVERLAP_CELL_METERS).floor() as i64,
+            (p.y / OVERLAP_CELL_METERS).floor() as i64,
+        )
+    };
+    let covers = |bounds: &geo::Rect, (cx, cy): (i64, i64)| {
+        let (x, y) = (
+            cx as f64 * OVERLAP_CELL_METERS,
+            cy as f64 * OVERLAP_CELL_METERS,
+        );
+        bounds.min().x <= x
+            && bounds.min().y <= y
+            && bounds.max().x >= x + OVERLAP_CELL_METERS
+            && bounds.max().y >= y + OVERLAP_CELL_METERS
+    };
+
+    let mut counts: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (fi, (_, points)) in files.iter().enumerate() {
+        for point in points {
+            counts
+                .entry(cell(point))
+                .or_insert_with(|| vec![0; files.len()])[fi] += 1;
+        }
+    }
+
+    // the densest covering file wins, the first file on a tie
+    let winners = counts
+        .into_iter()
+        .filter_map(|(c, count)| {
+            let candidates = (0..files.len())
+                .filter(|&fi| covers(&files[fi].0, c))
+                .collect::<Vec<_>>();
+            if candidates.len() < 2 {
+                return None;
+            }
+            candidates
+                .into_iter()
+                .max_by_key(|&fi| (count[fi], Reverse(fi)))
+                .map(|winner| (c, winner))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut merged = Vec::new();
+    for (fi, (bounds, points)) in files.into_iter().enumerate() {
+        merged.extend(points.into_iter().filter(|p| {
+            let c = cell(p);
+            winners
+                .get(&c)
+                .is_none_or(|&winner| winner == fi || !covers(&bounds, c))
+        }));
+    }
+    merged
+}
+
+fn envelope(rect: geo::Rect) -> AABB<[f64; 2]> {
+    AABB::from_corners([rect.min().x, rect.min().y], [rect.max().x, rect.max().y])
+}
+
+// true if the rectangles share an area, not only an edge
+fn overlaps(a: &geo::Rect, b: &geo::Rect) -> bool {
+    a.min().x < b.max().x && b.min().x < a.max().x && a.min().y < b.max().y && b.min().y < a.max().y
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lidar_index::{LidarIndex, merge_overlapping_points};
+    use geo::Area;
+
+    fn rect(min: (f64, f64), max: (f64, f64)) -> geo::Rect {
+        geo::Rect::new(min, max)
+    }
+
+    fn point(x: f64, y: f64) -> las::Point {
+        las::Point {
+            x,
+            y,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn overlapping_files_are_found_outside_a_grid() {
+        let index = LidarIndex::new(vec![
+            rect((0., 0.), (100., 100.)),
+            rect((100., 0.), (200., 100.)),
+            // a flight strip across both files
+            rect((50., 40.), (150., 60.)),
+            rect((500., 500.), (600., 600.)),
+        ]);
+
+        assert_eq!(
+            index.intersecting(rect((90., 45.), (110., 55.))),
+            vec![0, 1, 2]
+        );
+        assert_eq!(index.neighbors(3), Vec::<usize>::new());
+
+        let sides = index.covered_sides(0);
+        assert!(sides.right && !sides.left && !sides.top && !sides.bottom);
+
+        let claimed = index.claimed_before(2);
+        assert!((claimed.unsigned_area() - 2000.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn the_densest_file_wins_the_overlap() {
+        let a = rect((0., 0.), (20., 20.));
+        let b = rect((0., 0.), (40., 20.));
+        let sparse = vec![point(1., 1.), point(12., 12.)];
+        let dense = vec![point(2., 2.), point(3., 3.), point(26., 1.)];
+
+        let merged = merge_overlapping_points(vec![(a, sparse), (b, dense)]);
+
+        // the first cell goes to the denser file, the file with points wins the cell at (12, 12)
+        assert_eq!(merged.len(), 4);
+        assert!(!merged.iter().any(|p| p.x == 1.));
+    }
+}
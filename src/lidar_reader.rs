@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    time::SystemTime,
+};
+
+use copc_rs::{BoundsSelection, CopcReader, LodSelection};
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, geometry::MapRect};
+
+// The bounds of every run of this many consecutive points are indexed,
+// the default chunk size of LAZ files so every block is one seekable chunk
+const INDEX_BLOCK_POINTS: u64 = 50_000;
+const INDEX_EXTENSION: &str = "omidx";
+
+type IndexSlot = Arc<Mutex<Option<Arc<BlockIndex>>>>;
+
+// block indices of the plain files read by this process, built once per file
+static BLOCK_INDICES: LazyLock<Mutex<HashMap<PathBuf, IndexSlot>>> =
+    LazyLock::new(Default::default);
+
+/// Reads the points inside a rectangle from a COPC file through its octree,
+/// or from a plain LAS/LAZ file through an index of the bounds of its point blocks.
+///
+/// The block index is built by the first query reading the whole file once,
+/// and stored next to it as a sidecar file. Files with spatially ordered points, as most flight lines
+/// and tiled deliveries are, only read the blocks near the rectangle
+pub enum LidarReader {
+    Copc(Box<CopcReader<BufReader<File>>>),
+    Plain {
+        reader: las::Reader,
+        path: PathBuf,
+        index: Option<Arc<BlockIndex>>,
+    },
+}
+
+impl LidarReader {
+    pub fn from_path(path: &Path) -> Result<LidarReader> {
+        if let Ok(reader) = CopcReader::from_path(path) {
+            return Ok(LidarReader::Copc(Box::new(reader)));
+        }
+
+        Ok(LidarReader::Plain {
+            reader: las::Reader::from_path(path)?,
+            path: path.to_path_buf(),
+            index: None,
+        })
+    }
+
+    pub fn header(&self) -> &las::Header {
+        match self {
+            LidarReader::Copc(reader) => reader.header(),
+            LidarReader::Plain { reader, .. } => reader.header(),
+        }
+    }
+
+    /// Every point with x and y inside the rectangle, withheld points included
+    pub fn points_within(&mut self, rect: geo::Rect) -> Result<Vec<las::Point>> {
+        match self {
+            LidarReader::Copc(reader) => {
+                let header_bounds = reader.header().bounds();
+                let bounds = rect.into_bounds(header_bounds.min.z, header_bounds.max.z);
+                Ok(reader
+                    .points(LodSelection::All, BoundsSelection::Within(bounds))?
+                    .collect())
+            }
+            LidarReader::Plain {
+                reader,
+                path,
+                index,
+            } => {
+                let index = match index {
+                    Some(index) => index.clone(),
+                    None => index.insert(block_index(path, reader)?).clone(),
+                };
+
+                let mut points = Vec::new();
+                for block in index.intersecting(rect) {
+                    reader.seek(block as u64 * INDEX_BLOCK_POINTS)?;
+                    for point in reader.points().take(INDEX_BLOCK_POINTS as usize) {
+                        let point = point?;
+                        if rect.min().x <= point.x
+                            && point.x <= rect.max().x
+                            && rect.min().y <= point.y
+                            && point.y <= rect.max().y
+                        {
+                            points.push(point);
+                        }
+                    }
+                }
+                Ok(points)
+            }
+        }
+    }
+}
+
+/// The xy bounds of every block of points in a plain lidar file
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockIndex {
+    // the file the index was built from, the index is rebuilt if it changes
+    file_len: u64,
+    modified: Option<SystemTime>,
+    blocks: Vec<[f64; 4]>,
+}
+
+impl BlockIndex {
+    fn build(
+        reader: &mut las::Reader,
+        file_len: u64,
+        modified: Option<SystemTime>,
+    ) -> Result<Self> {
+        let mut blocks = Vec::new();
+        for (i, point) in reader.points().enumerate() {
+            let point = point?;
+            if i as u64 % INDEX_BLOCK_POINTS == 0 {
+                blocks.push([f64::MAX, f64::MAX, f64::MIN, f64::MIN]);
+            }
+            if let Some(block) = blocks.last_mut() {
+                block[0] = block[0].min(point.x);
+                block[1] = block[1].min(point.y);
+                block[2] = block[2].max(point.x);
+                block[3] = block[3].max(point.y);
+            }
+        }
+
+        Ok(Self {
+            file_len,
+            modified,
+            blocks,
+        })
+    }
+
+    fn intersecting(&self, rect: geo::Rect) -> impl Iterator<Item = usize> + '_ {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter(move |(_, b)| {
+                b[0] <= rect.max().x
+                    && rect.min().x <= b[2]
+                    && b[1] <= rect.max().y
+                    && rect.min().y <= b[3]
+            })
+            .map(|(i, _)| i)
+    }
+}
+
+fn block_index(path: &Path, reader: &las::Reader) -> Result<Arc<BlockIndex>> {
+    let slot = BLOCK_INDICES
+        .lock()
+        .map_err(|_| anyhow::anyhow!("The lidar index cache was poisoned"))?
+        .entry(path.to_path_buf())
+        .or_default()
+        .clone();
+
+    // other readers of the same file wait for the index instead of building it again
+    let mut slot = slot
+        .lock()
+        .map_err(|_| anyhow::anyhow!("The lidar index cache was poisoned"))?;
+    if let Some(index) = slot.as_ref() {
+        return Ok(index.clone());
+    }
+
+    let index = Arc::new(load_or_build_index(path, reader)?);
+    *slot = Some(index.clone());
+    Ok(index)
+}
+
+// the sidecar index if it still matches the file, otherwise a new index stored as the sidecar
+fn load_or_build_index(path: &Path, reader: &las::Reader) -> Result<BlockIndex> {
+    let metadata = std::fs::metadata(path)?;
+    let (file_len, modified) = (metadata.len(), metadata.modified().ok());
+    let sidecar = sidecar_path(path);
+
+    let stored = std::fs::read(&sidecar)
+        .ok()
+        .and_then(|bytes| postcard::from_bytes::<BlockIndex>(&bytes).ok())
+        .filter(|index| {
+            index.file_len == file_len
+                && index.modified == modified
+                && index.blocks.len() as u64
+                    == reader
+                        .header()
+                        .number_of_points()
+                        .div_ceil(INDEX_BLOCK_POINTS)
+        });
+
+    let index = match stored {
+        Some(index) => index,
+        None => {
+            // a separate reader leaves the position of the caller's reader alone
+            let mut index_reader = las::Reader::from_path(path)?;
+            let index = BlockIndex::build(&mut index_reader, file_len, modified)?;
+            // the index is only a cache, a read-only directory just means building it again
+            if let Ok(bytes) = postcard::to_stdvec(&index) {
+                let _ = std::fs::write(&sidecar, bytes);
+            }
+            index
+        }
+    };
+
+    Ok(index)
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(INDEX_EXTENSION);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::lidar_reader::{
+        BlockIndex, INDEX_BLOCK_POINTS, LidarReader, load_or_build_index, sidecar_path,
+    };
+
+    // points 1 mm apart along the x-axis, spatially ordered like a flight line
+    fn write_las(path: &Path, num_points: u64) {
+        let mut builder = las::Builder::from((1, 2));
+        builder.transforms.x.scale = 0.001;
+        builder.transforms.y.scale = 0.001;
+        builder.transforms.z.scale = 0.001;
+        let mut writer = las::Writer::from_path(path, builder.into_header().unwrap()).unwrap();
+        for i in 0..num_points {
+            writer
+                .write_point(las::Point {
+                    x: i as f64 * 0.001,
+                    y: 0.,
+                    z: 100.,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    fn test_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("omapmaker_{}_{name}.las", std::process::id()));
+        let _ = std::fs::remove_file(sidecar_path(&path));
+        path
+    }
+
+    fn clean_up(path: &Path) {
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(sidecar_path(path));
+    }
+
+    #[test]
+    fn only_the_blocks_in_the_rectangle_are_read() {
+        let path = test_path("blocks");
+        write_las(&path, 2 * INDEX_BLOCK_POINTS + 20_000);
+
+        let mut reader = LidarReader::from_path(&path).unwrap();
+        let rect = geo::Rect::new((59.9995, -1.), (70.0005, 1.));
+        let points = reader.points_within(rect).unwrap();
+
+        let LidarReader::Plain {
+            index: Some(index), ..
+        } = &reader
+        else {
+            panic!("a plain LAS file should be read through a block index");
+        };
+        assert_eq!(index.blocks.len(), 3);
+        assert_eq!(index.intersecting(rect).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(points.len(), 10_001);
+        assert!(points.iter().all(|p| (59.9995..=70.0005).contains(&p.x)));
+        assert!(sidecar_path(&path).exists());
+
+        clean_up(&path);
+    }
+
+    #[test]
+    fn sidecar_is_reused_until_the_file_changes() {
+        let path = test_path("sidecar");
+        write_las(&path, INDEX_BLOCK_POINTS + 1_000);
+
+        let reader = las::Reader::from_path(&path).unwrap();
+        let built = load_or_build_index(&path, &reader).unwrap();
+        let stored = std::fs::read(sidecar_path(&path)).unwrap();
+        assert_eq!(postcard::from_bytes::<BlockIndex>(&stored).unwrap(), built);
+
+        // a valid sidecar is read instead of building the index again
+        let mut marked = postcard::from_bytes::<BlockIndex>(&stored).unwrap();
+        marked.blocks[0] = [-1., -1., 1., 1.];
+        std::fs::write(sidecar_path(&path), postcard::to_stdvec(&marked).unwrap()).unwrap();
+        assert_eq!(load_or_build_index(&path, &reader).unwrap(), marked);
+
+        // a stale sidecar is replaced by the index of the new file
+        write_las(&path, 3 * INDEX_BLOCK_POINTS);
+        let reader = las::Reader::from_path(&path).unwrap();
+        let rebuilt = load_or_build_index(&path, &reader).unwrap();
+        assert_eq!(rebuilt.blocks.len(), 3);
+        assert_eq!(rebuilt.file_len, std::fs::metadata(&path).unwrap().len());
+        let stored = std::fs::read(sidecar_path(&path)).unwrap();
+        assert_eq!(
+            postcard::from_bytes::<BlockIndex>(&stored).unwrap(),
+            rebuilt
+        );
+
+        clean_up(&path);
+    }
+}
@@ -10,13 +10,16 @@ mod error;
 mod frontend;
 mod geometry;
 mod gui;
+mod headless;
+mod lidar_index;
+mod lidar_reader;
 mod map_gen;
-mod neighbors;
 mod parameters;
 mod parse_crs;
 mod project;
 mod raster;
 mod statistics;
+mod vector;
 
 pub use consts::*;
 pub use error::*;
@@ -26,6 +29,11 @@ use eframe::egui;
 use std::sync::Arc;
 
 fn main() -> anyhow::Result<()> {
+    // `OmapMaker make-map ...` runs the map generation without the GUI
+    if let Some(args) = headless::HeadlessArgs::from_env()? {
+        return headless::run(args);
+    }
+
     let icon = eframe::egui::IconData {
         rgba: include_bytes!("./assets/icon.raw").to_vec(),
         width: 64,
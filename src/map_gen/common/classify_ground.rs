@@ -0,0 +1,219 @@
+use las::point::Classification;
+
+use crate::parameters::{GroundClassification, GroundParameters};
+
+/// Classifies the ground of the points read from one file if the parameters ask for it,
+/// returns true if the points were classified
+pub fn prepare_ground(points: &mut [las::Point], params: &GroundParameters) -> bool {
+    let classify = match params.classification {
+        GroundClassification::Lidar => false,
+        GroundClassification::Missing => !points
+            .iter()
+            .any(|p| p.classification == Classification::Ground),
+        GroundClassification::Always => true,
+    };
+
+    if classify {
+        classify_ground(points, params);
+    }
+    classify
+}
+
+/// Progressive morphological filter (Zhang et al. 2003).
+///
+/// The lowest point in every cell is opened with square windows of growing size.
+/// A cell rising more above the opened surface than the elevation threshold of the
+/// window lies on an object, the threshold grows with the window size and the slope.
+/// The points close to the lowest point of the remaining cells are ground,
+/// earlier ground points elsewhere become unclassified. Water and noise keep their class.
+/// Returns the number of ground points
+pub fn classify_ground(points: &mut [las::Point], params: &GroundParameters) -> usize {
+    let candidates = points
+        .iter()
+        .filter(|p| is_candidate(p))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return 0;
+    }
+
+    let cell_size = params.cell_size.max(0.1);
+    let (min_x, min_y, max_x, max_y) = candidates.iter().fold(
+        (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+        |(min_x, min_y, max_x, max_y), p| {
+            (
+                min_x.min(p.x),
+                min_y.min(p.y),
+                max_x.max(p.x),
+                max_y.max(p.y),
+            )
+        },
+    );
+    let width = ((max_x - min_x) / cell_size) as usize + 1;
+    let height = ((max_y - min_y) / cell_size) as usize + 1;
+    let cell = |p: &las::Point| {
+        ((p.y - min_y) / cell_size) as usize * width + ((p.x - min_x) / cell_size) as usize
+    };
+
+    let mut lowest = vec![f64::INFINITY; width * height];
+    for p in candidates {
+        let c = cell(p);
+        lowest[c] = lowest[c].min(p.z);
+    }
+    fill_empty_cells(&mut lowest, width, height);
+
+    let mut object = vec![false; lowest.len()];
+    let mut surface = lowest.clone();
+    let mut previous_window = 1;
+    let mut half_window = 1;
+    loop {
+        let window = 2 * half_window + 1;
+        let threshold = if previous_window == 1 {
+            params.initial_threshold
+        } else {
+            (params.slope * (window - previous_window) as f64 * cell_size
+                + params.initial_threshold)
+                .min(params.max_threshold)
+        };
+
+        let eroded = filter_window(&surface, width, height, half_window, f64::min);
+        let opened = filter_window(&eroded, width, height, half_window, f64::max);
+        for (i, flagged) in object.iter_mut().enumerate() {
+            *flagged |= surface[i] - opened[i] > threshold;
+        }
+        surface = opened;
+
+        previous_window = window;
+        half_window *= 2;
+        if (2 * half_window + 1) as f64 * cell_size > params.max_window {
+            break;
+        }
+    }
+
+    // points anywhere in a ground cell, the lowest point may be on the downhill side
+    let tolerance = params.initial_threshold + params.slope * cell_size;
+    let mut ground = 0;
+    for point in points.iter_mut().filter(|p| is_candidate(p)) {
+        let c = cell(point);
+        if !object[c] && point.z - lowest[c] <= tolerance {
+            point.classification = Classification::Ground;
+            ground += 1;
+        } else if point.classification == Classification::Ground {
+            point.classification = Classification::Unclassified;
+        }
+    }
+    ground
+}
+
+fn is_candidate(point: &las::Point) -> bool {
+    !matches!(
+        point.classification,
+        Classification::Water | Classification::LowPoint | Classification::HighNoise
+    )
+}
+
+// cells without points get the lowest value of their neighbours, spreading inwards
+fn fill_empty_cells(cells: &mut [f64], width: usize, height: usize) {
+    while cells.iter().any(|c| c.is_infinite()) {
+        let previous = cells.to_vec();
+        for y in 0..height {
+            for x in 0..width {
+                if previous[y * width + x].is_finite() {
+                    continue;
+                }
+                let neighbors = [
+                    (y > 0).then(|| previous[(y - 1) * width + x]),
+                    (y + 1 < height).then(|| previous[(y + 1) * width + x]),
+                    (x > 0).then(|| previous[y * width + x - 1]),
+                    (x + 1 < width).then(|| previous[y * width + x + 1]),
+                ];
+                cells[y * width + x] = neighbors
+                    .into_iter()
+                    .flatten()
+                    .fold(f64::INFINITY, f64::min);
+            }
+        }
+    }
+}
+
+// a square min or max filter, separated into rows and columns
+fn filter_window(
+    cells: &[f64],
+    width: usize,
+    height: usize,
+    half_window: usize,
+    op: fn(f64, f64) -> f64,
+) -> Vec<f64> {
+    let mut rows = vec![0.; cells.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (left, right) = (
+                x.saturating_sub(half_window),
+                (x + half_window).min(width - 1),
+            );
+            rows[y * width + x] = cells[y * width + left..=y * width + right]
+                .iter()
+                .copied()
+                .reduce(op)
+                .unwrap_or_default();
+        }
+    }
+
+    let mut filtered = vec![0.; cells.len()];
+    for y in 0..height {
+        let (top, bottom) = (
+            y.saturating_sub(half_window),
+            (y + half_window).min(height - 1),
+        );
+        for x in 0..width {
+            filtered[y * width + x] = (top..=bottom)
+                .map(|yi| rows[yi * width + x])
+                .reduce(op)
+                .unwrap_or_default();
+        }
+    }
+    filtered
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{map_gen::common::classify_ground, parameters::GroundParameters};
+    use las::point::Classification;
+
+    #[test]
+    fn roofs_and_canopy_are_not_ground() {
+        let mut points = Vec::new();
+        for yi in 0..80 {
+            for xi in 0..80 {
+                let (x, y) = (xi as f64 * 0.5, yi as f64 * 0.5);
+                // a gentle slope with a 10 by 10 meter building in the middle
+                let on_roof = (15. ..25.).contains(&x) && (15. ..25.).contains(&y);
+                let z = 0.1 * x + if on_roof { 5. } else { 0. };
+                points.push(las::Point {
+                    x,
+                    y,
+                    z,
+                    ..Default::default()
+                });
+            }
+        }
+        // a tree crown above the ground
+        points.push(las::Point {
+            x: 5.2,
+            y: 5.2,
+            z: 12.,
+            ..Default::default()
+        });
+
+        let ground = classify_ground(&mut points, &GroundParameters::default());
+
+        let is_ground = |p: &las::Point| p.classification == Classification::Ground;
+        assert_eq!(ground, points.iter().filter(|p| is_ground(p)).count());
+        assert!(
+            points
+                .iter()
+                .filter(|p| p.z >= 0.1 * p.x + 5.)
+                .all(|p| !is_ground(p))
+        );
+        assert!(points.iter().filter(|p| p.z == 0.1 * p.x).all(is_ground));
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use crate::{
+    CELL_SIZE_METERS, TILE_SIZE_PIXELS,
+    geometry::PointCloud,
+    map_gen::egui_map::{MapObject, PointSymbol},
+    parameters::MapParameters,
+    raster::{
+        Dfm,
+        dfm::{Boulder, Elevation},
+    },
+};
+
+use geo::Contains;
+use las::point::Classification;
+
+// The ground around a boulder is sampled this far from every cell. It must
+// be wider than the largest boulder but short enough to follow the terrain.
+const REFERENCE_RADIUS_METERS: f64 = 3.;
+// Only single returns are used for the low objects, a solid surface does
+// not let the laser through, while shrubs and stems usually do. Returns
+// classified as vegetation or buildings are never boulders.
+const LOW_OBJECT_MAX_HEIGHT_METERS: f64 = 4.;
+
+// A single cell above the threshold is more likely noise than a boulder
+const MIN_FOOTPRINT_CELLS: usize = 2;
+const MAX_ASPECT_RATIO: f64 = 3.;
+const MIN_BOUNDING_BOX_FILL: f64 = 0.4;
+// Cells this far outside a bump must be close to the ground for it to
+// count as isolated, which rules out ridges, terraces and stone walls.
+const ISOLATION_MARGIN_CELLS: usize = 2;
+const ISOLATION_HEIGHT_FRACTION: f64 = 0.5;
+
+/// Height of every cell above its surroundings.
+///
+/// The largest of two estimates is kept: the bump in the DEM relative to
+/// the mean of the ground a few meters away, for boulders classified as
+/// ground, and the highest single ground or unclassified return in the
+/// cell, for boulders left unclassified.
+pub fn compute_boulder_height(all_point_cloud: &PointCloud, dem: &Dfm<Elevation>) -> Dfm<Boulder> {
+    let mut boulder = Dfm::<Boulder>::new_like(dem);
+    let radius = (REFERENCE_RADIUS_METERS / CELL_SIZE_METERS).round() as isize;
+    let last = TILE_SIZE_PIXELS as isize - 1;
+
+    // eight samples in opposite pairs, so a constant slope cancels out
+    let directions = [
+        (-1, -1),
+        (-1, 0),
+        (-1, 1),
+        (0, -1),
+        (0, 1),
+        (1, -1),
+        (1, 0),
+        (1, 1),
+    ];
+
+    for yi in 0..TILE_SIZE_PIXELS {
+        for xi in 0..TILE_SIZE_PIXELS {
+            let reference = directions
+                .iter()
+                .map(|(dy, dx)| {
+                    let yj = (yi as isize + dy * radius).clamp(0, last) as usize;
+                    let xj = (xi as isize + dx * radius).clamp(0, last) as usize;
+                    dem[(yj, xj)]
+                })
+                .sum::<f64>()
+                / directions.len() as f64;
+
+            boulder[(yi, xi)] = (dem[(yi, xi)] - reference).max(0.);
+        }
+    }
+
+    for point in all_point_cloud
+        .points
+        .iter()
+        .filter(|point| point.0.number_of_returns == 1 && is_boulder_class(point.0.classification))
+    {
+        let xi = ((point.x() - dem.tl_coord.x) / CELL_SIZE_METERS).round() as isize;
+        let yi = ((dem.tl_coord.y - point.y()) / CELL_SIZE_METERS).round() as isize;
+        if xi < 0 || yi < 0 || xi > last || yi > last {
+            continue;
+        }
+
+        let (yi, xi) = (yi as usize, xi as usize);
+        let height = point.0.z - dem[(yi, xi)];
+        if height <= LOW_OBJECT_MAX_HEIGHT_METERS && height > boulder[(yi, xi)] {
+            boulder[(yi, xi)] = height;
+        }
+    }
+
+    boulder
+}
+
+fn is_boulder_class(classification: Classification) -> bool {
+    matches!(
+        classification,
+        Classification::CreatedNeverClassified
+            | Classification::Unclassified
+            | Classification::Ground
+    )
+}
+
+pub fn compute_boulders(
+    boulder: &Dfm<Boulder>,
+    cut_overlay: &geo::Polygon,
+    params: &MapParameters,
+) -> Vec<MapObject> {
+    let boulder_params = &params.boulders;
+    let max_footprint_cells = (boulder_params.max_footprint / CELL_SIZE_METERS.powi(2)) as usize;
+
+    let mut visited = vec![false; TILE_SIZE_PIXELS * TILE_SIZE_PIXELS];
+    let mut objects = Vec::new();
+
+    for start in 0..visited.len() {
+        if visited[start] || boulder.field[start] < boulder_params.min_height {
+            continue;
+        }
+
+        let component = flood_fill(boulder, boulder_params.min_height, start, &mut visited);
+        if component.len() < MIN_FOOTPRINT_CELLS || component.len() > max_footprint_cells {
+            continue;
+        }
+
+        let height = component
+            .iter()
+            .map(|&i| boulder.field[i])
+            .fold(f64::MIN, f64::max);
+        if height > boulder_params.max_height {
+            continue;
+        }
+
+        let (mut top, mut bottom, mut left, mut right) = (usize::MAX, 0, usize::MAX, 0);
+        for &i in component.iter() {
+            let (yi, xi) = (i / TILE_SIZE_PIXELS, i % TILE_SIZE_PIXELS);
+            top = top.min(yi);
+            bottom = bottom.max(yi);
+            left = left.min(xi);
+            right = right.max(xi);
+        }
+        let (rows, cols) = ((bottom - top + 1) as f64, (right - left + 1) as f64);
+        if rows.max(cols) / rows.min(cols) > MAX_ASPECT_RATIO
+            || component.len() as f64 / (rows * cols) < MIN_BOUNDING_BOX_FILL
+        {
+            continue;
+        }
+
+        if !is_isolated(boulder, &component, (top, bottom, left, right), height) {
+            continue;
+        }
+
+        // the boulder is placed at the height-weighted center of its footprint
+        let mut weight_sum = 0.;
+        let mut center = geo::Coord { x: 0., y: 0. };
+        for &i in component.iter() {
+            let weight = boulder.field[i];
+            let coord = boulder.index2coord(i / TILE_SIZE_PIXELS, i % TILE_SIZE_PIXELS);
+            center.x += weight * coord.x;
+            center.y += weight * coord.y;
+            weight_sum += weight;
+        }
+        let center = geo::Point::from(center / weight_sum);
+
+        // bumps in the overlap between tiles are found twice,
+        // but only the tile owning the center keeps them
+        if !cut_overlay.contains(&center) {
+            continue;
+        }
+
+        let symbol = if height >= boulder_params.large_height {
+            PointSymbol::LargeBoulder
+        } else {
+            PointSymbol::SmallBoulder
+        };
+        objects.push(MapObject::Point {
+            object: center,
+            symbol,
+            rotation: 0.,
+            tags: HashMap::new(),
+        });
+    }
+
+    objects
+}
+
+// 4-connected cells at or above the threshold, as flat indices
+fn flood_fill(
+    boulder: &Dfm<Boulder>,
+    threshold: f64,
+    start: usize,
+    visited: &mut [bool],
+) -> Vec<usize> {
+    let mut component = Vec::new();
+    let mut stack = vec![start];
+    visited[start] = true;
+
+    while let Some(i) = stack.pop() {
+        component.push(i);
+        let (yi, xi) = (i / TILE_SIZE_PIXELS, i % TILE_SIZE_PIXELS);
+
+        let mut neighbors = Vec::with_capacity(4);
+        if yi > 0 {
+            neighbors.push(i - TILE_SIZE_PIXELS);
+        }
+        if yi + 1 < TILE_SIZE_PIXELS {
+            neighbors.push(i + TILE_SIZE_PIXELS);
+        }
+        if xi > 0 {
+            neighbors.push(i - 1);
+        }
+        if xi + 1 < TILE_SIZE_PIXELS {
+            neighbors.push(i + 1);
+        }
+
+        for j in neighbors {
+            if !visited[j] && boulder.field[j] >= threshold {
+                visited[j] = true;
+                stack.push(j);
+            }
+        }
+    }
+
+    component
+}
+
+fn is_isolated(
+    boulder: &Dfm<Boulder>,
+    component: &[usize],
+    (top, bottom, left, right): (usize, usize, usize, usize),
+    height: f64,
+) -> bool {
+    let top = top.saturating_sub(ISOLATION_MARGIN_CELLS);
+    let bottom = (bottom + ISOLATION_MARGIN_CELLS).min(TILE_SIZE_PIXELS - 1);
+    let left = left.saturating_sub(ISOLATION_MARGIN_CELLS);
+    let right = (right + ISOLATION_MARGIN_CELLS).min(TILE_SIZE_PIXELS - 1);
+
+    let mut sum = 0.;
+    let mut count = 0;
+    for yi in top..=bottom {
+        for xi in left..=right {
+            let i = yi * TILE_SIZE_PIXELS + xi;
+            if component.contains(&i) {
+                continue;
+            }
+            sum += boulder.field[i];
+            count += 1;
+        }
+    }
+
+    count > 0 && sum / (count as f64) < ISOLATION_HEIGHT_FRACTION * height
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compute_boulder_height;
+    use crate::{
+        CELL_SIZE_METERS,
+        geometry::{PointCloud, PointLaz},
+        raster::{Dfm, dfm::Elevation},
+    };
+    use las::point::Classification;
+
+    fn single_return(x: f64, y: f64, z: f64, classification: Classification) -> PointLaz {
+        PointLaz(las::Point {
+            x,
+            y,
+            z,
+            number_of_returns: 1,
+            return_number: 1,
+            classification,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn vegetation_returns_are_not_boulders() {
+        let mut dem = Dfm::<Elevation>::new(geo::Coord::zero());
+        dem.field.fill(100.);
+
+        let (bush, stone) = ((10, 10), (30, 30));
+        let points = [
+            (bush, Classification::LowVegetation),
+            (stone, Classification::Unclassified),
+        ]
+        .into_iter()
+        .map(|((yi, xi), classification)| {
+            let (x, y) = (
+                xi as f64 * CELL_SIZE_METERS,
+                -(yi as f64) * CELL_SIZE_METERS,
+            );
+            single_return(x, y, 101.5, classification)
+        })
+        .collect();
+        let point_cloud = PointCloud::new(points, las::Bounds::default());
+
+        let boulder = compute_boulder_height(&point_cloud, &dem);
+
+        assert_eq!(boulder[bush], 0.);
+        assert_eq!(boulder[stone], 1.5);
+    }
+}
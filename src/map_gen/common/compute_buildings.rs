@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+
+use crate::{
+    CELL_SIZE_METERS, TILE_SIZE_PIXELS,
+    geometry::{MapMultiPolygon, PointCloud},
+    map_gen::egui_map::{AreaSymbol, MapObject},
+    parameters::{BufferRule, MapParameters},
+    raster::{
+        Dfm,
+        dfm::{Building, Elevation, HeightAboveGround, SurfaceObjects},
+    },
+};
+
+use geo::{Area, BooleanOps, MinimumRotatedRect, Simplify};
+use las::point::Classification;
+
+// Class 6 returns are weighed within a radius of two cells, so the 0.5
+// probability contour follows the roof edge closely.
+const CLASSIFIED_RADIUS_METERS: f64 = 1.;
+
+// Unclassified data: a cell is a roof candidate if it is higher than a
+// garden shed, locally planar and with no returns from below it.
+const MIN_BUILDING_HEIGHT_METERS: f64 = 2.5;
+const HEIGHT_TRANSITION_METERS: f64 = 0.5;
+const ROOF_WINDOW_RADIUS_CELLS: usize = 1;
+const ROOF_RMSE_METERS: f64 = 0.15;
+const SURFACE_OBJECTS_STD_DEV: f64 = 0.05;
+
+// Edges are snapped to the dominant orientation after a simplification on
+// the scale of the raster, shorter edges are treated as noise.
+const REGULARIZATION_TOLERANCE_METERS: f64 = CELL_SIZE_METERS;
+const MIN_EDGE_LENGTH_METERS: f64 = 1.5;
+// A regularized outline far from the original area has gone wrong,
+// the minimum rotated rectangle is used instead
+const MAX_AREA_RATIO: f64 = 1.5;
+
+/// Estimate the probability of a building in every raster cell.
+///
+/// Lidar files with classified non-ground points give the fraction of
+/// building (class 6) returns around every cell. For unclassified data the
+/// roofs are found from the canopy height model instead: tall, locally
+/// planar surfaces without returns from low objects below them.
+pub fn compute_building_probability(
+    all_point_cloud: &PointCloud,
+    dem: &Dfm<Elevation>,
+    canopy_height: &Dfm<HeightAboveGround>,
+    surface_objects: &Dfm<SurfaceObjects>,
+) -> Dfm<Building> {
+    let is_classified = all_point_cloud.points.iter().any(|point| {
+        matches!(
+            point.0.classification,
+            Classification::Building
+                | Classification::LowVegetation
+                | Classification::MediumVegetation
+                | Classification::HighVegetation
+        )
+    });
+
+    if is_classified {
+        classified_building_probability(all_point_cloud, dem)
+    } else {
+        roof_probability(canopy_height, surface_objects)
+    }
+}
+
+fn classified_building_probability(
+    all_point_cloud: &PointCloud,
+    dem: &Dfm<Elevation>,
+) -> Dfm<Building> {
+    let mut building_sums = vec![0.; TILE_SIZE_PIXELS * TILE_SIZE_PIXELS];
+    let mut total_sums = vec![0.; TILE_SIZE_PIXELS * TILE_SIZE_PIXELS];
+
+    let radius_cells = (CLASSIFIED_RADIUS_METERS / CELL_SIZE_METERS).ceil() as isize;
+    let radius2 = CLASSIFIED_RADIUS_METERS.powi(2);
+    let two_sigma2 = 2.
+        * (CLASSIFIED_RADIUS_METERS / 2.)
+            .max(CELL_SIZE_METERS)
+            .powi(2);
+
+    for point in all_point_cloud.points.iter() {
+        let x_center = ((point.x() - dem.tl_coord.x) / CELL_SIZE_METERS).round() as isize;
+        let y_center = ((dem.tl_coord.y - point.y()) / CELL_SIZE_METERS).round() as isize;
+
+        if x_center < -radius_cells
+            || y_center < -radius_cells
+            || x_center >= TILE_SIZE_PIXELS as isize + radius_cells
+            || y_center >= TILE_SIZE_PIXELS as isize + radius_cells
+        {
+            continue;
+        }
+
+        let is_building = point.0.classification == Classification::Building;
+
+        let y_min = (y_center - radius_cells).max(0) as usize;
+        let y_max = (y_center + radius_cells).min(TILE_SIZE_PIXELS as isize - 1) as usize;
+        let x_min = (x_center - radius_cells).max(0) as usize;
+        let x_max = (x_center + radius_cells).min(TILE_SIZE_PIXELS as isize - 1) as usize;
+
+        for yi in y_min..=y_max {
+            for xi in x_min..=x_max {
+                let cell_coord = dem.index2coord(yi, xi);
+                let dist2 = (point.x() - cell_coord.x).powi(2) + (point.y() - cell_coord.y).powi(2);
+                if dist2 > radius2 {
+                    continue;
+                }
+
+                let weight = (-dist2 / two_sigma2).exp();
+                let index = yi * TILE_SIZE_PIXELS + xi;
+                total_sums[index] += weight;
+                if is_building {
+                    building_sums[index] += weight;
+                }
+            }
+        }
+    }
+
+    let mut building = Dfm::<Building>::new_like(dem);
+    for ((value, part), total) in building
+        .field
+        .iter_mut()
+        .zip(building_sums.iter())
+        .zip(total_sums.iter())
+    {
+        *value = if *total > f64::EPSILON {
+            *part / *total
+        } else {
+            0.
+        };
+    }
+    building
+}
+
+fn roof_probability(
+    canopy_height: &Dfm<HeightAboveGround>,
+    surface_objects: &Dfm<SurfaceObjects>,
+) -> Dfm<Building> {
+    let mut building = Dfm::<Building>::new_like(canopy_height);
+    let radius = ROOF_WINDOW_RADIUS_CELLS;
+
+    for yi in 0..TILE_SIZE_PIXELS {
+        let top = yi.saturating_sub(radius);
+        let bottom = (yi + radius).min(TILE_SIZE_PIXELS - 1);
+        for xi in 0..TILE_SIZE_PIXELS {
+            let height = canopy_height[(yi, xi)];
+            let height_score = 1.
+                / (1. + (-(height - MIN_BUILDING_HEIGHT_METERS) / HEIGHT_TRANSITION_METERS).exp());
+            if height_score < 0.01 {
+                building[(yi, xi)] = 0.;
+                continue;
+            }
+
+            let left = xi.saturating_sub(radius);
+            let right = (xi + radius).min(TILE_SIZE_PIXELS - 1);
+
+            // plane fit of the canopy height in the window, in cell-local
+            // coordinates as the window is a regular grid
+            let mut n = 0.;
+            let (mut sx, mut sy, mut sz) = (0., 0., 0.);
+            let (mut sxx, mut sxy, mut syy, mut sxz, mut syz, mut szz) = (0., 0., 0., 0., 0., 0.);
+            let (mut shade_sum, mut shade_sum2) = (0., 0.);
+            for yj in top..=bottom {
+                for xj in left..=right {
+                    let x = (xj as f64 - xi as f64) * CELL_SIZE_METERS;
+                    let y = (yj as f64 - yi as f64) * CELL_SIZE_METERS;
+                    let z = canopy_height[(yj, xj)];
+                    n += 1.;
+                    sx += x;
+                    sy += y;
+                    sz += z;
+                    sxx += x * x;
+                    sxy += x * y;
+                    syy += y * y;
+                    sxz += x * z;
+                    syz += y * z;
+                    szz += z * z;
+
+                    let shade = surface_objects[(yj, xj)];
+                    shade_sum += shade;
+                    shade_sum2 += shade * shade;
+                }
+            }
+
+            let cxx = sxx - sx * sx / n;
+            let cxy = sxy - sx * sy / n;
+            let cyy = syy - sy * sy / n;
+            let cxz = sxz - sx * sz / n;
+            let cyz = syz - sy * sz / n;
+            let czz = (szz - sz * sz / n).max(0.);
+            let determinant = cxx * cyy - cxy * cxy;
+            let residual_sum = if determinant > f64::EPSILON {
+                let plane_x = (cxz * cyy - cyz * cxy) / determinant;
+                let plane_y = (cyz * cxx - cxz * cxy) / determinant;
+                (czz - plane_x * cxz - plane_y * cyz).max(0.)
+            } else {
+                czz
+            };
+            let roof_rmse = (residual_sum / n).sqrt();
+            let planarity_score = (-(roof_rmse / ROOF_RMSE_METERS).powi(2)).exp();
+
+            // the laser does not reach below a roof, while shrubs and
+            // stems below a tree crown give an uneven surface objects raster
+            let shade_mean = shade_sum / n;
+            let shade_std_dev = (shade_sum2 / n - shade_mean * shade_mean).max(0.).sqrt();
+            let clear_below_score = (-(shade_std_dev / SURFACE_OBJECTS_STD_DEV).powi(2)).exp();
+
+            building[(yi, xi)] =
+                (height_score * planarity_score.powf(0.7) * clear_below_score.powf(0.3))
+                    .clamp(0., 1.);
+        }
+    }
+
+    building
+}
+
+pub fn compute_buildings(
+    building: &Dfm<Building>,
+    convex_hull: &geo::Polygon,
+    cut_overlay: &geo::Polygon,
+    params: &MapParameters,
+    buffer_rules: &[BufferRule],
+) -> Vec<MapObject> {
+    let contours = building.marching_squares(params.buildings.threshold);
+
+    let mut building_polygons = geo::MultiPolygon::from_contours(contours, convex_hull, false);
+
+    for buffer in buffer_rules.iter() {
+        building_polygons = building_polygons.apply_buffer_rule(buffer);
+    }
+
+    // regularize before cutting so buildings on the tile edge get
+    // the same orientation in both tiles
+    building_polygons = geo::MultiPolygon::new(
+        building_polygons
+            .into_iter()
+            .filter_map(regularize_polygon)
+            .collect(),
+    );
+
+    building_polygons = cut_overlay.intersection(&building_polygons);
+
+    let mut objects = Vec::with_capacity(building_polygons.0.len());
+    for polygon in building_polygons.into_iter() {
+        objects.push(MapObject::Area {
+            object: polygon,
+            symbol: AreaSymbol::Building,
+            tags: HashMap::new(),
+        });
+    }
+    objects
+}
+
+/// Squares up a traced building outline.
+///
+/// All edges are snapped to the dominant orientation of the outline or the
+/// orientation perpendicular to it. Outlines that can not be regularized
+/// are replaced by their minimum rotated rectangle
+fn regularize_polygon(polygon: geo::Polygon) -> Option<geo::Polygon> {
+    let area = polygon.unsigned_area();
+    if area <= f64::EPSILON {
+        return None;
+    }
+
+    let angle = dominant_orientation(polygon.exterior());
+    let origin = polygon.exterior().0[0];
+
+    let exterior = regularize_ring(polygon.exterior(), angle, origin).filter(|ring| {
+        let ratio = geo::Polygon::new(ring.clone(), vec![]).unsigned_area() / area;
+        ratio < MAX_AREA_RATIO && ratio > 1. / MAX_AREA_RATIO
+    });
+
+    let Some(exterior) = exterior else {
+        return polygon.minimum_rotated_rect();
+    };
+
+    let interiors = polygon
+        .interiors()
+        .iter()
+        .filter_map(|ring| regularize_ring(ring, angle, origin))
+        .collect();
+
+    Some(geo::Polygon::new(exterior, interiors))
+}
+
+// The edge directions are folded modulo 90 degrees by multiplying the angle
+// by four, so perpendicular edges reinforce each other in the average
+fn dominant_orientation(ring: &geo::LineString) -> f64 {
+    let (mut cos_sum, mut sin_sum) = (0., 0.);
+    for line in ring.lines() {
+        let (dx, dy) = line.delta().x_y();
+        let length = dx.hypot(dy);
+        let angle = dy.atan2(dx);
+        cos_sum += length * (4. * angle).cos();
+        sin_sum += length * (4. * angle).sin();
+    }
+    sin_sum.atan2(cos_sum) / 4.
+}
+
+// an axis-aligned edge in the rotated frame, `level` is its y-coordinate
+// for horizontal edges and its x-coordinate for vertical edges
+struct AlignedEdge {
+    is_horizontal: bool,
+    level: f64,
+    length: f64,
+}
+
+fn regularize_ring(
+    ring: &geo::LineString,
+    angle: f64,
+    origin: geo::Coord,
+) -> Option<geo::LineString> {
+    let (sin, cos) = angle.sin_cos();
+
+    let rotated = geo::LineString::new(
+        ring.coords()
+            .map(|c| {
+                let (dx, dy) = (c.x - origin.x, c.y - origin.y);
+                geo::Coord {
+                    x: dx * cos + dy * sin,
+                    y: -dx * sin + dy * cos,
+                }
+            })
+            .collect(),
+    )
+    .simplify(REGULARIZATION_TOLERANCE_METERS);
+
+    let mut edges = rotated
+        .lines()
+        .filter_map(|line| {
+            let (dx, dy) = line.delta().x_y();
+            let length = dx.hypot(dy);
+            if length <= f64::EPSILON {
+                return None;
+            }
+
+            let is_horizontal = dx.abs() >= dy.abs();
+            let level = if is_horizontal {
+                (line.start.y + line.end.y) / 2.
+            } else {
+                (line.start.x + line.end.x) / 2.
+            };
+            Some(AlignedEdge {
+                is_horizontal,
+                level,
+                length,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    loop {
+        merge_parallel_neighbors(&mut edges);
+        if edges.len() <= 4 {
+            break;
+        }
+
+        let (shortest, length) = edges
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (i, e.length))
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+        if length >= MIN_EDGE_LENGTH_METERS {
+            break;
+        }
+        edges.remove(shortest);
+    }
+
+    // after merging the directions alternate, so an odd number of edges
+    // is only possible if everything collapsed to a single direction
+    if edges.len() < 4 || edges.len() % 2 != 0 {
+        return None;
+    }
+
+    let mut coords = Vec::with_capacity(edges.len() + 1);
+    for i in 0..edges.len() {
+        let (a, b) = (&edges[i], &edges[(i + 1) % edges.len()]);
+        let (x, y) = if a.is_horizontal {
+            (b.level, a.level)
+        } else {
+            (a.level, b.level)
+        };
+        coords.push(geo::Coord {
+            x: origin.x + x * cos - y * sin,
+            y: origin.y + x * sin + y * cos,
+        });
+    }
+    coords.push(coords[0]);
+
+    Some(geo::LineString::new(coords))
+}
+
+// consecutive edges in the same direction become one edge at their
+// length-weighted mean level, including across the start of the ring
+fn merge_parallel_neighbors(edges: &mut Vec<AlignedEdge>) {
+    let mut i = 0;
+    while edges.len() > 1 && i < edges.len() {
+        let next = (i + 1) % edges.len();
+        if edges[i].is_horizontal == edges[next].is_horizontal {
+            let removed = edges.remove(next);
+            let kept = &mut edges[if next < i { i - 1 } else { i }];
+            let length = kept.length + removed.length;
+            kept.level = (kept.level * kept.length + removed.level * removed.length) / length;
+            kept.length = length;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::regularize_polygon;
+    use geo::Area;
+
+    #[test]
+    fn noisy_rectangle_is_squared_up() {
+        // a 20 m x 10 m building rotated by 30 degrees, traced with some noise
+        let (sin, cos) = 30f64.to_radians().sin_cos();
+        let corners = [(0., 0.), (20., 0.), (20., 10.), (0., 10.)];
+        let mut coords = Vec::new();
+        for i in 0..4 {
+            let (start, end) = (corners[i], corners[(i + 1) % 4]);
+            for step in 0..10 {
+                let t = step as f64 / 10.;
+                let noise = 0.15 * (coords.len() as f64 * 1.7).sin();
+                let (x, y) = (
+                    start.0 + (end.0 - start.0) * t + noise,
+                    start.1 + (end.1 - start.1) * t - noise,
+                );
+                coords.push((x * cos - y * sin, x * sin + y * cos));
+            }
+        }
+        coords.push(coords[0]);
+        let traced = geo::Polygon::new(geo::LineString::from(coords), vec![]);
+
+        let building = regularize_polygon(traced).unwrap();
+
+        let ring = &building.exterior().0;
+        assert_eq!(ring.len(), 5);
+        for i in 0..4 {
+            let a = ring[i + 1] - ring[i];
+            let b = ring[(i + 2) % 4] - ring[i + 1];
+            let cos_corner = (a.x * b.x + a.y * b.y) / (a.x.hypot(a.y) * b.x.hypot(b.y));
+            assert!(cos_corner.abs() < 1e-9);
+        }
+        assert!((building.unsigned_area() - 200.).abs() < 20.);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::TILE_SIZE_PIXELS;
 use crate::geometry::{ContourLevel, ContourSet, MapMultiPolygon};
+use crate::map_gen::common::generalise_contours;
 use crate::map_gen::egui_map::{LineSymbol, MapObject};
 use crate::parameters::{ContourAlgo, FormlinePruneAlgo, MapParameters};
 use crate::raster::Dfm;
@@ -12,7 +13,9 @@ use std::collections::HashMap;
 const FORMLINE_PRUNE_BUFFER_METERS: f64 = 2.;
 const FORMLINE_RECONNECT_GAP_METERS: f64 = 3.;
 
-fn contour_symbol(elevation: f64, interval: f64) -> LineSymbol {
+// the levels are counted from the base level
+fn contour_symbol(elevation: f64, interval: f64, base_level: f64) -> LineSymbol {
+    let elevation = elevation - base_level;
     if is_interval_level(elevation, 5. * interval) {
         LineSymbol::IndexContour
     } else if is_interval_level(elevation, interval) {
@@ -78,7 +81,8 @@ impl FormlinePruner {
                 .0
                 .iter()
                 .filter(|level| {
-                    contour_symbol(level.z, params.contour.interval) != LineSymbol::FormLine
+                    contour_symbol(level.z, params.contour.interval, params.contour.base_level)
+                        != LineSymbol::FormLine
                 })
                 .cloned()
                 .collect(),
@@ -336,7 +340,9 @@ pub fn compute_naive_contours(
     };
 
     let c_levels = ((z_range.1 - z_range.0) / effective_interval).ceil() as usize + 1;
-    let start_level = (z_range.0 / effective_interval).floor() * effective_interval;
+    let base_level = params.contour.base_level;
+    let start_level =
+        ((z_range.0 - base_level) / effective_interval).floor() * effective_interval + base_level;
 
     let mut adjusted_dem = true_dem.smoothen(15., 15, 10);
     let mut interpolated_dem = adjusted_dem.clone();
@@ -441,7 +447,7 @@ pub fn compute_naive_contours(
     for c_level in contours.0 {
         let z = c_level.z;
 
-        let symbol = contour_symbol(z, params.contour.interval);
+        let symbol = contour_symbol(z, params.contour.interval, params.contour.base_level);
         let lines = if symbol == LineSymbol::FormLine {
             let pruned = c_level
                 .lines
@@ -483,14 +489,20 @@ pub fn extract_contours(
         params.contour.interval
     };
 
-    let dem = if params.contour.algorithm == ContourAlgo::Raw {
+    // the generalisation smooths the contours of the raw dem instead
+    let dem = if matches!(
+        params.contour.algorithm,
+        ContourAlgo::Raw | ContourAlgo::Generalisation
+    ) {
         true_dem
     } else {
         &true_dem.smoothen(15., 15, params.contour.algo_steps as usize)
     };
 
     let c_levels = ((z_range.1 - z_range.0) / effective_interval).ceil() as usize + 1;
-    let start_level = (z_range.0 / effective_interval).floor() * effective_interval;
+    let base_level = params.contour.base_level;
+    let start_level =
+        ((z_range.0 - base_level) / effective_interval).floor() * effective_interval + base_level;
 
     let clip_poly = geo::Polygon::new(
         geo::LineString::new(vec![
@@ -517,6 +529,35 @@ pub fn extract_contours(
         contour_set.0.push(ContourLevel::new(contours, c_level));
     }
 
+    // the features the form line pruner flags as important are kept by the generalisation,
+    // the pruner is flagged from the contours before they are moved
+    let generalisation_pruner = if params.contour.algorithm == ContourAlgo::Generalisation {
+        let pruner = if params.contour.form_lines
+            && params.contour.form_line_prune_algorithm == FormlinePruneAlgo::InterpolationError
+        {
+            FormlinePruner::from_contour_interpolation_error(
+                &contour_set,
+                dem,
+                true_dem,
+                None,
+                &clip_poly,
+                params,
+            )?
+        } else {
+            FormlinePruner::from_terrain_change(true_dem, &clip_poly, params)
+        };
+        generalise_contours(
+            &mut contour_set,
+            &pruner.important_terrain,
+            cut_overlay,
+            params.scale,
+            params.contour.generalisation_steps,
+        );
+        Some(pruner)
+    } else {
+        None
+    };
+
     let needs_interpolated_dem = compute_energy
         || params.contour.form_lines
             && params.contour.form_line_prune_algorithm == FormlinePruneAlgo::InterpolationError;
@@ -544,6 +585,7 @@ pub fn extract_contours(
     let formline_pruner = if params.contour.form_lines {
         match params.contour.form_line_prune_algorithm {
             FormlinePruneAlgo::None => None,
+            _ if generalisation_pruner.is_some() => generalisation_pruner,
             FormlinePruneAlgo::TerrainChange => Some(FormlinePruner::from_terrain_change(
                 true_dem, &clip_poly, params,
             )),
@@ -564,7 +606,11 @@ pub fn extract_contours(
 
     let mut objects = Vec::with_capacity(contour_set.0.len());
     for c_level in contour_set.0 {
-        let symbol = contour_symbol(c_level.z, params.contour.interval);
+        let symbol = contour_symbol(
+            c_level.z,
+            params.contour.interval,
+            params.contour.base_level,
+        );
         let lines = if symbol == LineSymbol::FormLine {
             let pruned = c_level
                 .lines
@@ -2,8 +2,8 @@ use crate::geometry::{PointCloud, PointLaz};
 use crate::parameters::VegetationWeights;
 use crate::raster::Dfm;
 use crate::raster::dfm::{
-    Elevation, Ground, HeightAboveGround, HighVegetation, Intensity, LastReturn, LowVegetation,
-    MediumVegetation, Ndvd, Returns, SurfaceObjects, Water,
+    Boulder, Building, Elevation, Ground, HeightAboveGround, HighVegetation, Intensity, LastReturn,
+    LowVegetation, MediumVegetation, Ndvd, Returns, SurfaceObjects, Water,
 };
 use crate::statistics::LidarStats;
 use crate::{CELL_SIZE_METERS, TILE_SIZE_PIXELS};
@@ -28,6 +28,8 @@ pub struct ComputedDfms {
     pub high_vegetation: Dfm<HighVegetation>,
     pub surface_objects: Dfm<SurfaceObjects>,
     pub water: Dfm<Water>,
+    pub building: Dfm<Building>,
+    pub boulder: Dfm<Boulder>,
     pub canopy_height: Dfm<HeightAboveGround>,
     pub z_range: (f64, f64),
 }
@@ -115,6 +117,13 @@ pub fn compute_dfms(
     let vegetation_density =
         compute_vegetation_density_dfms(all_point_cloud, &dem, VEGETATION_DENSITY_RADIUS_METERS);
     let water = super::compute_water_probability(all_point_cloud, &dem, stats);
+    let building = super::compute_building_probability(
+        all_point_cloud,
+        &dem,
+        &canopy_height,
+        &surface_objects,
+    );
+    let boulder = super::compute_boulder_height(all_point_cloud, &dem);
 
     Ok(ComputedDfms {
         dem,
@@ -127,6 +136,8 @@ pub fn compute_dfms(
         high_vegetation: vegetation_density.high,
         surface_objects,
         water,
+        building,
+        boulder,
         canopy_height,
         z_range,
     })
@@ -0,0 +1,68 @@
+use crate::{
+    CELL_SIZE_METERS,
+    raster::{
+        Dfm,
+        dfm::{HighVegetation, Intensity, Marsh, MediumVegetation, Slope},
+        hydrology::FlowNetwork,
+    },
+};
+
+// the Sobel based slope raster is the elevation gradient scaled by 4 / sqrt(2)
+const SLOPE_TO_GRADIENT: f64 = std::f64::consts::SQRT_2 / 4.;
+// keeps the wetness index finite on perfectly flat ground
+const MIN_GRADIENT: f64 = 1e-3;
+const WET_INDEX: f64 = 10.;
+const WET_INDEX_SCALE: f64 = 1.5;
+// A gradient of 0.05 is approximately a 3 degree incline
+const FLAT_GRADIENT: f64 = 0.05;
+
+/// Estimate the probability of marsh in every raster cell.
+///
+/// Marsh is expected where water collects and stays: a high topographic
+/// wetness index ln(a / tan(slope)) on flat terrain. Weak intensity, as wet
+/// ground absorbs the laser, and sparse taller vegetation are supporting evidence
+pub fn compute_marsh_probability(
+    slope: &Dfm<Slope>,
+    flow: &FlowNetwork,
+    intensity: &Dfm<Intensity>,
+    medium_vegetation: &Dfm<MediumVegetation>,
+    high_vegetation: &Dfm<HighVegetation>,
+) -> Dfm<Marsh> {
+    let mut marsh = Dfm::<Marsh>::new_like(slope);
+
+    let num_cells = intensity.field.len() as f64;
+    let intensity_mean = intensity.field.iter().sum::<f64>() / num_cells;
+    let intensity_std_dev = (intensity
+        .field
+        .iter()
+        .map(|i| (i - intensity_mean).powi(2))
+        .sum::<f64>()
+        / num_cells)
+        .sqrt()
+        .max(f64::EPSILON);
+
+    for index in 0..marsh.field.len() {
+        let gradient = slope.field[index] * SLOPE_TO_GRADIENT;
+
+        // the specific catchment area is the contributing area per unit contour width
+        let catchment = flow.accumulation.field[index] / CELL_SIZE_METERS;
+        let wetness_index = (catchment / gradient.max(MIN_GRADIENT)).ln();
+        let wetness_score = 1. / (1. + (-(wetness_index - WET_INDEX) / WET_INDEX_SCALE).exp());
+
+        let flatness_score = (-(gradient / FLAT_GRADIENT).powi(2)).exp();
+
+        let intensity_score =
+            1. / (1. + ((intensity.field[index] - intensity_mean) / intensity_std_dev).exp());
+
+        let openness_score =
+            (1. - medium_vegetation.field[index] - high_vegetation.field[index]).clamp(0., 1.);
+
+        marsh.field[index] = (wetness_score.powf(0.4)
+            * flatness_score.powf(0.3)
+            * intensity_score.powf(0.15)
+            * openness_score.powf(0.15))
+        .clamp(0., 1.);
+    }
+
+    marsh
+}
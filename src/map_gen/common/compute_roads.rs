@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+
+use crate::{
+    CELL_SIZE_METERS, TILE_SIZE_PIXELS,
+    map_gen::egui_map::MapObject,
+    parameters::MapParameters,
+    raster::{
+        Dfm,
+        dfm::{Elevation, Ground, Intensity, LowVegetation, Road},
+    },
+};
+
+use geo::{BooleanOps, Euclidean, Length, Simplify};
+
+// The bare ground of a corridor is compared to its surroundings within
+// this radius, it must be wider than the widest road so open fields and
+// clear-cuts do not stand out as roads.
+const CONTEXT_RADIUS_METERS: f64 = 8.;
+const BARE_CONTRAST: f64 = 0.15;
+const BARE_CONTRAST_SCALE: f64 = 0.05;
+const INTENSITY_CONTRAST: f64 = 0.1;
+// road beds are smooth across, the curvature is computed over this distance
+const CURVATURE_STEP_CELLS: usize = 2;
+const CURVATURE_SCALE: f64 = 0.2;
+
+const ROAD_SIMPLIFICATION_DIST: f64 = 0.5;
+
+const NEIGHBORS: [(isize, isize); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+];
+
+/// Estimate the probability of a road or path in every raster cell.
+///
+/// Paths and roads are narrow corridors with more bare ground than their
+/// surroundings. A smooth cross-section in the DEM and a different
+/// intensity than the surroundings are supporting evidence
+pub fn compute_road_probability(
+    dem: &Dfm<Elevation>,
+    ground: &Dfm<Ground>,
+    low_vegetation: &Dfm<LowVegetation>,
+    intensity: &Dfm<Intensity>,
+) -> Dfm<Road> {
+    let bare = ground
+        .field
+        .iter()
+        .zip(low_vegetation.field.iter())
+        .map(|(g, l)| (g - l).clamp(0., 1.))
+        .collect::<Vec<_>>();
+
+    let radius = (CONTEXT_RADIUS_METERS / CELL_SIZE_METERS).round() as usize;
+    let bare_context = local_mean(&bare, radius);
+    let intensity_context = local_mean(&intensity.field, radius);
+
+    let mut road = Dfm::<Road>::new_like(dem);
+    for yi in 0..TILE_SIZE_PIXELS {
+        for xi in 0..TILE_SIZE_PIXELS {
+            let index = yi * TILE_SIZE_PIXELS + xi;
+
+            let contrast = bare[index] - bare_context[index];
+            let bare_score = 1. / (1. + (-(contrast - BARE_CONTRAST) / BARE_CONTRAST_SCALE).exp());
+
+            let intensity_deviation = (intensity.field[index] - intensity_context[index]).abs();
+            let intensity_score = 1. - (-(intensity_deviation / INTENSITY_CONTRAST).powi(2)).exp();
+
+            let smooth_score = (-(max_curvature(dem, yi, xi) / CURVATURE_SCALE).powi(2)).exp();
+
+            road[(yi, xi)] = (bare_score.powf(0.7)
+                * smooth_score.powf(0.2)
+                * (0.5 + 0.5 * intensity_score).powf(0.1))
+            .clamp(0., 1.);
+        }
+    }
+
+    road
+}
+
+/// Centre lines of the corridors above the threshold, with a symbol
+/// chosen from the width of each line
+pub fn compute_roads(
+    road: &Dfm<Road>,
+    cut_overlay: &geo::Polygon,
+    params: &MapParameters,
+) -> Vec<MapObject> {
+    let road_params = &params.roads;
+    let mask = road
+        .field
+        .iter()
+        .map(|&p| p >= road_params.threshold)
+        .collect::<Vec<_>>();
+
+    let distance = distance_to_background(&mask);
+
+    let mut skeleton = mask;
+    thin(&mut skeleton);
+    remove_staircases(&mut skeleton);
+
+    let mut objects = Vec::new();
+    for cells in trace_skeleton(&skeleton) {
+        let mut widths = cells
+            .iter()
+            .map(|&i| (2. * distance[i] - 0.5) * CELL_SIZE_METERS)
+            .collect::<Vec<_>>();
+        widths.sort_unstable_by(f64::total_cmp);
+        let width = widths[widths.len() / 2];
+
+        let line = geo::LineString::new(
+            cells
+                .iter()
+                .map(|&i| road.index2coord(i / TILE_SIZE_PIXELS, i % TILE_SIZE_PIXELS))
+                .collect(),
+        );
+        if Euclidean.length(&line) < road_params.min_length {
+            continue;
+        }
+
+        let symbol = road_params.line_symbol(width);
+        let lines = cut_overlay.clip(
+            &geo::MultiLineString::new(vec![line.simplify(ROAD_SIMPLIFICATION_DIST)]),
+            false,
+        );
+        objects.extend(lines.into_iter().map(|line| {
+            // kept so the final map can classify the road again once it is merged across tiles
+            let mut road = MapObject::Line {
+                object: line,
+                symbol,
+                tags: HashMap::new(),
+            };
+            road.add_width_tag(width);
+            road
+        }));
+    }
+
+    objects
+}
+
+// the largest absolute eigenvalue of the Hessian of the DEM
+fn max_curvature(dem: &Dfm<Elevation>, yi: usize, xi: usize) -> f64 {
+    let step = CURVATURE_STEP_CELLS;
+    let top = yi.saturating_sub(step);
+    let bottom = (yi + step).min(TILE_SIZE_PIXELS - 1);
+    let left = xi.saturating_sub(step);
+    let right = (xi + step).min(TILE_SIZE_PIXELS - 1);
+    let h = step as f64 * CELL_SIZE_METERS;
+
+    let z = dem[(yi, xi)];
+    let zxx = (dem[(yi, right)] - 2. * z + dem[(yi, left)]) / (h * h);
+    let zyy = (dem[(bottom, xi)] - 2. * z + dem[(top, xi)]) / (h * h);
+    let zxy = (dem[(bottom, right)] - dem[(bottom, left)] - dem[(top, right)] + dem[(top, left)])
+        / (4. * h * h);
+
+    ((zxx + zyy) / 2.).abs() + (((zxx - zyy) / 2.).powi(2) + zxy * zxy).sqrt()
+}
+
+fn local_mean(values: &[f64], radius: usize) -> Vec<f64> {
+    let stride = TILE_SIZE_PIXELS + 1;
+    let mut table = vec![0.; stride * stride];
+    for y in 0..TILE_SIZE_PIXELS {
+        let mut row_sum = 0.;
+        for x in 0..TILE_SIZE_PIXELS {
+            row_sum += values[y * TILE_SIZE_PIXELS + x];
+            table[(y + 1) * stride + x + 1] = table[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    let mut means = vec![0.; values.len()];
+    for y in 0..TILE_SIZE_PIXELS {
+        let top = y.saturating_sub(radius);
+        let bottom = (y + radius + 1).min(TILE_SIZE_PIXELS);
+        for x in 0..TILE_SIZE_PIXELS {
+            let left = x.saturating_sub(radius);
+            let right = (x + radius + 1).min(TILE_SIZE_PIXELS);
+            let sum = table[bottom * stride + right] + table[top * stride + left]
+                - table[top * stride + right]
+                - table[bottom * stride + left];
+            means[y * TILE_SIZE_PIXELS + x] = sum / ((bottom - top) * (right - left)) as f64;
+        }
+    }
+    means
+}
+
+// chamfer distance in cells from every cell in the mask to the closest cell outside it
+fn distance_to_background(mask: &[bool]) -> Vec<f64> {
+    let side = TILE_SIZE_PIXELS as isize;
+    let mut distance = mask
+        .iter()
+        .map(|&m| if m { f64::MAX } else { 0. })
+        .collect::<Vec<_>>();
+
+    let forward = [(-1, -1), (-1, 0), (-1, 1), (0, -1)];
+    let backward = [(1, 1), (1, 0), (1, -1), (0, 1)];
+    let mut pass = |yi: isize, xi: isize, offsets: &[(isize, isize)]| {
+        let index = (yi * side + xi) as usize;
+        for (dy, dx) in offsets {
+            let (yj, xj) = (yi + dy, xi + dx);
+            // the tile edge is treated as background
+            let neighbor = if yj < 0 || xj < 0 || yj >= side || xj >= side {
+                0.
+            } else {
+                distance[(yj * side + xj) as usize]
+            };
+            let step = if *dy != 0 && *dx != 0 {
+                std::f64::consts::SQRT_2
+            } else {
+                1.
+            };
+            distance[index] = distance[index].min(neighbor + step);
+        }
+    };
+
+    for yi in 0..side {
+        for xi in 0..side {
+            if mask[(yi * side + xi) as usize] {
+                pass(yi, xi, &forward);
+            }
+        }
+    }
+    for yi in (0..side).rev() {
+        for xi in (0..side).rev() {
+            if mask[(yi * side + xi) as usize] {
+                pass(yi, xi, &backward);
+            }
+        }
+    }
+
+    distance
+}
+
+// the 8 neighbours clockwise from the top left, cells outside the tile are unset
+fn neighborhood(cells: &[bool], index: usize) -> [bool; 8] {
+    let (yi, xi) = (
+        (index / TILE_SIZE_PIXELS) as isize,
+        (index % TILE_SIZE_PIXELS) as isize,
+    );
+    NEIGHBORS.map(|(dy, dx)| {
+        let (yj, xj) = (yi + dy, xi + dx);
+        yj >= 0
+            && xj >= 0
+            && yj < TILE_SIZE_PIXELS as isize
+            && xj < TILE_SIZE_PIXELS as isize
+            && cells[yj as usize * TILE_SIZE_PIXELS + xj as usize]
+    })
+}
+
+fn neighbor_index(index: usize, k: usize) -> Option<usize> {
+    let (dy, dx) = NEIGHBORS[k];
+    let yj = (index / TILE_SIZE_PIXELS) as isize + dy;
+    let xj = (index % TILE_SIZE_PIXELS) as isize + dx;
+    (yj >= 0 && xj >= 0 && yj < TILE_SIZE_PIXELS as isize && xj < TILE_SIZE_PIXELS as isize)
+        .then_some(yj as usize * TILE_SIZE_PIXELS + xj as usize)
+}
+
+// Zhang-Suen thinning down to 8-connected lines one cell wide
+fn thin(cells: &mut [bool]) {
+    // indices into the clockwise neighbourhood
+    const N: usize = 1;
+    const E: usize = 3;
+    const S: usize = 5;
+    const W: usize = 7;
+
+    loop {
+        let mut changed = false;
+        for sub_iteration in 0..2 {
+            let removable = (0..cells.len())
+                .filter(|&i| {
+                    if !cells[i] {
+                        return false;
+                    }
+                    let n = neighborhood(cells, i);
+                    let count = n.iter().filter(|&&c| c).count();
+                    let transitions = (0..8).filter(|&k| !n[k] && n[(k + 1) % 8]).count();
+                    let (a, b) = if sub_iteration == 0 {
+                        (n[N] && n[E] && n[S], n[E] && n[S] && n[W])
+                    } else {
+                        (n[N] && n[E] && n[W], n[N] && n[S] && n[W])
+                    };
+                    (2..=6).contains(&count) && transitions == 1 && !a && !b
+                })
+                .collect::<Vec<_>>();
+
+            changed |= !removable.is_empty();
+            for i in removable {
+                cells[i] = false;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+// Thinning leaves L-shaped corners where a diagonal step would do, which
+// look like junctions to the tracing. A corner cell is removed if its
+// neighbours stay connected without it
+fn remove_staircases(cells: &mut [bool]) {
+    for i in 0..cells.len() {
+        if !cells[i] {
+            continue;
+        }
+        let n = neighborhood(cells, i);
+        let has_corner = (0..4).any(|k| n[2 * k + 1] && n[(2 * k + 3) % 8]);
+        if has_corner && neighbors_stay_connected(&n) {
+            cells[i] = false;
+        }
+    }
+}
+
+fn neighbors_stay_connected(n: &[bool; 8]) -> bool {
+    let set = (0..8).filter(|&k| n[k]).collect::<Vec<_>>();
+    let Some(&first) = set.first() else {
+        return true;
+    };
+
+    // neighbours next to each other in the ring are adjacent, and so are
+    // two edge neighbours around a corner neighbour
+    let adjacent = |a: usize, b: usize| {
+        let d = (a + 8 - b) % 8;
+        d == 1 || d == 7 || (d == 2 || d == 6) && a % 2 == 1 && b % 2 == 1
+    };
+
+    let mut reached = vec![first];
+    let mut k = 0;
+    while k < reached.len() {
+        let current = reached[k];
+        for &other in set.iter() {
+            if !reached.contains(&other) && adjacent(current, other) {
+                reached.push(other);
+            }
+        }
+        k += 1;
+    }
+    reached.len() == set.len()
+}
+
+// Splits the skeleton into lines between end points and junctions,
+// closed loops without either become one line each
+fn trace_skeleton(cells: &[bool]) -> Vec<Vec<usize>> {
+    let degree = |i: usize| neighborhood(cells, i).iter().filter(|&&c| c).count();
+    let is_node = |i: usize| degree(i) != 2;
+
+    let mut visited = vec![false; cells.len()];
+    let mut lines = Vec::new();
+
+    let follow = |start: usize, first: usize, visited: &mut [bool]| {
+        let mut line = vec![start, first];
+        let (mut previous, mut current) = (start, first);
+        while !is_node(current) && !visited[current] {
+            visited[current] = true;
+            let Some(next) = (0..8)
+                .filter_map(|k| neighbor_index(current, k))
+                .find(|&j| {
+                    cells[j] && (j != previous && j != start || j == start && line.len() > 2)
+                })
+            else {
+                break;
+            };
+            line.push(next);
+            if next == start {
+                break;
+            }
+            (previous, current) = (current, next);
+        }
+        line
+    };
+
+    for node in (0..cells.len()).filter(|&i| cells[i] && is_node(i)) {
+        for k in 0..8 {
+            let Some(first) = neighbor_index(node, k) else {
+                continue;
+            };
+            if !cells[first] || visited[first] || is_node(first) && first < node {
+                continue;
+            }
+            lines.push(follow(node, first, &mut visited));
+        }
+    }
+
+    for start in 0..cells.len() {
+        if cells[start] && !visited[start] && !is_node(start) {
+            visited[start] = true;
+            let first = (0..8)
+                .filter_map(|k| neighbor_index(start, k))
+                .find(|&j| cells[j]);
+            if let Some(first) = first {
+                lines.push(follow(start, first, &mut visited));
+            }
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{distance_to_background, remove_staircases, thin, trace_skeleton};
+    use crate::{
+        CELL_SIZE_METERS, TILE_SIZE_PIXELS, map_gen::egui_map::LineSymbol,
+        parameters::RoadParameters,
+    };
+
+    #[test]
+    fn road_junction_is_traced_as_three_centre_lines() {
+        // a 5 m wide road ending in a T-junction with another 5 m wide road
+        let side = TILE_SIZE_PIXELS;
+        let mut mask = vec![false; side * side];
+        for yi in 100..110 {
+            for xi in 50..250 {
+                mask[yi * side + xi] = true;
+            }
+        }
+        for yi in 110..300 {
+            for xi in 145..155 {
+                mask[yi * side + xi] = true;
+            }
+        }
+
+        let distance = distance_to_background(&mask);
+        let mut skeleton = mask.clone();
+        thin(&mut skeleton);
+        remove_staircases(&mut skeleton);
+        let lines = trace_skeleton(&skeleton);
+
+        assert_eq!(lines.len(), 3);
+        let junction = lines[0][lines[0].len() - 1];
+        for line in &lines {
+            assert!(line[0] == junction || line[line.len() - 1] == junction);
+            assert!(line.iter().all(|&i| mask[i]));
+            // one cell wide and unbroken
+            assert!(line.windows(2).all(|w| {
+                let (dy, dx) = (
+                    (w[0] / side).abs_diff(w[1] / side),
+                    (w[0] % side).abs_diff(w[1] % side),
+                );
+                dy <= 1 && dx <= 1 && dy + dx > 0
+            }));
+
+            let width = (2. * distance[line[line.len() / 2]] - 0.5) * CELL_SIZE_METERS;
+            assert_eq!(
+                RoadParameters::default().line_symbol(width),
+                LineSymbol::Road
+            );
+        }
+    }
+}
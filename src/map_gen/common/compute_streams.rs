@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::{
+    TILE_SIZE_PIXELS,
+    map_gen::egui_map::{LineSymbol, MapObject},
+    parameters::MapParameters,
+    raster::hydrology::FlowNetwork,
+};
+
+use geo::{BooleanOps, Simplify};
+
+// D8 channels are staircases along the cells, simplifying by a cell
+// width removes the steps but keeps the actual bends of the stream
+const STREAM_SIMPLIFICATION_DIST: f64 = 0.5;
+
+/// Channels where the contributing area exceeds the threshold.
+///
+/// Every line runs downstream from a channel head to where it joins a
+/// larger channel or leaves the tile, so lines cut at the tile edges meet
+/// tip to tail and can be joined by `TempMap::merge_lines`.
+/// The contributing area is the one of `flow`, the final map adds the water
+/// entering the tile from its neighbours to it first
+pub fn compute_streams(
+    flow: &FlowNetwork,
+    cut_overlay: &geo::Polygon,
+    params: &MapParameters,
+) -> Vec<MapObject> {
+    let accumulation = &flow.accumulation;
+    let num_cells = TILE_SIZE_PIXELS * TILE_SIZE_PIXELS;
+    let is_channel = |i: usize| accumulation.field[i] >= params.streams.min_contributing_area;
+
+    // the channel donor with the largest area continues through a junction,
+    // the other donors end there
+    let mut main_donor = vec![None::<usize>; num_cells];
+    for (donor, receiver) in flow.receivers.iter().enumerate() {
+        let Some(receiver) = *receiver else {
+            continue;
+        };
+        if !is_channel(donor) {
+            continue;
+        }
+        if main_donor[receiver]
+            .is_none_or(|current| accumulation.field[donor] > accumulation.field[current])
+        {
+            main_donor[receiver] = Some(donor);
+        }
+    }
+
+    let mut lines = Vec::new();
+    for head in (0..num_cells).filter(|&i| is_channel(i) && main_donor[i].is_none()) {
+        let mut coords =
+            vec![accumulation.index2coord(head / TILE_SIZE_PIXELS, head % TILE_SIZE_PIXELS)];
+
+        let mut current = head;
+        while let Some(next) = flow.receivers[current] {
+            coords.push(accumulation.index2coord(next / TILE_SIZE_PIXELS, next % TILE_SIZE_PIXELS));
+            if main_donor[next] != Some(current) {
+                break;
+            }
+            current = next;
+        }
+
+        if coords.len() >= 2 {
+            lines.push(geo::LineString::new(coords));
+        }
+    }
+
+    let streams = cut_overlay.clip(
+        &geo::MultiLineString::new(lines).simplify(STREAM_SIMPLIFICATION_DIST),
+        false,
+    );
+
+    streams
+        .into_iter()
+        .map(|line| MapObject::Line {
+            object: line,
+            symbol: LineSymbol::SmallCrossableWatercourse,
+            tags: HashMap::new(),
+        })
+        .collect()
+}
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, fmt::Display, ops::AddAssign};
+
+use las::point::Classification;
+use rayon::prelude::*;
+use rstar::RTree;
+
+use crate::parameters::NoiseParameters;
+
+// The ground surface the isolated points are compared to is the median ground
+// elevation of cells this size, small enough to follow steep terrain
+const GROUND_CELL_METERS: f64 = 5.;
+
+/// The number of points removed by every step of the noise filter
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RemovedNoise {
+    pub classified: usize,
+    pub isolated: usize,
+    pub outliers: usize,
+}
+
+impl RemovedNoise {
+    pub fn total(&self) -> usize {
+        self.classified + self.isolated + self.outliers
+    }
+}
+
+impl AddAssign for RemovedNoise {
+    fn add_assign(&mut self, other: Self) {
+        self.classified += other.classified;
+        self.isolated += other.isolated;
+        self.outliers += other.outliers;
+    }
+}
+
+impl Display for RemovedNoise {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "removed {} noise points ({} classified as noise, {} far from the ground, {} statistical outliers)",
+            self.total(),
+            self.classified,
+            self.isolated,
+            self.outliers
+        )
+    }
+}
+
+/// Removes the noise classes and the statistical outliers of the points read from one file.
+/// Must run before the ground is classified, a low outlier would otherwise be taken as ground
+pub fn filter_noise(
+    mut points: Vec<las::Point>,
+    params: &NoiseParameters,
+) -> (Vec<las::Point>, RemovedNoise) {
+    let mut removed = RemovedNoise::default();
+
+    if params.remove_noise_classes {
+        let count = points.len();
+        points.retain(|p| {
+            !matches!(
+                p.classification,
+                Classification::LowPoint | Classification::HighNoise
+            )
+        });
+        removed.classified = count - points.len();
+    }
+
+    if params.remove_outliers {
+        let count = points.len();
+        points = remove_outliers(points, params);
+        removed.outliers = count - points.len();
+    }
+
+    (points, removed)
+}
+
+/// Removes the points far above or below the local ground, needs the ground to be classified
+pub fn filter_isolated(
+    points: Vec<las::Point>,
+    params: &NoiseParameters,
+    removed: &mut RemovedNoise,
+) -> Vec<las::Point> {
+    if !params.remove_isolated {
+        return points;
+    }
+
+    let count = points.len();
+    let points = remove_isolated(points, params);
+    removed.isolated += count - points.len();
+    points
+}
+
+fn remove_isolated(points: Vec<las::Point>, params: &NoiseParameters) -> Vec<las::Point> {
+    let cell = |p: &las::Point| {
+        (
+            (p.x / GROUND_CELL_METERS).floor() as i64,
+            (p.y / GROUND_CELL_METERS).floor() as i64,
+        )
+    };
+
+    let mut ground_cells: HashMap<(i64, i64), Vec<f64>> = HashMap::new();
+    for p in points
+        .iter()
+        .filter(|p| p.classification == Classification::Ground)
+    {
+        ground_cells.entry(cell(p)).or_default().push(p.z);
+    }
+    let medians = ground_cells
+        .into_iter()
+        .map(|(c, mut zs)| {
+            zs.sort_unstable_by(f64::total_cmp);
+            (c, zs[zs.len() / 2])
+        })
+        .collect::<HashMap<_, _>>();
+
+    // the ground range of the surrounding cells allows for steep terrain,
+    // points without ground nearby are kept
+    points
+        .into_iter()
+        .filter(|p| {
+            let (cx, cy) = cell(p);
+            let (low, high) = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (cx + dx, cy + dy)))
+                .filter_map(|c| medians.get(&c))
+                .fold((f64::MAX, f64::MIN), |(low, high), &z| {
+                    (low.min(z), high.max(z))
+                });
+            low > high
+                || (p.z >= low - params.max_below_ground && p.z <= high + params.max_above_ground)
+        })
+        .collect()
+}
+
+// Points whose mean distance to their nearest neighbours is more than the
+// given number of standard deviations above the mean of all points
+fn remove_outliers(points: Vec<las::Point>, params: &NoiseParameters) -> Vec<las::Point> {
+    let neighbors = params.outlier_neighbors.max(1);
+    if points.len() <= neighbors {
+        return points;
+    }
+
+    let tree = RTree::bulk_load(points.iter().map(|p| [p.x, p.y, p.z]).collect());
+    let mean_distances = points
+        .par_iter()
+        .map(|p| {
+            let query = [p.x, p.y, p.z];
+            tree.nearest_neighbor_iter_with_distance_2(query)
+                .skip(1)
+                .take(neighbors)
+                .map(|(_, d2)| d2.sqrt())
+                .sum::<f64>()
+                / neighbors as f64
+        })
+        .collect::<Vec<_>>();
+
+    let mean = mean_distances.iter().sum::<f64>() / mean_distances.len() as f64;
+    let std = (mean_distances
+        .iter()
+        .map(|d| (d - mean).powi(2))
+        .sum::<f64>()
+        / mean_distances.len() as f64)
+        .sqrt();
+    let max_distance = mean + params.outlier_std_ratio * std;
+
+    points
+        .into_iter()
+        .zip(mean_distances)
+        .filter_map(|(p, d)| (d <= max_distance).then_some(p))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        map_gen::common::{filter_isolated, filter_noise},
+        parameters::NoiseParameters,
+    };
+    use las::point::Classification;
+
+    fn point(x: f64, y: f64, z: f64, classification: Classification) -> las::Point {
+        las::Point {
+            x,
+            y,
+            z,
+            classification,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn noise_is_removed_from_a_flat_tile() {
+        let mut points = Vec::new();
+        for yi in 0..40 {
+            for xi in 0..40 {
+                let (x, y) = (xi as f64 * 0.5, yi as f64 * 0.5);
+                points.push(point(x, y, 100., Classification::Ground));
+            }
+        }
+        points.push(point(5., 5., 300., Classification::HighNoise));
+        // a multipath return below the ground, alone
+        points.push(point(12.2, 12.2, 80., Classification::Unclassified));
+        // a flock of birds, too dense to be statistical outliers
+        for yi in 0..3 {
+            for xi in 0..3 {
+                let (x, y) = (10. + xi as f64 * 0.5, 10. + yi as f64 * 0.5);
+                points.push(point(x, y, 200., Classification::Unclassified));
+            }
+        }
+
+        let params = NoiseParameters::default();
+        let (filtered, mut removed) = filter_noise(points, &params);
+        assert_eq!(removed.classified, 1);
+        assert_eq!(removed.outliers, 1);
+        assert!(filtered.iter().all(|p| p.z != 80.));
+
+        let filtered = filter_isolated(filtered, &params, &mut removed);
+        assert_eq!(removed.isolated, 9);
+
+        // every point of the ground grid survives
+        assert_eq!(filtered.len(), 1600);
+        assert!(
+            filtered
+                .iter()
+                .all(|p| p.z == 100. && p.classification == Classification::Ground)
+        );
+        for yi in 0..40 {
+            for xi in 0..40 {
+                let (x, y) = (xi as f64 * 0.5, yi as f64 * 0.5);
+                assert!(filtered.iter().any(|p| p.x == x && p.y == y));
+            }
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use geo::{Contains, Intersects};
+use rstar::{RTree, primitives::GeomWithData};
+
+use crate::{
+    geometry::{ContourSet, MapLineString},
+    parameters::Scale,
+};
+
+// in paper millimetres, centre to centre, the contour width and the smallest readable gap
+const MIN_SEPARATION_MM: f64 = 0.3;
+const MAX_DISPLACEMENT_MM: f64 = 0.4;
+// the share of the way towards the midpoint of its neighbours a vertex moves each iteration
+const SMOOTHING_RATE: f64 = 0.5;
+// vertices in important terrain are smoothed this much less, which keeps small features
+const IMPORTANT_SMOOTHING_FACTOR: f64 = 0.2;
+// the pull back towards the extracted contour
+const ATTACHMENT_RATE: f64 = 0.1;
+// a line is no longer smoothed once an iteration lowers its bending force by less than this
+const MIN_ENERGY_DECREASE: f64 = 0.01;
+
+type SegmentTree = RTree<GeomWithData<rstar::primitives::Line<[f64; 2]>, usize>>;
+
+// a contour being generalised, closed lines keep their last vertex out until written back
+struct Snake {
+    level: usize,
+    index: usize,
+    original: Vec<geo::Coord>,
+    coords: Vec<geo::Coord>,
+    important: Vec<bool>,
+    pinned: Vec<bool>,
+    is_closed: bool,
+    energy: f64,
+    smoothing: bool,
+}
+
+/// Smooths the contour lines themselves as snakes, minimising their bending force while
+/// they are held close to the extracted contours.
+///
+/// Every iteration moves each vertex towards the midpoint of its neighbours, less so in the
+/// important terrain flagged by the form line pruner, and pushes it away from other contours
+/// closer than the minimum separation on paper. No vertex moves further than a fraction of a
+/// paper millimetre. The vertices outside `cut_overlay`, in the overlap with the neighbouring
+/// tiles, and the vertices next to them are never moved, so the segments crossing a tile seam
+/// are the extracted ones in both tiles and the clipped lines still meet there.
+/// A line stops being smoothed once its bending force no longer decreases
+pub fn generalise_contours(
+    contour_set: &mut ContourSet,
+    important_terrain: &geo::MultiPolygon,
+    cut_overlay: &geo::Polygon,
+    scale: Scale,
+    iterations: u8,
+) {
+    let meters_per_mm = 1. / scale.meters_to_paper_mm(1.);
+    let min_separation = MIN_SEPARATION_MM * meters_per_mm;
+    let max_displacement = MAX_DISPLACEMENT_MM * meters_per_mm;
+
+    let mut snakes = Vec::new();
+    for (level, contour_level) in contour_set.0.iter().enumerate() {
+        for (index, line) in contour_level.lines.iter().enumerate() {
+            let is_closed = line.is_closed();
+            let mut coords = line.0.clone();
+            if is_closed {
+                coords.pop();
+            }
+            if coords.len() < 3 {
+                continue;
+            }
+
+            let n = coords.len();
+            let outside = coords
+                .iter()
+                .map(|c| !cut_overlay.contains(c))
+                .collect::<Vec<_>>();
+            let pinned = (0..n)
+                .map(|i| {
+                    (!is_closed && (i == 0 || i == n - 1))
+                        || outside[(i + n - 1) % n]
+                        || outside[i]
+                        || outside[(i + 1) % n]
+                })
+                .collect();
+
+            snakes.push(Snake {
+                level,
+                index,
+                important: coords
+                    .iter()
+                    .map(|c| important_terrain.intersects(&geo::Point(*c)))
+                    .collect(),
+                pinned,
+                original: coords.clone(),
+                coords,
+                is_closed,
+                energy: line.adjusted_bending_force(1),
+                smoothing: true,
+            });
+        }
+    }
+
+    for _ in 0..iterations {
+        let tree = segment_tree(&snakes);
+
+        let moved = snakes
+            .iter()
+            .enumerate()
+            .map(|(id, snake)| step(id, snake, &tree, min_separation, max_displacement))
+            .collect::<Vec<_>>();
+
+        for (snake, coords) in snakes.iter_mut().zip(moved) {
+            snake.coords = coords;
+            if snake.smoothing {
+                let energy = to_line_string(snake).adjusted_bending_force(1);
+                snake.smoothing = energy < snake.energy * (1. - MIN_ENERGY_DECREASE);
+                snake.energy = energy;
+            }
+        }
+    }
+
+    for snake in snakes {
+        contour_set.0[snake.level].lines.0[snake.index] = to_line_string(&snake);
+    }
+}
+
+// the new vertices of a snake after one iteration
+fn step(
+    id: usize,
+    snake: &Snake,
+    tree: &SegmentTree,
+    min_separation: f64,
+    max_displacement: f64,
+) -> Vec<geo::Coord> {
+    let n = snake.coords.len();
+    let mut moved = snake.coords.clone();
+
+    for (i, new) in moved.iter_mut().enumerate() {
+        if snake.pinned[i] {
+            continue;
+        }
+        let p = snake.coords[i];
+
+        if snake.smoothing {
+            let prev = snake.coords[(i + n - 1) % n];
+            let next = snake.coords[(i + 1) % n];
+            let rate = if snake.important[i] {
+                SMOOTHING_RATE * IMPORTANT_SMOOTHING_FACTOR
+            } else {
+                SMOOTHING_RATE
+            };
+            *new =
+                *new + ((prev + next) / 2. - p) * rate + (snake.original[i] - p) * ATTACHMENT_RATE;
+        }
+
+        // the closest point of every other line nearby
+        let mut nearest: Vec<(usize, geo::Coord, f64)> = Vec::new();
+        for segment in tree.locate_within_distance([p.x, p.y], min_separation.powi(2)) {
+            if segment.data == id {
+                continue;
+            }
+            let q = closest_point(segment.geom(), p);
+            let distance = (p - q).x.hypot((p - q).y);
+            match nearest
+                .iter_mut()
+                .find(|(other, ..)| *other == segment.data)
+            {
+                Some(closest) if distance < closest.2 => *closest = (segment.data, q, distance),
+                Some(_) => (),
+                None => nearest.push((segment.data, q, distance)),
+            }
+        }
+
+        // both lines move half the way apart
+        for (_, q, distance) in nearest {
+            if distance > f64::EPSILON && distance < min_separation {
+                *new = *new + (p - q) / distance * (min_separation - distance) / 2.;
+            }
+        }
+
+        let offset = *new - snake.original[i];
+        let displacement = offset.x.hypot(offset.y);
+        if displacement > max_displacement {
+            *new = snake.original[i] + offset / displacement * max_displacement;
+        }
+    }
+    moved
+}
+
+fn segment_tree(snakes: &[Snake]) -> SegmentTree {
+    let segments = snakes
+        .iter()
+        .enumerate()
+        .flat_map(|(id, snake)| {
+            let n = snake.coords.len();
+            let segments = if snake.is_closed { n } else { n - 1 };
+            (0..segments).map(move |i| {
+                let (a, b) = (snake.coords[i], snake.coords[(i + 1) % n]);
+                GeomWithData::new(rstar::primitives::Line::new([a.x, a.y], [b.x, b.y]), id)
+            })
+        })
+        .collect();
+    RTree::bulk_load(segments)
+}
+
+fn closest_point(segment: &rstar::primitives::Line<[f64; 2]>, p: geo::Coord) -> geo::Coord {
+    let a = geo::Coord::from(segment.from);
+    let delta = geo::Coord::from(segment.to) - a;
+    let length_squared = delta.x.powi(2) + delta.y.powi(2);
+    if length_squared == 0. {
+        return a;
+    }
+    let t = (((p - a).x * delta.x + (p - a).y * delta.y) / length_squared).clamp(0., 1.);
+    a + delta * t
+}
+
+fn to_line_string(snake: &Snake) -> geo::LineString {
+    let mut coords = snake.coords.clone();
+    if snake.is_closed {
+        coords.push(coords[0]);
+    }
+    geo::LineString::new(coords)
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::BooleanOps;
+
+    use super::generalise_contours;
+    use crate::{
+        geometry::{ContourLevel, ContourSet},
+        map_gen::egui_map::{LineSymbol, MapObject, Symbol, TempMap},
+        parameters::{MapParameters, Scale},
+    };
+
+    fn cut_overlay(min_x: f64, max_x: f64) -> geo::Polygon {
+        geo::Rect::new((min_x, -50.), (max_x, 50.)).to_polygon()
+    }
+
+    fn straight_contour(y: f64) -> geo::MultiLineString {
+        geo::MultiLineString::new(vec![geo::LineString::from(
+            (0..=10).map(|i| (i as f64 * 10., y)).collect::<Vec<_>>(),
+        )])
+    }
+
+    #[test]
+    fn crowded_contours_are_moved_apart_within_the_displacement_limit() {
+        // at 1:15 000 the minimum separation is 4.5 m and the displacement limit 6 m
+        let mut contours = ContourSet(vec![
+            ContourLevel::new(straight_contour(0.), 100.),
+            ContourLevel::new(straight_contour(2.), 105.),
+        ]);
+
+        generalise_contours(
+            &mut contours,
+            &geo::MultiPolygon::new(vec![]),
+            &cut_overlay(0., 100.),
+            Scale::S15_000,
+            10,
+        );
+
+        let lower = &contours.0[0].lines.0[0];
+        let higher = &contours.0[1].lines.0[0];
+        assert!(higher.0[5].y - lower.0[5].y >= 4.);
+        assert!(lower.0.iter().all(|c| c.y.abs() <= 6.));
+        // the ends stay on the tile seams
+        assert_eq!(lower.0[0], geo::Coord { x: 0., y: 0. });
+        assert_eq!(higher.0[10], geo::Coord { x: 100., y: 2. });
+    }
+
+    #[test]
+    fn contours_crossing_a_tile_seam_are_merged() {
+        // the tiles are cut at x = 100 and overlap by 20 m, only the right one has a contour
+        // crowding the line at the seam
+        let line = |from: i32, to: i32| {
+            geo::LineString::from(
+                (from / 2..=to / 2)
+                    .map(|i| (i as f64 * 2., 0.))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let mut left = ContourSet(vec![ContourLevel::new(
+            geo::MultiLineString::new(vec![line(-20, 120)]),
+            100.,
+        )]);
+        let mut right = ContourSet(vec![
+            ContourLevel::new(geo::MultiLineString::new(vec![line(80, 220)]), 100.),
+            ContourLevel::new(
+                geo::MultiLineString::new(vec![geo::LineString::from(vec![
+                    (85., 1.),
+                    (100., 1.),
+                    (115., 1.),
+                ])]),
+                105.,
+            ),
+        ]);
+
+        let mut map = TempMap::new(geo::Coord::zero(), Scale::S15_000, None);
+        for (contours, cut_overlay) in [
+            (&mut left, cut_overlay(0., 100.)),
+            (&mut right, cut_overlay(100., 200.)),
+        ] {
+            generalise_contours(
+                contours,
+                &geo::MultiPolygon::new(vec![]),
+                &cut_overlay,
+                Scale::S15_000,
+                10,
+            );
+            for line in cut_overlay.clip(&contours.0[0].lines, false) {
+                let mut contour = MapObject::Line {
+                    object: line,
+                    symbol: LineSymbol::Contour,
+                    tags: Default::default(),
+                };
+                contour.add_elevation_tag(100.);
+                map.add_object(contour);
+            }
+        }
+        // the crowded line was moved away from the seam in the right tile
+        assert!(right.0[0].lines.0[0].0.iter().any(|c| c.y < -1.));
+
+        map.merge_lines(5. * crate::SIMPLIFICATION_DIST);
+
+        let contours = &map.objects[&Symbol::Line(LineSymbol::Contour)];
+        assert_eq!(contours.len(), 1);
+    }
+
+    #[test]
+    fn the_default_parameters_move_the_contours() {
+        let params = MapParameters::default();
+        let mut contours = ContourSet(vec![
+            ContourLevel::new(straight_contour(0.), 100.),
+            ContourLevel::new(straight_contour(2.), 105.),
+        ]);
+
+        generalise_contours(
+            &mut contours,
+            &geo::MultiPolygon::new(vec![]),
+            &cut_overlay(0., 100.),
+            params.scale,
+            params.contour.generalisation_steps,
+        );
+
+        assert_ne!(contours.0[0].lines.0[0], straight_contour(0.).0[0]);
+        assert_ne!(contours.0[1].lines.0[0], straight_contour(2.).0[0]);
+    }
+}
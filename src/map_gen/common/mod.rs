@@ -1,17 +1,33 @@
+mod classify_ground;
 mod compute_basemap;
+mod compute_boulders;
+mod compute_buildings;
 mod compute_cliffs;
 mod compute_contours;
 mod compute_dfm;
 mod compute_intensity;
+mod compute_marsh;
+mod compute_roads;
+mod compute_streams;
 mod compute_vegetation;
 mod compute_water;
+mod filter_noise;
+mod generalise_contours;
 mod retile_laz;
 
+pub use classify_ground::{classify_ground, prepare_ground};
 pub use compute_basemap::compute_basemap;
+pub use compute_boulders::{compute_boulder_height, compute_boulders};
+pub use compute_buildings::{compute_building_probability, compute_buildings};
 pub use compute_cliffs::compute_cliffs;
 pub use compute_contours::*;
 pub use compute_dfm::{ComputedDfms, compute_dfms, compute_ndvd};
 pub use compute_intensity::compute_intensity;
+pub use compute_marsh::compute_marsh_probability;
+pub use compute_roads::{compute_road_probability, compute_roads};
+pub use compute_streams::compute_streams;
 pub use compute_vegetation::compute_vegetation;
 pub use compute_water::compute_water_probability;
+pub use filter_noise::{RemovedNoise, filter_isolated, filter_noise};
+pub use generalise_contours::generalise_contours;
 pub use retile_laz::retile_bounds;
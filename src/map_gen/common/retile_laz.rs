@@ -1,29 +1,29 @@
 use crate::{
-    MIN_NEIGHBOR_MARGIN_METERS, TILE_SIZE_METERS, TILE_SIZE_METERS_USIZE, neighbors::Neighborhood,
+    MIN_NEIGHBOR_MARGIN_METERS, TILE_SIZE_METERS, TILE_SIZE_METERS_USIZE, lidar_index::CoveredSides,
 };
 
 pub fn retile_bounds(
     bounds: &geo::Rect,
-    lidar_neighbors: &Neighborhood,
+    covered_sides: CoveredSides,
 ) -> (Vec<geo::Rect>, Vec<geo::Rect>, usize, usize) {
     let mut neighbor_file_margin = [(0., 0.), (0., 0.)];
     let mut cut_margin = [(0., 0.), (0., 0.)];
-    if lidar_neighbors.has_neighbor_above() {
+    if covered_sides.top {
         neighbor_file_margin[1].1 = MIN_NEIGHBOR_MARGIN_METERS;
     } else {
         cut_margin[1].1 = 2. * crate::CELL_SIZE_METERS;
     }
-    if lidar_neighbors.has_neighbor_below() {
+    if covered_sides.bottom {
         neighbor_file_margin[0].1 = -MIN_NEIGHBOR_MARGIN_METERS;
     } else {
         cut_margin[0].1 = -2. * crate::CELL_SIZE_METERS;
     }
-    if lidar_neighbors.has_neighbor_right() {
+    if covered_sides.right {
         neighbor_file_margin[1].0 = MIN_NEIGHBOR_MARGIN_METERS;
     } else {
         cut_margin[1].0 = 2. * crate::CELL_SIZE_METERS;
     }
-    if lidar_neighbors.has_neighbor_left() {
+    if covered_sides.left {
         neighbor_file_margin[0].0 = -MIN_NEIGHBOR_MARGIN_METERS;
     } else {
         cut_margin[0].0 = -2. * crate::CELL_SIZE_METERS;
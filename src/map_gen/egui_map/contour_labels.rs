@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use rstar::{RTree, primitives::GeomWithData};
+
+use super::{
+    LineSymbol, MapObject, PointSymbol, Symbol, TempMap,
+    temp_map::{line_string_length, normalize_full_angle, point_along_line},
+};
+
+// all distances in paper millimetres
+const LABEL_SPACING_MM: f64 = 100.;
+// the space a contour value takes along the line
+const LABEL_LENGTH_MM: f64 = 3.;
+// other contours closer than this to a label would run through the figures
+const LABEL_CLEARANCE_MM: f64 = 1.;
+const MIN_LABEL_DISTANCE_MM: f64 = 40.;
+// how far a label may be shifted along the line to find free space
+const CANDIDATE_STEP_MM: f64 = 2.5;
+const MAX_CANDIDATE_SHIFT_MM: f64 = 30.;
+// the straight distance over the length of a label compared to the length along the line
+const MIN_STRAIGHTNESS: f64 = 0.9;
+
+const CONTOUR_SYMBOLS: [LineSymbol; 3] = [
+    LineSymbol::Contour,
+    LineSymbol::IndexContour,
+    LineSymbol::FormLine,
+];
+
+impl TempMap {
+    /// Places contour values along the index contours at regular spacing.
+    ///
+    /// A value is rotated to follow the contour with the top of the figures on the higher
+    /// side, which is the left side of the contour. Values are shifted along the line to
+    /// a straight part with room between the neighbouring contours, and left out where
+    /// there is none or another value is close by. The text is the elevation tag of the contour
+    pub fn add_index_contour_labels(&mut self) {
+        let meters_per_mm = self.scale.denominator() / 1000.;
+        let spacing = LABEL_SPACING_MM * meters_per_mm;
+        let label_length = LABEL_LENGTH_MM * meters_per_mm;
+        let clearance = LABEL_CLEARANCE_MM * meters_per_mm;
+        let min_label_distance = MIN_LABEL_DISTANCE_MM * meters_per_mm;
+        let step = CANDIDATE_STEP_MM * meters_per_mm;
+        let max_shift = MAX_CANDIDATE_SHIFT_MM * meters_per_mm;
+
+        let segments = self.contour_segments();
+        let mut labels: RTree<[f64; 2]> = RTree::new();
+        let mut label_objects = Vec::new();
+
+        let Some(index_contours) = self.objects.get(&Symbol::Line(LineSymbol::IndexContour)) else {
+            return;
+        };
+        for (index, object) in index_contours.iter().enumerate() {
+            let MapObject::Line { object, tags, .. } = object else {
+                continue;
+            };
+            let Some(elevation) = tags.get("Elevation").and_then(|e| e.parse::<f64>().ok()) else {
+                continue;
+            };
+
+            let length = line_string_length(object);
+            if length < label_length {
+                continue;
+            }
+
+            let is_closed = object.is_closed();
+            // the first value half a spacing in, so short lines get one in their middle
+            let count = ((length - spacing / 2.) / spacing).floor().max(0.) as usize + 1;
+            let first = (length - (count - 1) as f64 * spacing) / 2.;
+
+            for target in (0..count).map(|i| first + i as f64 * spacing) {
+                let candidates = std::iter::once(0.).chain(
+                    (1..=(max_shift / step) as usize)
+                        .flat_map(|i| [i as f64 * step, -(i as f64) * step]),
+                );
+
+                for shift in candidates {
+                    let mut distance = target + shift;
+                    if is_closed {
+                        distance = distance.rem_euclid(length);
+                    } else if distance < label_length / 2. || distance > length - label_length / 2.
+                    {
+                        continue;
+                    }
+
+                    let Some((position, direction)) =
+                        label_placement(object, distance, label_length, is_closed, length)
+                    else {
+                        continue;
+                    };
+                    let position_array = [position.x, position.y];
+
+                    let crowded = segments
+                        .locate_within_distance(position_array, clearance.powi(2))
+                        .any(|segment| segment.data != index);
+                    if crowded {
+                        continue;
+                    }
+                    if labels
+                        .locate_within_distance(position_array, min_label_distance.powi(2))
+                        .next()
+                        .is_some()
+                    {
+                        continue;
+                    }
+
+                    labels.insert(position_array);
+                    let mut label = MapObject::Point {
+                        object: geo::Point(position),
+                        symbol: PointSymbol::ContourValue,
+                        rotation: normalize_full_angle(direction.y.atan2(direction.x)),
+                        tags: HashMap::new(),
+                    };
+                    label.add_elevation_tag(elevation);
+                    label_objects.push(label);
+                    break;
+                }
+            }
+        }
+
+        for label in label_objects {
+            self.add_object(label);
+        }
+    }
+
+    // the segments of every contour and form line, index contours are marked by their index
+    fn contour_segments(&self) -> RTree<GeomWithData<rstar::primitives::Line<[f64; 2]>, usize>> {
+        let mut segments = Vec::new();
+        for symbol in CONTOUR_SYMBOLS {
+            let Some(objects) = self.objects.get(&Symbol::Line(symbol)) else {
+                continue;
+            };
+
+            for (index, object) in objects.iter().enumerate() {
+                let MapObject::Line { object, .. } = object else {
+                    continue;
+                };
+                let data = if symbol == LineSymbol::IndexContour {
+                    index
+                } else {
+                    usize::MAX
+                };
+                segments.extend(object.lines().map(|segment| {
+                    GeomWithData::new(
+                        rstar::primitives::Line::new(
+                            [segment.start.x, segment.start.y],
+                            [segment.end.x, segment.end.y],
+                        ),
+                        data,
+                    )
+                }));
+            }
+        }
+        RTree::bulk_load(segments)
+    }
+}
+
+// the middle of a label centred at the given distance along the line and the direction of
+// its baseline, None if the line bends too much under the label
+fn label_placement(
+    line: &geo::LineString,
+    distance: f64,
+    label_length: f64,
+    is_closed: bool,
+    length: f64,
+) -> Option<(geo::Coord, geo::Coord)> {
+    let along = |d: f64| {
+        let d = if is_closed { d.rem_euclid(length) } else { d };
+        point_along_line(line, d).0
+    };
+
+    let start = along(distance - label_length / 2.);
+    let end = along(distance + label_length / 2.);
+    let chord = end - start;
+    let chord_length = chord.x.hypot(chord.y);
+    if chord_length < MIN_STRAIGHTNESS * label_length {
+        return None;
+    }
+
+    Some((point_along_line(line, distance).0, chord / chord_length))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map_gen::egui_map::test_utils::contour;
+    use crate::map_gen::egui_map::{LineSymbol, MapObject, PointSymbol, Symbol, TempMap};
+    use crate::parameters::Scale;
+
+    #[test]
+    fn index_contour_values_read_uphill_and_avoid_crowded_spots() {
+        // 1 mm on paper is 10 m
+        let mut map = TempMap::new(geo::Coord::zero(), Scale::S10_000, None);
+        // runs west to east with higher ground to the north
+        map.add_object(contour(
+            vec![(0., 0.), (1000., 0.)],
+            LineSymbol::IndexContour,
+            125.,
+        ));
+        // a contour squeezing in close to the middle of the index contour
+        map.add_object(contour(
+            vec![(400., 5.), (600., 5.)],
+            LineSymbol::Contour,
+            130.,
+        ));
+
+        map.add_index_contour_labels();
+
+        let labels = &map.objects[&Symbol::Point(PointSymbol::ContourValue)];
+        assert_eq!(labels.len(), 1);
+        let MapObject::Point {
+            object,
+            rotation,
+            tags,
+            ..
+        } = &labels[0]
+        else {
+            panic!("contour values are point objects");
+        };
+        assert!(object.x() < 400. - 10. || object.x() > 600. + 10.);
+        assert_eq!(object.y(), 0.);
+        assert_eq!(*rotation, 0.);
+        assert_eq!(tags["Elevation"], "125");
+    }
+}
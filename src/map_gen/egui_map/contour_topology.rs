@@ -0,0 +1,536 @@
+use std::{collections::HashSet, fmt::Display};
+
+use geo::algorithm::line_intersection::{LineIntersection, line_intersection};
+use linestring2bezier::BezierString;
+use rstar::{AABB, RTree, RTreeObject, primitives::GeomWithData};
+
+use super::{LineSymbol, MapObject, Symbol, TempMap};
+
+// contours closer than this are moved apart, in paper millimetres
+const MIN_GAP_MM: f64 = 0.1;
+// contours closer than this print as one line and are reported as touching
+const TOUCHING_MM: f64 = 0.05;
+// the bezier curves are checked as polylines this close to the curves
+const BEZIER_SAMPLE_ERROR_MM: f64 = 0.01;
+// vertices are not moved further than this to repair a crossing
+const MAX_DISPLACEMENT_MM: f64 = 0.5;
+// self-intersection loops up to this length are cut off
+const MAX_CUT_LOOP_MM: f64 = 2.;
+const MAX_REPAIR_PASSES: usize = 5;
+
+const CONTOUR_SYMBOLS: [LineSymbol; 3] = [
+    LineSymbol::Contour,
+    LineSymbol::IndexContour,
+    LineSymbol::FormLine,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContourConflictKind {
+    Crossing { elevations: (f64, f64) },
+    Touching { elevations: (f64, f64) },
+    SelfIntersection { elevation: f64 },
+}
+
+/// A place where contours of different elevations cross or touch, or a contour crosses itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContourConflict {
+    pub location: geo::Coord,
+    pub kind: ContourConflictKind,
+}
+
+impl Display for ContourConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ContourConflictKind::Crossing { elevations } => write!(
+                f,
+                "Contours at {} m and {} m cross",
+                elevations.0, elevations.1
+            ),
+            ContourConflictKind::Touching { elevations } => write!(
+                f,
+                "Contours at {} m and {} m touch",
+                elevations.0, elevations.1
+            ),
+            ContourConflictKind::SelfIntersection { elevation } => {
+                write!(f, "Contour at {elevation} m crosses itself")
+            }
+        }
+    }
+}
+
+// a copy of a contour, with where to write it back to
+struct ContourLine {
+    symbol: LineSymbol,
+    index: usize,
+    line: geo::LineString,
+    elevation: f64,
+}
+
+// a found conflict with the segments involved, `b` is the segment of the other line
+struct Intersection {
+    location: geo::Coord,
+    a: (usize, usize),
+    b: (usize, usize),
+    crossing: bool,
+}
+
+impl TempMap {
+    /// The remaining conflicts between the contours and form lines.
+    ///
+    /// Only lines with an elevation tag are checked, and lines of the same elevation
+    /// may touch as they are the same contour split at a tile seam
+    pub fn contour_conflicts(&self) -> Vec<ContourConflict> {
+        let lines = self.contour_lines();
+        to_conflicts(
+            &lines,
+            &find_intersections(&lines, self.touching_distance()),
+        )
+    }
+
+    /// The conflicts between the contours as they are written, fitted by bezier curves
+    /// with the given error in meters. A curve may cross where its polyline does not
+    pub fn written_contour_conflicts(&self, bezier_error: Option<f64>) -> Vec<ContourConflict> {
+        let Some(bezier_error) = bezier_error else {
+            return self.contour_conflicts();
+        };
+        let sample_error = self.scale.paper_mm_to_meters(BEZIER_SAMPLE_ERROR_MM);
+
+        let mut lines = self.contour_lines();
+        for line in lines.iter_mut() {
+            // the writers keep the polyline where the fit fails
+            if let Ok(sampled) = BezierString::from_line_string(line.line.clone(), bezier_error)
+                .and_then(|bezier| bezier.to_line_string(sample_error))
+            {
+                line.line = sampled;
+            }
+        }
+        to_conflicts(
+            &lines,
+            &find_intersections(&lines, self.touching_distance()),
+        )
+    }
+
+    /// Moves contours apart where they cross or touch and cuts off small self-intersection
+    /// loops. Every repair is local and limited to a fraction of a paper millimetre,
+    /// the conflicts that remain are found by [`TempMap::written_contour_conflicts`]
+    pub fn repair_contour_topology(&mut self) {
+        let meters_per_mm = self.scale.denominator() / 1000.;
+        let touching = self.touching_distance();
+        let gap = MIN_GAP_MM * meters_per_mm;
+        let max_displacement = MAX_DISPLACEMENT_MM * meters_per_mm;
+        let max_loop_length = MAX_CUT_LOOP_MM * meters_per_mm;
+
+        let mut lines = self.contour_lines();
+        for _ in 0..MAX_REPAIR_PASSES {
+            let intersections = find_intersections(&lines, touching);
+            if intersections.is_empty() {
+                break;
+            }
+
+            // the indices of a line are stale once it is repaired, so each line
+            // is only repaired once per pass
+            let mut repaired = HashSet::new();
+            for intersection in &intersections {
+                let (a, b) = (intersection.a.0, intersection.b.0);
+                if repaired.contains(&a) || repaired.contains(&b) {
+                    continue;
+                }
+
+                if a == b {
+                    if cut_loop(
+                        &mut lines[a].line,
+                        intersection.a.1,
+                        intersection.b.1,
+                        intersection.location,
+                        max_loop_length,
+                    ) {
+                        repaired.insert(a);
+                    }
+                    continue;
+                }
+
+                // form lines give way to contours, otherwise the shorter line is moved
+                let moves_a = match (
+                    lines[a].symbol == LineSymbol::FormLine,
+                    lines[b].symbol == LineSymbol::FormLine,
+                ) {
+                    (true, false) => true,
+                    (false, true) => false,
+                    _ => lines[a].line.0.len() <= lines[b].line.0.len(),
+                };
+                let (moved, fixed) = if moves_a {
+                    (intersection.a, intersection.b)
+                } else {
+                    (intersection.b, intersection.a)
+                };
+
+                let fixed_line = &lines[fixed.0].line.0;
+                let fixed_segment = (fixed_line[fixed.1], fixed_line[fixed.1 + 1]);
+                let higher = lines[moved.0].elevation > lines[fixed.0].elevation;
+                if displace(
+                    &mut lines[moved.0].line,
+                    moved.1,
+                    fixed_segment,
+                    higher,
+                    intersection.location,
+                    gap,
+                    max_displacement,
+                ) {
+                    repaired.insert(moved.0);
+                }
+            }
+
+            if repaired.is_empty() {
+                break;
+            }
+        }
+
+        for line in lines {
+            if let Some(MapObject::Line { object, .. }) = self
+                .objects
+                .get_mut(&Symbol::Line(line.symbol))
+                .and_then(|objects| objects.get_mut(line.index))
+            {
+                *object = line.line;
+            }
+        }
+    }
+
+    fn touching_distance(&self) -> f64 {
+        self.scale.paper_mm_to_meters(TOUCHING_MM)
+    }
+
+    fn contour_lines(&self) -> Vec<ContourLine> {
+        let mut lines = Vec::new();
+        for symbol in CONTOUR_SYMBOLS {
+            let Some(objects) = self.objects.get(&Symbol::Line(symbol)) else {
+                continue;
+            };
+
+            for (index, object) in objects.iter().enumerate() {
+                let MapObject::Line { object, tags, .. } = object else {
+                    continue;
+                };
+                let Some(elevation) = tags.get("Elevation").and_then(|e| e.parse().ok()) else {
+                    continue;
+                };
+
+                if object.0.len() >= 2 {
+                    lines.push(ContourLine {
+                        symbol,
+                        index,
+                        line: object.clone(),
+                        elevation,
+                    });
+                }
+            }
+        }
+        lines
+    }
+}
+
+// crossings, and places where lines of different elevations come closer than `touching`
+fn find_intersections(lines: &[ContourLine], touching: f64) -> Vec<Intersection> {
+    let segments = lines
+        .iter()
+        .enumerate()
+        .flat_map(|(li, line)| {
+            line.line.lines().enumerate().map(move |(si, segment)| {
+                GeomWithData::new(
+                    rstar::primitives::Line::new(
+                        [segment.start.x, segment.start.y],
+                        [segment.end.x, segment.end.y],
+                    ),
+                    (li, si),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let tree = RTree::bulk_load(segments);
+
+    let mut intersections = Vec::new();
+    for segment in tree.iter() {
+        let a = segment.data;
+        let envelope = segment.envelope();
+        let (lower, upper) = (envelope.lower(), envelope.upper());
+        let search = AABB::from_corners(
+            [lower[0] - touching, lower[1] - touching],
+            [upper[0] + touching, upper[1] + touching],
+        );
+        for other in tree.locate_in_envelope_intersecting(&search) {
+            let b = other.data;
+            // every pair once
+            if b <= a {
+                continue;
+            }
+
+            let (line_a, line_b) = (&lines[a.0], &lines[b.0]);
+            if a.0 == b.0 {
+                let last = line_a.line.0.len() - 2;
+                let neighbours =
+                    b.1 - a.1 == 1 || (line_a.line.is_closed() && a.1 == 0 && b.1 == last);
+                if neighbours {
+                    continue;
+                }
+            } else if line_a.elevation == line_b.elevation {
+                continue;
+            }
+
+            let segment_a = geo::Line::new(line_a.line.0[a.1], line_a.line.0[a.1 + 1]);
+            let segment_b = geo::Line::new(line_b.line.0[b.1], line_b.line.0[b.1 + 1]);
+            let (location, crossing) = match line_intersection(segment_a, segment_b) {
+                Some(LineIntersection::SinglePoint { intersection, .. }) => (intersection, true),
+                Some(LineIntersection::Collinear { intersection }) => (intersection.start, true),
+                // the neighbouring segments of a line are always close
+                None if a.0 == b.0 => continue,
+                None => {
+                    let (p, q) = closest_points(segment_a, segment_b);
+                    if length(q - p) >= touching {
+                        continue;
+                    }
+                    ((p + q) / 2., false)
+                }
+            };
+            intersections.push(Intersection {
+                location,
+                a,
+                b,
+                crossing,
+            });
+        }
+    }
+    intersections
+}
+
+fn to_conflicts(lines: &[ContourLine], intersections: &[Intersection]) -> Vec<ContourConflict> {
+    intersections
+        .iter()
+        .map(|intersection| {
+            let (a, b) = (&lines[intersection.a.0], &lines[intersection.b.0]);
+            let elevations = (a.elevation.min(b.elevation), a.elevation.max(b.elevation));
+            let kind = if intersection.a.0 == intersection.b.0 {
+                ContourConflictKind::SelfIntersection {
+                    elevation: a.elevation,
+                }
+            } else if intersection.crossing {
+                ContourConflictKind::Crossing { elevations }
+            } else {
+                ContourConflictKind::Touching { elevations }
+            };
+            ContourConflict {
+                location: intersection.location,
+                kind,
+            }
+        })
+        .collect()
+}
+
+// pushes the vertices of `line` near the crossing to the side of the fixed segment its elevation
+// belongs on, the higher ground is on the left of a contour
+fn displace(
+    line: &mut geo::LineString,
+    segment: usize,
+    fixed: (geo::Coord, geo::Coord),
+    higher: bool,
+    location: geo::Coord,
+    gap: f64,
+    max_displacement: f64,
+) -> bool {
+    let delta = fixed.1 - fixed.0;
+    if length(delta) == 0. {
+        return false;
+    }
+    let left = geo::Coord {
+        x: -delta.y,
+        y: delta.x,
+    } / length(delta);
+    let normal = if higher { left } else { left * -1. };
+
+    // the last vertex of a closed line follows the first
+    let last = line.0.len() - 1;
+    let is_closed = line.is_closed();
+    let vertices = if is_closed { last } else { last + 1 };
+
+    let mut moves = Vec::new();
+    for (i, c) in line.0.iter().enumerate().take(vertices) {
+        // the crossing segment is always moved, the rest only close to the crossing
+        let in_segment =
+            i == segment || i == segment + 1 || (is_closed && i == 0 && segment + 1 == last);
+        if !in_segment && length(*c - location) > max_displacement {
+            continue;
+        }
+
+        let offset = (*c - fixed.0).x * normal.x + (*c - fixed.0).y * normal.y;
+        if offset >= gap {
+            continue;
+        }
+        if gap - offset > max_displacement {
+            return false;
+        }
+        moves.push((i, normal * (gap - offset)));
+    }
+
+    if moves.is_empty() {
+        return false;
+    }
+
+    for (i, shift) in moves {
+        line.0[i] = line.0[i] + shift;
+    }
+    if is_closed {
+        line.0[last] = line.0[0];
+    }
+    true
+}
+
+// replaces the loop between two crossing segments by the crossing point
+fn cut_loop(
+    line: &mut geo::LineString,
+    first: usize,
+    second: usize,
+    location: geo::Coord,
+    max_loop_length: f64,
+) -> bool {
+    let loop_length: f64 = line.0[first + 1..=second]
+        .windows(2)
+        .map(|w| length(w[1] - w[0]))
+        .sum();
+    if loop_length > max_loop_length {
+        return false;
+    }
+
+    line.0.splice(first + 1..=second, [location]);
+    true
+}
+
+// the closest points of two segments that do not cross, one of them is an end point
+fn closest_points(a: geo::Line, b: geo::Line) -> (geo::Coord, geo::Coord) {
+    [
+        (a.start, closest_on_segment(b, a.start)),
+        (a.end, closest_on_segment(b, a.end)),
+        (closest_on_segment(a, b.start), b.start),
+        (closest_on_segment(a, b.end), b.end),
+    ]
+    .into_iter()
+    .fold((a.start, b.start), |closest, pair| {
+        if length(pair.1 - pair.0) < length(closest.1 - closest.0) {
+            pair
+        } else {
+            closest
+        }
+    })
+}
+
+fn closest_on_segment(segment: geo::Line, c: geo::Coord) -> geo::Coord {
+    let delta = segment.delta();
+    let length_squared = delta.x * delta.x + delta.y * delta.y;
+    if length_squared == 0. {
+        return segment.start;
+    }
+    let along = (c - segment.start).x * delta.x + (c - segment.start).y * delta.y;
+    segment.start + delta * (along / length_squared).clamp(0., 1.)
+}
+
+fn length(c: geo::Coord) -> f64 {
+    c.x.hypot(c.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContourConflictKind;
+    use crate::map_gen::egui_map::test_utils::contour;
+    use crate::map_gen::egui_map::{LineSymbol, MapObject, Symbol, TempMap};
+    use crate::parameters::Scale;
+
+    #[test]
+    fn crossing_form_line_is_moved_to_its_side() {
+        let mut map = TempMap::new(geo::Coord::zero(), Scale::S10_000, None);
+        // higher ground to the north of the contour, which runs west to east
+        map.add_object(contour(
+            vec![(0., 0.), (10., 0.), (20., 0.)],
+            LineSymbol::Contour,
+            100.,
+        ));
+        // the form line is higher but dips just below the contour
+        map.add_object(contour(
+            vec![(0., 3.), (9., 3.), (10., -0.5), (11., 3.), (20., 3.)],
+            LineSymbol::FormLine,
+            102.5,
+        ));
+
+        let crossings = map
+            .contour_conflicts()
+            .into_iter()
+            .filter(|c| matches!(c.kind, ContourConflictKind::Crossing { .. }))
+            .count();
+        assert_eq!(crossings, 2);
+
+        map.repair_contour_topology();
+
+        assert!(map.contour_conflicts().is_empty());
+    }
+
+    #[test]
+    fn near_touching_contours_are_moved_apart() {
+        let mut map = TempMap::new(geo::Coord::zero(), Scale::S10_000, None);
+        map.add_object(contour(
+            vec![(0., 0.), (10., 0.), (20., 0.)],
+            LineSymbol::Contour,
+            100.,
+        ));
+        // 0.3 m, 0.03 mm on paper, above the lower contour
+        map.add_object(contour(
+            vec![(0., 5.), (10., 0.3), (20., 5.)],
+            LineSymbol::Contour,
+            105.,
+        ));
+
+        let conflicts = map.contour_conflicts();
+        assert!(!conflicts.is_empty());
+        assert!(
+            conflicts
+                .iter()
+                .all(|c| matches!(c.kind, ContourConflictKind::Touching { .. }))
+        );
+
+        map.repair_contour_topology();
+
+        assert!(map.contour_conflicts().is_empty());
+    }
+
+    #[test]
+    fn small_self_intersection_loop_is_cut_off() {
+        let mut map = TempMap::new(geo::Coord::zero(), Scale::S10_000, None);
+        // the fourth segment crosses back over the second
+        map.add_object(contour(
+            vec![
+                (0., 0.),
+                (10., 0.),
+                (11., 1.),
+                (10., 1.5),
+                (10.5, -1.),
+                (20., -1.),
+            ],
+            LineSymbol::Contour,
+            100.,
+        ));
+
+        let conflicts = map.contour_conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert!(matches!(
+            conflicts[0].kind,
+            ContourConflictKind::SelfIntersection { .. }
+        ));
+
+        map.repair_contour_topology();
+
+        assert!(map.contour_conflicts().is_empty());
+        let MapObject::Line { object, .. } = &map.objects[&Symbol::Line(LineSymbol::Contour)][0]
+        else {
+            panic!("contours are lines");
+        };
+        // the two vertices of the loop are replaced by the crossing
+        assert_eq!(object.0.len(), 5);
+        assert!(!object.0.contains(&geo::coord! { x: 11., y: 1. }));
+    }
+}
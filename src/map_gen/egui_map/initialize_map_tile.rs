@@ -1,4 +1,4 @@
-use copc_rs::{Bounds, BoundsSelection, CopcReader, LodSelection, Vector};
+use copc_rs::{Bounds, Vector};
 use geo::{Area, BooleanOps, ConvexHull, Intersects};
 use las::point::Classification;
 
@@ -10,7 +10,10 @@ use crate::{
     Result,
     comms::{FrontendSender, messages::*},
     geometry::{MapRect, PointCloud, PointLaz},
+    lidar_index::merge_overlapping_points,
+    lidar_reader::LidarReader,
     map_gen::{self, common, pipeline::PreparedTile},
+    parameters::{GroundParameters, NoiseParameters},
     statistics::LidarStats,
 };
 
@@ -25,14 +28,15 @@ pub fn initialize_map_tile(
     paths: Vec<PathBuf>,
     test_area: geo::Rect,
     stats: LidarStats,
+    ground: GroundParameters,
+    noise: NoiseParameters,
 ) -> Result<InitializedMapTile> {
     let _ = sender.send(FrontendTask::Log(
         "Calculating test tile rasters...".to_string(),
     ));
     let _ = sender.send(FrontendTask::ProgressBar(ProgressBar::Start));
 
-    let (tile_bounds, cut_bounds, _nx, _ny) =
-        common::retile_bounds(&test_area, &Default::default());
+    let (tile_bounds, cut_bounds, _nx, _ny) = common::retile_bounds(&test_area, Default::default());
     let inc_size = 1. / tile_bounds.len() as f32;
 
     let ref_point = geo::Coord {
@@ -43,7 +47,7 @@ pub fn initialize_map_tile(
     let mut z_range = (f64::MAX, f64::MIN);
     let mut all_hulls = Vec::with_capacity(4);
     let mut tiles = Vec::with_capacity(4);
-    for (tile_bounds, cut_bounds) in tile_bounds.iter().zip(cut_bounds.iter()) {
+    for (ti, (tile_bounds, cut_bounds)) in tile_bounds.iter().zip(cut_bounds.iter()).enumerate() {
         let cut_bounds = geo::Rect::new(cut_bounds.min() - ref_point, cut_bounds.max() - ref_point);
 
         let mut shifted_bounds = Bounds {
@@ -63,47 +67,59 @@ pub fn initialize_map_tile(
         shifted_bounds.max.y -= ref_point.y;
         shifted_bounds.min.y -= ref_point.y;
 
-        let mut points = Vec::new();
-        let mut all_points = Vec::new();
+        let mut removed_noise = common::RemovedNoise::default();
+        let mut file_points = Vec::new();
         for path in &paths {
-            let mut reader = CopcReader::from_path(path)?;
+            let mut reader = LidarReader::from_path(path)?;
             let header_bounds = reader.header().bounds();
             if !geo::Rect::from_bounds(header_bounds).intersects(tile_bounds) {
                 continue;
             }
 
-            shifted_bounds.min.z = shifted_bounds.min.z.min(header_bounds.min.z);
-            shifted_bounds.max.z = shifted_bounds.max.z.max(header_bounds.max.z);
-
-            let bounds = Bounds {
-                min: Vector {
-                    x: tile_bounds.min().x,
-                    y: tile_bounds.min().y,
-                    z: header_bounds.min.z,
-                },
-                max: Vector {
-                    x: tile_bounds.max().x,
-                    y: tile_bounds.max().y,
-                    z: header_bounds.max.z,
-                },
-            };
-
-            for mut p in reader.points(LodSelection::All, BoundsSelection::Within(bounds))? {
-                if p.is_withheld {
-                    continue;
-                }
-
-                p.x -= ref_point.x;
-                p.y -= ref_point.y;
-                let point = PointLaz(p);
-
-                if point.0.classification == Classification::Ground
-                    || point.0.classification == Classification::Water
-                {
-                    points.push(point.clone());
-                }
-                all_points.push(point);
+            let points = reader
+                .points_within(*tile_bounds)?
+                .into_iter()
+                .filter(|p| !p.is_withheld)
+                .collect::<Vec<_>>();
+            let (mut points, removed) = common::filter_noise(points, &noise);
+            removed_noise += removed;
+            if common::prepare_ground(&mut points, &ground) {
+                let _ = sender.send(FrontendTask::Log(format!(
+                    "Classified ground points in {:?}",
+                    path.file_name().unwrap_or_default()
+                )));
+            }
+            file_points.push((geo::Rect::from_bounds(header_bounds), points));
+        }
+
+        let mut points = Vec::new();
+        let mut all_points = Vec::new();
+        let filtered = common::filter_isolated(
+            merge_overlapping_points(file_points),
+            &noise,
+            &mut removed_noise,
+        );
+        if removed_noise.total() > 0 {
+            let _ = sender.send(FrontendTask::Log(format!(
+                "Test tile {}: {removed_noise}",
+                ti + 1
+            )));
+        }
+        for mut p in filtered {
+            // the header range would include the removed noise
+            shifted_bounds.min.z = shifted_bounds.min.z.min(p.z);
+            shifted_bounds.max.z = shifted_bounds.max.z.max(p.z);
+
+            p.x -= ref_point.x;
+            p.y -= ref_point.y;
+            let point = PointLaz(p);
+
+            if point.0.classification == Classification::Ground
+                || point.0.classification == Classification::Water
+            {
+                points.push(point.clone());
             }
+            all_points.push(point);
         }
 
         if points.is_empty() {
@@ -10,7 +10,7 @@ use std::{collections::HashSet, path::PathBuf};
 use crate::Result;
 use crate::comms::{FrontendSender, messages::*};
 use crate::geometry::MapRect;
-use crate::neighbors::Neighborhood;
+use crate::lidar_index::LidarIndex;
 
 pub fn map_laz(sender: FrontendSender, paths: Vec<PathBuf>, crs_defs: Option<Vec<Option<CrsDef>>>) {
     let (boundaries, boundary_areas, mid_point, components) = match read_boundaries(paths, crs_defs)
@@ -107,35 +107,34 @@ fn read_boundaries(
 }
 
 fn spatial_laz_analysis(paths: &Vec<PathBuf>) -> (Vec<geo::Rect>, Vec<Vec<usize>>) {
-    let mut tile_centers = Vec::with_capacity(paths.len());
     let mut tile_bounds = Vec::with_capacity(paths.len());
 
     for las_path in paths {
         if let Ok(las_reader) = Reader::from_path(las_path) {
             let b = las_reader.header().bounds();
-            tile_centers.push([(b.min.x + b.max.x) / 2., (b.min.y + b.max.y) / 2.]);
             tile_bounds.push(geo::Rect::from_bounds(b));
         }
     }
 
-    if tile_centers.len() == 1 {
+    if tile_bounds.len() == 1 {
         return (tile_bounds, vec![vec![0]]);
     }
 
-    let neighbors = Neighborhood::neighboring_tiles(&tile_centers, &tile_bounds);
-    let components = connected_components(&neighbors);
+    let index = LidarIndex::new(tile_bounds.clone());
+    let components = connected_components(&index);
 
     (tile_bounds, components)
 }
 
-fn connected_components(graph: &Vec<Neighborhood>) -> Vec<Vec<usize>> {
+fn connected_components(index: &LidarIndex) -> Vec<Vec<usize>> {
     let mut cc: Vec<HashSet<usize>> = vec![];
 
-    for node in graph {
+    for node in 0..index.bounds().len() {
+        let neighbors = index.neighbors(node);
         let mut belongs_to = usize::MAX;
 
         for (i, component) in cc.iter().enumerate() {
-            if component.contains(&node.center) {
+            if component.contains(&node) {
                 belongs_to = i;
                 break;
             }
@@ -144,7 +143,7 @@ fn connected_components(graph: &Vec<Neighborhood>) -> Vec<Vec<usize>> {
         if belongs_to != usize::MAX {
             // the main node belongs to a component and so all
             // of its neighbors also belong to that component
-            for ni in node.neighbor_indices() {
+            for ni in neighbors {
                 let _ = cc[belongs_to].insert(ni);
             }
         } else {
@@ -152,7 +151,8 @@ fn connected_components(graph: &Vec<Neighborhood>) -> Vec<Vec<usize>> {
             // create a new component and add it and all of its neighbors to that component
             let mut new_component = HashSet::new();
 
-            for ni in node.all_indices() {
+            let _ = new_component.insert(node);
+            for ni in neighbors {
                 let _ = new_component.insert(ni);
             }
             cc.push(new_component);
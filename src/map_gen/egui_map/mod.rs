@@ -1,9 +1,17 @@
+mod contour_labels;
+mod contour_topology;
 mod initialize_map_tile;
 mod map_laz;
+mod ocd;
 mod regenerate_map_tile;
+mod suggest_contours;
 mod temp_map;
+#[cfg(test)]
+mod test_utils;
 
+pub use self::contour_topology::ContourConflict;
 pub use self::initialize_map_tile::initialize_map_tile;
 pub use self::map_laz::map_laz;
 pub use self::regenerate_map_tile::regenerate_map_tile;
+pub use self::suggest_contours::{ContourSuggestion, suggest_contour_interval};
 pub use self::temp_map::{AreaSymbol, LineSymbol, MapObject, PointSymbol, Symbol, TempMap};
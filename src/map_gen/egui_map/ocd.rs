@@ -0,0 +1,1108 @@
+use std::{collections::HashSet, path::Path};
+
+use anyhow::Context;
+use linestring2bezier::{BezierSegment, BezierString};
+use proj_core::CrsDef;
+
+use super::{AreaSymbol, LineSymbol, MapObject, PointSymbol, Symbol, TempMap};
+use crate::parameters::{Scale, SymbolStandard};
+
+// OCAD 12 file layout, every number is little endian.
+// Coordinates are 1/100 mm on paper with the y axis pointing up,
+// stored in the upper 24 bits of an i32 with flags in the lowest byte
+const OCD_MARK: u16 = 0x0CAD;
+const OCD_VERSION: u16 = 12;
+const FILE_HEADER_SIZE: usize = 60;
+const INDEX_BLOCK_ENTRIES: usize = 256;
+const SYMBOL_INDEX_ENTRY_SIZE: usize = 4;
+const OBJECT_INDEX_ENTRY_SIZE: usize = 40;
+const STRING_INDEX_ENTRY_SIZE: usize = 16;
+
+// the header positions of the first index blocks
+const FIRST_SYMBOL_BLOCK_POS: usize = 8;
+const FIRST_OBJECT_BLOCK_POS: usize = 12;
+const FIRST_STRING_BLOCK_POS: usize = 32;
+
+const POINT_SYMBOL_TYPE: u8 = 1;
+const LINE_SYMBOL_TYPE: u8 = 2;
+const AREA_SYMBOL_TYPE: u8 = 3;
+
+// the point objects keep their own angle instead of pointing north
+const ROTATABLE_SYMBOL: u8 = 1;
+
+// point symbol element types
+const LINE_ELEMENT: i16 = 1;
+const AREA_ELEMENT: i16 = 2;
+const DOT_ELEMENT: i16 = 4;
+
+// x flags mark bezier control points, the y flag marks the first point of a hole
+const FIRST_CONTROL_POINT: i32 = 1;
+const SECOND_CONTROL_POINT: i32 = 2;
+const HOLE_START: i32 = 2;
+
+const COLOR_STRING: i32 = 9;
+const SCALE_PARAMETER_STRING: i32 = 1039;
+const MAP_NOTES_STRING: i32 = 1061;
+
+// the real world distance between grid lines
+const GRID_METERS: f64 = 500.;
+
+// ISOM colours in print order, the first is printed on top. (number, name, CMYK)
+const COLORS: [(u16, &str, [u8; 4]); 14] = [
+    (BLACK, "Black", [0, 0, 0, 100]),
+    (BLUE, "Blue", [100, 0, 0, 0]),
+    (BROWN, "Brown", [0, 56, 100, 18]),
+    (OLIVE, "Olive green", [0, 27, 100, 55]),
+    (BLACK_65, "Black 65%", [0, 0, 0, 65]),
+    (BROWN_50, "Brown 50%", [0, 28, 50, 9]),
+    (BLUE_50, "Blue 50%", [50, 0, 0, 0]),
+    (BLACK_20, "Black 20%", [0, 0, 0, 20]),
+    (GREEN, "Green", [76, 0, 91, 0]),
+    (GREEN_60, "Green 60%", [46, 0, 55, 0]),
+    (GREEN_30, "Green 30%", [23, 0, 27, 0]),
+    (YELLOW, "Yellow", [0, 27, 79, 0]),
+    (YELLOW_50, "Yellow 50%", [0, 14, 40, 0]),
+    (WHITE, "White", [0, 0, 0, 0]),
+];
+
+const BLACK: u16 = 0;
+const BLUE: u16 = 1;
+const BROWN: u16 = 2;
+const OLIVE: u16 = 3;
+const BLACK_65: u16 = 4;
+const BROWN_50: u16 = 5;
+const BLUE_50: u16 = 6;
+const BLACK_20: u16 = 7;
+const GREEN: u16 = 8;
+const GREEN_60: u16 = 9;
+const GREEN_30: u16 = 10;
+const YELLOW: u16 = 11;
+const YELLOW_50: u16 = 12;
+const WHITE: u16 = 13;
+
+impl TempMap {
+    /// Writes the map as an OCAD 12 file.
+    ///
+    /// Every symbol is numbered by its ISOM (or ISSprOM) code, but only gets a simplified
+    /// definition of colour, line width and dashes. Loading the matching symbol set
+    /// in OCAD replaces the definitions and keeps the objects. Contour values are left out.
+    /// The georeferencing uses the same grid rotation as the omap output, the
+    /// grid is only named for UTM zones so the EPSG code is also written to the map notes
+    pub fn write_ocd(
+        self,
+        path: &Path,
+        meters_above_sea: f64,
+        bezier_error: Option<f64>,
+    ) -> crate::Result<()> {
+        let grid_rotation = self.grid_rotation(meters_above_sea)?;
+        let bytes = self.into_ocd_bytes(grid_rotation, bezier_error);
+
+        std::fs::write(path, bytes).with_context(|| format!("Failed to write OCAD file {path:?}"))
+    }
+
+    /// The number of objects of every symbol without an OCAD definition,
+    /// which are left out of the OCAD file
+    pub fn ocd_omitted_objects(&self) -> Vec<(Symbol, usize)> {
+        let mut omitted = self
+            .objects
+            .iter()
+            .filter(|(symbol, objects)| {
+                !objects.is_empty() && symbol_definition(**symbol).is_none()
+            })
+            .map(|(symbol, objects)| (*symbol, objects.len()))
+            .collect::<Vec<_>>();
+        omitted.sort();
+        omitted
+    }
+
+    // The angle from the paper x axis to grid east, counter clockwise in radians.
+    // Taken from the omap georeferencing so both outputs agree on magnetic north
+    fn grid_rotation(&self, meters_above_sea: f64) -> crate::Result<f64> {
+        let omap = self.georeferenced_omap(meters_above_sea, None)?;
+        let transform = omap.geo_referencing.get_transform();
+
+        let origin = transform.to_map_point(geo::Point(self.ref_point));
+        let east = transform
+            .to_map_point(geo::Point(self.ref_point + geo::coord! {x: 1000., y: 0.}))
+            - origin;
+        let north = transform
+            .to_map_point(geo::Point(self.ref_point + geo::coord! {x: 0., y: 1000.}))
+            - origin;
+
+        // the map y axis may point down, then north is a clockwise turn from east
+        let y_up = if east.x() * north.y() - east.y() * north.x() < 0. {
+            -1.
+        } else {
+            1.
+        };
+        Ok((y_up * east.y()).atan2(east.x()))
+    }
+
+    fn into_ocd_bytes(mut self, grid_rotation: f64, bezier_error: Option<f64>) -> Vec<u8> {
+        let paper = Paper::new(self.scale, grid_rotation);
+
+        let mut buf = OcdBuffer::default();
+        buf.u16(OCD_MARK);
+        // normal map, version 12.0
+        buf.u8(0);
+        buf.u8(0);
+        buf.u16(OCD_VERSION);
+        buf.u8(0);
+        buf.u8(0);
+        buf.zeros(FILE_HEADER_SIZE - buf.len());
+
+        // symbols without a definition are left out, e.g. the contour values as they are text
+        let mut symbols = self
+            .objects
+            .keys()
+            .filter_map(|&symbol| Some((symbol, symbol_definition(symbol)?)))
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|(symbol, _)| *symbol);
+
+        // ISSprOM draws several of the symbols with the same symbol, which is written once
+        let mut numbers = HashSet::new();
+        let mut symbol_index = IndexBlocks::new(SYMBOL_INDEX_ENTRY_SIZE);
+        for (symbol, definition) in &symbols {
+            if !numbers.insert(isom_number(*symbol, self.scale.symbol_standard())) {
+                continue;
+            }
+            let entry = symbol_index.next_entry(&mut buf);
+            let pos = buf.len();
+            write_symbol(&mut buf, *symbol, definition, self.scale);
+            buf.patch_u32(entry, pos as u32);
+        }
+
+        let mut object_index = IndexBlocks::new(OBJECT_INDEX_ENTRY_SIZE);
+        for (symbol, _) in symbols {
+            let Some(objects) = self.objects.remove(&symbol) else {
+                continue;
+            };
+            for object in objects {
+                let Some(object) = OcdObject::new(object, &paper, bezier_error) else {
+                    continue;
+                };
+                let entry = object_index.next_entry(&mut buf);
+                object.write(&mut buf, entry);
+            }
+        }
+
+        let mut strings = COLORS
+            .iter()
+            .map(|(number, name, [c, m, y, k])| {
+                (
+                    COLOR_STRING,
+                    format!("{name}\tn{number}\tc{c}\tm{m}\ty{y}\tk{k}"),
+                )
+            })
+            .collect::<Vec<_>>();
+        strings.push((SCALE_PARAMETER_STRING, self.scale_parameters(grid_rotation)));
+        if let Some(crs) = &self.crs {
+            strings.push((
+                MAP_NOTES_STRING,
+                format!("Coordinate reference system: EPSG:{}", crs.epsg()),
+            ));
+        }
+
+        let mut string_index = IndexBlocks::new(STRING_INDEX_ENTRY_SIZE);
+        for (string_type, string) in strings {
+            let entry = string_index.next_entry(&mut buf);
+            let pos = buf.len();
+            buf.extend(string.as_bytes());
+            buf.u8(0);
+            // strings are allocated in 64 byte chunks
+            buf.zeros((64 - (buf.len() - pos) % 64) % 64);
+
+            buf.patch_u32(entry, pos as u32);
+            buf.patch_u32(entry + 4, (buf.len() - pos) as u32);
+            buf.patch_u32(entry + 8, string_type as u32);
+        }
+
+        buf.patch_u32(FIRST_SYMBOL_BLOCK_POS, symbol_index.first);
+        buf.patch_u32(FIRST_OBJECT_BLOCK_POS, object_index.first);
+        buf.patch_u32(FIRST_STRING_BLOCK_POS, string_index.first);
+        buf.0
+    }
+
+    // The paper origin is the ref point
+    fn scale_parameters(&self, grid_rotation: f64) -> String {
+        let denominator = self.scale.denominator();
+        let grid_id = self.crs.as_ref().and_then(ocad_grid_id).unwrap_or(1000);
+        format!(
+            "\tm{denominator:.0}\tg{:.4}\tr1\tx{:.4}\ty{:.4}\ta{:.8}\td{GRID_METERS:.4}\ti{grid_id}",
+            GRID_METERS * 1000. / denominator,
+            self.ref_point.x,
+            self.ref_point.y,
+            grid_rotation.to_degrees(),
+        )
+    }
+}
+
+// OCAD numbers the WGS 84 UTM grids 2000 + zone, negative on the southern hemisphere
+fn ocad_grid_id(crs: &CrsDef) -> Option<i32> {
+    match crs.epsg() {
+        epsg @ 32601..=32660 => Some(2000 + (epsg - 32600) as i32),
+        epsg @ 32701..=32760 => Some(-(2000 + (epsg - 32700) as i32)),
+        _ => None,
+    }
+}
+
+/// The ISOM or ISSprOM symbol number times 1000 plus the sub number, e.g. 101.1 is 101001
+fn isom_number(symbol: Symbol, standard: SymbolStandard) -> i32 {
+    if standard == SymbolStandard::Issprom2019 {
+        // the same numbers as the ISSprOM set of the omap output
+        let sprint = match symbol {
+            Symbol::Area(AreaSymbol::PavedAreaWithBoundary) => Some((501, 1)),
+            // not ISSprOM symbols, the 0.5 m contours of the set share a number
+            Symbol::Line(LineSymbol::BasemapContour) => Some((101, 5)),
+            Symbol::Line(LineSymbol::NegBasemapContour) => Some((101, 6)),
+            Symbol::Line(LineSymbol::WideRoad) => Some((501, 12)),
+            Symbol::Line(LineSymbol::Road) => Some((501, 11)),
+            Symbol::Line(LineSymbol::VehicleTrack | LineSymbol::Footpath) => Some((506, 0)),
+            Symbol::Point(PointSymbol::ContourValue) => Some((102, 3)),
+            _ => None,
+        };
+        if let Some((major, minor)) = sprint {
+            return major * 1000 + minor;
+        }
+    }
+
+    let (major, minor) = match symbol {
+        Symbol::Area(area) => match area {
+            AreaSymbol::UncrossableWaterWithBankLine => (301, 0),
+            AreaSymbol::ShallowWaterWithSolidBankLine => (302, 0),
+            AreaSymbol::Marsh => (308, 0),
+            AreaSymbol::GiganticBoulder => (206, 0),
+            AreaSymbol::SandyGround => (213, 0),
+            AreaSymbol::BareRock => (214, 0),
+            AreaSymbol::OpenLand => (401, 0),
+            AreaSymbol::RoughOpenLand => (403, 0),
+            AreaSymbol::WhiteForest => (405, 0),
+            AreaSymbol::LightGreen => (406, 0),
+            AreaSymbol::MediumGreen => (408, 0),
+            AreaSymbol::DarkGreen => (410, 0),
+            AreaSymbol::PavedAreaWithBoundary => (501, 0),
+            AreaSymbol::PrivateArea => (520, 0),
+            AreaSymbol::Building => (521, 0),
+        },
+        Symbol::Line(line) => match line {
+            LineSymbol::Contour => (101, 0),
+            // not ISOM symbols, kept next to the contours they are made from
+            LineSymbol::BasemapContour => (101, 2),
+            LineSymbol::NegBasemapContour => (101, 3),
+            LineSymbol::IndexContour => (102, 0),
+            LineSymbol::FormLine => (103, 0),
+            LineSymbol::SmallCrossableWatercourse => (305, 0),
+            LineSymbol::WideRoad => (502, 0),
+            LineSymbol::Road => (503, 0),
+            LineSymbol::VehicleTrack => (504, 0),
+            LineSymbol::Footpath => (505, 0),
+            LineSymbol::SmallPath => (506, 0),
+            LineSymbol::LessDistinctSmallPath => (507, 0),
+        },
+        Symbol::Point(point) => match point {
+            PointSymbol::SlopeLineContour => (101, 1),
+            PointSymbol::SlopeLineFormLine => (103, 1),
+            PointSymbol::DotKnoll => (109, 0),
+            PointSymbol::ElongatedDotKnoll => (110, 0),
+            PointSymbol::UDepression => (111, 0),
+            PointSymbol::SmallBoulder => (204, 0),
+            PointSymbol::LargeBoulder => (205, 0),
+            PointSymbol::ContourValue => (102, 1),
+        },
+    };
+    major * 1000 + minor
+}
+
+// Simplified symbol definitions, sizes in mm at 1:15 000
+enum SymbolDefinition {
+    Area {
+        color: u16,
+        // hatch line width and distance
+        hatch: Option<(f64, f64)>,
+    },
+    Line {
+        color: u16,
+        width: f64,
+        // dash and gap length
+        dash: Option<(f64, f64)>,
+    },
+    Point(Vec<PointElement>),
+}
+
+enum PointElement {
+    Dot {
+        color: u16,
+        diameter: f64,
+    },
+    Line {
+        color: u16,
+        width: f64,
+        coords: Vec<[f64; 2]>,
+    },
+    Area {
+        color: u16,
+        coords: Vec<[f64; 2]>,
+    },
+}
+
+impl PointElement {
+    fn extent(&self) -> f64 {
+        let coords_extent =
+            |coords: &[[f64; 2]]| coords.iter().map(|[x, y]| x.hypot(*y)).fold(0., f64::max);
+        match self {
+            PointElement::Dot { diameter, .. } => diameter / 2.,
+            PointElement::Line { width, coords, .. } => coords_extent(coords) + width / 2.,
+            PointElement::Area { coords, .. } => coords_extent(coords),
+        }
+    }
+}
+
+// None for the symbols that are not written to OCAD files
+fn symbol_definition(symbol: Symbol) -> Option<SymbolDefinition> {
+    let area = |color| SymbolDefinition::Area { color, hatch: None };
+    let line = |color, width| SymbolDefinition::Line {
+        color,
+        width,
+        dash: None,
+    };
+    let dashed = |color, width, dash, gap| SymbolDefinition::Line {
+        color,
+        width,
+        dash: Some((dash, gap)),
+    };
+    let dot =
+        |color, diameter| SymbolDefinition::Point(vec![PointElement::Dot { color, diameter }]);
+    let slope_line = |width| {
+        SymbolDefinition::Point(vec![PointElement::Line {
+            color: BROWN,
+            width,
+            coords: vec![[0., 0.], [0., -0.5]],
+        }])
+    };
+
+    let definition = match symbol {
+        Symbol::Area(area_symbol) => match area_symbol {
+            AreaSymbol::UncrossableWaterWithBankLine => area(BLUE),
+            AreaSymbol::ShallowWaterWithSolidBankLine => area(BLUE_50),
+            AreaSymbol::Marsh => SymbolDefinition::Area {
+                color: BLUE,
+                hatch: Some((0.1, 0.35)),
+            },
+            AreaSymbol::GiganticBoulder => area(BLACK),
+            AreaSymbol::SandyGround => area(YELLOW_50),
+            AreaSymbol::BareRock => area(BLACK_20),
+            AreaSymbol::OpenLand => area(YELLOW),
+            AreaSymbol::RoughOpenLand => area(YELLOW_50),
+            AreaSymbol::WhiteForest => area(WHITE),
+            AreaSymbol::LightGreen => area(GREEN_30),
+            AreaSymbol::MediumGreen => area(GREEN_60),
+            AreaSymbol::DarkGreen => area(GREEN),
+            AreaSymbol::PavedAreaWithBoundary => area(BROWN_50),
+            AreaSymbol::PrivateArea => area(OLIVE),
+            AreaSymbol::Building => area(BLACK_65),
+        },
+        Symbol::Line(line_symbol) => match line_symbol {
+            LineSymbol::Contour => line(BROWN, 0.14),
+            LineSymbol::BasemapContour | LineSymbol::NegBasemapContour => line(BROWN, 0.07),
+            LineSymbol::IndexContour => line(BROWN, 0.25),
+            LineSymbol::FormLine => dashed(BROWN, 0.14, 2.5, 0.25),
+            LineSymbol::SmallCrossableWatercourse => line(BLUE, 0.18),
+            LineSymbol::WideRoad => line(BLACK, 0.6),
+            LineSymbol::Road => line(BLACK, 0.35),
+            LineSymbol::VehicleTrack => dashed(BLACK, 0.35, 3., 0.25),
+            LineSymbol::Footpath => dashed(BLACK, 0.25, 2., 0.25),
+            LineSymbol::SmallPath => dashed(BLACK, 0.18, 1., 0.25),
+            LineSymbol::LessDistinctSmallPath => dashed(BLACK, 0.18, 0.5, 0.25),
+        },
+        Symbol::Point(point_symbol) => match point_symbol {
+            PointSymbol::SlopeLineContour => slope_line(0.14),
+            PointSymbol::SlopeLineFormLine => slope_line(0.14),
+            PointSymbol::DotKnoll => dot(BROWN, 0.75),
+            PointSymbol::ElongatedDotKnoll => SymbolDefinition::Point(vec![PointElement::Area {
+                color: BROWN,
+                // an ellipse of 1.2 by 0.7 mm along the x axis
+                coords: (0..=16)
+                    .map(|i| {
+                        let angle = i as f64 * std::f64::consts::TAU / 16.;
+                        [0.6 * angle.cos(), 0.35 * angle.sin()]
+                    })
+                    .collect(),
+            }]),
+            PointSymbol::UDepression => SymbolDefinition::Point(vec![PointElement::Line {
+                color: BROWN,
+                width: 0.18,
+                coords: vec![
+                    [-0.4, 0.3],
+                    [-0.3, -0.1],
+                    [0., -0.25],
+                    [0.3, -0.1],
+                    [0.4, 0.3],
+                ],
+            }]),
+            PointSymbol::SmallBoulder => dot(BLACK, 0.4),
+            PointSymbol::LargeBoulder => dot(BLACK, 0.6),
+            PointSymbol::ContourValue => return None,
+        },
+    };
+    Some(definition)
+}
+
+fn write_symbol(buf: &mut OcdBuffer, symbol: Symbol, definition: &SymbolDefinition, scale: Scale) {
+    // ISOM symbols are enlarged 150% at scales larger than 1:15 000,
+    // ISSprOM keeps its dimensions at 1:5 000
+    let enlargement = match scale.symbol_standard() {
+        SymbolStandard::Isom2017 if scale.denominator() < 15_000. => 1.5,
+        SymbolStandard::Isom2017 | SymbolStandard::Issprom2019 => 1.,
+    };
+    let ocd_size = |mm: f64| (mm * enlargement * 100.).round() as i16;
+
+    let (symbol_type, colors, extent) = match definition {
+        SymbolDefinition::Area { color, .. } => (AREA_SYMBOL_TYPE, vec![*color], 0.),
+        SymbolDefinition::Line { color, width, .. } => (LINE_SYMBOL_TYPE, vec![*color], width / 2.),
+        SymbolDefinition::Point(elements) => {
+            let mut colors = elements
+                .iter()
+                .map(|e| match e {
+                    PointElement::Dot { color, .. }
+                    | PointElement::Line { color, .. }
+                    | PointElement::Area { color, .. } => *color,
+                })
+                .collect::<Vec<_>>();
+            colors.dedup();
+            let extent = elements.iter().map(PointElement::extent).fold(0., f64::max);
+            (POINT_SYMBOL_TYPE, colors, extent)
+        }
+    };
+
+    // the base symbol, shared by every symbol type
+    let pos = buf.len();
+    buf.i32(0);
+    buf.i32(isom_number(symbol, scale.symbol_standard()));
+    buf.u8(symbol_type);
+    buf.u8(if symbol_type == POINT_SYMBOL_TYPE {
+        ROTATABLE_SYMBOL
+    } else {
+        0
+    });
+    // selected, status, drawing tool and course setting fields
+    buf.zeros(6);
+    buf.i32(ocd_size(extent) as i32);
+    buf.u32(pos as u32);
+    // two unused fields before the number of colours
+    buf.u16(0);
+    buf.u16(0);
+    buf.u16(colors.len() as u16);
+    for i in 0..14 {
+        buf.u16(colors.get(i).copied().unwrap_or_default());
+    }
+    let description = symbol
+        .to_string()
+        .encode_utf16()
+        .take(63)
+        .collect::<Vec<_>>();
+    for i in 0..64 {
+        buf.u16(description.get(i).copied().unwrap_or_default());
+    }
+    // icon and symbol tree groups
+    buf.zeros(22 * 22 + 64 * 2);
+
+    match definition {
+        SymbolDefinition::Area { color, hatch } => {
+            // border symbol
+            buf.i32(0);
+            buf.u16(*color);
+            let (hatch_mode, hatch_width, hatch_distance) =
+                hatch.map_or((0, 0, 0), |(w, d)| (1, ocd_size(w), ocd_size(d)));
+            buf.u16(hatch_mode);
+            buf.u16(*color);
+            buf.i16(hatch_width);
+            buf.i16(hatch_distance);
+            // hatch angles
+            buf.i16(0);
+            buf.i16(0);
+            // fill on, border off, the rest is structure settings and data size
+            buf.u8(hatch.is_none() as u8);
+            buf.zeros(17);
+        }
+        SymbolDefinition::Line { color, width, dash } => {
+            let (dash, gap) = dash.map_or((0, 0), |(d, g)| (ocd_size(d), ocd_size(g)));
+            buf.u16(*color);
+            buf.i16(ocd_size(*width));
+            // round joins and caps
+            buf.u16(1);
+            // distance from start and to end
+            buf.i16(0);
+            buf.i16(0);
+            // main and end dash length, main and secondary gap, end gap
+            buf.i16(dash);
+            buf.i16(dash);
+            buf.i16(gap);
+            buf.i16(0);
+            buf.i16(0);
+            // double lines, decrease, framing and symbol data sizes
+            buf.zeros(56);
+        }
+        SymbolDefinition::Point(elements) => {
+            // the element data size in coordinates, the element header counts as two
+            let data_size = elements
+                .iter()
+                .map(|e| match e {
+                    PointElement::Dot { .. } => 3,
+                    PointElement::Line { coords, .. } | PointElement::Area { coords, .. } => {
+                        2 + coords.len()
+                    }
+                })
+                .sum::<usize>();
+            buf.u16(data_size as u16);
+            buf.u16(0);
+
+            for element in elements {
+                let (element_type, color, width, diameter, coords) = match element {
+                    PointElement::Dot { color, diameter } => {
+                        (DOT_ELEMENT, *color, 0, ocd_size(*diameter), vec![[0., 0.]])
+                    }
+                    PointElement::Line {
+                        color,
+                        width,
+                        coords,
+                    } => (LINE_ELEMENT, *color, ocd_size(*width), 0, coords.clone()),
+                    PointElement::Area { color, coords } => {
+                        (AREA_ELEMENT, *color, 0, 0, coords.clone())
+                    }
+                };
+                buf.i16(element_type);
+                buf.u16(0);
+                buf.u16(color);
+                buf.i16(width);
+                buf.i16(diameter);
+                buf.i16(coords.len() as i16);
+                buf.u32(0);
+                for [x, y] in coords {
+                    buf.i32(ocd_coordinate(x * enlargement * 100., 0));
+                    buf.i32(ocd_coordinate(y * enlargement * 100., 0));
+                }
+            }
+        }
+    }
+
+    let size = buf.len() - pos;
+    buf.patch_u32(pos, size as u32);
+}
+
+// Map coordinates relative to the ref point to paper coordinates in 1/100 mm
+struct Paper {
+    standard: SymbolStandard,
+    scale: f64,
+    rotation: f64,
+    cos: f64,
+    sin: f64,
+}
+
+impl Paper {
+    fn new(scale: Scale, grid_rotation: f64) -> Self {
+        Paper {
+            standard: scale.symbol_standard(),
+            scale: 100_000. / scale.denominator(),
+            rotation: grid_rotation,
+            cos: grid_rotation.cos(),
+            sin: grid_rotation.sin(),
+        }
+    }
+
+    fn to_paper(&self, c: geo::Coord) -> geo::Coord {
+        geo::coord! {
+            x: (c.x * self.cos - c.y * self.sin) * self.scale,
+            y: (c.x * self.sin + c.y * self.cos) * self.scale,
+        }
+    }
+}
+
+fn ocd_coordinate(value: f64, flags: i32) -> i32 {
+    ((value.round() as i32) << 8) | flags
+}
+
+struct OcdObject {
+    symbol: i32,
+    object_type: u8,
+    // tenths of a degree
+    angle: i16,
+    coords: Vec<(geo::Coord, i32, i32)>,
+}
+
+impl OcdObject {
+    fn new(object: MapObject, paper: &Paper, bezier_error: Option<f64>) -> Option<Self> {
+        let symbol = isom_number(object.get_symbol(), paper.standard);
+
+        let (object_type, angle, coords) = match object {
+            MapObject::Point {
+                object, rotation, ..
+            } => {
+                let angle = (rotation + paper.rotation).to_degrees();
+                (
+                    1,
+                    (angle.rem_euclid(360.) * 10.).round() as i16,
+                    vec![(paper.to_paper(object.0), 0, 0)],
+                )
+            }
+            MapObject::Line { object, symbol, .. } => {
+                let line =
+                    geo::LineString::new(object.coords().map(|&c| paper.to_paper(c)).collect());
+                let bezier_error = bezier_error.filter(|_| {
+                    !matches!(
+                        symbol,
+                        LineSymbol::BasemapContour | LineSymbol::NegBasemapContour
+                    )
+                });
+                (2, 0, line_coords(line, bezier_error.map(|e| e * 100.)))
+            }
+            MapObject::Area { object, .. } => {
+                let bezier_error = bezier_error.map(|e| e * 100.);
+                let ring = |ring: geo::LineString| {
+                    geo::LineString::new(ring.coords().map(|&c| paper.to_paper(c)).collect())
+                };
+
+                let (exterior, interiors) = object.into_inner();
+                let mut coords = line_coords(ring(exterior), bezier_error);
+                for hole in interiors {
+                    let start = coords.len();
+                    coords.extend(line_coords(ring(hole), bezier_error));
+                    if let Some(first) = coords.get_mut(start) {
+                        first.2 = HOLE_START;
+                    }
+                }
+                (3, 0, coords)
+            }
+        };
+
+        (!coords.is_empty()).then_some(OcdObject {
+            symbol,
+            object_type,
+            angle,
+            coords,
+        })
+    }
+
+    // writes the object at the end of the buffer and fills its index entry
+    fn write(&self, buf: &mut OcdBuffer, entry: usize) {
+        let pos = buf.len();
+        buf.i32(self.symbol);
+        buf.u8(self.object_type);
+        // customer
+        buf.u8(0);
+        buf.i16(self.angle);
+        // color, line width, diameter flags, server object id, height
+        buf.zeros(16);
+        // creation date, multirepresentation id and modification date
+        buf.zeros(20);
+        buf.u32(self.coords.len() as u32);
+        // text, object and database strings
+        buf.zeros(8);
+
+        let (mut min, mut max) = (
+            geo::coord! {x: f64::MAX, y: f64::MAX},
+            geo::coord! {x: f64::MIN, y: f64::MIN},
+        );
+        for (c, x_flags, y_flags) in &self.coords {
+            buf.i32(ocd_coordinate(c.x, *x_flags));
+            buf.i32(ocd_coordinate(c.y, *y_flags));
+            min = geo::coord! {x: min.x.min(c.x), y: min.y.min(c.y)};
+            max = geo::coord! {x: max.x.max(c.x), y: max.y.max(c.y)};
+        }
+        let size = buf.len() - pos;
+
+        let mut index_entry = OcdBuffer::default();
+        index_entry.i32(ocd_coordinate(min.x, 0));
+        index_entry.i32(ocd_coordinate(min.y, 0));
+        index_entry.i32(ocd_coordinate(max.x, 0));
+        index_entry.i32(ocd_coordinate(max.y, 0));
+        index_entry.u32(pos as u32);
+        index_entry.u32(size as u32);
+        index_entry.i32(self.symbol);
+        index_entry.u8(self.object_type);
+        // not encrypted, normal status and view type
+        index_entry.u8(0);
+        index_entry.u8(1);
+        index_entry.u8(0);
+        // color, group, layer, layout font
+        index_entry.zeros(8);
+        buf.patch(entry, &index_entry.0);
+    }
+}
+
+// The coordinates of a line in 1/100 mm, as bezier curves if the fit succeeds
+fn line_coords(line: geo::LineString, bezier_error: Option<f64>) -> Vec<(geo::Coord, i32, i32)> {
+    let bezier = bezier_error.and_then(|e| BezierString::from_line_string(line.clone(), e).ok());
+    let Some(bezier) = bezier else {
+        return line.0.into_iter().map(|c| (c, 0, 0)).collect();
+    };
+
+    let mut coords = Vec::with_capacity(3 * bezier.0.len() + 1);
+    for segment in bezier.0 {
+        match segment {
+            BezierSegment::Bezier(curve) => {
+                let [start, c1, c2, end] = curve.to_array();
+                if coords.is_empty() {
+                    coords.push((start, 0, 0));
+                }
+                coords.push((c1, FIRST_CONTROL_POINT, 0));
+                coords.push((c2, SECOND_CONTROL_POINT, 0));
+                coords.push((end, 0, 0));
+            }
+            BezierSegment::Line(line) => {
+                if coords.is_empty() {
+                    coords.push((line.start, 0, 0));
+                }
+                coords.push((line.end, 0, 0));
+            }
+        }
+    }
+    coords
+}
+
+// Index blocks of 256 entries, a new block is chained on when one is full
+struct IndexBlocks {
+    entry_size: usize,
+    first: u32,
+    block: Option<usize>,
+    used: usize,
+}
+
+impl IndexBlocks {
+    fn new(entry_size: usize) -> Self {
+        IndexBlocks {
+            entry_size,
+            first: 0,
+            block: None,
+            used: 0,
+        }
+    }
+
+    // the position of the next free entry, filled in when its record is written
+    fn next_entry(&mut self, buf: &mut OcdBuffer) -> usize {
+        let block = match self.block {
+            Some(block) if self.used < INDEX_BLOCK_ENTRIES => block,
+            previous => {
+                let block = buf.len();
+                buf.zeros(4 + INDEX_BLOCK_ENTRIES * self.entry_size);
+                match previous {
+                    Some(previous) => buf.patch_u32(previous, block as u32),
+                    None => self.first = block as u32,
+                }
+                self.block = Some(block);
+                self.used = 0;
+                block
+            }
+        };
+        self.used += 1;
+        block + 4 + (self.used - 1) * self.entry_size
+    }
+}
+
+#[derive(Default)]
+struct OcdBuffer(Vec<u8>);
+
+impl OcdBuffer {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn zeros(&mut self, count: usize) {
+        self.0.resize(self.0.len() + count, 0);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.extend(&value.to_le_bytes());
+    }
+
+    fn i16(&mut self, value: i16) {
+        self.extend(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.extend(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.extend(&value.to_le_bytes());
+    }
+
+    fn patch(&mut self, pos: usize, bytes: &[u8]) {
+        self.0[pos..pos + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn patch_u32(&mut self, pos: usize, value: u32) {
+        self.patch(pos, &value.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        map_gen::egui_map::{AreaSymbol, LineSymbol, MapObject, PointSymbol, Symbol, TempMap},
+        parameters::Scale,
+    };
+
+    fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn objects_are_indexed_by_isom_number() {
+        let mut map = TempMap::new(
+            geo::coord! {x: 500_000., y: 7_000_000.},
+            Scale::S15_000,
+            None,
+        );
+        map.add_object(MapObject::Line {
+            object: geo::LineString::from(vec![(0., 0.), (150., 0.), (150., 150.)]),
+            symbol: LineSymbol::Contour,
+            tags: HashMap::new(),
+        });
+        map.add_object(MapObject::Point {
+            object: geo::Point::new(-150., 300.),
+            symbol: PointSymbol::DotKnoll,
+            rotation: 0.,
+            tags: HashMap::new(),
+        });
+
+        let bytes = map.into_ocd_bytes(0., None);
+
+        assert_eq!(&bytes[0..2], &0x0CADu16.to_le_bytes());
+        assert_eq!(&bytes[4..6], &12u16.to_le_bytes());
+
+        // the object index is sorted by symbol, lines before points
+        let object_block = u32_at(&bytes, 12) as usize;
+        let line_entry = object_block + 4;
+        let point_entry = line_entry + 40;
+        assert_eq!(u32_at(&bytes, line_entry + 24), 101_000);
+        assert_eq!(u32_at(&bytes, point_entry + 24), 109_000);
+
+        // 150 m is 1 cm on paper at 1:15 000, stored in 1/100 mm above the flag byte
+        let line = u32_at(&bytes, line_entry + 16) as usize;
+        assert_eq!(u32_at(&bytes, line + 44), 3);
+        let second_x = i32::from_le_bytes(bytes[line + 64..line + 68].try_into().unwrap());
+        assert_eq!(second_x >> 8, 1000);
+    }
+
+    #[test]
+    fn area_rings_are_curved_and_holes_flagged() {
+        let circle = |radius: f64| {
+            geo::LineString::from(
+                (0..=64)
+                    .map(|i| {
+                        let angle = i as f64 * std::f64::consts::TAU / 64.;
+                        (radius * angle.cos(), radius * angle.sin())
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let mut map = TempMap::new(geo::Coord::zero(), Scale::S15_000, None);
+        map.add_object(MapObject::Area {
+            object: geo::Polygon::new(circle(300.), vec![circle(100.)]),
+            symbol: AreaSymbol::UncrossableWaterWithBankLine,
+            tags: HashMap::new(),
+        });
+        map.add_object(MapObject::Point {
+            object: geo::Point::new(0., 0.),
+            symbol: PointSymbol::ContourValue,
+            rotation: 0.,
+            tags: HashMap::new(),
+        });
+
+        assert_eq!(
+            map.ocd_omitted_objects(),
+            [(Symbol::Point(PointSymbol::ContourValue), 1)]
+        );
+        let bytes = map.into_ocd_bytes(0., Some(0.05));
+
+        let object_block = u32_at(&bytes, 12) as usize;
+        let area = u32_at(&bytes, object_block + 4 + 16) as usize;
+        let num_coords = u32_at(&bytes, area + 44) as usize;
+        let flags = (0..num_coords)
+            .map(|i| {
+                let pos = area + 56 + 8 * i;
+                (bytes[pos], bytes[pos + 4])
+            })
+            .collect::<Vec<_>>();
+
+        // the rings are written as bezier curves, with fewer points than the polygon has
+        assert!(num_coords < 2 * 65);
+        assert!(flags.iter().any(|&(x_flags, _)| x_flags == 1));
+        // the hole starts on a point of the ring, not a control point
+        let hole_starts = flags
+            .iter()
+            .filter(|&&(_, y_flags)| y_flags == 2)
+            .collect::<Vec<_>>();
+        assert_eq!(hole_starts, [&(0, 2)]);
+    }
+
+    // Reads the records field by field as laid out in the OCAD 12 file format
+    // description, without the writer's constants
+    struct OcdReader<'a>(&'a [u8]);
+
+    impl OcdReader<'_> {
+        fn u8(&self, pos: usize) -> u8 {
+            self.0[pos]
+        }
+
+        fn i16(&self, pos: usize) -> i16 {
+            i16::from_le_bytes(self.0[pos..pos + 2].try_into().unwrap())
+        }
+
+        fn i32(&self, pos: usize) -> i32 {
+            i32::from_le_bytes(self.0[pos..pos + 4].try_into().unwrap())
+        }
+
+        fn wide_string(&self, pos: usize, max_chars: usize) -> String {
+            let chars = (0..max_chars)
+                .map(|i| self.i16(pos + 2 * i) as u16)
+                .take_while(|c| *c != 0)
+                .collect::<Vec<_>>();
+            String::from_utf16(&chars).unwrap()
+        }
+
+        // the used entries of a chain of index blocks, an entry is used if its file position is set
+        fn index(&self, mut block: usize, entry_size: usize, pos_offset: usize) -> Vec<usize> {
+            let mut entries = Vec::new();
+            while block != 0 {
+                for i in 0..256 {
+                    let entry = block + 4 + i * entry_size;
+                    if self.i32(entry + pos_offset) != 0 {
+                        entries.push(entry);
+                    }
+                }
+                block = self.i32(block) as usize;
+            }
+            entries
+        }
+    }
+
+    #[test]
+    fn records_follow_the_ocad_12_layout() {
+        let mut map = TempMap::new(
+            geo::coord! {x: 500_000., y: 7_000_000.},
+            Scale::S15_000,
+            None,
+        );
+        map.add_object(MapObject::Line {
+            object: geo::LineString::from(vec![(0., 0.), (150., 0.), (150., 150.)]),
+            symbol: LineSymbol::Contour,
+            tags: HashMap::new(),
+        });
+        map.add_object(MapObject::Point {
+            object: geo::Point::new(-150., 300.),
+            symbol: PointSymbol::DotKnoll,
+            rotation: 0.,
+            tags: HashMap::new(),
+        });
+        map.add_object(MapObject::Area {
+            object: geo::Polygon::new(
+                geo::LineString::from(vec![(0., 0.), (15., 0.), (15., 15.), (0., 0.)]),
+                vec![],
+            ),
+            symbol: AreaSymbol::Marsh,
+            tags: HashMap::new(),
+        });
+
+        let bytes = map.into_ocd_bytes(0., None);
+        let ocd = OcdReader(&bytes);
+
+        // TFileHeader
+        assert_eq!(ocd.i16(0), 0x0CAD);
+        assert_eq!(ocd.i16(4), 12);
+
+        // TBaseSym is 798 bytes, followed by the fields of the symbol type
+        let mut symbols = HashMap::new();
+        for entry in ocd.index(ocd.i32(8) as usize, 4, 0) {
+            let pos = ocd.i32(entry) as usize;
+            assert_eq!(ocd.i32(pos + 20) as usize, pos, "FilePos");
+            assert_eq!(ocd.i16(pos + 24), 0, "notUsed1");
+            assert_eq!(ocd.i16(pos + 26), 0, "notUsed2");
+            assert_eq!(ocd.i16(pos + 28), 1, "nColors");
+            symbols.insert(ocd.i32(pos + 4), pos);
+        }
+        assert_eq!(symbols.len(), 3);
+
+        // TLineSym: 10 line fields then 56 bytes of double line, decrease and framing
+        let contour = symbols[&101_000];
+        assert_eq!(ocd.u8(contour + 8), 2);
+        assert_eq!(ocd.i16(contour + 30), 2, "brown");
+        assert_eq!(
+            ocd.wide_string(contour + 58, 64),
+            Symbol::Line(LineSymbol::Contour).to_string()
+        );
+        assert_eq!(ocd.i16(contour + 798), 2, "LineColor");
+        assert_eq!(ocd.i16(contour + 800), 14, "LineWidth");
+        assert_eq!(ocd.i32(contour), 798 + 20 + 56);
+
+        // TPointSym: DataSize and Res, then one dot element of 2 + 1 coordinates
+        let knoll = symbols[&109_000];
+        assert_eq!(ocd.u8(knoll + 8), 1);
+        assert_eq!(ocd.u8(knoll + 9) & 1, 1, "rotatable");
+        assert_eq!(ocd.i16(knoll + 798), 3, "DataSize");
+        assert_eq!(ocd.i16(knoll + 802), 4, "stType dot");
+        assert_eq!(ocd.i16(knoll + 806), 2, "stColor");
+        assert_eq!(ocd.i16(knoll + 810), 75, "stDiameter");
+        assert_eq!(ocd.i32(knoll), 798 + 4 + 3 * 8);
+
+        // TAreaSym: the hatched marsh is not filled
+        let marsh = symbols[&308_000];
+        assert_eq!(ocd.u8(marsh + 8), 3);
+        assert_eq!(ocd.i16(marsh + 802), 1, "FillColor");
+        assert_eq!(ocd.i16(marsh + 804), 1, "HatchMode");
+        assert_eq!(ocd.i16(marsh + 810), 35, "HatchDist");
+        assert_eq!(ocd.u8(marsh + 816), 0, "FillOn");
+        assert_eq!(ocd.i32(marsh), 798 + 36);
+
+        // TObjectIndex entries point to TElement records of the same symbol
+        let objects = ocd.index(ocd.i32(12) as usize, 40, 16);
+        assert_eq!(objects.len(), 3);
+        for entry in objects {
+            let (pos, len, symbol) = (
+                ocd.i32(entry + 16) as usize,
+                ocd.i32(entry + 20) as usize,
+                ocd.i32(entry + 24),
+            );
+            assert_eq!(ocd.i32(pos), symbol);
+            assert_eq!(ocd.u8(pos + 4), ocd.u8(entry + 28), "Otp");
+            let n_items = ocd.i32(pos + 44) as usize;
+            assert_eq!(len, 56 + 8 * n_items);
+
+            let coords = (0..n_items)
+                .map(|i| {
+                    (
+                        ocd.i32(pos + 56 + 8 * i) >> 8,
+                        ocd.i32(pos + 60 + 8 * i) >> 8,
+                    )
+                })
+                .collect::<Vec<_>>();
+            if symbol == 101_000 {
+                // 150 m is 1 cm on paper at 1:15 000, in 1/100 mm
+                assert_eq!(coords, vec![(0, 0), (1000, 0), (1000, 1000)]);
+                assert_eq!(ocd.i32(entry + 8) >> 8, 1000, "upper right x");
+            }
+        }
+
+        // the scale and georeferencing string
+        let scale = ocd
+            .index(ocd.i32(32) as usize, 16, 0)
+            .into_iter()
+            .find(|entry| ocd.i32(entry + 8) == 1039)
+            .map(|entry| ocd.i32(entry) as usize)
+            .unwrap();
+        let end = scale + bytes[scale..].iter().position(|b| *b == 0).unwrap();
+        let scale = std::str::from_utf8(&bytes[scale..end]).unwrap();
+        assert!(scale.contains("\tm15000\t"));
+        assert!(scale.contains("\tx500000.0000\ty7000000.0000\t"));
+    }
+}
@@ -1,13 +1,14 @@
 use crate::{
-    comms::{OmapComms, messages::*},
+    comms::{OmapComms, is_cancellation, messages::*},
     drawable::DrawableOmap,
     map_gen::{
-        egui_map::{AreaSymbol, LineSymbol, PointSymbol, TempMap},
+        egui_map::{AreaSymbol, LineSymbol, PointSymbol, Symbol, TempMap},
         pipeline::{self, PipelineSteps, PreparedTile},
     },
     parameters::MapParameters,
 };
 
+use geo::BooleanOps;
 use rayon::{ThreadPool, prelude::*};
 
 #[allow(clippy::too_many_arguments)]
@@ -46,12 +47,17 @@ pub fn regenerate_map_tile(
     let outputs = thread_pool.install(|| {
         tiles
             .par_iter()
-            .map(|tile| pipeline::compute_tile(tile, params, steps, steps.contours))
+            .map(|tile| {
+                sender.cancel_token().check()?;
+                pipeline::compute_tile(tile, params, steps, steps.contours)
+            })
             .collect::<anyhow::Result<Vec<_>>>()
     });
 
     let outputs = match outputs {
         Ok(o) => o,
+        // the frontend has already dropped this job
+        Err(e) if is_cancellation(&e) => return,
         Err(e) => {
             let _ = sender.send(FrontendTask::Error(e.to_string(), true));
             return;
@@ -66,13 +72,7 @@ pub fn regenerate_map_tile(
         }
     }
 
-    let min_size_filter_symbols = params.min_size_filter_symbols(
-        steps.openness,
-        steps.vegetation,
-        steps.cliffs,
-        steps.intensity,
-        steps.water,
-    );
+    let min_size_filter_symbols = params.min_size_filter_symbols(steps);
     if let Err(e) = omap.merge_and_filter_min_size(min_size_filter_symbols) {
         let _ = sender.send(FrontendTask::Error(e.to_string(), true));
         return;
@@ -105,12 +105,49 @@ pub fn regenerate_map_tile(
     if steps.water {
         omap.reserve_capacity(AreaSymbol::UncrossableWaterWithBankLine, 0);
     }
+    if steps.streams {
+        omap.reserve_capacity(LineSymbol::SmallCrossableWatercourse, 0);
+    }
+    if steps.marsh {
+        omap.reserve_capacity(AreaSymbol::Marsh, 0);
+    }
+    if steps.buildings {
+        omap.reserve_capacity(AreaSymbol::Building, 0);
+    }
+    if steps.boulders {
+        omap.reserve_capacity(PointSymbol::SmallBoulder, 0);
+        omap.reserve_capacity(PointSymbol::LargeBoulder, 0);
+    }
+    if steps.roads {
+        for symbol in [
+            LineSymbol::WideRoad,
+            LineSymbol::Road,
+            LineSymbol::VehicleTrack,
+            LineSymbol::Footpath,
+            LineSymbol::SmallPath,
+            LineSymbol::LessDistinctSmallPath,
+        ] {
+            omap.reserve_capacity(symbol, 0);
+        }
+    }
     if steps.intensity {
         for filter in params.intensity.filters.iter() {
             omap.reserve_capacity(filter.symbol, 0);
         }
     }
 
+    let conflicts = steps.contours.then(|| {
+        if params.contour.repair_topology {
+            omap.repair_contour_topology();
+        }
+        // checked as drawn, the bezier curves may cross where the polylines do not
+        omap.written_contour_conflicts(
+            params
+                .geometry
+                .bezier_error_for_symbol(Symbol::Line(LineSymbol::Contour)),
+        )
+    });
+
     if steps.contours {
         omap.reserve_capacity(PointSymbol::DotKnoll, 1);
         omap.reserve_capacity(PointSymbol::ElongatedDotKnoll, 1);
@@ -120,9 +157,28 @@ pub fn regenerate_map_tile(
             params.contour.dot_knoll_area.1,
             1.5,
         );
+
+        omap.reserve_capacity(PointSymbol::SlopeLineContour, 0);
+        omap.reserve_capacity(PointSymbol::SlopeLineFormLine, 0);
+        if params.contour.slope_lines {
+            // the contours of the test tiles end at their cut overlays
+            let map_edge = tiles
+                .iter()
+                .fold(geo::MultiPolygon::new(vec![]), |edge, tile| {
+                    edge.union(&tile.cut_overlay)
+                });
+            omap.add_slope_lines(&map_edge);
+        }
+
+        omap.reserve_capacity(PointSymbol::ContourValue, 0);
+        if params.contour.index_contour_labels {
+            omap.add_index_contour_labels();
+        }
     }
 
-    let map = match DrawableOmap::from_temp_map(omap, hull.exterior().clone(), &params.geometry) {
+    let crs = omap.crs.clone();
+    let mut map = match DrawableOmap::from_temp_map(omap, hull.exterior().clone(), &params.geometry)
+    {
         Ok(m) => m,
         Err(e) => {
             let _ = sender.send(FrontendTask::Error(e.to_string(), true));
@@ -130,6 +186,16 @@ pub fn regenerate_map_tile(
         }
     };
 
+    if let Some(conflicts) = conflicts {
+        if let Err(e) = map.set_contour_conflicts(&conflicts, ref_point, crs.as_ref()) {
+            let _ = sender.send(FrontendTask::Error(e.to_string(), true));
+            return;
+        }
+        let _ = sender.send(FrontendTask::UpdateVariable(Variable::ContourConflicts(
+            job_id, conflicts,
+        )));
+    }
+
     if steps.contours {
         tot_energy /= tiles.len() as f64;
         tot_error /= tiles.len() as f64;
@@ -177,6 +243,12 @@ fn changed_steps(
         || new.geometry.vegetation != old.geometry.vegetation;
     steps.cliffs = new.cliff.cliff != old.cliff.cliff || new.geometry.cliffs != old.geometry.cliffs;
     steps.water = new.water != old.water || new.geometry.water != old.geometry.water;
+    steps.streams = new.streams != old.streams;
+    steps.marsh = new.marsh != old.marsh || new.geometry.marsh != old.geometry.marsh;
+    steps.buildings =
+        new.buildings != old.buildings || new.geometry.buildings != old.geometry.buildings;
+    steps.boulders = new.boulders != old.boulders;
+    steps.roads = new.roads != old.roads;
 
     steps.basemap = new.contour.basemap_interval != old.contour.basemap_interval
         || new.contour.basemap_contour != old.contour.basemap_contour;
@@ -184,14 +256,19 @@ fn changed_steps(
     steps.contours = new.contour.algorithm != old.contour.algorithm
         || new.contour.algo_lambda != old.contour.algo_lambda
         || new.contour.algo_steps != old.contour.algo_steps
+        || new.contour.generalisation_steps != old.contour.generalisation_steps
         || new.geometry.contours != old.geometry.contours
         || new.contour.form_lines != old.contour.form_lines
         || new.contour.form_line_prune_algorithm != old.contour.form_line_prune_algorithm
         || new.contour.form_line_prune_threshold != old.contour.form_line_prune_threshold
         || new.contour.form_line_error_threshold != old.contour.form_line_error_threshold
         || new.contour.interval != old.contour.interval
+        || new.contour.base_level != old.contour.base_level
         || new.contour.dot_knoll_area.0 != old.contour.dot_knoll_area.0
-        || new.contour.dot_knoll_area.1 != old.contour.dot_knoll_area.1;
+        || new.contour.dot_knoll_area.1 != old.contour.dot_knoll_area.1
+        || new.contour.slope_lines != old.contour.slope_lines
+        || new.contour.repair_topology != old.contour.repair_topology
+        || new.contour.index_contour_labels != old.contour.index_contour_labels;
 
     force_scope(&mut steps, scope);
     steps
@@ -204,6 +281,23 @@ fn force_scope(steps: &mut PipelineSteps, scope: RegenerationScope) {
         RegenerationScope::Section(MapPreviewSection::Vegetation) => steps.vegetation = true,
         RegenerationScope::Section(MapPreviewSection::Cliffs) => steps.cliffs = true,
         RegenerationScope::Section(MapPreviewSection::Water) => steps.water = true,
+        RegenerationScope::Section(MapPreviewSection::Streams) => steps.streams = true,
+        RegenerationScope::Section(MapPreviewSection::Marsh) => steps.marsh = true,
+        RegenerationScope::Section(MapPreviewSection::Buildings) => steps.buildings = true,
+        RegenerationScope::Section(MapPreviewSection::Boulders) => steps.boulders = true,
+        RegenerationScope::Section(MapPreviewSection::Roads) => steps.roads = true,
         RegenerationScope::Section(MapPreviewSection::Intensity) => steps.intensity = true,
+        RegenerationScope::Through(last) => {
+            steps.openness |= MapPreviewSection::Openness <= last;
+            steps.vegetation |= MapPreviewSection::Vegetation <= last;
+            steps.cliffs |= MapPreviewSection::Cliffs <= last;
+            steps.water |= MapPreviewSection::Water <= last;
+            steps.streams |= MapPreviewSection::Streams <= last;
+            steps.marsh |= MapPreviewSection::Marsh <= last;
+            steps.buildings |= MapPreviewSection::Buildings <= last;
+            steps.boulders |= MapPreviewSection::Boulders <= last;
+            steps.roads |= MapPreviewSection::Roads <= last;
+            steps.intensity |= MapPreviewSection::Intensity <= last;
+        }
     }
 }
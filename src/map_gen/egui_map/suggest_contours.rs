@@ -0,0 +1,208 @@
+use std::fmt::Display;
+
+use rayon::{ThreadPool, prelude::*};
+
+use crate::{
+    comms::{OmapComms, messages::*},
+    map_gen::{common, pipeline::PreparedTile},
+    parameters::{MapParameters, Scale},
+    raster::{
+        Dfm,
+        dfm::{Elevation, Slope},
+    },
+};
+
+const FINE_INTERVAL: f64 = 2.5;
+const STANDARD_INTERVAL: f64 = 5.;
+// terrain where the median spacing of standard contours is wider than this on paper is flat
+const FLAT_TERRAIN_SPACING_MM: f64 = 5.;
+// the steepest tenth of the terrain must keep this much room between fine contours
+const MIN_CONTOUR_SPACING_MM: f64 = 0.6;
+const STEEP_QUANTILE: f64 = 0.9;
+// the base levels tried, evenly spread over one interval
+const BASE_LEVEL_STEPS: usize = 5;
+// ground flatter than this gradient next to a contour level gets wandering contours
+const FLAT_GRADIENT: f64 = 0.02;
+const NEAR_LEVEL_FRACTION: f64 = 0.1;
+// base levels this close to the fewest near-flat cells are compared by their interpolation score
+const NEAR_FLAT_TOLERANCE: f64 = 0.002;
+// the slope raster is the Sobel gradient norm over √2, the Sobel kernel weighs the gradient by 4
+const SLOPE_PER_GRADIENT: f64 = 2. * std::f64::consts::SQRT_2;
+
+/// A contour interval and base level suggested from the terrain of the test tiles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContourSuggestion {
+    pub interval: f64,
+    pub base_level: f64,
+    // the mean interpolation error plus weighted bending energy of the test tiles, lower is better
+    pub score: f64,
+    // the share of cells on flat ground close to a contour level
+    pub near_flat_share: f64,
+}
+
+impl Display for ContourSuggestion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} m interval, levels from {} m ({:.1}% of the ground is flat and close to a level)",
+            self.interval,
+            self.base_level,
+            self.near_flat_share * 100.
+        )
+    }
+}
+
+/// Suggests 2.5 m contours for flat terrain when the steep parts leave room for them,
+/// otherwise 5 m. The base level is then chosen to keep the contours off flat ground
+/// that lies close to a level, and among the base levels doing so equally well the one
+/// whose contours best interpolate the terrain, scored as for the preview, is suggested
+pub fn suggest_contour_interval(
+    sender: &OmapComms<FrontendTask, BackendTask>,
+    thread_pool: &ThreadPool,
+    tiles: &[PreparedTile],
+    params: &MapParameters,
+) -> crate::Result<ContourSuggestion> {
+    if tiles.is_empty() {
+        anyhow::bail!("There are no test tiles to suggest a contour interval from");
+    }
+
+    let mut gradients = tiles
+        .iter()
+        .flat_map(|tile| inner_values(&tile.rasters.slope))
+        .map(|slope| slope / SLOPE_PER_GRADIENT)
+        .collect::<Vec<_>>();
+    let interval = suggest_interval(&mut gradients, params.scale);
+
+    let base_levels = (0..BASE_LEVEL_STEPS)
+        .map(|i| i as f64 * interval / BASE_LEVEL_STEPS as f64)
+        .collect::<Vec<_>>();
+
+    let suggestions = thread_pool.install(|| {
+        base_levels
+            .into_par_iter()
+            .map(|base_level| {
+                // form lines are left out so every base level is scored by its contours alone
+                let mut candidate = params.clone();
+                candidate.contour.interval = interval;
+                candidate.contour.base_level = base_level;
+                candidate.contour.form_lines = false;
+
+                let mut score = 0.;
+                let mut near_flat_share = 0.;
+                for tile in tiles {
+                    sender.cancel_token().check()?;
+                    let (_, error, energy) = common::extract_contours(
+                        &tile.rasters.dem,
+                        tile.z_range,
+                        &tile.cut_overlay,
+                        &candidate,
+                        true,
+                    )?;
+                    score += error + params.contour.algo_lambda * energy;
+                    near_flat_share += near_flat_share_of_tile(
+                        &tile.rasters.dem,
+                        &tile.rasters.slope,
+                        interval,
+                        base_level,
+                    );
+                }
+
+                Ok(ContourSuggestion {
+                    interval,
+                    base_level,
+                    score: score / tiles.len() as f64,
+                    near_flat_share: near_flat_share / tiles.len() as f64,
+                })
+            })
+            .collect::<crate::Result<Vec<_>>>()
+    })?;
+
+    let fewest_near_flat = suggestions
+        .iter()
+        .map(|s| s.near_flat_share)
+        .fold(f64::INFINITY, f64::min);
+    suggestions
+        .into_iter()
+        .filter(|s| s.near_flat_share <= fewest_near_flat + NEAR_FLAT_TOLERANCE)
+        .min_by(|a, b| a.score.total_cmp(&b.score))
+        .ok_or_else(|| anyhow::anyhow!("No contour base level could be scored"))
+}
+
+fn suggest_interval(gradients: &mut [f64], scale: Scale) -> f64 {
+    if gradients.is_empty() {
+        return STANDARD_INTERVAL;
+    }
+    gradients.sort_by(f64::total_cmp);
+    let quantile = |q: f64| gradients[((gradients.len() - 1) as f64 * q).round() as usize];
+    let paper_spacing =
+        |interval: f64, gradient: f64| scale.meters_to_paper_mm(interval / gradient.max(1e-6));
+
+    let is_flat = paper_spacing(STANDARD_INTERVAL, quantile(0.5)) > FLAT_TERRAIN_SPACING_MM;
+    let has_room = paper_spacing(FINE_INTERVAL, quantile(STEEP_QUANTILE)) >= MIN_CONTOUR_SPACING_MM;
+    if is_flat && has_room {
+        FINE_INTERVAL
+    } else {
+        STANDARD_INTERVAL
+    }
+}
+
+// the share of the cells inside the cut bounds that are flat and close to a contour level
+fn near_flat_share_of_tile(
+    dem: &Dfm<Elevation>,
+    slope: &Dfm<Slope>,
+    interval: f64,
+    base_level: f64,
+) -> f64 {
+    let mut cells = 0;
+    let mut near_flat = 0;
+    for y in dem.inner.top..dem.inner.bottom {
+        for x in dem.inner.left..dem.inner.right {
+            let (z, gradient) = (dem[(y, x)], slope[(y, x)] / SLOPE_PER_GRADIENT);
+            if !is_value(z) || !is_value(gradient) {
+                continue;
+            }
+            cells += 1;
+
+            let above_level = (z - base_level).rem_euclid(interval);
+            let to_level = above_level.min(interval - above_level);
+            if gradient < FLAT_GRADIENT && to_level < NEAR_LEVEL_FRACTION * interval {
+                near_flat += 1;
+            }
+        }
+    }
+
+    if cells == 0 {
+        0.
+    } else {
+        near_flat as f64 / cells as f64
+    }
+}
+
+fn inner_values<T>(dfm: &Dfm<T>) -> impl Iterator<Item = f64> + '_ {
+    (dfm.inner.top..dfm.inner.bottom).flat_map(move |y| {
+        (dfm.inner.left..dfm.inner.right)
+            .map(move |x| dfm[(y, x)])
+            .filter(|v| is_value(*v))
+    })
+}
+
+fn is_value(value: f64) -> bool {
+    value.is_finite() && value != f64::MIN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::near_flat_share_of_tile;
+    use crate::raster::{Dfm, dfm::Elevation};
+
+    #[test]
+    fn base_level_moves_contours_off_a_flat_plain() {
+        // a plain at 100.2 m, just above the 100 m level
+        let mut dem = Dfm::<Elevation>::new(geo::Coord::zero());
+        dem.field.fill(100.2);
+        let slope = dem.slope();
+
+        assert_eq!(near_flat_share_of_tile(&dem, &slope, 5., 0.), 1.);
+        assert_eq!(near_flat_share_of_tile(&dem, &slope, 5., 2.), 0.);
+    }
+}
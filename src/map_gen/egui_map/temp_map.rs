@@ -1,15 +1,28 @@
 use std::collections::HashMap;
 
-use geo::{Area, BooleanOps, BoundingRect, Buffer, Intersects, MapCoords, MapCoordsInPlace};
+use geo::{
+    Area, BooleanOps, BoundingRect, Buffer, Distance, Euclidean, Intersects, Length, MapCoords,
+    MapCoordsInPlace,
+};
 use omap::{
     NonNegativeF64, Omap,
-    objects::{AreaObject, LineObject, PointObject},
+    objects::{AreaObject, LineObject, PointObject, TextObject},
     symbols::{WeakAreaPathSymbol, WeakLinePathSymbol},
 };
 use proj_core::CrsDef;
 use rstar::{AABB, PointDistance, RTree, RTreeObject, primitives::GeomWithData};
+use serde::{Deserialize, Serialize};
+
+use crate::parameters::{RoadParameters, Scale, SymbolStandard};
 
-use crate::parameters::Scale;
+const ROAD_SYMBOLS: [LineSymbol; 6] = [
+    LineSymbol::WideRoad,
+    LineSymbol::Road,
+    LineSymbol::VehicleTrack,
+    LineSymbol::Footpath,
+    LineSymbol::SmallPath,
+    LineSymbol::LessDistinctSmallPath,
+];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Symbol {
@@ -32,14 +45,26 @@ impl Symbol {
     pub fn get_omap_symbol<'a>(
         &self,
         symbol_set: &'a omap::symbols::SymbolSet,
+        standard: SymbolStandard,
     ) -> Option<&'a omap::symbols::Symbol> {
-        let code = match self {
-            Symbol::Area(area_symbol) => area_symbol.get_code(),
-            Symbol::Line(line_symbol) => line_symbol.get_code(),
-            Symbol::Point(point_symbol) => point_symbol.get_code(),
+        let code = |standard: SymbolStandard| match self {
+            Symbol::Area(area_symbol) => area_symbol.get_code(standard),
+            Symbol::Line(line_symbol) => line_symbol.get_code(standard),
+            Symbol::Point(point_symbol) => point_symbol.get_code(standard),
         };
 
-        symbol_set.get_symbol_by_code(code)
+        // both 0.5 m contours of the ISSprOM set share a code
+        if standard == SymbolStandard::Issprom2019
+            && *self == Symbol::Line(LineSymbol::NegBasemapContour)
+            && let Some(symbol) = symbol_set.get_symbol_by_name("Negative 0.5m contour")
+        {
+            return Some(symbol);
+        }
+
+        // a custom template may only be numbered by ISOM
+        symbol_set
+            .get_symbol_by_code(code(standard))
+            .or_else(|| symbol_set.get_symbol_by_code(code(SymbolStandard::Isom2017)))
     }
 }
 
@@ -61,7 +86,7 @@ impl From<PointSymbol> for Symbol {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum AreaSymbol {
     WhiteForest,
     RoughOpenLand,
@@ -81,7 +106,12 @@ pub enum AreaSymbol {
 }
 
 impl AreaSymbol {
-    pub fn get_code(&self) -> omap::Code {
+    pub fn get_code(&self, standard: SymbolStandard) -> omap::Code {
+        // ISSprOM splits paved areas by their bounding line
+        if standard == SymbolStandard::Issprom2019 && *self == AreaSymbol::PavedAreaWithBoundary {
+            return omap::Code::new(501, 1, 0);
+        }
+
         match self {
             AreaSymbol::RoughOpenLand => omap::Code::new(403, 0, 0),
             AreaSymbol::OpenLand => omap::Code::new(401, 0, 0),
@@ -101,8 +131,9 @@ impl AreaSymbol {
         }
     }
 
+    /// The minimum area in square meters, given at the reference scale of the standard
     pub fn min_size(&self, scale: &Scale) -> f64 {
-        let a = match self {
+        let isom = match self {
             AreaSymbol::WhiteForest => 64.,
             AreaSymbol::RoughOpenLand => 225.,
             AreaSymbol::OpenLand => 64.,
@@ -119,15 +150,31 @@ impl AreaSymbol {
             AreaSymbol::GiganticBoulder => 67.,
             AreaSymbol::Building => 56.,
         };
-        let multiplier = match scale {
-            Scale::S10_000 => 4. / 9.,
-            Scale::S15_000 => 1.,
+        let a = match scale.symbol_standard() {
+            SymbolStandard::Isom2017 => isom,
+            SymbolStandard::Issprom2019 => match self {
+                AreaSymbol::WhiteForest => 4.,
+                AreaSymbol::RoughOpenLand => 8.,
+                AreaSymbol::OpenLand => 4.,
+                AreaSymbol::SandyGround => 8.,
+                AreaSymbol::BareRock => 8.,
+                AreaSymbol::LightGreen => 8.,
+                AreaSymbol::MediumGreen => 6.,
+                AreaSymbol::DarkGreen => 4.,
+                AreaSymbol::Marsh => 4.,
+                AreaSymbol::PrivateArea => 4.,
+                AreaSymbol::PavedAreaWithBoundary => 4.,
+                AreaSymbol::ShallowWaterWithSolidBankLine => 4.,
+                AreaSymbol::UncrossableWaterWithBankLine => 4.,
+                AreaSymbol::GiganticBoulder => 4.,
+                AreaSymbol::Building => 4.,
+            },
         };
-        a * multiplier
+        a * scale.reference_ratio().powi(2)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum LineSymbol {
     BasemapContour,
     FormLine,
@@ -135,10 +182,31 @@ pub enum LineSymbol {
     IndexContour,
     NegBasemapContour,
     SmallCrossableWatercourse,
+    WideRoad,
+    Road,
+    VehicleTrack,
+    Footpath,
+    SmallPath,
+    LessDistinctSmallPath,
 }
 
 impl LineSymbol {
-    pub fn get_code(&self) -> omap::Code {
+    pub fn get_code(&self, standard: SymbolStandard) -> omap::Code {
+        // ISSprOM has no road lines, the paved ones become paved footpaths of their width
+        if standard == SymbolStandard::Issprom2019 {
+            match self {
+                LineSymbol::BasemapContour | LineSymbol::NegBasemapContour => {
+                    return omap::Code::new(101, 5, 0);
+                }
+                LineSymbol::WideRoad => return omap::Code::new(501, 12, 0),
+                LineSymbol::Road => return omap::Code::new(501, 11, 0),
+                LineSymbol::VehicleTrack | LineSymbol::Footpath => {
+                    return omap::Code::new(506, 0, 0);
+                }
+                _ => (),
+            }
+        }
+
         match self {
             LineSymbol::BasemapContour => omap::Code::new(101, 2, 0),
             LineSymbol::FormLine => omap::Code::new(103, 0, 0),
@@ -146,10 +214,22 @@ impl LineSymbol {
             LineSymbol::IndexContour => omap::Code::new(102, 0, 0),
             LineSymbol::NegBasemapContour => omap::Code::new(101, 3, 0),
             LineSymbol::SmallCrossableWatercourse => omap::Code::new(305, 0, 0),
+            LineSymbol::WideRoad => omap::Code::new(502, 0, 0),
+            LineSymbol::Road => omap::Code::new(503, 0, 0),
+            LineSymbol::VehicleTrack => omap::Code::new(504, 0, 0),
+            LineSymbol::Footpath => omap::Code::new(505, 0, 0),
+            LineSymbol::SmallPath => omap::Code::new(506, 0, 0),
+            LineSymbol::LessDistinctSmallPath => omap::Code::new(507, 0, 0),
         }
     }
 
+    /// The minimum length in meters on the ground, the ISOM lengths are given at 1:15 000 and
+    /// the ISSprOM lengths on paper
     pub fn min_length(&self, scale: Scale, is_closed: bool) -> f64 {
+        if scale.symbol_standard() == SymbolStandard::Issprom2019 {
+            return scale.paper_mm_to_meters(self.sprint_min_length(is_closed));
+        }
+
         let l = match self {
             LineSymbol::BasemapContour => 3.,
             LineSymbol::FormLine => {
@@ -168,16 +248,51 @@ impl LineSymbol {
             }
             LineSymbol::NegBasemapContour => 3.,
             LineSymbol::SmallCrossableWatercourse => 15.,
+            LineSymbol::WideRoad | LineSymbol::Road | LineSymbol::VehicleTrack => 20.,
+            LineSymbol::Footpath | LineSymbol::SmallPath | LineSymbol::LessDistinctSmallPath => 15.,
         };
-        let multiplier = match scale {
-            Scale::S10_000 => 2. / 3.,
-            Scale::S15_000 => 1.,
-        };
-        l * multiplier
+        l * scale.reference_ratio()
+    }
+
+    /// The contours keep the higher ground on one side, the other lines may be turned around
+    pub fn is_directed(&self) -> bool {
+        matches!(
+            self,
+            LineSymbol::BasemapContour
+                | LineSymbol::FormLine
+                | LineSymbol::Contour
+                | LineSymbol::IndexContour
+                | LineSymbol::NegBasemapContour
+        )
+    }
+
+    // In paper mm. ISSprOM allows shorter lines than the ISOM lengths on paper,
+    // the paths and small features of parks and towns are mapped
+    fn sprint_min_length(&self, is_closed: bool) -> f64 {
+        match self {
+            LineSymbol::BasemapContour | LineSymbol::NegBasemapContour => 0.2,
+            LineSymbol::FormLine => {
+                if is_closed {
+                    8.
+                } else {
+                    12.
+                }
+            }
+            LineSymbol::Contour | LineSymbol::IndexContour => {
+                if is_closed {
+                    6.
+                } else {
+                    0.6
+                }
+            }
+            LineSymbol::SmallCrossableWatercourse => 0.8,
+            LineSymbol::WideRoad | LineSymbol::Road | LineSymbol::VehicleTrack => 1.,
+            LineSymbol::Footpath | LineSymbol::SmallPath | LineSymbol::LessDistinctSmallPath => 0.8,
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum PointSymbol {
     SlopeLineFormLine,
     SlopeLineContour,
@@ -186,10 +301,15 @@ pub enum PointSymbol {
     UDepression,
     SmallBoulder,
     LargeBoulder,
+    ContourValue,
 }
 
 impl PointSymbol {
-    pub fn get_code(&self) -> omap::Code {
+    pub fn get_code(&self, standard: SymbolStandard) -> omap::Code {
+        if standard == SymbolStandard::Issprom2019 && *self == PointSymbol::ContourValue {
+            return omap::Code::new(102, 3, 0);
+        }
+
         match self {
             PointSymbol::SlopeLineFormLine => omap::Code::new(103, 1, 0),
             PointSymbol::SlopeLineContour => omap::Code::new(101, 1, 0),
@@ -198,10 +318,12 @@ impl PointSymbol {
             PointSymbol::UDepression => omap::Code::new(111, 0, 0),
             PointSymbol::SmallBoulder => omap::Code::new(204, 0, 0),
             PointSymbol::LargeBoulder => omap::Code::new(205, 0, 0),
+            PointSymbol::ContourValue => omap::Code::new(102, 1, 0),
         }
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub enum MapObject {
     Area {
         object: geo::Polygon,
@@ -277,8 +399,16 @@ impl MapObject {
     }
 
     pub fn add_elevation_tag(&mut self, elevation: f64) {
-        let key = "Elevation".to_string();
-        let value = elevation.to_string();
+        self.add_tag("Elevation", elevation.to_string());
+    }
+
+    /// The width of a road or path in meters
+    pub fn add_width_tag(&mut self, width: f64) {
+        self.add_tag("Width", width.to_string());
+    }
+
+    fn add_tag(&mut self, key: &str, value: String) {
+        let key = key.to_string();
 
         match self {
             MapObject::Area {
@@ -348,6 +478,24 @@ impl MergeLine {
             .map(|elevation| (elevation * 100.).round() as i64)
     }
 
+    fn width(&self) -> Option<f64> {
+        self.tags
+            .get("Width")
+            .and_then(|width| width.parse::<f64>().ok())
+    }
+
+    // the width of two merged roads is their mean width over the length
+    fn merge_width(&mut self, other: &MergeLine) {
+        if let (Some(width), Some(other_width)) = (self.width(), other.width()) {
+            let length = Euclidean.length(&self.object);
+            let other_length = Euclidean.length(&other.object);
+            if length + other_length > 0. {
+                let width = (width * length + other_width * other_length) / (length + other_length);
+                self.tags.insert("Width".to_string(), width.to_string());
+            }
+        }
+    }
+
     fn start_point(&self) -> [f64; 2] {
         let start = self.object.0[0];
         [start.x, start.y]
@@ -521,10 +669,16 @@ impl TempMap {
         map_objects.extend(others);
     }
 
-    pub fn into_omap(
-        mut self,
+    /// An empty omap with the symbol set of the scale, ISSprOM 2019 for the sprint scales,
+    /// georeferenced at the ref point.
+    ///
+    /// Scales without a default set of their own, e.g. 1:5 000, use the set of their standard at
+    /// the paper dimensions it is drawn for. The colours and symbols of a template replace the
+    /// default ones and the symbols are looked up by their ISOM code
+    pub(super) fn georeferenced_omap(
+        &self,
         meters_above_sea: f64,
-        bezier_error: Option<f64>,
+        template: Option<Omap>,
     ) -> crate::Result<Omap> {
         let crs = self
             .crs
@@ -532,14 +686,47 @@ impl TempMap {
             .map(|crs| omap::geo_referencing::CrsType::Epsg(crs.epsg() as u16))
             .unwrap_or(omap::geo_referencing::CrsType::Local);
 
-        let mut omap = match self.scale {
-            Scale::S10_000 => {
-                Omap::default_10_000_geo_referenced(self.ref_point, crs, meters_above_sea)?
+        let mut omap = match (self.scale, self.scale.symbol_standard()) {
+            (Scale::S10_000, _) => {
+                Omap::default_10_000_geo_referenced(self.ref_point, crs.clone(), meters_above_sea)?
             }
-            Scale::S15_000 => {
-                Omap::default_15_000_geo_referenced(self.ref_point, crs, meters_above_sea)?
+            (_, SymbolStandard::Isom2017) => {
+                Omap::default_15_000_geo_referenced(self.ref_point, crs.clone(), meters_above_sea)?
+            }
+            (_, SymbolStandard::Issprom2019) => {
+                Omap::default_4_000_geo_referenced(self.ref_point, crs.clone(), meters_above_sea)?
             }
         };
+
+        // the default sets are drawn for 1:4 000, 1:10 000 and 1:15 000,
+        // the other scales keep the paper dimensions of the set
+        let denominator = self.scale.denominator() as u32;
+        if omap.geo_referencing.scale_denominator != denominator {
+            omap.geo_referencing = omap::geo_referencing::GeoRef::initialize(
+                self.ref_point,
+                crs,
+                meters_above_sea,
+                denominator,
+            )?;
+        }
+
+        // the symbols refer to the colours of their own set
+        if let Some(template) = template {
+            omap.colors = template.colors;
+            omap.symbols = template.symbols;
+        }
+
+        Ok(omap)
+    }
+
+    pub fn into_omap(
+        mut self,
+        meters_above_sea: f64,
+        bezier_error: Option<f64>,
+        template: Option<Omap>,
+    ) -> crate::Result<Omap> {
+        let mut omap = self.georeferenced_omap(meters_above_sea, template)?;
+        let standard = self.scale.symbol_standard();
         let transform = omap.geo_referencing.get_transform();
 
         for (_, objects) in self.objects.drain() {
@@ -554,7 +741,7 @@ impl TempMap {
                         let mut area = AreaObject::new(
                             WeakAreaPathSymbol::try_from(
                                 Symbol::Area(symbol)
-                                    .get_omap_symbol(&omap.symbols)
+                                    .get_omap_symbol(&omap.symbols, standard)
                                     .ok_or_else(|| omap::Error::MissingSymbolId)?
                                     .downgrade(),
                             )?,
@@ -572,7 +759,7 @@ impl TempMap {
                         let mut line = LineObject::new(
                             WeakLinePathSymbol::try_from(
                                 Symbol::Line(symbol)
-                                    .get_omap_symbol(&omap.symbols)
+                                    .get_omap_symbol(&omap.symbols, standard)
                                     .ok_or_else(|| omap::Error::MissingSymbolId)?
                                     .downgrade(),
                             )?,
@@ -598,16 +785,32 @@ impl TempMap {
                     } => {
                         let object = object.map_coords(|c| c + self.ref_point);
                         let omap_symbol = Symbol::Point(symbol)
-                            .get_omap_symbol(&omap.symbols)
+                            .get_omap_symbol(&omap.symbols, standard)
                             .ok_or_else(|| omap::Error::MissingSymbolId)?;
-                        let symbol = match omap_symbol {
-                            omap::symbols::Symbol::Point(symbol) => std::rc::Rc::downgrade(symbol),
+                        match omap_symbol {
+                            omap::symbols::Symbol::Point(symbol) => {
+                                let mut point = PointObject::new(
+                                    std::rc::Rc::downgrade(symbol),
+                                    transform.to_map_point(object),
+                                );
+                                point.rotation = rotation;
+                                point.tags = tags;
+                                point.into()
+                            }
+                            // contour values, the text is the elevation tag
+                            omap::symbols::Symbol::Text(symbol) => {
+                                let text = tags.get("Elevation").cloned().unwrap_or_default();
+                                let mut label = TextObject::new(
+                                    std::rc::Rc::downgrade(symbol),
+                                    transform.to_map_point(object),
+                                    text,
+                                );
+                                label.rotation = rotation;
+                                label.tags = tags;
+                                label.into()
+                            }
                             _ => Err(omap::Error::MissingSymbolId)?,
-                        };
-                        let mut point = PointObject::new(symbol, transform.to_map_point(object));
-                        point.rotation = rotation;
-                        point.tags = tags;
-                        point.into()
+                        }
                     }
                 };
                 omap.parts.0[0].add_object(omap_object);
@@ -660,6 +863,83 @@ impl TempMap {
         }
     }
 
+    /// Adds slope lines on the downhill side of contour and form line loops around depressions,
+    /// and at the ends of short open contours with both ends at the edge of the mapped area,
+    /// as their slope can not be read from the shape of the line.
+    ///
+    /// The contours are traced with the higher ground on their left side,
+    /// the same orientation that makes depression loops clockwise
+    pub fn add_slope_lines(&mut self, map_edge: &geo::MultiPolygon) {
+        let spacing = SLOPE_LINE_SPACING_MM * self.scale.denominator() / 1000.;
+        let max_edge_length = EDGE_SLOPE_LINE_MAX_LENGTH_MM * self.scale.denominator() / 1000.;
+        let end_offset = SLOPE_LINE_END_OFFSET_MM * self.scale.denominator() / 1000.;
+        let edge_tolerance = MAP_EDGE_TOLERANCE_MM * self.scale.denominator() / 1000.;
+
+        let keys = [
+            (LineSymbol::Contour, PointSymbol::SlopeLineContour),
+            (LineSymbol::IndexContour, PointSymbol::SlopeLineContour),
+            (LineSymbol::FormLine, PointSymbol::SlopeLineFormLine),
+        ];
+
+        let mut slope_lines = Vec::new();
+        for (line_symbol, point_symbol) in keys {
+            let Some(lines) = self.objects.get(&Symbol::Line(line_symbol)) else {
+                continue;
+            };
+
+            for object in lines {
+                let MapObject::Line { object, .. } = object else {
+                    continue;
+                };
+                let length = line_string_length(object);
+                if length == 0. {
+                    continue;
+                }
+
+                let positions = if object.is_closed() {
+                    if line_string_signed_area(object) >= 0. {
+                        continue;
+                    }
+                    // evenly spread around the loop, larger depressions get a few more
+                    let count = (length / spacing)
+                        .ceil()
+                        .clamp(1., MAX_SLOPE_LINES_PER_LOOP);
+                    (0..count as usize)
+                        .map(|i| (i as f64 + 0.5) * length / count)
+                        .collect::<Vec<_>>()
+                } else if length <= max_edge_length
+                    && object
+                        .points()
+                        .next()
+                        .zip(object.points().last())
+                        .is_some_and(|(first, last)| {
+                            distance_to_edge(map_edge, first) <= edge_tolerance
+                                && distance_to_edge(map_edge, last) <= edge_tolerance
+                        })
+                {
+                    let offset = end_offset.min(length / 2.);
+                    vec![offset, length - offset]
+                } else {
+                    continue;
+                };
+
+                for distance in positions {
+                    let (point, direction) = point_along_line(object, distance);
+                    slope_lines.push(MapObject::Point {
+                        object: geo::Point(point),
+                        symbol: point_symbol,
+                        rotation: slope_line_rotation(direction),
+                        tags: HashMap::new(),
+                    });
+                }
+            }
+        }
+
+        for slope_line in slope_lines {
+            self.add_object(slope_line);
+        }
+    }
+
     /// Turn small contour loops to dotknolls and depressions and remove the smallest ones
     /// dot_knolls smaller than (min+max)/2 + min will never be drawn as elongated
     pub fn make_dotknolls_and_depressions(
@@ -757,139 +1037,282 @@ impl TempMap {
     /// Merge line objects that are tip to tail.
     /// Line ends (directed) of the same symbol that are less than `delta`
     /// units apart are merged. Elevation tags are respected and only elements
-    /// with equal elevation tags can be merged.
+    /// with equal elevation tags can be merged. Lines of symbols without a
+    /// direction are turned around where that lets them merge.
     pub fn merge_lines(&mut self, delta: f64) {
         for (key, map_objects) in self.objects.iter_mut() {
-            if !matches!(key, Symbol::Line(_)) {
+            let Symbol::Line(symbol) = key else {
                 continue;
+            };
+            merge_line_objects(map_objects, symbol.is_directed(), delta);
+        }
+    }
+
+    /// Merge the road and path lines of every class, a road cut at a tile edge
+    /// may be classified differently on each side. Every merged line is
+    /// classified again from its width over its whole length.
+    pub fn merge_roads(&mut self, delta: f64, params: &RoadParameters) {
+        let mut roads = Vec::new();
+        for symbol in ROAD_SYMBOLS {
+            if let Some(objects) = self.objects.remove(&Symbol::Line(symbol)) {
+                roads.extend(objects);
             }
-            let delta = delta * delta;
+        }
 
-            let mut unclosed_objects = Vec::with_capacity(map_objects.len());
+        merge_line_objects(&mut roads, false, delta);
 
-            let mut i = 0;
-            while i < map_objects.len() {
-                if let MapObject::Line {
+        for mut road in roads {
+            if let MapObject::Line { symbol, tags, .. } = &mut road
+                && let Some(width) = tags.get("Width").and_then(|w| w.parse::<f64>().ok())
+            {
+                *symbol = params.line_symbol(width);
+            }
+            self.add_object(road);
+        }
+    }
+}
+
+// merges the open lines of one symbol, see `TempMap::merge_lines`
+fn merge_line_objects(map_objects: &mut Vec<MapObject>, directed: bool, delta: f64) {
+    let delta = delta * delta;
+
+    let mut unclosed_objects = Vec::with_capacity(map_objects.len());
+
+    let mut i = 0;
+    while i < map_objects.len() {
+        if let MapObject::Line {
+            object,
+            symbol: _,
+            tags: _,
+        } = &map_objects[i]
+        {
+            if !object.is_closed() && object.0.len() >= 2 {
+                let MapObject::Line {
                     object,
-                    symbol: _,
-                    tags: _,
-                } = &map_objects[i]
-                {
-                    if !object.is_closed() && object.0.len() >= 2 {
-                        let MapObject::Line {
-                            object,
-                            symbol,
-                            tags,
-                        } = map_objects.swap_remove(i)
-                        else {
-                            unreachable!("checked line object before swap_remove");
-                        };
-                        unclosed_objects.push(MergeLine {
-                            object,
-                            symbol,
-                            tags,
-                        });
-                    } else {
-                        i += 1;
-                    }
-                } else {
-                    i += 1;
-                }
+                    symbol,
+                    tags,
+                } = map_objects.swap_remove(i)
+                else {
+                    unreachable!("checked line object before swap_remove");
+                };
+                unclosed_objects.push(MergeLine {
+                    object,
+                    symbol,
+                    tags,
+                });
+            } else {
+                i += 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+
+    let mut unclosed_object_groups = HashMap::<Option<i64>, Vec<MergeLine>>::new();
+    for unclosed_object in unclosed_objects {
+        unclosed_object_groups
+            .entry(unclosed_object.elevation_key())
+            .or_default()
+            .push(unclosed_object);
+    }
+
+    for (_, mut unclosed_objects) in unclosed_object_groups {
+        if !directed {
+            orient_lines(&mut unclosed_objects, delta);
+        }
+
+        let (line_ends, line_starts): (Vec<_>, Vec<_>) = unclosed_objects
+            .iter()
+            .enumerate()
+            .map(|(i, o)| (GeomWithData::new(o.end_point(), i), o.start_point()))
+            .collect();
+
+        // detect the merges needed
+        let end_tree = RTree::bulk_load(line_ends);
+
+        let mut merges = Vec::with_capacity(line_starts.len());
+        for (start_i, line_start) in line_starts.iter().enumerate() {
+            if let Some(nn) = end_tree.nearest_neighbor(*line_start)
+                && nn.distance_2(line_start) <= delta
+            {
+                merges.push((start_i, nn.data));
             }
+        }
+
+        // start doing merges keeping track of the moved objects
+        while let Some(merge) = merges.pop() {
+            if merge.0 == merge.1 {
+                let mut line = unclosed_objects.swap_remove(merge.0);
+                line.object.close();
+
+                map_objects.push(line.into_map_object());
+            } else {
+                // merge
+                let part2 = unclosed_objects.swap_remove(merge.0);
+
+                let part1 = if merge.1 >= unclosed_objects.len() {
+                    &mut unclosed_objects[merge.0]
+                } else {
+                    &mut unclosed_objects[merge.1]
+                };
 
-            let mut unclosed_object_groups = HashMap::<Option<i64>, Vec<MergeLine>>::new();
-            for unclosed_object in unclosed_objects {
-                unclosed_object_groups
-                    .entry(unclosed_object.elevation_key())
-                    .or_default()
-                    .push(unclosed_object);
+                part1.merge_width(&part2);
+                let _ = part1.object.0.pop();
+                part1.object.0.extend(part2.object.0);
             }
+            // update map
+            let mut i = 0;
+            while i < merges.len() {
+                let other_merge = &mut merges[i];
 
-            for (_, mut unclosed_objects) in unclosed_object_groups {
-                let (line_ends, line_starts): (Vec<_>, Vec<_>) = unclosed_objects
-                    .iter()
-                    .enumerate()
-                    .map(|(i, o)| (GeomWithData::new(o.end_point(), i), o.start_point()))
-                    .collect();
-
-                // detect the merges needed
-                let end_tree = RTree::bulk_load(line_ends);
-
-                let mut merges = Vec::with_capacity(line_starts.len());
-                for (start_i, line_start) in line_starts.iter().enumerate() {
-                    if let Some(nn) = end_tree.nearest_neighbor(*line_start)
-                        && nn.distance_2(line_start) <= delta
-                    {
-                        merges.push((start_i, nn.data));
-                    }
+                // find merges made impossible
+                if other_merge.1 == merge.1 || other_merge.0 == merge.0 {
+                    let _ = merges.swap_remove(i);
+                    continue;
+                } else {
+                    i += 1;
                 }
 
-                // start doing merges keeping track of the moved objects
-                while let Some(merge) = merges.pop() {
-                    if merge.0 == merge.1 {
-                        let mut line = unclosed_objects.swap_remove(merge.0);
-                        line.object.close();
+                // update map as merge.0 is now called merge.1
+                if other_merge.0 == merge.0 {
+                    other_merge.0 = merge.1
+                }
+                if other_merge.1 == merge.0 {
+                    other_merge.1 = merge.1
+                }
 
-                        map_objects.push(line.into_map_object());
-                    } else {
-                        // merge
-                        let part2 = unclosed_objects.swap_remove(merge.0);
+                // correct map for swap remove moving object
+                if other_merge.0 >= unclosed_objects.len() {
+                    other_merge.0 = merge.0;
+                }
+                if other_merge.1 >= unclosed_objects.len() {
+                    other_merge.1 = merge.0;
+                }
+            }
+        }
+        let unclosed = unclosed_objects.into_iter().map(|mut line_object| {
+            // check if it is almost closed
+            let start = line_object.object.0[0];
+            let end = line_object.object.0[line_object.object.0.len() - 1];
 
-                        let part1 = if merge.1 >= unclosed_objects.len() {
-                            &mut unclosed_objects[merge.0]
-                        } else {
-                            &mut unclosed_objects[merge.1]
-                        };
+            if (start.x - end.x).powi(2) + (start.y - end.y).powi(2) <= delta {
+                line_object.object.close();
+            }
 
-                        let _ = part1.object.0.pop();
-                        part1.object.0.extend(part2.object.0);
-                    }
-                    // update map
-                    let mut i = 0;
-                    while i < merges.len() {
-                        let other_merge = &mut merges[i];
-
-                        // find merges made impossible
-                        if other_merge.1 == merge.1 || other_merge.0 == merge.0 {
-                            let _ = merges.swap_remove(i);
-                            continue;
-                        } else {
-                            i += 1;
-                        }
+            line_object.into_map_object()
+        });
 
-                        // update map as merge.0 is now called merge.1
-                        if other_merge.0 == merge.0 {
-                            other_merge.0 = merge.1
-                        }
-                        if other_merge.1 == merge.0 {
-                            other_merge.1 = merge.1
-                        }
+        map_objects.extend(unclosed);
+    }
+}
 
-                        // correct map for swap remove moving object
-                        if other_merge.0 >= unclosed_objects.len() {
-                            other_merge.0 = merge.0;
-                        }
-                        if other_merge.1 >= unclosed_objects.len() {
-                            other_merge.1 = merge.0;
-                        }
+// turns lines around so the ends that meet are tip to tail, a line is only turned
+// the first time it is reached from a line meeting it
+fn orient_lines(lines: &mut [MergeLine], delta: f64) {
+    let ends = RTree::bulk_load(
+        lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                [
+                    GeomWithData::new(line.start_point(), (i, true)),
+                    GeomWithData::new(line.end_point(), (i, false)),
+                ]
+            })
+            .collect(),
+    );
+
+    let mut reversed = vec![false; lines.len()];
+    let mut reached = vec![false; lines.len()];
+    for first in 0..lines.len() {
+        if reached[first] {
+            continue;
+        }
+        reached[first] = true;
+
+        let mut queue = vec![first];
+        while let Some(i) = queue.pop() {
+            for (point, is_start) in [
+                (lines[i].start_point(), true),
+                (lines[i].end_point(), false),
+            ] {
+                // whether this end is the start once the line is oriented
+                let starts = is_start != reversed[i];
+                for end in ends.locate_within_distance(point, delta) {
+                    let (j, other_is_start) = end.data;
+                    if reached[j] {
+                        continue;
                     }
+                    reached[j] = true;
+                    reversed[j] = other_is_start == starts;
+                    queue.push(j);
                 }
-                let unclosed = unclosed_objects.into_iter().map(|mut line_object| {
-                    // check if it is almost closed
-                    let start = line_object.object.0[0];
-                    let end = line_object.object.0[line_object.object.0.len() - 1];
+            }
+        }
+    }
 
-                    if (start.x - end.x).powi(2) + (start.y - end.y).powi(2) <= delta {
-                        line_object.object.close();
-                    }
+    for (line, reversed) in lines.iter_mut().zip(reversed) {
+        if reversed {
+            line.object.0.reverse();
+        }
+    }
+}
 
-                    line_object.into_map_object()
-                });
+// distance between the slope lines around a depression, in paper millimetres
+const SLOPE_LINE_SPACING_MM: f64 = 25.;
+const MAX_SLOPE_LINES_PER_LOOP: f64 = 4.;
+// open contours up to this length get slope lines at their ends
+const EDGE_SLOPE_LINE_MAX_LENGTH_MM: f64 = 20.;
+// keeps the slope lines at the ends of a contour off the map edge
+const SLOPE_LINE_END_OFFSET_MM: f64 = 1.;
+// the contours are cut at the convex hull of the points, which may lie a bit inside the edge
+const MAP_EDGE_TOLERANCE_MM: f64 = 0.5;
+
+fn distance_to_edge(map_edge: &geo::MultiPolygon, point: geo::Point) -> f64 {
+    map_edge
+        .iter()
+        .flat_map(|polygon| std::iter::once(polygon.exterior()).chain(polygon.interiors()))
+        .map(|ring| Euclidean.distance(&point, ring))
+        .fold(f64::INFINITY, f64::min)
+}
 
-                map_objects.extend(unclosed);
-            }
+pub(super) fn line_string_length(line: &geo::LineString) -> f64 {
+    line.lines().map(|l| l.dx().hypot(l.dy())).sum()
+}
+
+// the point at the given distance along the line and the direction of the line there
+pub(super) fn point_along_line(line: &geo::LineString, distance: f64) -> (geo::Coord, geo::Coord) {
+    let mut walked = 0.;
+    let mut last = (line.0[0], geo::Coord::zero());
+    for segment in line.lines() {
+        let delta = segment.end - segment.start;
+        let length = delta.x.hypot(delta.y);
+        if length == 0. {
+            continue;
         }
+
+        let direction = delta / length;
+        if walked + length >= distance {
+            return (segment.start + direction * (distance - walked), direction);
+        }
+        walked += length;
+        last = (segment.end, direction);
     }
+    last
+}
+
+// the slope line symbols point south when unrotated and are turned to the right side
+// of the line, which is downhill
+fn slope_line_rotation(direction: geo::Coord) -> f64 {
+    let downhill = geo::Coord {
+        x: direction.y,
+        y: -direction.x,
+    };
+    normalize_full_angle(downhill.y.atan2(downhill.x) + std::f64::consts::FRAC_PI_2)
+}
+
+pub(super) fn normalize_full_angle(angle: f64) -> f64 {
+    angle.rem_euclid(std::f64::consts::TAU)
 }
 
 fn line_string_signed_area(line: &geo::LineString) -> f64 {
@@ -1119,3 +1542,113 @@ fn polygon_envelope(polygon: &geo::Polygon) -> Option<AABB<[f64; 2]>> {
         [rect.max().x, rect.max().y],
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::map_gen::egui_map::{LineSymbol, MapObject, PointSymbol, Symbol, TempMap};
+    use crate::parameters::{RoadParameters, Scale};
+
+    #[test]
+    fn slope_lines_point_into_depressions() {
+        let mut map = TempMap::new(geo::Coord::zero(), Scale::S4_000, None);
+        // clockwise, so the inside is lower
+        let depression =
+            geo::LineString::from(vec![(0., 0.), (0., 40.), (40., 40.), (40., 0.), (0., 0.)]);
+        map.add_object(MapObject::Line {
+            object: depression,
+            symbol: LineSymbol::Contour,
+            tags: HashMap::new(),
+        });
+
+        map.add_slope_lines(&geo::MultiPolygon::new(vec![]));
+
+        let slope_lines = &map.objects[&Symbol::Point(PointSymbol::SlopeLineContour)];
+        assert_eq!(slope_lines.len(), 2);
+        for slope_line in slope_lines {
+            let MapObject::Point {
+                object, rotation, ..
+            } = slope_line
+            else {
+                panic!("slope lines are points");
+            };
+            // the unrotated symbol points south, rotate that to the tick direction
+            let tick = geo::Coord {
+                x: rotation.sin(),
+                y: -rotation.cos(),
+            };
+            let to_center = geo::Coord { x: 20., y: 20. } - object.0;
+            assert!(tick.x * to_center.x + tick.y * to_center.y > 0.);
+        }
+    }
+
+    #[test]
+    fn sprint_lengths_follow_the_paper() {
+        // the same length on paper, so 1:5 000 needs longer lines on the ground
+        let at_4_000 = LineSymbol::Contour.min_length(Scale::S4_000, true);
+        let at_5_000 = LineSymbol::Contour.min_length(Scale::S5_000, true);
+        assert!((at_4_000 - 24.).abs() < 1e-9);
+        assert!((at_5_000 - 30.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn only_contours_cut_by_the_map_edge_get_end_slope_lines() {
+        let mut map = TempMap::new(geo::Coord::zero(), Scale::S4_000, None);
+        let map_edge =
+            geo::MultiPolygon::new(vec![geo::Rect::new((0., 0.), (200., 200.)).to_polygon()]);
+        // both ends on the left edge
+        let cut = geo::LineString::from(vec![(0., 40.), (20., 50.), (0., 60.)]);
+        // as short, but ending inside the map, e.g. cut by a removed conflict
+        let inside = geo::LineString::from(vec![(100., 40.), (120., 50.), (100., 60.)]);
+        // one end on the edge
+        let half = geo::LineString::from(vec![(0., 140.), (20., 150.), (40., 150.)]);
+        for line in [cut, inside, half] {
+            map.add_object(MapObject::Line {
+                object: line,
+                symbol: LineSymbol::Contour,
+                tags: HashMap::new(),
+            });
+        }
+
+        map.add_slope_lines(&map_edge);
+
+        let slope_lines = &map.objects[&Symbol::Point(PointSymbol::SlopeLineContour)];
+        assert_eq!(slope_lines.len(), 2);
+        for slope_line in slope_lines {
+            let MapObject::Point { object, .. } = slope_line else {
+                panic!("slope lines are points");
+            };
+            assert!(object.x() < 20. && (40. ..=60.).contains(&object.y()));
+        }
+    }
+
+    #[test]
+    fn roads_are_merged_across_classes_and_directions() {
+        let mut map = TempMap::new(geo::Coord::zero(), Scale::S10_000, None);
+        // the same road traced in opposite directions and classified apart in two tiles
+        for (line, symbol, width) in [
+            (vec![(0., 0.), (10., 0.)], LineSymbol::VehicleTrack, 3.5),
+            (vec![(20., 0.), (10., 0.)], LineSymbol::WideRoad, 6.5),
+        ] {
+            let mut road = MapObject::Line {
+                object: geo::LineString::from(line),
+                symbol,
+                tags: HashMap::new(),
+            };
+            road.add_width_tag(width);
+            map.add_object(road);
+        }
+
+        map.merge_roads(0.5, &RoadParameters::default());
+
+        // classified by the mean width of 5 m
+        assert_eq!(map.objects.len(), 1);
+        let roads = &map.objects[&Symbol::Line(LineSymbol::Road)];
+        assert_eq!(roads.len(), 1);
+        let MapObject::Line { object, .. } = &roads[0] else {
+            panic!("roads are lines");
+        };
+        assert_eq!(object.0.len(), 3);
+    }
+}
@@ -0,0 +1,14 @@
+use std::collections::HashMap;
+
+use super::{LineSymbol, MapObject};
+
+/// A contour line with its elevation tag
+pub fn contour(coords: Vec<(f64, f64)>, symbol: LineSymbol, elevation: f64) -> MapObject {
+    let mut object = MapObject::Line {
+        object: geo::LineString::from(coords),
+        symbol,
+        tags: HashMap::new(),
+    };
+    object.add_elevation_tag(elevation);
+    object
+}
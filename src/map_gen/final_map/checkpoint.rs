@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use proj_core::CrsDef;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Result,
+    map_gen::{egui_map::MapObject, final_map::streams::TileFlow},
+    parameters::{FileParameters, MapParameters},
+    raster::{
+        Dfm,
+        dfm::{
+            Elevation, FlowAccumulation, Ground, HeightAboveGround, HighVegetation, Hillshade,
+            Intensity, LastReturn, LowVegetation, MediumVegetation, Ndvd, Returns, Slope,
+            SurfaceObjects, Water,
+        },
+    },
+    statistics::LidarStats,
+};
+
+// bump when the tile or manifest encoding or the tiling changes so old checkpoints are discarded
+const CHECKPOINT_VERSION: u32 = 5;
+const MANIFEST_FILE: &str = "manifest.bin";
+
+/// Everything a finished tile adds to the final map
+#[derive(Default, Serialize, Deserialize)]
+pub struct TileCheckpoint {
+    pub objects: Vec<MapObject>,
+    pub slope: Option<Dfm<Slope>>,
+    pub hillshade: Option<Dfm<Hillshade>>,
+    pub last_return: Option<Dfm<LastReturn>>,
+    pub canopy_height: Option<Dfm<HeightAboveGround>>,
+    pub surface_objects: Option<Dfm<SurfaceObjects>>,
+    pub ndvd: Option<Dfm<Ndvd>>,
+    pub flow_accumulation: Option<Dfm<FlowAccumulation>>,
+    pub dem: Option<Dfm<Elevation>>,
+    pub intensity: Option<Dfm<Intensity>>,
+    pub return_number: Option<Dfm<Returns>>,
+    pub vegetation_density: Option<VegetationDensity>,
+    pub water: Option<Dfm<Water>>,
+    pub flow: Option<TileFlow>,
+}
+
+/// The point density bands the vegetation is mapped from
+#[derive(Serialize, Deserialize)]
+pub struct VegetationDensity {
+    pub ground: Dfm<Ground>,
+    pub low: Dfm<LowVegetation>,
+    pub medium: Dfm<MediumVegetation>,
+    pub high: Dfm<HighVegetation>,
+}
+
+// the inputs that decide the content of every tile
+#[derive(Serialize)]
+struct Manifest<'a> {
+    version: u32,
+    paths: &'a [PathBuf],
+    output_epsg: Option<u32>,
+    rasters: [bool; 12],
+    polygon_filter: &'a Option<geo::Polygon>,
+    stats: &'a LidarStats,
+    parameters: &'a MapParameters,
+}
+
+/// A directory next to the output file with one file per finished tile.
+///
+/// A crashed or cancelled map generation restarted with the same inputs
+/// only computes the tiles missing from the directory.
+/// The directory is removed once the map is written
+pub struct Checkpoint {
+    dir: PathBuf,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint for the given inputs, any checkpoint left by
+    /// a run with other inputs is discarded
+    pub fn open(
+        map_params: &MapParameters,
+        file_params: &FileParameters,
+        polygon_filter: &Option<geo::Polygon>,
+        stats: &LidarStats,
+    ) -> Result<Checkpoint> {
+        let dir = file_params.save_location.with_extension("checkpoint");
+
+        let manifest = postcard::to_stdvec(&Manifest {
+            version: CHECKPOINT_VERSION,
+            paths: &file_params.paths,
+            output_epsg: map_params.output.crs.as_ref().map(CrsDef::epsg),
+            rasters: [
+                file_params.save_slope_raster,
+                file_params.save_hillshade_raster,
+                file_params.save_last_return_raster,
+                file_params.save_canopy_height_raster,
+                file_params.save_surface_objects_raster,
+                file_params.save_ndvd_raster,
+                file_params.save_flow_accumulation_raster,
+                file_params.save_dem_raster,
+                file_params.save_intensity_raster,
+                file_params.save_return_number_raster,
+                file_params.save_vegetation_density_rasters,
+                file_params.save_water_raster,
+            ],
+            polygon_filter,
+            stats,
+            parameters: map_params,
+        })?;
+
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let matches = std::fs::read(&manifest_path).is_ok_and(|old| old == manifest);
+        if !matches {
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)
+                    .with_context(|| format!("Failed to clear the checkpoint at {dir:?}"))?;
+            }
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create the checkpoint at {dir:?}"))?;
+            write_atomic(&manifest_path, &manifest)?;
+        }
+
+        Ok(Checkpoint { dir })
+    }
+
+    pub fn num_finished_tiles(&self) -> usize {
+        std::fs::read_dir(&self.dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.path().extension().is_some_and(|ext| ext == "tile"))
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    /// A missing or unreadable tile is simply computed again
+    pub fn load_tile(&self, file_index: usize, tile_index: usize) -> Option<TileCheckpoint> {
+        let bytes = std::fs::read(self.tile_path(file_index, tile_index)).ok()?;
+        postcard::from_bytes(&bytes).ok()
+    }
+
+    pub fn save_tile(
+        &self,
+        file_index: usize,
+        tile_index: usize,
+        tile: &TileCheckpoint,
+    ) -> Result<()> {
+        let bytes = postcard::to_stdvec(tile)?;
+        write_atomic(&self.tile_path(file_index, tile_index), &bytes)
+    }
+
+    pub fn remove(self) -> Result<()> {
+        std::fs::remove_dir_all(&self.dir)
+            .with_context(|| format!("Failed to remove the checkpoint at {:?}", self.dir))
+    }
+
+    fn tile_path(&self, file_index: usize, tile_index: usize) -> PathBuf {
+        self.dir.join(format!("{file_index}_{tile_index}.tile"))
+    }
+}
+
+// a crash while writing must not leave a truncated file that looks finished
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, bytes)
+        .with_context(|| format!("Failed to write checkpoint file {tmp_path:?}"))?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to write checkpoint file {path:?}"))?;
+    Ok(())
+}
@@ -10,19 +10,10 @@ pub fn compute_tile_map_objects(
     args: &MapParameters,
     tile: &PreparedTile,
 ) -> crate::Result<Vec<MapObject>> {
-    Ok(pipeline::compute_tile(
-        tile,
-        args,
-        PipelineSteps {
-            basemap: true,
-            contours: true,
-            openness: true,
-            vegetation: true,
-            cliffs: true,
-            intensity: true,
-            water: true,
-        },
-        false,
-    )?
-    .objects)
+    // the streams are traced once every tile is done, with the water from the tiles upstream
+    let steps = PipelineSteps {
+        streams: false,
+        ..PipelineSteps::all()
+    };
+    Ok(pipeline::compute_tile(tile, args, steps, false)?.objects)
 }
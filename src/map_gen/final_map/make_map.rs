@@ -1,28 +1,38 @@
 use crate::{
     Result,
     comms::{FrontendSender, messages::*},
+    lidar_index::LidarIndex,
     map_gen::{
         self,
-        egui_map::{AreaSymbol, TempMap},
-        pipeline::PreparedTile,
+        egui_map::{AreaSymbol, ContourConflict, LineSymbol, Symbol, TempMap},
+        final_map::{
+            checkpoint::{Checkpoint, TileCheckpoint, VegetationDensity},
+            streams::{StreamNetwork, TileFlow},
+        },
+        pipeline::{PipelineSteps, PreparedTile},
     },
-    neighbors::NeighborSide,
-    parameters::{FileParameters, MapParameters},
+    parameters::{FileParameters, MapParameters, OutputFormat},
     raster::{
         Dfm,
-        dfm::{HeightAboveGround, Hillshade, LastReturn, Ndvd, Slope, SurfaceObjects},
+        dfm::{
+            Elevation, FlowAccumulation, Ground, HeightAboveGround, HighVegetation, Hillshade,
+            Intensity, LastReturn, LowVegetation, MediumVegetation, Ndvd, Returns, Slope,
+            SurfaceObjects, Water,
+        },
     },
     statistics::LidarStats,
 };
 use anyhow::Context;
-use geo::{Area, BooleanOps, Intersects};
+use geo::{BooleanOps, Contains, Intersects, MapCoords};
 use rayon::{ThreadPool, prelude::*};
 
 use std::{
-    cmp::Ordering,
+    path::PathBuf,
     sync::{Arc, Mutex},
 };
 
+const MAX_LOGGED_CONFLICTS: usize = 20;
+
 pub fn make_map(
     sender: FrontendSender,
     thread_pool: &ThreadPool,
@@ -40,33 +50,81 @@ pub fn make_map(
         num_threads
     )));
 
-    // Figure out spatial relationships of the lidar files, assuming they are divided from a big lidar-project by a square-ish grid
-    let (laz_paths, laz_neighbor_map, bounds, ref_point, masl) =
+    // Index the lidar files spatially, they may be laid out in any grid and overlap
+    let (laz_paths, lidar_index, ref_point, masl) =
         super::map_laz(&file_params.paths, &polygon_filter)?;
 
+    let checkpoint = Checkpoint::open(&map_params, &file_params, &polygon_filter, &stats)?;
+    let finished_tiles = checkpoint.num_finished_tiles();
+    if finished_tiles > 0 {
+        let _ = sender.send(FrontendTask::Log(format!(
+            "Continuing from a checkpoint with {finished_tiles} finished tiles"
+        )));
+    }
+
     let map = Arc::new(Mutex::new(TempMap::new(
         ref_point,
         map_params.scale,
         map_params.output.crs.clone(),
     )));
-    let saved_slope_rasters = file_params
-        .save_slope_raster
-        .then(|| Arc::new(Mutex::new(Vec::<Dfm<Slope>>::new())));
-    let saved_hillshade_rasters = file_params
-        .save_hillshade_raster
-        .then(|| Arc::new(Mutex::new(Vec::<Dfm<Hillshade>>::new())));
-    let saved_last_return_rasters = file_params
-        .save_last_return_raster
-        .then(|| Arc::new(Mutex::new(Vec::<Dfm<LastReturn>>::new())));
-    let saved_canopy_height_rasters = file_params
-        .save_canopy_height_raster
-        .then(|| Arc::new(Mutex::new(Vec::<Dfm<HeightAboveGround>>::new())));
-    let saved_surface_objects_rasters = file_params
-        .save_surface_objects_raster
-        .then(|| Arc::new(Mutex::new(Vec::<Dfm<SurfaceObjects>>::new())));
-    let saved_ndvd_rasters = file_params
-        .save_ndvd_raster
-        .then(|| Arc::new(Mutex::new(Vec::<Dfm<Ndvd>>::new())));
+    let saved_slope_rasters =
+        SavedRasters::<Slope>::new(file_params.save_slope_raster, "slope", "slope");
+    let saved_hillshade_rasters =
+        SavedRasters::<Hillshade>::new(file_params.save_hillshade_raster, "hillshade", "hillshade");
+    let saved_last_return_rasters = SavedRasters::<LastReturn>::new(
+        file_params.save_last_return_raster,
+        "last-return",
+        "last_return",
+    );
+    let saved_canopy_height_rasters = SavedRasters::<HeightAboveGround>::new(
+        file_params.save_canopy_height_raster,
+        "canopy height",
+        "canopy_height",
+    );
+    let saved_surface_objects_rasters = SavedRasters::<SurfaceObjects>::new(
+        file_params.save_surface_objects_raster,
+        "surface objects",
+        "surface_objects",
+    );
+    let saved_ndvd_rasters =
+        SavedRasters::<Ndvd>::new(file_params.save_ndvd_raster, "NDVD", "ndvd");
+    let saved_flow_accumulation_rasters = SavedRasters::<FlowAccumulation>::new(
+        file_params.save_flow_accumulation_raster,
+        "flow accumulation",
+        "flow_accumulation",
+    );
+    let saved_dem_rasters =
+        SavedRasters::<Elevation>::new(file_params.save_dem_raster, "DEM", "dem");
+    let saved_intensity_rasters =
+        SavedRasters::<Intensity>::new(file_params.save_intensity_raster, "intensity", "intensity");
+    let saved_return_number_rasters = SavedRasters::<Returns>::new(
+        file_params.save_return_number_raster,
+        "return number",
+        "return_number",
+    );
+    let saved_ground_density_rasters = SavedRasters::<Ground>::new(
+        file_params.save_vegetation_density_rasters,
+        "ground density",
+        "ground_density",
+    );
+    let saved_low_density_rasters = SavedRasters::<LowVegetation>::new(
+        file_params.save_vegetation_density_rasters,
+        "low vegetation density",
+        "low_vegetation_density",
+    );
+    let saved_medium_density_rasters = SavedRasters::<MediumVegetation>::new(
+        file_params.save_vegetation_density_rasters,
+        "medium vegetation density",
+        "medium_vegetation_density",
+    );
+    let saved_high_density_rasters = SavedRasters::<HighVegetation>::new(
+        file_params.save_vegetation_density_rasters,
+        "high vegetation density",
+        "high_vegetation_density",
+    );
+    let saved_water_rasters =
+        SavedRasters::<Water>::new(file_params.save_water_raster, "water", "water");
+    let stream_network = StreamNetwork::default();
 
     if let Some(polygon) = &mut polygon_filter {
         polygon.exterior_mut(|l| {
@@ -95,148 +153,122 @@ pub fn make_map(
 
         // first get the sub-tile bounds for the current lidar file
         // need tile-neighbor maps, bounds, cut-bounds and touched files (for the edge tiles)
-        let (tile_bounds, mut cut_bounds, nx, ny) =
-            map_gen::common::retile_bounds(&bounds[fi], &laz_neighbor_map[fi]);
+        let (tile_bounds, mut cut_bounds, nx, ny) = map_gen::common::retile_bounds(
+            &lidar_index.bounds()[fi],
+            lidar_index.covered_sides(fi),
+        );
 
         for cb in cut_bounds.iter_mut() {
             *cb = geo::Rect::new(cb.min() - ref_point, cb.max() - ref_point);
         }
 
+        // the parts of the file overlapped by earlier files are already mapped
+        let claimed = lidar_index.claimed_before(fi).map_coords(|c| c - ref_point);
+
         let num_tiles = nx * ny;
         let inc = 1. / num_tiles as f32;
 
         thread_pool.install(|| {
             (0..num_tiles).into_par_iter().for_each(|tile_i| {
-                let edge_tile = NeighborSide::is_edge_tile(tile_i, nx, ny);
+                // the remaining tiles are skipped, the cancellation is reported after the loop
+                if sender.cancel_token().is_cancelled() {
+                    return;
+                }
 
                 if let Some(polygon) = &polygon_filter
                     && !cut_bounds[tile_i].intersects(polygon)
                 {
                     return;
                 }
+                if claimed.contains(&cut_bounds[tile_i]) {
+                    return;
+                }
 
-                let (cloud, all_point_cloud, mut hull) = match super::read_laz(
-                    &laz_paths,
-                    &laz_neighbor_map[fi],
-                    tile_bounds[tile_i],
-                    edge_tile,
-                    ref_point,
-                ) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        if e.downcast_ref::<crate::Error>()
-                            .is_some_and(|e| matches!(e, crate::Error::NoGroundPoints))
-                        {
+                let tile = match checkpoint.load_tile(fi, tile_i) {
+                    Some(tile) => tile,
+                    None => {
+                        let tile = match compute_tile(
+                            &sender,
+                            tile_i,
+                            &map_params,
+                            &file_params,
+                            &stats,
+                            &laz_paths,
+                            &lidar_index,
+                            tile_bounds[tile_i],
+                            cut_bounds[tile_i],
+                            ref_point,
+                            &polygon_filter,
+                            &claimed,
+                        ) {
+                            Ok(tile) => tile,
+                            Err(e) => {
+                                let _ = sender.send(FrontendTask::Error(e.to_string(), true));
+                                return;
+                            }
+                        };
+
+                        if let Err(e) = checkpoint.save_tile(fi, tile_i, &tile) {
+                            let _ = sender.send(FrontendTask::Error(format!("{e:#}"), true));
                             return;
                         }
-                        let _ = sender.send(FrontendTask::Error(e.to_string(), true));
-                        return;
-                    }
-                };
-
-                if let Some(polygon) = &polygon_filter {
-                    let mut mp = polygon.intersection(&hull);
-
-                    if mp.0.is_empty() {
-                        return;
-                    }
-
-                    mp.0.sort_by(|a, b| {
-                        a.signed_area()
-                            .partial_cmp(&b.signed_area())
-                            .unwrap_or(Ordering::Equal)
-                    });
-                    hull = mp.0.swap_remove(0);
-                }
-
-                let tile = match PreparedTile::from_cloud(
-                    cloud,
-                    all_point_cloud,
-                    &stats,
-                    hull,
-                    cut_bounds[tile_i],
-                ) {
-                    Ok(Some(tile)) => tile,
-                    Ok(None) => return,
-                    Err(e) => {
-                        let _ = sender.send(FrontendTask::Error(e.to_string(), true));
-                        return;
+                        tile
                     }
                 };
 
-                let objects = match super::compute_tile_map_objects(&map_params, &tile) {
-                    Ok(objects) => objects,
-                    Err(e) => {
-                        let _ = sender.send(FrontendTask::Error(e.to_string(), true));
-                        return;
-                    }
-                };
-
-                if let Some(saved_rasters) = &saved_slope_rasters
-                    && !push_saved_raster(
-                        saved_rasters,
-                        tile.rasters.slope.clone(),
-                        "Slope",
-                        &sender,
-                    )
-                {
-                    return;
-                }
-
-                if let Some(saved_rasters) = &saved_hillshade_rasters
-                    && !push_saved_raster(
-                        saved_rasters,
-                        tile.rasters.dem.hillshade(3. * std::f64::consts::FRAC_PI_4),
-                        "Hillshade",
-                        &sender,
-                    )
-                {
-                    return;
-                }
-
-                if let Some(saved_rasters) = &saved_last_return_rasters
-                    && !push_saved_raster(
-                        saved_rasters,
-                        tile.rasters.last_return.clone(),
-                        "Last-return",
-                        &sender,
-                    )
-                {
-                    return;
-                }
-
-                if let Some(saved_rasters) = &saved_canopy_height_rasters
-                    && !push_saved_raster(
-                        saved_rasters,
-                        tile.rasters.canopy_height.clone(),
-                        "Canopy Height",
-                        &sender,
-                    )
+                let TileCheckpoint {
+                    objects,
+                    slope,
+                    hillshade,
+                    last_return,
+                    canopy_height,
+                    surface_objects,
+                    ndvd,
+                    flow_accumulation,
+                    dem,
+                    intensity,
+                    return_number,
+                    vegetation_density,
+                    water,
+                    flow,
+                } = tile;
+                let (ground_density, low_density, medium_density, high_density) =
+                    match vegetation_density {
+                        Some(VegetationDensity {
+                            ground,
+                            low,
+                            medium,
+                            high,
+                        }) => (Some(ground), Some(low), Some(medium), Some(high)),
+                        None => (None, None, None, None),
+                    };
+
+                if !saved_slope_rasters.push(slope, &sender)
+                    || !saved_hillshade_rasters.push(hillshade, &sender)
+                    || !saved_last_return_rasters.push(last_return, &sender)
+                    || !saved_canopy_height_rasters.push(canopy_height, &sender)
+                    || !saved_surface_objects_rasters.push(surface_objects, &sender)
+                    || !saved_ndvd_rasters.push(ndvd, &sender)
+                    || !saved_flow_accumulation_rasters.push(flow_accumulation, &sender)
+                    || !saved_dem_rasters.push(dem, &sender)
+                    || !saved_intensity_rasters.push(intensity, &sender)
+                    || !saved_return_number_rasters.push(return_number, &sender)
+                    || !saved_ground_density_rasters.push(ground_density, &sender)
+                    || !saved_low_density_rasters.push(low_density, &sender)
+                    || !saved_medium_density_rasters.push(medium_density, &sender)
+                    || !saved_high_density_rasters.push(high_density, &sender)
+                    || !saved_water_rasters.push(water, &sender)
                 {
                     return;
                 }
 
-                if let Some(saved_rasters) = &saved_surface_objects_rasters
-                    && !push_saved_raster(
-                        saved_rasters,
-                        tile.rasters.surface_objects.clone(),
-                        "Surface objects",
-                        &sender,
-                    )
+                if let Some(flow) = &flow
+                    && let Err(e) = stream_network.add_tile(fi, tile_i, flow)
                 {
+                    let _ = sender.send(FrontendTask::Error(format!("{e:#}"), true));
                     return;
                 }
 
-                if let Some(saved_rasters) = &saved_ndvd_rasters
-                    && !push_saved_raster(
-                        saved_rasters,
-                        tile.rasters.compute_ndvd(map_params.vegetation.weights),
-                        "NDVD",
-                        &sender,
-                    )
-                {
-                    return;
-                }
                 {
                     if let Ok(mut map) = map.lock() {
                         for object in objects {
@@ -255,6 +287,7 @@ pub fn make_map(
         });
 
         let _ = sender.send(FrontendTask::ProgressBar(ProgressBar::Finish));
+        sender.cancel_token().check()?;
     }
 
     let mut map = Arc::<Mutex<TempMap>>::into_inner(map)
@@ -262,7 +295,13 @@ pub fn make_map(
         .into_inner()
         .map_err(|_| anyhow::anyhow!("Map mutex was poisoned during generation"))?;
 
-    let min_size_filter_symbols = map_params.min_size_filter_symbols(true, true, true, true, true);
+    let _ = sender.send(FrontendTask::Log("Tracing streams...".to_string()));
+    let streams = thread_pool.install(|| stream_network.trace(&checkpoint, &map_params))?;
+    for stream in streams {
+        map.add_object(stream);
+    }
+
+    let min_size_filter_symbols = map_params.min_size_filter_symbols(PipelineSteps::all());
     if !min_size_filter_symbols.is_empty() {
         let _ = sender.send(FrontendTask::Log(
             "Filtering polygons by minimum symbol size...".to_string(),
@@ -280,6 +319,18 @@ pub fn make_map(
     map.mark_basemap_depressions();
 
     map.merge_lines(5. * crate::SIMPLIFICATION_DIST);
+    map.merge_roads(5. * crate::SIMPLIFICATION_DIST, &map_params.roads);
+
+    if map_params.contour.repair_topology {
+        map.repair_contour_topology();
+    }
+    // checked as written, the bezier curves may cross where the polylines do not
+    let conflicts = map.written_contour_conflicts(
+        map_params
+            .geometry
+            .bezier_error_for_symbol(Symbol::Line(LineSymbol::Contour)),
+    );
+    log_contour_conflicts(&sender, &conflicts, ref_point);
 
     // convert the smallest knolls and depressions to point symbols
     map.make_dotknolls_and_depressions(
@@ -288,130 +339,365 @@ pub fn make_map(
         1.5,
     );
 
-    let _ = sender.send(FrontendTask::Log("Writing Omap file...".to_string()));
+    if map_params.contour.slope_lines {
+        // the edge of the lidar files, cut by the polygon filter
+        let mut map_edge = lidar_index
+            .bounds()
+            .iter()
+            .fold(geo::MultiPolygon::new(vec![]), |edge, bounds| {
+                edge.union(&bounds.to_polygon())
+            })
+            .map_coords(|c| c - ref_point);
+        if let Some(polygon) = &polygon_filter {
+            map_edge = map_edge.intersection(polygon);
+        }
+        map.add_slope_lines(&map_edge);
+    }
+
+    if map_params.contour.index_contour_labels {
+        map.add_index_contour_labels();
+    }
+
+    write_vector_exports(&sender, &map, &file_params, map_params.output.crs.as_ref())?;
 
     let bezier_line_error = map_params.geometry.contours.enabled.then(|| {
         map_params
             .scale
             .meters_to_paper_mm(map_params.geometry.contours.error)
     });
-    let omap = map.into_omap(masl, bezier_line_error)?;
+    match file_params.output_format {
+        OutputFormat::Omap => {
+            let _ = sender.send(FrontendTask::Log("Writing Omap file...".to_string()));
+
+            let template = match &file_params.symbol_template {
+                Some(path) => {
+                    let template = omap::Omap::from_path(path).with_context(|| {
+                        format!("Failed to read the symbol set template {path:?}")
+                    })?;
+                    let template_scale = template.geo_referencing.scale_denominator;
+                    if template_scale != map_params.scale.denominator() as u32 {
+                        let _ = sender.send(FrontendTask::Log(format!(
+                            "Warning: the symbol set template is drawn for 1:{template_scale} but the map is made at 1:{}, the symbols keep their template dimensions",
+                            map_params.scale.denominator()
+                        )));
+                    }
+                    Some(template)
+                }
+                None => None,
+            };
 
-    omap.write_to_file(file_params.save_location.clone())?;
+            let omap = map.into_omap(masl, bezier_line_error, template)?;
+            omap.write_to_file(file_params.save_location.clone())?;
+        }
+        OutputFormat::Ocd => {
+            let _ = sender.send(FrontendTask::Log("Writing OCAD file...".to_string()));
+            for (symbol, count) in map.ocd_omitted_objects() {
+                let _ = sender.send(FrontendTask::Log(format!(
+                    "{count} {symbol} objects are left out, they are only written to omap files"
+                )));
+            }
 
-    write_saved_rasters(
-        &sender,
-        saved_slope_rasters,
-        "slope",
-        "slope",
-        &file_params,
-        ref_point,
-        map_params.output.crs.as_ref(),
-    )?;
-    write_saved_rasters(
-        &sender,
-        saved_hillshade_rasters,
-        "hillshade",
-        "hillshade",
-        &file_params,
-        ref_point,
-        map_params.output.crs.as_ref(),
-    )?;
-    write_saved_rasters(
-        &sender,
-        saved_last_return_rasters,
-        "last-return",
-        "last_return",
-        &file_params,
-        ref_point,
-        map_params.output.crs.as_ref(),
-    )?;
-    write_saved_rasters(
-        &sender,
-        saved_canopy_height_rasters,
-        "canopy height",
-        "canopy_height",
-        &file_params,
-        ref_point,
-        map_params.output.crs.as_ref(),
-    )?;
-    write_saved_rasters(
-        &sender,
-        saved_surface_objects_rasters,
-        "surface objects",
-        "surface_objects",
-        &file_params,
-        ref_point,
-        map_params.output.crs.as_ref(),
-    )?;
-    write_saved_rasters(
-        &sender,
-        saved_ndvd_rasters,
-        "NDVD",
-        "ndvd",
-        &file_params,
-        ref_point,
-        map_params.output.crs.as_ref(),
-    )?;
+            map.write_ocd(&file_params.save_location, masl, bezier_line_error)?;
+        }
+    }
+
+    let crs = map_params.output.crs.as_ref();
+    saved_slope_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_hillshade_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_last_return_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_canopy_height_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_surface_objects_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_ndvd_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_flow_accumulation_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_dem_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_intensity_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_return_number_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_ground_density_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_low_density_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_medium_density_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_high_density_rasters.write(&sender, &file_params, ref_point, crs)?;
+    saved_water_rasters.write(&sender, &file_params, ref_point, crs)?;
+
+    // the map is written, a failed clean-up is not worth failing the map for
+    if let Err(e) = checkpoint.remove() {
+        let _ = sender.send(FrontendTask::Log(format!("{e:#}")));
+    }
 
     let _ = sender.send(FrontendTask::Log("Done!".to_string()));
     Ok(())
 }
 
-fn push_saved_raster<T>(
-    saved_rasters: &Arc<Mutex<Vec<Dfm<T>>>>,
-    raster: Dfm<T>,
-    label: &str,
+// reads the points of a tile and computes its map objects and the rasters to save,
+// tiles without any ground points or outside the polygon filter are empty
+#[allow(clippy::too_many_arguments)]
+fn compute_tile(
     sender: &FrontendSender,
-) -> bool {
-    if let Ok(mut rasters) = saved_rasters.lock() {
-        rasters.push(raster);
-        true
-    } else {
-        let _ = sender.send(FrontendTask::Error(
-            format!("{label} raster mutex was poisoned"),
-            true,
-        ));
-        false
+    tile_i: usize,
+    map_params: &MapParameters,
+    file_params: &FileParameters,
+    stats: &LidarStats,
+    laz_paths: &[PathBuf],
+    lidar_index: &LidarIndex,
+    tile_bounds: geo::Rect,
+    cut_bounds: geo::Rect,
+    ref_point: geo::Coord,
+    polygon_filter: &Option<geo::Polygon>,
+    claimed: &geo::MultiPolygon,
+) -> Result<TileCheckpoint> {
+    let (cloud, all_point_cloud, hull, removed_noise) = match super::read_laz(
+        laz_paths,
+        lidar_index,
+        tile_bounds,
+        ref_point,
+        &map_params.ground,
+        &map_params.noise,
+    ) {
+        Ok(p) => p,
+        Err(e)
+            if e.downcast_ref::<crate::Error>()
+                .is_some_and(|e| matches!(e, crate::Error::NoGroundPoints)) =>
+        {
+            return Ok(TileCheckpoint::default());
+        }
+        Err(e) => return Err(e),
+    };
+
+    if removed_noise.total() > 0 {
+        let _ = sender.send(FrontendTask::Log(format!(
+            "Tile {}: {removed_noise}",
+            tile_i + 1
+        )));
+    }
+
+    let pieces = tile_pieces(&hull, polygon_filter, claimed);
+    if pieces.0.is_empty() {
+        return Ok(TileCheckpoint::default());
     }
+
+    let Some(mut tile) = PreparedTile::from_cloud(cloud, all_point_cloud, stats, hull, cut_bounds)?
+    else {
+        return Ok(TileCheckpoint::default());
+    };
+
+    // the rasters cover the whole tile, the map objects are computed for each piece of it
+    let mut objects = Vec::new();
+    for piece in &pieces {
+        for cut_overlay in cut_bounds.to_polygon().intersection(piece) {
+            tile.hull = piece.clone();
+            tile.cut_overlay = cut_overlay;
+            objects.extend(super::compute_tile_map_objects(map_params, &tile)?);
+        }
+    }
+
+    Ok(TileCheckpoint {
+        objects,
+        slope: file_params
+            .save_slope_raster
+            .then(|| tile.rasters.slope.clone()),
+        hillshade: file_params
+            .save_hillshade_raster
+            .then(|| tile.rasters.dem.hillshade(3. * std::f64::consts::FRAC_PI_4)),
+        last_return: file_params
+            .save_last_return_raster
+            .then(|| tile.rasters.last_return.clone()),
+        canopy_height: file_params
+            .save_canopy_height_raster
+            .then(|| tile.rasters.canopy_height.clone()),
+        surface_objects: file_params
+            .save_surface_objects_raster
+            .then(|| tile.rasters.surface_objects.clone()),
+        ndvd: file_params
+            .save_ndvd_raster
+            .then(|| tile.rasters.compute_ndvd(map_params.vegetation.weights)),
+        flow_accumulation: file_params
+            .save_flow_accumulation_raster
+            .then(|| tile.rasters.flow.accumulation.clone()),
+        dem: file_params
+            .save_dem_raster
+            .then(|| tile.rasters.dem.clone()),
+        intensity: file_params
+            .save_intensity_raster
+            .then(|| tile.rasters.intensity.clone()),
+        return_number: file_params
+            .save_return_number_raster
+            .then(|| tile.rasters.return_number.clone()),
+        vegetation_density: file_params.save_vegetation_density_rasters.then(|| {
+            VegetationDensity {
+                ground: tile.rasters.ground_vegetation.clone(),
+                low: tile.rasters.low_vegetation.clone(),
+                medium: tile.rasters.medium_vegetation.clone(),
+                high: tile.rasters.high_vegetation.clone(),
+            }
+        }),
+        water: file_params
+            .save_water_raster
+            .then(|| tile.rasters.water.clone()),
+        flow: Some(TileFlow::new(tile, &pieces, cut_bounds)),
+    })
+}
+
+// the parts of the tile hull inside the polygon filter and not claimed by an earlier file,
+// a flight strip or the filter may split the hull into several pieces
+fn tile_pieces(
+    hull: &geo::Polygon,
+    polygon_filter: &Option<geo::Polygon>,
+    claimed: &geo::MultiPolygon,
+) -> geo::MultiPolygon {
+    let mut pieces = geo::MultiPolygon::new(vec![hull.clone()]);
+    if let Some(polygon) = polygon_filter {
+        pieces = pieces.intersection(polygon);
+    }
+    if !claimed.0.is_empty() {
+        pieces = pieces.difference(claimed);
+    }
+    pieces
 }
 
-fn write_saved_rasters<T>(
+// the locations are logged in the output CRS so they can be found in the map
+fn log_contour_conflicts(
     sender: &FrontendSender,
-    saved_rasters: Option<Arc<Mutex<Vec<Dfm<T>>>>>,
-    label: &str,
-    suffix: &str,
-    file_params: &FileParameters,
+    conflicts: &[ContourConflict],
     ref_point: geo::Coord,
+) {
+    if conflicts.is_empty() {
+        return;
+    }
+
+    let _ = sender.send(FrontendTask::Log(format!(
+        "{} contour conflicts could not be repaired:",
+        conflicts.len()
+    )));
+    for conflict in conflicts.iter().take(MAX_LOGGED_CONFLICTS) {
+        let location = conflict.location + ref_point;
+        let _ = sender.send(FrontendTask::Log(format!(
+            "\t{conflict} at ({:.1}, {:.1})",
+            location.x, location.y
+        )));
+    }
+    if conflicts.len() > MAX_LOGGED_CONFLICTS {
+        let _ = sender.send(FrontendTask::Log(format!(
+            "\t... and {} more",
+            conflicts.len() - MAX_LOGGED_CONFLICTS
+        )));
+    }
+}
+
+fn write_vector_exports(
+    sender: &FrontendSender,
+    map: &TempMap,
+    file_params: &FileParameters,
     crs: Option<&proj_core::CrsDef>,
 ) -> Result<()> {
-    let Some(saved_rasters) = saved_rasters else {
-        return Ok(());
-    };
+    if file_params.save_geopackage {
+        let _ = sender.send(FrontendTask::Log("Writing GeoPackage...".to_string()));
+        let path =
+            crate::vector::geopackage::write_geopackage(&file_params.save_location, map, crs)?;
+        let _ = sender.send(FrontendTask::Log(format!(
+            "Wrote map objects to {}",
+            path.display()
+        )));
+    }
 
-    let rasters = Arc::<Mutex<Vec<Dfm<T>>>>::into_inner(saved_rasters)
-        .with_context(|| {
-            format!("Could not get saved {label} rasters; a worker still holds a reference")
-        })?
-        .into_inner()
-        .map_err(|_| anyhow::anyhow!("{label} raster mutex was poisoned during generation"))?;
+    if file_params.save_geojson {
+        let _ = sender.send(FrontendTask::Log("Writing GeoJSON...".to_string()));
+        let path = crate::vector::geojson::write_geojson(&file_params.save_location, map, crs)?;
+        let _ = sender.send(FrontendTask::Log(format!(
+            "Wrote map objects to {}",
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
+
+// the rasters of one kind saved by the tiles, merged into one GeoTIFF after the last tile
+struct SavedRasters<T> {
+    rasters: Option<Mutex<Vec<Dfm<T>>>>,
+    label: &'static str,
+    suffix: &'static str,
+}
 
-    if rasters.is_empty() {
-        return Ok(());
+impl<T> SavedRasters<T> {
+    fn new(save: bool, label: &'static str, suffix: &'static str) -> Self {
+        SavedRasters {
+            rasters: save.then(|| Mutex::new(Vec::new())),
+            label,
+            suffix,
+        }
     }
 
-    let _ = sender.send(FrontendTask::Log(format!("Writing {label} GeoTIFF...")));
-    let path = crate::raster::geotiff::write_merged_dfm_geotiff(
-        &file_params.save_location,
-        suffix,
-        &rasters,
-        ref_point,
-        crs,
-    )?;
-    let _ = sender.send(FrontendTask::Log(format!(
-        "Wrote {label} raster to {}",
-        path.display()
-    )));
+    // false if the tile could not be saved and should be skipped
+    fn push(&self, raster: Option<Dfm<T>>, sender: &FrontendSender) -> bool {
+        let (Some(rasters), Some(raster)) = (&self.rasters, raster) else {
+            return true;
+        };
+
+        if let Ok(mut rasters) = rasters.lock() {
+            rasters.push(raster);
+            true
+        } else {
+            let _ = sender.send(FrontendTask::Error(
+                format!("{} raster mutex was poisoned", self.label),
+                true,
+            ));
+            false
+        }
+    }
 
-    Ok(())
+    fn write(
+        self,
+        sender: &FrontendSender,
+        file_params: &FileParameters,
+        ref_point: geo::Coord,
+        crs: Option<&proj_core::CrsDef>,
+    ) -> Result<()> {
+        let Some(rasters) = self.rasters else {
+            return Ok(());
+        };
+        let label = self.label;
+
+        let rasters = rasters
+            .into_inner()
+            .map_err(|_| anyhow::anyhow!("{label} raster mutex was poisoned during generation"))?;
+
+        if rasters.is_empty() {
+            return Ok(());
+        }
+
+        let _ = sender.send(FrontendTask::Log(format!("Writing {label} GeoTIFF...")));
+        let path = crate::raster::geotiff::write_merged_dfm_geotiff(
+            &file_params.save_location,
+            self.suffix,
+            &rasters,
+            ref_point,
+            crs,
+            file_params.raster_format,
+        )?;
+        let _ = sender.send(FrontendTask::Log(format!(
+            "Wrote {label} raster to {}",
+            path.display()
+        )));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::Area;
+
+    use super::tile_pieces;
+
+    #[test]
+    fn every_piece_of_a_split_tile_is_kept() {
+        let hull = geo::Rect::new((0., 0.), (100., 100.)).to_polygon();
+        // an earlier flight strip crossing the middle of the tile
+        let claimed =
+            geo::MultiPolygon::new(vec![geo::Rect::new((40., -10.), (60., 110.)).to_polygon()]);
+
+        let pieces = tile_pieces(&hull, &None, &claimed);
+
+        assert_eq!(pieces.0.len(), 2);
+        assert!((pieces.unsigned_area() - 8000.).abs() < 1e-9);
+    }
 }
@@ -6,19 +6,13 @@ use las::Reader;
 use std::path::PathBuf;
 
 use crate::geometry::MapRect;
-use crate::neighbors::Neighborhood;
+use crate::lidar_index::LidarIndex;
 use crate::{Error, Result};
 
 pub fn map_laz(
     paths: &Vec<PathBuf>,
     polygon_filter: &Option<geo::Polygon>,
-) -> Result<(
-    Vec<PathBuf>,
-    Vec<Neighborhood>,
-    Vec<geo::Rect>,
-    geo::Coord,
-    f64,
-)> {
+) -> Result<(Vec<PathBuf>, LidarIndex, geo::Coord, f64)> {
     let mut tile_centers = Vec::with_capacity(paths.len());
     let mut las_paths = Vec::with_capacity(paths.len());
     let mut avg_elevation = 0.;
@@ -54,15 +48,12 @@ pub fn map_laz(
 
         return Ok((
             las_paths,
-            vec![Default::default()],
-            tile_bounds,
+            LidarIndex::new(tile_bounds),
             geo::Coord::from(center_point),
             avg_elevation,
         ));
     }
 
-    let neighbors = Neighborhood::neighboring_tiles(&tile_centers, &tile_bounds);
-
     let mut ref_point = geo::Coord::<f64>::default();
     tile_centers.iter().for_each(|tc| {
         ref_point.x += tc[0];
@@ -72,5 +63,10 @@ pub fn map_laz(
     ref_point.y = (ref_point.y / (10 * tile_centers.len()) as f64).round() * 10.;
     avg_elevation = (avg_elevation / (10 * tile_centers.len()) as f64).round() * 10.;
 
-    Ok((las_paths, neighbors, tile_bounds, ref_point, avg_elevation))
+    Ok((
+        las_paths,
+        LidarIndex::new(tile_bounds),
+        ref_point,
+        avg_elevation,
+    ))
 }
@@ -1,7 +1,9 @@
+mod checkpoint;
 mod compute_map_objects;
 mod make_map;
 mod map_laz;
 mod read_laz;
+mod streams;
 
 pub use self::compute_map_objects::compute_tile_map_objects;
 pub use self::make_map::make_map;
@@ -1,10 +1,12 @@
 use crate::{
     Error, Result,
     geometry::{MapRect, PointCloud, PointLaz},
-    neighbors::{NeighborSide, Neighborhood},
+    lidar_index::{LidarIndex, merge_overlapping_points},
+    lidar_reader::LidarReader,
+    map_gen::common::{RemovedNoise, filter_isolated, filter_noise, prepare_ground},
+    parameters::{GroundParameters, NoiseParameters},
 };
 
-use copc_rs::CopcReader;
 use las::point::Classification;
 use rstar::{PointDistance, RTree, primitives::GeomWithData};
 
@@ -23,109 +25,69 @@ fn jitter_point(point: &mut las::Point, ref_point: geo::Coord) {
 
 pub fn read_laz(
     las_paths: &[PathBuf],
-    neighbor_map: &Neighborhood,
+    lidar_index: &LidarIndex,
     tile_bounds: geo::Rect,
-    edge_tile: NeighborSide,
     ref_point: geo::Coord,
-) -> Result<(PointCloud, PointCloud, geo::Polygon)> {
-    let mut las_reader = CopcReader::from_path(&las_paths[neighbor_map.center])?;
-
-    let header = las_reader.header();
-
-    let query_bounds = tile_bounds.into_bounds(header.bounds().min.z, header.bounds().max.z);
-    let mut rel_bounds = query_bounds;
+    ground: &GroundParameters,
+    noise: &NoiseParameters,
+) -> Result<(PointCloud, PointCloud, geo::Polygon, RemovedNoise)> {
+    let mut rel_bounds = tile_bounds.into_bounds(f64::MAX, f64::MIN);
     rel_bounds.max.x -= ref_point.x;
     rel_bounds.min.x -= ref_point.x;
     rel_bounds.max.y -= ref_point.y;
     rel_bounds.min.y -= ref_point.y;
 
-    let center_points = las_reader
-        .points(
-            copc_rs::LodSelection::All,
-            copc_rs::BoundsSelection::Within(query_bounds),
-        )?
-        .filter_map(|mut p| {
-            (!p.is_withheld).then(|| {
-                jitter_point(&mut p, ref_point);
-                PointLaz(p)
-            })
+    // every file intersecting the tile contributes, not only the grid neighbours
+    let mut removed_noise = RemovedNoise::default();
+    let mut file_points = Vec::new();
+    for fi in lidar_index.intersecting(tile_bounds) {
+        let mut las_reader = LidarReader::from_path(&las_paths[fi])?;
+
+        let points = las_reader
+            .points_within(tile_bounds)?
+            .into_iter()
+            .filter(|p| !p.is_withheld)
+            .collect::<Vec<_>>();
+        let (mut points, removed) = filter_noise(points, noise);
+        removed_noise += removed;
+        prepare_ground(&mut points, ground);
+
+        file_points.push((lidar_index.bounds()[fi], points));
+    }
+
+    // both the ground cloud and the full cloud are taken from the filtered points
+    let points = filter_isolated(
+        merge_overlapping_points(file_points),
+        noise,
+        &mut removed_noise,
+    );
+    // the header range would include the removed noise
+    for p in &points {
+        rel_bounds.min.z = rel_bounds.min.z.min(p.z);
+        rel_bounds.max.z = rel_bounds.max.z.max(p.z);
+    }
+    let all_points = points
+        .into_iter()
+        .map(|mut p| {
+            jitter_point(&mut p, ref_point);
+            PointLaz(p)
         })
         .collect::<Vec<_>>();
 
     let mut point_cloud = PointCloud::new(
-        center_points
+        all_points
             .iter()
             .filter(|p| p.0.classification == Classification::Ground)
             .cloned()
             .collect(),
         rel_bounds,
     );
-    let mut all_points = center_points;
 
     // skip this tile if there is almost no ground points
     if point_cloud.points.len() < 4 {
         return Err(Error::NoGroundPoints.into());
     }
 
-    // get the indices for neighboring laz file if edge tile
-    let edge_paths_index = match edge_tile {
-        NeighborSide::TopLeft => [neighbor_map.left, neighbor_map.top_left, neighbor_map.top]
-            .into_iter()
-            .flatten()
-            .collect(),
-        NeighborSide::Top => [neighbor_map.top].into_iter().flatten().collect(),
-        NeighborSide::TopRight => [neighbor_map.right, neighbor_map.top_right, neighbor_map.top]
-            .into_iter()
-            .flatten()
-            .collect(),
-        NeighborSide::Right => [neighbor_map.right].into_iter().flatten().collect(),
-        NeighborSide::BottomRight => [
-            neighbor_map.right,
-            neighbor_map.bottom_right,
-            neighbor_map.bottom,
-        ]
-        .into_iter()
-        .flatten()
-        .collect(),
-        NeighborSide::Bottom => [neighbor_map.bottom].into_iter().flatten().collect(),
-        NeighborSide::BottomLeft => [
-            neighbor_map.bottom,
-            neighbor_map.bottom_left,
-            neighbor_map.left,
-        ]
-        .into_iter()
-        .flatten()
-        .collect(),
-        NeighborSide::Left => [neighbor_map.left].into_iter().flatten().collect(),
-        _ => vec![],
-    };
-
-    for ei in edge_paths_index.iter() {
-        let mut edge_reader = CopcReader::from_path(&las_paths[*ei])?;
-
-        let edge_points = edge_reader
-            .points(
-                copc_rs::LodSelection::All,
-                copc_rs::BoundsSelection::Within(query_bounds),
-            )?
-            .filter_map(|mut p| {
-                (!p.is_withheld).then(|| {
-                    jitter_point(&mut p, ref_point);
-                    PointLaz(p)
-                })
-            })
-            .collect::<Vec<_>>();
-
-        point_cloud.add(
-            edge_points
-                .iter()
-                .filter(|p| p.0.classification == Classification::Ground)
-                .cloned()
-                .collect(),
-        );
-        all_points.extend(edge_points);
-    }
-
     let map_bounds = point_cloud.get_dfm_dimensions();
 
     let convex_hull = point_cloud.bounded_convex_hull(&map_bounds, 2. * crate::CELL_SIZE_METERS)?;
@@ -177,5 +139,5 @@ pub fn read_laz(
 
     let all_point_cloud = PointCloud::new(all_points, rel_bounds);
 
-    Ok((point_cloud, all_point_cloud, convex_hull))
+    Ok((point_cloud, all_point_cloud, convex_hull, removed_noise))
 }
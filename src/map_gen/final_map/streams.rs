@@ -0,0 +1,266 @@
+use std::sync::Mutex;
+
+use anyhow::Context;
+use geo::{BooleanOps, BoundingRect, Contains, Intersects};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Result, TILE_SIZE_PIXELS,
+    map_gen::{
+        self, egui_map::MapObject, final_map::checkpoint::Checkpoint, pipeline::PreparedTile,
+    },
+    parameters::MapParameters,
+    raster::hydrology::{FlowNetwork, FlowOutlets},
+};
+
+// the cut overlays of neighbouring tiles share their edges, a cell centre on a shared edge
+// belongs to the tile containing it moved by this much
+const OWNER_OFFSET: geo::Coord = geo::Coord { x: 1e-6, y: 2e-6 };
+
+/// The flow routing of a tile, kept in the checkpoint until the streams of all tiles are traced
+#[derive(Serialize, Deserialize)]
+pub struct TileFlow {
+    pub network: FlowNetwork,
+    /// The part of the tile it is mapped for
+    pub overlay: geo::MultiPolygon,
+}
+
+impl TileFlow {
+    pub fn new(tile: PreparedTile, pieces: &geo::MultiPolygon, cut_bounds: geo::Rect) -> Self {
+        Self {
+            network: tile.rasters.flow,
+            overlay: cut_bounds.to_polygon().intersection(pieces),
+        }
+    }
+
+    fn inside(&self) -> Vec<bool> {
+        let Some(bounds) = self.overlay.bounding_rect() else {
+            return vec![false; TILE_SIZE_PIXELS * TILE_SIZE_PIXELS];
+        };
+        let accumulation = &self.network.accumulation;
+        (0..TILE_SIZE_PIXELS * TILE_SIZE_PIXELS)
+            .map(|index| {
+                let coord = accumulation
+                    .index2coord(index / TILE_SIZE_PIXELS, index % TILE_SIZE_PIXELS)
+                    + OWNER_OFFSET;
+                bounds.intersects(&coord) && self.overlay.contains(&coord)
+            })
+            .collect()
+    }
+}
+
+// the outlets of a tile, all that is kept in memory until every tile is done
+struct TileOutlets {
+    file_index: usize,
+    tile_index: usize,
+    overlay: geo::MultiPolygon,
+    bounds: geo::Rect,
+    outlets: FlowOutlets,
+}
+
+impl TileOutlets {
+    fn new(file_index: usize, tile_index: usize, flow: &TileFlow) -> Option<Self> {
+        Some(Self {
+            file_index,
+            tile_index,
+            bounds: flow.overlay.bounding_rect()?,
+            overlay: flow.overlay.clone(),
+            outlets: flow.network.outlets(&flow.inside()),
+        })
+    }
+
+    fn owns(&self, coord: geo::Coord) -> bool {
+        let coord = coord + OWNER_OFFSET;
+        self.bounds.intersects(&coord) && self.overlay.contains(&coord)
+    }
+}
+
+/// The water leaving every tile, passed on to the tile it flows into.
+///
+/// Each tile only routes the water over its own DEM, the streams are traced once every
+/// tile is done so the upslope area of a stream includes the tiles upstream of it
+#[derive(Default)]
+pub struct StreamNetwork {
+    tiles: Mutex<Vec<TileOutlets>>,
+}
+
+impl StreamNetwork {
+    pub fn add_tile(&self, file_index: usize, tile_index: usize, flow: &TileFlow) -> Result<()> {
+        let Some(tile) = TileOutlets::new(file_index, tile_index, flow) else {
+            return Ok(());
+        };
+        self.tiles
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Stream network mutex was poisoned"))?
+            .push(tile);
+        Ok(())
+    }
+
+    /// Traces the streams of every tile, with the water entering it from the tiles upstream
+    pub fn trace(self, checkpoint: &Checkpoint, params: &MapParameters) -> Result<Vec<MapObject>> {
+        let tiles = self
+            .tiles
+            .into_inner()
+            .map_err(|_| anyhow::anyhow!("Stream network mutex was poisoned"))?;
+        let inflows = route_inflows(&tiles);
+
+        let streams = tiles
+            .par_iter()
+            .zip(inflows)
+            .map(|(tile, inflows)| -> Result<Vec<MapObject>> {
+                let flow = checkpoint
+                    .load_tile(tile.file_index, tile.tile_index)
+                    .and_then(|tile| tile.flow)
+                    .with_context(|| {
+                        format!(
+                            "Failed to read the flow routing of tile {} from the checkpoint",
+                            tile.tile_index + 1
+                        )
+                    })?;
+                Ok(trace_tile(flow, &inflows, params))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(streams.into_iter().flatten().collect())
+    }
+}
+
+// the water entering every tile, at the last cell of the tile it comes from
+fn route_inflows(tiles: &[TileOutlets]) -> Vec<Vec<(geo::Coord, f64)>> {
+    let offsets = tiles
+        .iter()
+        .scan(0, |offset, tile| {
+            let first = *offset;
+            *offset += tile.outlets.outlets.len();
+            Some(first)
+        })
+        .collect::<Vec<_>>();
+
+    // the tile every outlet drains into and the outlet the water leaves that tile through
+    let mut receivers = Vec::new();
+    let mut areas = Vec::new();
+    for (ti, tile) in tiles.iter().enumerate() {
+        for outlet in &tile.outlets.outlets {
+            let receiver = tiles
+                .iter()
+                .enumerate()
+                .find(|&(tj, other)| tj != ti && other.owns(outlet.to))
+                .map(|(tj, other)| {
+                    let next = other
+                        .outlets
+                        .outlet_entered_at(outlet.to)
+                        .map(|next| offsets[tj] + next);
+                    (tj, next)
+                });
+            receivers.push(receiver);
+            areas.push(outlet.area);
+        }
+    }
+
+    // the outlets in receiving order, an outlet draining in a loop between tiles
+    // never gets all of its inflow and passes on only what it has
+    let mut num_donors = vec![0; areas.len()];
+    for (_, next) in receivers.iter().flatten() {
+        if let Some(next) = next {
+            num_donors[*next] += 1;
+        }
+    }
+    let mut order = (0..areas.len())
+        .filter(|&i| num_donors[i] == 0)
+        .collect::<Vec<_>>();
+    let mut next_i = 0;
+    while next_i < order.len() {
+        if let Some((_, Some(next))) = receivers[order[next_i]] {
+            areas[next] += areas[order[next_i]];
+            num_donors[next] -= 1;
+            if num_donors[next] == 0 {
+                order.push(next);
+            }
+        }
+        next_i += 1;
+    }
+
+    let mut inflows = vec![Vec::new(); tiles.len()];
+    for (ti, tile) in tiles.iter().enumerate() {
+        for (oi, outlet) in tile.outlets.outlets.iter().enumerate() {
+            if let Some((tj, _)) = receivers[offsets[ti] + oi] {
+                inflows[tj].push((outlet.from, areas[offsets[ti] + oi]));
+            }
+        }
+    }
+    inflows
+}
+
+fn trace_tile(
+    mut flow: TileFlow,
+    inflows: &[(geo::Coord, f64)],
+    params: &MapParameters,
+) -> Vec<MapObject> {
+    let inflows = inflows
+        .iter()
+        .filter_map(|&(coord, area)| Some((flow.network.cell_at(coord)?, area)))
+        .collect::<Vec<_>>();
+    flow.network.accumulation = flow.network.inside_accumulation(&flow.inside(), &inflows);
+
+    flow.overlay
+        .iter()
+        .flat_map(|overlay| map_gen::common::compute_streams(&flow.network, overlay, params))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TileFlow, TileOutlets, route_inflows};
+    use crate::{
+        CELL_SIZE_METERS, TILE_SIZE_PIXELS,
+        raster::{Dfm, dfm::Elevation},
+    };
+
+    // a valley along x = 128 draining south
+    fn valley_flow(tl_coord: geo::Coord, cut_bounds: geo::Rect) -> TileFlow {
+        let mut dem = Dfm::<Elevation>::new(tl_coord);
+        for yi in 0..TILE_SIZE_PIXELS {
+            for xi in 0..TILE_SIZE_PIXELS {
+                let coord = dem.index2coord(yi, xi);
+                dem[(yi, xi)] = 0.1 * coord.y + 0.5 * (coord.x - 128.).abs();
+            }
+        }
+        TileFlow {
+            network: dem.flow_network(),
+            overlay: geo::MultiPolygon::new(vec![cut_bounds.to_polygon()]),
+        }
+    }
+
+    #[test]
+    fn water_is_passed_on_to_the_tile_downstream() {
+        let upper = valley_flow(
+            geo::Coord { x: 0., y: 256. },
+            geo::Rect::new((5., 64.), (250., 250.)),
+        );
+        let lower = valley_flow(
+            geo::Coord { x: 0., y: 136. },
+            geo::Rect::new((5., -100.), (250., 64.)),
+        );
+        let tiles = [
+            TileOutlets::new(0, 0, &upper).unwrap(),
+            TileOutlets::new(0, 1, &lower).unwrap(),
+        ];
+
+        let inflows = route_inflows(&tiles);
+
+        assert!(inflows[0].is_empty());
+        let inflows = inflows[1]
+            .iter()
+            .map(|&(coord, area)| (lower.network.cell_at(coord).unwrap(), area))
+            .collect::<Vec<_>>();
+        let accumulation = lower.network.inside_accumulation(&lower.inside(), &inflows);
+        // the valley just below the seam drains all of the upper tile
+        let below_seam = lower
+            .network
+            .cell_at(geo::Coord { x: 128., y: 60. })
+            .unwrap();
+        let upper_area = 186. * 245.;
+        assert!(accumulation.field[below_seam] > 0.95 * upper_area);
+        assert!(accumulation.field[below_seam] < upper_area + 8. / CELL_SIZE_METERS * 245.);
+    }
+}
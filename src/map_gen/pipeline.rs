@@ -9,9 +9,11 @@ use crate::{
     raster::{
         Dfm, Threshold,
         dfm::{
-            Elevation, Ground, HeightAboveGround, HighVegetation, Intensity, LastReturn,
-            LowVegetation, MediumVegetation, Ndvd, Returns, Slope, SurfaceObjects, Water,
+            Boulder, Building, Elevation, Ground, HeightAboveGround, HighVegetation, Intensity,
+            LastReturn, LowVegetation, Marsh, MediumVegetation, Ndvd, Returns, Road, Slope,
+            SurfaceObjects, Water,
         },
+        hydrology::FlowNetwork,
     },
     statistics::LidarStats,
 };
@@ -30,7 +32,12 @@ pub struct TileRasters {
     pub high_vegetation: Dfm<HighVegetation>,
     pub surface_objects: Dfm<SurfaceObjects>,
     pub water: Dfm<Water>,
+    pub building: Dfm<Building>,
+    pub boulder: Dfm<Boulder>,
     pub canopy_height: Dfm<HeightAboveGround>,
+    pub flow: FlowNetwork,
+    pub marsh: Dfm<Marsh>,
+    pub road: Dfm<Road>,
 }
 
 pub struct PreparedTile {
@@ -55,6 +62,31 @@ pub struct PipelineSteps {
     pub cliffs: bool,
     pub intensity: bool,
     pub water: bool,
+    pub streams: bool,
+    pub marsh: bool,
+    pub buildings: bool,
+    pub boulders: bool,
+    pub roads: bool,
+}
+
+impl PipelineSteps {
+    /// Every step, as used for the final map
+    pub fn all() -> Self {
+        Self {
+            basemap: true,
+            contours: true,
+            openness: true,
+            vegetation: true,
+            cliffs: true,
+            intensity: true,
+            water: true,
+            streams: true,
+            marsh: true,
+            buildings: true,
+            boulders: true,
+            roads: true,
+        }
+    }
 }
 
 impl PreparedTile {
@@ -70,13 +102,34 @@ impl PreparedTile {
             high_vegetation,
             surface_objects,
             water,
+            building,
+            boulder,
             canopy_height,
             z_range,
         } = dfms;
 
+        let slope = dem.slope();
+        let flow = dem.flow_network();
+        let marsh = map_gen::common::compute_marsh_probability(
+            &slope,
+            &flow,
+            &intensity,
+            &medium_vegetation,
+            &high_vegetation,
+        );
+        let road = map_gen::common::compute_road_probability(
+            &dem,
+            &ground_vegetation,
+            &low_vegetation,
+            &intensity,
+        );
+
         Self {
             rasters: TileRasters {
-                slope: dem.slope(),
+                slope,
+                flow,
+                marsh,
+                road,
                 dem,
                 return_number,
                 intensity,
@@ -87,6 +140,8 @@ impl PreparedTile {
                 high_vegetation,
                 surface_objects,
                 water,
+                building,
+                boulder,
                 canopy_height,
             },
             hull,
@@ -163,7 +218,7 @@ pub fn compute_tile(
                 },
                 params,
             )?,
-            ContourAlgo::NormalFieldSmoothing | ContourAlgo::Raw => {
+            ContourAlgo::NormalFieldSmoothing | ContourAlgo::Raw | ContourAlgo::Generalisation => {
                 map_gen::common::extract_contours(
                     &tile.rasters.dem,
                     tile.z_range,
@@ -231,6 +286,52 @@ pub fn compute_tile(
         ));
     }
 
+    if steps.streams {
+        objects.extend(map_gen::common::compute_streams(
+            &tile.rasters.flow,
+            &tile.cut_overlay,
+            params,
+        ));
+    }
+
+    if steps.marsh {
+        objects.extend(map_gen::common::compute_vegetation(
+            &tile.rasters.marsh,
+            Threshold::Lower(params.marsh.threshold),
+            &tile.hull,
+            &tile.cut_overlay,
+            AreaSymbol::Marsh,
+            params,
+            &params.geometry.marsh.buffer_rules,
+        ));
+    }
+
+    if steps.buildings {
+        objects.extend(map_gen::common::compute_buildings(
+            &tile.rasters.building,
+            &tile.hull,
+            &tile.cut_overlay,
+            params,
+            &params.geometry.buildings.buffer_rules,
+        ));
+    }
+
+    if steps.boulders {
+        objects.extend(map_gen::common::compute_boulders(
+            &tile.rasters.boulder,
+            &tile.cut_overlay,
+            params,
+        ));
+    }
+
+    if steps.roads {
+        objects.extend(map_gen::common::compute_roads(
+            &tile.rasters.road,
+            &tile.cut_overlay,
+            params,
+        ));
+    }
+
     if steps.intensity {
         objects.extend(map_gen::common::compute_intensity(
             &tile.rasters.intensity,
@@ -1,11 +1,23 @@
-use std::{fmt::Display, path::PathBuf};
+use std::{
+    fmt::Display,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
 
+use anyhow::{Context, bail};
 use proj_core::CrsDef;
+use serde::{Deserialize, Serialize};
 
-use crate::map_gen::egui_map::{AreaSymbol, LineSymbol, Symbol};
+use crate::map_gen::{
+    egui_map::{AreaSymbol, LineSymbol, Symbol},
+    pipeline::PipelineSteps,
+};
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MapParameters {
+    // the output CRS is decided by the lidar files, not the parameter file
+    #[serde(skip)]
     pub output: OutputParameters,
     pub scale: Scale,
     pub contour: ContourParameters,
@@ -14,6 +26,13 @@ pub struct MapParameters {
     pub intensity: IntensityParameters,
     pub cliff: CliffParameters,
     pub water: WaterParameters,
+    pub streams: StreamParameters,
+    pub marsh: MarshParameters,
+    pub buildings: BuildingParameters,
+    pub boulders: BoulderParameters,
+    pub roads: RoadParameters,
+    pub ground: GroundParameters,
+    pub noise: NoiseParameters,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -21,41 +40,115 @@ pub struct OutputParameters {
     pub crs: Option<CrsDef>,
 }
 
+/// Version of the parameter file format.
+/// Bump it when a change means old files can no longer be read as they were written
+pub const PARAMETER_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct ParameterFileRef<'a> {
+    version: u32,
+    #[serde(flatten)]
+    parameters: &'a MapParameters,
+}
+
+#[derive(Deserialize)]
+struct ParameterFile {
+    version: u32,
+    #[serde(flatten)]
+    parameters: MapParameters,
+}
+
 impl MapParameters {
-    pub fn min_size_filter_symbols(
-        &self,
-        openness: bool,
-        vegetation: bool,
-        cliffs: bool,
-        intensity: bool,
-        water: bool,
-    ) -> Vec<AreaSymbol> {
+    /// Reads a TOML parameter file, missing fields are given their default value.
+    /// The output CRS is not part of the file and is left as `None`
+    pub fn from_file(path: &Path) -> crate::Result<MapParameters> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read parameter file {path:?}"))?;
+
+        Self::from_toml(&contents)
+            .with_context(|| format!("Failed to parse parameter file {path:?}"))
+    }
+
+    pub fn to_file(&self, path: &Path) -> crate::Result<()> {
+        std::fs::write(path, self.to_toml()?)
+            .with_context(|| format!("Failed to write parameter file {path:?}"))
+    }
+
+    pub fn from_toml(contents: &str) -> crate::Result<MapParameters> {
+        let file: ParameterFile = toml::from_str(contents)?;
+
+        if file.version > PARAMETER_FILE_VERSION {
+            bail!(
+                "The parameter file has version {}, but only versions up to {PARAMETER_FILE_VERSION} are supported. \
+                It was probably written by a newer version of OmapMaker",
+                file.version
+            );
+        }
+
+        if let Scale::Custom(denominator) = file.parameters.scale
+            && !Scale::CUSTOM_DENOMINATORS.contains(&denominator)
+        {
+            bail!(
+                "The scale 1:{denominator} is outside the supported 1:{} to 1:{}",
+                Scale::CUSTOM_DENOMINATORS.start(),
+                Scale::CUSTOM_DENOMINATORS.end()
+            );
+        }
+
+        // TOML allows nan and inf, which would put every contour at the same level
+        if !file.parameters.contour.base_level.is_finite() {
+            bail!(
+                "The contour base level {} is not a number",
+                file.parameters.contour.base_level
+            );
+        }
+
+        Ok(file.parameters)
+    }
+
+    pub fn to_toml(&self) -> crate::Result<String> {
+        Ok(toml::to_string_pretty(&ParameterFileRef {
+            version: PARAMETER_FILE_VERSION,
+            parameters: self,
+        })?)
+    }
+
+    /// The area symbols of the given pipeline steps that are filtered by their minimum size
+    pub fn min_size_filter_symbols(&self, steps: PipelineSteps) -> Vec<AreaSymbol> {
         let mut symbols = Vec::new();
 
-        if openness && self.geometry.openness.min_size_filter {
+        if steps.openness && self.geometry.openness.min_size_filter {
             push_unique_area_symbol(&mut symbols, AreaSymbol::RoughOpenLand);
         }
 
-        if vegetation && self.geometry.vegetation.min_size_filter {
+        if steps.vegetation && self.geometry.vegetation.min_size_filter {
             push_unique_area_symbol(&mut symbols, AreaSymbol::LightGreen);
             push_unique_area_symbol(&mut symbols, AreaSymbol::MediumGreen);
             push_unique_area_symbol(&mut symbols, AreaSymbol::DarkGreen);
         }
 
-        if cliffs && self.geometry.cliffs.min_size_filter {
+        if steps.cliffs && self.geometry.cliffs.min_size_filter {
             push_unique_area_symbol(&mut symbols, AreaSymbol::GiganticBoulder);
         }
 
-        if intensity && self.geometry.intensity.min_size_filter {
+        if steps.intensity && self.geometry.intensity.min_size_filter {
             for filter in &self.intensity.filters {
                 push_unique_area_symbol(&mut symbols, filter.symbol);
             }
         }
 
-        if water && self.geometry.water.min_size_filter {
+        if steps.water && self.geometry.water.min_size_filter {
             push_unique_area_symbol(&mut symbols, AreaSymbol::UncrossableWaterWithBankLine);
         }
 
+        if steps.marsh && self.geometry.marsh.min_size_filter {
+            push_unique_area_symbol(&mut symbols, AreaSymbol::Marsh);
+        }
+
+        if steps.buildings && self.geometry.buildings.min_size_filter {
+            push_unique_area_symbol(&mut symbols, AreaSymbol::Building);
+        }
+
         symbols
     }
 }
@@ -66,14 +159,22 @@ fn push_unique_area_symbol(symbols: &mut Vec<AreaSymbol>, symbol: AreaSymbol) {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ContourParameters {
     pub algorithm: ContourAlgo,
     pub form_line_prune_algorithm: FormlinePruneAlgo,
     pub basemap_interval: f64,
     pub interval: f64,
+    // the contour levels are counted from here, in meters
+    pub base_level: f64,
     pub dot_knoll_area: (f64, f64),
+    pub slope_lines: bool,
+    pub repair_topology: bool,
+    pub index_contour_labels: bool,
     pub algo_steps: u8,
+    // the iterations of the contour generalisation
+    pub generalisation_steps: u8,
     pub algo_lambda: f64,
     pub basemap_contour: bool,
     pub form_lines: bool,
@@ -81,21 +182,24 @@ pub struct ContourParameters {
     pub form_line_error_threshold: f64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct VegetationParameters {
     pub green: (f64, f64, f64),
     pub weights: VegetationWeights,
     pub yellow: f64,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct VegetationWeights {
     pub low: f64,
     pub medium: f64,
     pub high: f64,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GeometryParameters {
     pub contours: BezierParameters,
     pub openness: BufferedGeometryParameters,
@@ -103,9 +207,12 @@ pub struct GeometryParameters {
     pub cliffs: BufferedGeometryParameters,
     pub intensity: BufferedGeometryParameters,
     pub water: BufferedGeometryParameters,
+    pub marsh: BufferedGeometryParameters,
+    pub buildings: BufferedGeometryParameters,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WaterParameters {
     pub threshold: f64,
 }
@@ -116,7 +223,192 @@ impl Default for WaterParameters {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BuildingParameters {
+    pub threshold: f64,
+}
+
+impl Default for BuildingParameters {
+    fn default() -> Self {
+        Self { threshold: 0.5 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarshParameters {
+    pub threshold: f64,
+}
+
+impl Default for MarshParameters {
+    fn default() -> Self {
+        Self { threshold: 0.6 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamParameters {
+    /// In square meters
+    pub min_contributing_area: f64,
+}
+
+impl Default for StreamParameters {
+    fn default() -> Self {
+        Self {
+            min_contributing_area: 5000.,
+        }
+    }
+}
+
+/// Heights in meters above the surrounding ground, the footprint in square meters
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BoulderParameters {
+    pub min_height: f64,
+    pub large_height: f64,
+    pub max_height: f64,
+    pub max_footprint: f64,
+}
+
+impl Default for BoulderParameters {
+    fn default() -> Self {
+        Self {
+            min_height: 0.8,
+            large_height: 1.5,
+            max_height: 3.,
+            max_footprint: 16.,
+        }
+    }
+}
+
+/// Every width is the minimum corridor width in meters for its symbol,
+/// narrower corridors are less distinct small paths
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoadParameters {
+    pub threshold: f64,
+    /// In meters
+    pub min_length: f64,
+    pub small_path_width: f64,
+    pub footpath_width: f64,
+    pub vehicle_track_width: f64,
+    pub road_width: f64,
+    pub wide_road_width: f64,
+}
+
+impl RoadParameters {
+    /// The symbol of a road or path of the given width in meters
+    pub fn line_symbol(&self, width: f64) -> LineSymbol {
+        if width >= self.wide_road_width {
+            LineSymbol::WideRoad
+        } else if width >= self.road_width {
+            LineSymbol::Road
+        } else if width >= self.vehicle_track_width {
+            LineSymbol::VehicleTrack
+        } else if width >= self.footpath_width {
+            LineSymbol::Footpath
+        } else if width >= self.small_path_width {
+            LineSymbol::SmallPath
+        } else {
+            LineSymbol::LessDistinctSmallPath
+        }
+    }
+}
+
+impl Default for RoadParameters {
+    fn default() -> Self {
+        Self {
+            threshold: 0.6,
+            min_length: 20.,
+            small_path_width: 1.,
+            footpath_width: 1.5,
+            vehicle_track_width: 2.5,
+            road_width: 4.,
+            wide_road_width: 6.,
+        }
+    }
+}
+
+/// The progressive morphological filter classifying ground in unclassified lidar.
+/// Distances and heights in meters, the slope in meters per meter
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GroundParameters {
+    pub classification: GroundClassification,
+    pub cell_size: f64,
+    pub max_window: f64,
+    pub slope: f64,
+    pub initial_threshold: f64,
+    pub max_threshold: f64,
+}
+
+impl Default for GroundParameters {
+    fn default() -> Self {
+        Self {
+            classification: GroundClassification::Missing,
+            cell_size: 1.,
+            max_window: 20.,
+            slope: 0.3,
+            initial_threshold: 0.3,
+            max_threshold: 3.,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroundClassification {
+    /// Only the ground class of the lidar files is used
+    Lidar,
+    /// Files without any ground points are classified
+    #[default]
+    Missing,
+    /// Every file is classified, replacing its ground class
+    Always,
+}
+
+impl Display for GroundClassification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GroundClassification::Lidar => f.write_str("From lidar"),
+            GroundClassification::Missing => f.write_str("When missing"),
+            GroundClassification::Always => f.write_str("Always"),
+        }
+    }
+}
+
+/// Heights in meters relative to the ground of the surrounding cells,
+/// the outlier ratio in standard deviations of the mean neighbour distance
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseParameters {
+    /// Drop the low point (7) and high noise (18) classes
+    pub remove_noise_classes: bool,
+    pub remove_isolated: bool,
+    pub max_below_ground: f64,
+    pub max_above_ground: f64,
+    pub remove_outliers: bool,
+    pub outlier_neighbors: usize,
+    pub outlier_std_ratio: f64,
+}
+
+impl Default for NoiseParameters {
+    fn default() -> Self {
+        Self {
+            remove_noise_classes: true,
+            remove_isolated: true,
+            max_below_ground: 3.,
+            max_above_ground: 80.,
+            remove_outliers: true,
+            outlier_neighbors: 8,
+            outlier_std_ratio: 3.,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CliffParameters {
     pub cliff: f64,
 }
@@ -127,7 +419,8 @@ impl Default for CliffParameters {
     }
 }
 
-#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct IntensityParameters {
     pub filters: Vec<IntensityFilter>,
 }
@@ -139,8 +432,13 @@ impl Default for ContourParameters {
             form_line_prune_algorithm: Default::default(),
             basemap_interval: 0.5,
             interval: 5.,
+            base_level: 0.,
             dot_knoll_area: (10., 160.),
+            slope_lines: true,
+            repair_topology: true,
+            index_contour_labels: false,
             algo_steps: 0,
+            generalisation_steps: 10,
             algo_lambda: 0.01,
             basemap_contour: false,
             form_lines: false,
@@ -182,6 +480,9 @@ impl GeometryParameters {
             | Symbol::Area(AreaSymbol::DarkGreen) => &self.vegetation.bezier,
             Symbol::Area(AreaSymbol::GiganticBoulder) => &self.cliffs.bezier,
             Symbol::Area(AreaSymbol::UncrossableWaterWithBankLine) => &self.water.bezier,
+            Symbol::Area(AreaSymbol::Marsh) => &self.marsh.bezier,
+            // regularized building outlines keep their corners
+            Symbol::Area(AreaSymbol::Building) => return None,
             Symbol::Area(_) => &self.intensity.bezier,
             Symbol::Line(_) | Symbol::Point(_) => return None,
         };
@@ -190,7 +491,8 @@ impl GeometryParameters {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BezierParameters {
     pub error: f64,
     pub enabled: bool,
@@ -205,7 +507,8 @@ impl Default for BezierParameters {
     }
 }
 
-#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BufferedGeometryParameters {
     pub bezier: BezierParameters,
     pub buffer_rules: Vec<BufferRule>,
@@ -216,23 +519,88 @@ pub struct BufferedGeometryParameters {
 pub struct FileParameters {
     pub paths: Vec<PathBuf>,
     pub save_location: PathBuf,
+    pub output_format: OutputFormat,
+    // an omap or xmap whose symbols replace the default symbol set of the scale
+    pub symbol_template: Option<PathBuf>,
     pub save_slope_raster: bool,
     pub save_hillshade_raster: bool,
     pub save_last_return_raster: bool,
     pub save_canopy_height_raster: bool,
     pub save_surface_objects_raster: bool,
     pub save_ndvd_raster: bool,
+    pub save_flow_accumulation_raster: bool,
+    pub save_dem_raster: bool,
+    pub save_intensity_raster: bool,
+    pub save_return_number_raster: bool,
+    pub save_vegetation_density_rasters: bool,
+    pub save_water_raster: bool,
+    pub raster_format: RasterFormat,
+    pub save_geopackage: bool,
+    pub save_geojson: bool,
 
     // lidar crs's
     pub crs_epsg: Vec<Option<CrsDef>>,
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OutputFormat {
+    #[default]
+    Omap,
+    Ocd,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Omap => "omap",
+            OutputFormat::Ocd => "ocd",
+        }
+    }
+
+    /// The format matching the extension of the path, omap for unknown extensions
+    pub fn from_path(path: &Path) -> OutputFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("ocd") => OutputFormat::Ocd,
+            _ => OutputFormat::Omap,
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Omap => f.write_str("OpenOrienteering Mapper (.omap)"),
+            OutputFormat::Ocd => f.write_str("OCAD 12 (.ocd)"),
+        }
+    }
+}
+
+/// How the values of the saved rasters are written
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RasterFormat {
+    /// Stretched to 8 bits, for looking at the raster
+    #[default]
+    Rendered,
+    /// The true values as 32-bit floats, for analysis
+    Float32,
+}
+
+impl Display for RasterFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RasterFormat::Rendered => f.write_str("8-bit image"),
+            RasterFormat::Float32 => f.write_str("32-bit float values"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ContourAlgo {
     NaiveIterations,
     NormalFieldSmoothing,
     #[default]
     Raw,
+    Generalisation,
 }
 
 impl Display for ContourAlgo {
@@ -241,11 +609,12 @@ impl Display for ContourAlgo {
             ContourAlgo::NaiveIterations => f.write_str("Naive"),
             ContourAlgo::NormalFieldSmoothing => f.write_str("Smooth"),
             ContourAlgo::Raw => f.write_str("Raw"),
+            ContourAlgo::Generalisation => f.write_str("Generalised"),
         }
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum FormlinePruneAlgo {
     #[default]
     None,
@@ -263,7 +632,8 @@ impl Display for FormlinePruneAlgo {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct IntensityFilter {
     pub low: f64,
     pub high: f64,
@@ -280,7 +650,8 @@ impl Default for IntensityFilter {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BufferRule {
     pub direction: BufferDirection,
     pub amount: f64,
@@ -295,28 +666,157 @@ impl Default for BufferRule {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BufferDirection {
     Grow,
     Shrink,
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
 pub enum Scale {
+    S4_000,
+    S5_000,
     S10_000,
     #[default]
     S15_000,
+    /// Any other scale denominator, drawn by the standard of the nearest fixed scale
+    Custom(u32),
+}
+
+/// The symbol specification a map is drawn by, decided by its scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolStandard {
+    Isom2017,
+    Issprom2019,
+}
+
+impl SymbolStandard {
+    /// The scale the symbol dimensions of the standard are given for
+    pub fn reference_denominator(self) -> f64 {
+        match self {
+            SymbolStandard::Isom2017 => 15_000.,
+            SymbolStandard::Issprom2019 => 4_000.,
+        }
+    }
 }
 
 impl Scale {
+    /// The denominators a custom scale can be given
+    pub const CUSTOM_DENOMINATORS: RangeInclusive<u32> = 1_000..=25_000;
+
+    // custom scales up to this denominator are sprint maps
+    const MAX_SPRINT_DENOMINATOR: u32 = 5_000;
+
     pub fn denominator(self) -> f64 {
         match self {
+            Self::S4_000 => 4_000.,
+            Self::S5_000 => 5_000.,
             Self::S10_000 => 10_000.,
             Self::S15_000 => 15_000.,
+            Self::Custom(denominator) => denominator as f64,
+        }
+    }
+
+    /// Sprint scales use ISSprOM, forest scales ISOM
+    pub fn symbol_standard(self) -> SymbolStandard {
+        match self {
+            Self::S4_000 | Self::S5_000 => SymbolStandard::Issprom2019,
+            Self::S10_000 | Self::S15_000 => SymbolStandard::Isom2017,
+            Self::Custom(denominator) if denominator <= Self::MAX_SPRINT_DENOMINATOR => {
+                SymbolStandard::Issprom2019
+            }
+            Self::Custom(_) => SymbolStandard::Isom2017,
         }
     }
 
+    /// The ratio of the ground size of a paper dimension to that at the reference scale of the standard
+    pub fn reference_ratio(self) -> f64 {
+        self.denominator() / self.symbol_standard().reference_denominator()
+    }
+
     pub fn meters_to_paper_mm(self, meters: f64) -> f64 {
         meters * 1000. / self.denominator()
     }
+
+    pub fn paper_mm_to_meters(self, mm: f64) -> f64 {
+        mm * self.denominator() / 1000.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BufferDirection, BufferRule, ContourAlgo, FormlinePruneAlgo, IntensityFilter,
+        MapParameters, PARAMETER_FILE_VERSION, Scale, SymbolStandard,
+    };
+    use crate::map_gen::egui_map::AreaSymbol;
+
+    #[test]
+    fn parameter_file_round_trips() {
+        let mut params = MapParameters {
+            scale: Scale::S10_000,
+            ..Default::default()
+        };
+        params.contour.algorithm = ContourAlgo::NormalFieldSmoothing;
+        params.contour.form_line_prune_algorithm = FormlinePruneAlgo::InterpolationError;
+        params.contour.dot_knoll_area = (12., 140.);
+        params.geometry.vegetation.buffer_rules.push(BufferRule {
+            direction: BufferDirection::Shrink,
+            amount: 3.,
+        });
+        params.intensity.filters.push(IntensityFilter {
+            low: 0.1,
+            high: 0.3,
+            symbol: AreaSymbol::SandyGround,
+        });
+
+        let toml = params.to_toml().unwrap();
+        let read = MapParameters::from_toml(&toml).unwrap();
+
+        assert_eq!(read.scale, Scale::S10_000);
+        assert_eq!(read.contour.algorithm, ContourAlgo::NormalFieldSmoothing);
+        assert_eq!(read.contour.dot_knoll_area, (12., 140.));
+        assert_eq!(read.geometry, params.geometry);
+        assert_eq!(read.intensity, params.intensity);
+        assert_eq!(read.to_toml().unwrap(), toml);
+    }
+
+    #[test]
+    fn missing_fields_get_default_values() {
+        let read = MapParameters::from_toml("version = 1\n[contour]\ninterval = 2.5\n").unwrap();
+
+        assert_eq!(read.contour.interval, 2.5);
+        assert_eq!(read.contour.basemap_interval, 0.5);
+        assert_eq!(read.scale, Scale::S15_000);
+    }
+
+    #[test]
+    fn newer_parameter_files_are_rejected() {
+        let toml = format!("version = {}\n", PARAMETER_FILE_VERSION + 1);
+
+        assert!(MapParameters::from_toml(&toml).is_err());
+    }
+
+    #[test]
+    fn custom_scales_are_read_and_bounded() {
+        let read = MapParameters::from_toml("version = 1\nscale = { Custom = 7500 }\n").unwrap();
+
+        assert_eq!(read.scale, Scale::Custom(7_500));
+        assert_eq!(read.scale.symbol_standard(), SymbolStandard::Isom2017);
+        assert_eq!(read.scale.reference_ratio(), 0.5);
+        assert!(MapParameters::from_toml("version = 1\nscale = { Custom = 100 }\n").is_err());
+    }
+
+    #[test]
+    fn non_finite_base_levels_are_rejected() {
+        let read = MapParameters::from_toml("version = 1\n[contour]\nbase_level = 1.25\n").unwrap();
+
+        assert_eq!(read.contour.base_level, 1.25);
+        for level in ["nan", "inf", "-inf"] {
+            let toml = format!("version = 1\n[contour]\nbase_level = {level}\n");
+            assert!(MapParameters::from_toml(&toml).is_err());
+        }
+    }
 }
@@ -5,7 +5,9 @@ use crate::{
 
 use std::path::PathBuf;
 
+use anyhow::Context;
 use las::Reader;
+use proj_core::CrsDef;
 
 pub fn parse_crs(sender: FrontendSender, mut paths: Vec<PathBuf>) {
     let _ = sender.send(FrontendTask::Log(
@@ -95,3 +97,25 @@ pub fn parse_crs(sender: FrontendSender, mut paths: Vec<PathBuf>) {
         let _ = sender.send(FrontendTask::OpenModal(OmapModal::ManualSetCRS));
     }
 }
+
+/// The CRS shared by the most lidar files, `None` if no file has a CRS
+pub fn most_popular_crs(crs_defs: &[Option<CrsDef>]) -> Option<CrsDef> {
+    let mut crs_tally: Vec<(u32, u16, CrsDef)> = Vec::new();
+    for crs in crs_defs.iter().flatten() {
+        let epsg = crs.epsg();
+        if let Some((_, count, _)) = crs_tally.iter_mut().find(|(code, _, _)| *code == epsg) {
+            *count += 1;
+        } else {
+            crs_tally.push((epsg, 1, crs.clone()));
+        }
+    }
+    crs_tally
+        .into_iter()
+        .max_by(|(_, v1, _), (_, v2, _)| v1.cmp(v2))
+        .map(|(_, _, crs)| crs)
+}
+
+/// The OGC WKT definition of a CRS, for the file formats storing the CRS as WKT
+pub fn crs_wkt(crs: &CrsDef) -> crate::Result<String> {
+    proj_wkt::to_wkt(crs).with_context(|| format!("Could not write EPSG:{} as WKT", crs.epsg()))
+}
@@ -1,6 +1,7 @@
 use crate::geometry::contour_set::ContourPoint;
 use crate::{CELL_SIZE_METERS, TILE_SIZE_PIXELS};
 
+use serde::{Deserialize, Serialize};
 use std::marker::PhantomData;
 use std::ops::{Index, IndexMut};
 
@@ -35,9 +36,19 @@ pub struct SurfaceObjects;
 #[derive(Clone, Copy, Debug)]
 pub struct Water;
 #[derive(Clone, Copy, Debug)]
+pub struct Building;
+#[derive(Clone, Copy, Debug)]
+pub struct Boulder;
+#[derive(Clone, Copy, Debug)]
+pub struct FlowAccumulation;
+#[derive(Clone, Copy, Debug)]
+pub struct Marsh;
+#[derive(Clone, Copy, Debug)]
+pub struct Road;
+#[derive(Clone, Copy, Debug)]
 pub struct Ndvd;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct DfmPixelBounds {
     pub top: usize,
     pub bottom: usize,
@@ -60,7 +71,9 @@ impl DfmPixelBounds {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+// the marker types carry no data, so they need no serde bounds
+#[serde(bound = "")]
 pub struct Dfm<T> {
     pub field: Box<[f64]>,
     pub tl_coord: geo::Coord,
@@ -4,31 +4,37 @@ use std::{
 };
 
 use anyhow::Context;
-use geotiff_writer::GeoTiffBuilder;
+use geotiff_writer::{CogBuilder, Compression, GeoTiffBuilder, Resampling};
 use ndarray::Array2;
 use proj_core::CrsDef;
 
-use crate::{CELL_SIZE_METERS, raster::Dfm};
+use crate::{CELL_SIZE_METERS, parameters::RasterFormat, raster::Dfm};
 
 const NODATA_VALUE: f64 = -9999.;
+const NODATA_TEXT: &str = "-9999";
 const RENDERED_NODATA_VALUE: u8 = 0;
 const RENDERED_NODATA_TEXT: &str = "0";
+const COG_TILE_SIZE: u32 = 512;
 
+/// Merges the tiles into one Cloud Optimized GeoTIFF next to the save location,
+/// deflate compressed in 512 pixel tiles with averaged overviews.
+///
+/// Rendered rasters are stretched between their min and max value, float rasters
+/// keep the values of the tiles and mark cells without data by -9999
 pub fn write_merged_dfm_geotiff<T>(
     save_location: &Path,
     suffix: &str,
     tiles: &[Dfm<T>],
     ref_point: geo::Coord,
     crs: Option<&CrsDef>,
+    format: RasterFormat,
 ) -> crate::Result<PathBuf> {
     let path = raster_output_path(save_location, suffix);
     let Some((merged, top_left)) = merge_dfms(tiles) else {
         return Ok(path);
     };
 
-    let rendered = render_raster_for_image_viewers(&merged);
-
-    let (height, width) = rendered.dim();
+    let (height, width) = merged.dim();
     let width = u32::try_from(width).context("Merged raster width does not fit in u32")?;
     let height = u32::try_from(height).context("Merged raster height does not fit in u32")?;
 
@@ -36,7 +42,8 @@ pub fn write_merged_dfm_geotiff<T>(
     let mut builder = GeoTiffBuilder::new(width, height)
         .pixel_scale(CELL_SIZE_METERS, CELL_SIZE_METERS)
         .origin(origin_x, origin_y)
-        .nodata(RENDERED_NODATA_TEXT);
+        .tile_size(COG_TILE_SIZE, COG_TILE_SIZE)
+        .compression(Compression::Deflate);
 
     if let Some(epsg) = crs
         .map(CrsDef::epsg)
@@ -46,7 +53,16 @@ pub fn write_merged_dfm_geotiff<T>(
         builder = builder.epsg(epsg);
     }
 
-    builder.write_2d(&path, rendered.view()).with_context(|| {
+    // the overviews skip the no data cells when averaging
+    let cog = |nodata: &str| {
+        CogBuilder::new(builder.clone().nodata(nodata)).resampling(Resampling::Average)
+    };
+    let written = match format {
+        RasterFormat::Rendered => cog(RENDERED_NODATA_TEXT)
+            .write_2d(&path, render_raster_for_image_viewers(&merged).view()),
+        RasterFormat::Float32 => cog(NODATA_TEXT).write_2d(&path, to_float32(&merged).view()),
+    };
+    written.with_context(|| {
         format!(
             "Failed to write merged {suffix} raster to {}",
             path.display()
@@ -164,6 +180,17 @@ fn render_raster_for_image_viewers(raster: &Array2<f64>) -> Array2<u8> {
     })
 }
 
+// values the merge could not average, e.g. infinite slopes, are written as no data
+fn to_float32(raster: &Array2<f64>) -> Array2<f32> {
+    raster.mapv(|value| {
+        if is_renderable(value) && value.abs() <= f64::from(f32::MAX) {
+            value as f32
+        } else {
+            NODATA_VALUE as f32
+        }
+    })
+}
+
 fn is_renderable(value: f64) -> bool {
     value.is_finite() && value != NODATA_VALUE
 }
@@ -177,7 +204,7 @@ fn geotiff_origin(top_left: geo::Coord, ref_point: geo::Coord) -> (f64, f64) {
 
 #[cfg(test)]
 mod tests {
-    use super::{geotiff_origin, merge_dfms};
+    use super::{NODATA_VALUE, geotiff_origin, merge_dfms, to_float32};
     use crate::{CELL_SIZE_METERS, raster::Dfm, raster::dfm::Slope};
 
     #[test]
@@ -226,4 +253,16 @@ mod tests {
             assert_eq!(row.to_vec(), vec![1., 1., 1., 2., 2.]);
         }
     }
+
+    #[test]
+    fn float_rasters_keep_values_and_nodata() {
+        let raster = ndarray::array![[1.25, NODATA_VALUE], [f64::INFINITY, -3.5]];
+
+        let float = to_float32(&raster);
+
+        assert_eq!(
+            float,
+            ndarray::array![[1.25, NODATA_VALUE as f32], [NODATA_VALUE as f32, -3.5]]
+        );
+    }
 }
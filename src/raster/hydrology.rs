@@ -0,0 +1,369 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Dfm,
+    dfm::{Elevation, FlowAccumulation},
+};
+use crate::{CELL_SIZE_METERS, TILE_SIZE_PIXELS};
+
+// the filled surface gets this small gradient so every cell has a lower neighbour
+const FILL_EPSILON_METERS: f64 = 1e-5;
+
+const NEIGHBORS: [(isize, isize); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+/// D8 routing of the water over a tile
+#[derive(Serialize, Deserialize)]
+pub struct FlowNetwork {
+    /// The neighbouring cell every cell drains to, `None` where the water leaves the tile
+    pub receivers: Vec<Option<usize>>,
+    /// Upslope contributing area of every cell in square meters, the cell itself included
+    pub accumulation: Dfm<FlowAccumulation>,
+}
+
+// min-heap entry for the priority flood
+struct FloodCell {
+    z: f64,
+    index: usize,
+}
+
+impl PartialEq for FloodCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FloodCell {}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FloodCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .z
+            .total_cmp(&self.z)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl Dfm<Elevation> {
+    /// Raises every closed depression to its spill level with a priority flood
+    /// from the tile edge, so all water can drain out of the tile.
+    ///
+    /// The filled areas get a tiny gradient towards the spill point instead of being flat
+    pub fn fill_depressions(&self) -> Dfm<Elevation> {
+        let mut filled = self.clone();
+        let mut visited = vec![false; TILE_SIZE_PIXELS * TILE_SIZE_PIXELS];
+        let mut queue = BinaryHeap::new();
+
+        for yi in 0..TILE_SIZE_PIXELS {
+            for xi in 0..TILE_SIZE_PIXELS {
+                if yi == 0 || xi == 0 || yi == TILE_SIZE_PIXELS - 1 || xi == TILE_SIZE_PIXELS - 1 {
+                    let index = yi * TILE_SIZE_PIXELS + xi;
+                    visited[index] = true;
+                    queue.push(FloodCell {
+                        z: filled.field[index],
+                        index,
+                    });
+                }
+            }
+        }
+
+        while let Some(FloodCell { z, index }) = queue.pop() {
+            for neighbor in neighbor_indices(index) {
+                if visited[neighbor] {
+                    continue;
+                }
+                visited[neighbor] = true;
+
+                let spill = z + FILL_EPSILON_METERS;
+                if filled.field[neighbor] < spill {
+                    filled.field[neighbor] = spill;
+                }
+                queue.push(FloodCell {
+                    z: filled.field[neighbor],
+                    index: neighbor,
+                });
+            }
+        }
+
+        filled
+    }
+
+    /// Fills the depressions and routes every cell to its steepest
+    /// downhill neighbour before accumulating the contributing area
+    pub fn flow_network(&self) -> FlowNetwork {
+        let filled = self.fill_depressions();
+
+        let mut receivers = vec![None; TILE_SIZE_PIXELS * TILE_SIZE_PIXELS];
+        for (index, receiver) in receivers.iter_mut().enumerate() {
+            let (yi, xi) = (index / TILE_SIZE_PIXELS, index % TILE_SIZE_PIXELS);
+            if yi == 0 || xi == 0 || yi == TILE_SIZE_PIXELS - 1 || xi == TILE_SIZE_PIXELS - 1 {
+                continue;
+            }
+
+            let z = filled.field[index];
+            let mut steepest = 0.;
+            for neighbor in neighbor_indices(index) {
+                let (yj, xj) = (neighbor / TILE_SIZE_PIXELS, neighbor % TILE_SIZE_PIXELS);
+                let distance = if yi != yj && xi != xj {
+                    std::f64::consts::SQRT_2 * CELL_SIZE_METERS
+                } else {
+                    CELL_SIZE_METERS
+                };
+                let gradient = (z - filled.field[neighbor]) / distance;
+                if gradient > steepest {
+                    steepest = gradient;
+                    *receiver = Some(neighbor);
+                }
+            }
+        }
+
+        // every receiver is strictly lower, so visiting the cells from the
+        // top down passes each cell's area on after all its donors are done
+        let mut order = (0..receivers.len()).collect::<Vec<_>>();
+        order.sort_unstable_by(|&a, &b| filled.field[b].total_cmp(&filled.field[a]));
+
+        let mut accumulation = Dfm::<FlowAccumulation>::new_like(self);
+        accumulation.field.fill(CELL_SIZE_METERS * CELL_SIZE_METERS);
+        for index in order {
+            if let Some(receiver) = receivers[index] {
+                accumulation.field[receiver] += accumulation.field[index];
+            }
+        }
+
+        FlowNetwork {
+            receivers,
+            accumulation,
+        }
+    }
+}
+
+/// A cell where the water leaves the inside of a tile
+pub struct Outlet {
+    /// The last cell inside
+    pub from: geo::Coord,
+    /// The first cell outside, in the neighbouring tile
+    pub to: geo::Coord,
+    /// Upslope contributing area of the inside cells in square meters
+    pub area: f64,
+}
+
+/// Where the water of a tile leaves its inside, and which outlet the water entering it
+/// from a neighbouring tile leaves through
+pub struct FlowOutlets {
+    pub tl_coord: geo::Coord,
+    pub outlets: Vec<Outlet>,
+    // the outlet of every inside cell next to an outside cell
+    entries: HashMap<usize, usize>,
+}
+
+impl FlowOutlets {
+    /// The outlet of the water entering the tile at `coord`, `None` if it drains out of the
+    /// tile somewhere else or is not at the edge of the inside
+    pub fn outlet_entered_at(&self, coord: geo::Coord) -> Option<usize> {
+        cell_at(self.tl_coord, coord).and_then(|cell| self.entries.get(&cell).copied())
+    }
+}
+
+impl FlowNetwork {
+    /// Upslope contributing area of the inside cells, counted only from the inside cells,
+    /// plus the water entering at the given cells. The water entering follows its path
+    /// through the tile to the first cell after the inside.
+    ///
+    /// Neighbouring tiles each count the cells of their own inside, so the water is only
+    /// passed on between them through the outlets
+    pub fn inside_accumulation(
+        &self,
+        inside: &[bool],
+        inflows: &[(usize, f64)],
+    ) -> Dfm<FlowAccumulation> {
+        let mut accumulation = Dfm::<FlowAccumulation>::new_like(&self.accumulation);
+        for (area, &inside) in accumulation.field.iter_mut().zip(inside) {
+            *area = if inside {
+                CELL_SIZE_METERS * CELL_SIZE_METERS
+            } else {
+                0.
+            };
+        }
+        for index in self.donor_order() {
+            if let Some(receiver) = self.receivers[index]
+                && inside[index]
+                && inside[receiver]
+            {
+                accumulation.field[receiver] += accumulation.field[index];
+            }
+        }
+
+        for &(cell, area) in inflows {
+            let mut current = Some(cell);
+            let mut entered = false;
+            while let Some(index) = current {
+                accumulation.field[index] += area;
+                if entered && !inside[index] {
+                    break;
+                }
+                entered |= inside[index];
+                current = self.receivers[index];
+            }
+        }
+        accumulation
+    }
+
+    /// The outlets of the inside of the tile, the water draining out over the edge
+    /// of the tile itself is lost
+    pub fn outlets(&self, inside: &[bool]) -> FlowOutlets {
+        let accumulation = self.inside_accumulation(inside, &[]);
+        let tl_coord = accumulation.tl_coord;
+        let coord = |index: usize| {
+            accumulation.index2coord(index / TILE_SIZE_PIXELS, index % TILE_SIZE_PIXELS)
+        };
+
+        // visiting the receivers before their donors
+        let mut outlet_of = vec![None; self.receivers.len()];
+        let mut outlets = Vec::new();
+        for index in self.donor_order().into_iter().rev() {
+            if !inside[index] {
+                continue;
+            }
+            match self.receivers[index] {
+                Some(receiver) if inside[receiver] => outlet_of[index] = outlet_of[receiver],
+                Some(receiver) => {
+                    outlet_of[index] = Some(outlets.len());
+                    outlets.push(Outlet {
+                        from: coord(index),
+                        to: coord(receiver),
+                        area: accumulation.field[index],
+                    });
+                }
+                None => (),
+            }
+        }
+
+        let entries = (0..self.receivers.len())
+            .filter(|&index| {
+                inside[index] && neighbor_indices(index).any(|neighbor| !inside[neighbor])
+            })
+            .filter_map(|index| Some((index, outlet_of[index]?)))
+            .collect();
+
+        FlowOutlets {
+            tl_coord,
+            outlets,
+            entries,
+        }
+    }
+
+    /// The cell at `coord`, if it is on the tile
+    pub fn cell_at(&self, coord: geo::Coord) -> Option<usize> {
+        cell_at(self.accumulation.tl_coord, coord)
+    }
+
+    // every cell comes after all the cells draining to it
+    fn donor_order(&self) -> Vec<usize> {
+        let mut num_donors = vec![0_u8; self.receivers.len()];
+        for &receiver in self.receivers.iter().flatten() {
+            num_donors[receiver] += 1;
+        }
+
+        let mut order = (0..self.receivers.len())
+            .filter(|&index| num_donors[index] == 0)
+            .collect::<Vec<_>>();
+        let mut next = 0;
+        while next < order.len() {
+            if let Some(receiver) = self.receivers[order[next]] {
+                num_donors[receiver] -= 1;
+                if num_donors[receiver] == 0 {
+                    order.push(receiver);
+                }
+            }
+            next += 1;
+        }
+        order
+    }
+}
+
+fn cell_at(tl_coord: geo::Coord, coord: geo::Coord) -> Option<usize> {
+    let xi = ((coord.x - tl_coord.x) / CELL_SIZE_METERS).round();
+    let yi = ((tl_coord.y - coord.y) / CELL_SIZE_METERS).round();
+    let range = 0. ..TILE_SIZE_PIXELS as f64;
+    (range.contains(&xi) && range.contains(&yi))
+        .then_some(yi as usize * TILE_SIZE_PIXELS + xi as usize)
+}
+
+fn neighbor_indices(index: usize) -> impl Iterator<Item = usize> {
+    let (yi, xi) = (
+        (index / TILE_SIZE_PIXELS) as isize,
+        (index % TILE_SIZE_PIXELS) as isize,
+    );
+    NEIGHBORS.iter().filter_map(move |(dy, dx)| {
+        let (yj, xj) = (yi + dy, xi + dx);
+        (yj >= 0 && xj >= 0 && yj < TILE_SIZE_PIXELS as isize && xj < TILE_SIZE_PIXELS as isize)
+            .then_some(yj as usize * TILE_SIZE_PIXELS + xj as usize)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        CELL_SIZE_METERS, TILE_SIZE_PIXELS,
+        raster::{Dfm, dfm::Elevation},
+    };
+
+    fn tilted_dem() -> Dfm<Elevation> {
+        let mut dem = Dfm::<Elevation>::new(geo::Coord { x: 0., y: 0. });
+        for yi in 0..TILE_SIZE_PIXELS {
+            for xi in 0..TILE_SIZE_PIXELS {
+                dem[(yi, xi)] = (TILE_SIZE_PIXELS - yi) as f64;
+            }
+        }
+        dem
+    }
+
+    #[test]
+    fn depressions_are_filled_to_the_spill_level() {
+        let mut dem = tilted_dem();
+        let center = TILE_SIZE_PIXELS / 2;
+        let rim = dem[(center - 1, center)];
+        dem[(center, center)] = rim - 10.;
+
+        let filled = dem.fill_depressions();
+
+        assert!(filled[(center, center)] > dem[(center + 1, center)]);
+        assert!(filled[(center, center)] < rim);
+        assert_eq!(filled[(0, 0)], dem[(0, 0)]);
+    }
+
+    #[test]
+    fn area_accumulates_downslope() {
+        let network = tilted_dem().flow_network();
+
+        let column = TILE_SIZE_PIXELS / 2;
+        let cell_area = CELL_SIZE_METERS * CELL_SIZE_METERS;
+        // the inner cells of a column drain straight down, the top edge row drains out of the tile
+        let expected = (TILE_SIZE_PIXELS - 2) as f64 * cell_area;
+        assert!(
+            (network.accumulation[(TILE_SIZE_PIXELS - 2, column)] - expected).abs() < 1e-9,
+            "{}",
+            network.accumulation[(TILE_SIZE_PIXELS - 2, column)]
+        );
+        assert_eq!(network.receivers[column], None);
+    }
+}
@@ -1,5 +1,6 @@
 pub mod dfm;
 pub mod geotiff;
+pub mod hydrology;
 
 pub use self::dfm::Dfm;
 
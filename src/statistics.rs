@@ -1,10 +1,11 @@
 use las::Reader;
+use serde::{Deserialize, Serialize};
 use std::{ops::Div, path::Path};
 
 const STATS_SAMPLE_SIZE: usize = 10_000;
 const MAX_NUMBER_OF_RETURNS: u8 = 15;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LidarStats {
     pub return_distr: Vec<u64>,
     pub return_number: Stat,
@@ -131,7 +132,7 @@ impl LidarStats {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Stat {
     pub min: f64,
     pub max: f64,
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use geo::MapCoords;
+use proj_core::{CrsDef, Transform};
+use serde_json::{Map, Value, json};
+
+use super::{Layer, vector_output_path};
+use crate::map_gen::egui_map::TempMap;
+
+/// Writes one GeoJSON file per symbol to a folder next to the map, returns the folder.
+///
+/// The coordinates are transformed to WGS 84 longitude and latitude as RFC 7946 asks for.
+/// A map without a CRS keeps its local coordinates, which no GeoJSON reader can place
+pub fn write_geojson(
+    save_location: &Path,
+    map: &TempMap,
+    crs: Option<&CrsDef>,
+) -> crate::Result<PathBuf> {
+    let dir = vector_output_path(save_location, "_geojson");
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create the GeoJSON folder {}", dir.display()))?;
+
+    let transform = match crs.map(CrsDef::epsg).filter(|epsg| *epsg != 0) {
+        Some(epsg) => Some(Transform::from_epsg(epsg, 4326)?),
+        None => None,
+    };
+
+    for layer in Layer::from_map(map) {
+        let path = dir.join(format!("{}.geojson", layer.name));
+        let contents = serde_json::to_string(&feature_collection(&layer, transform.as_ref())?)?;
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    Ok(dir)
+}
+
+fn feature_collection(layer: &Layer, transform: Option<&Transform>) -> crate::Result<Value> {
+    let features = layer
+        .features
+        .iter()
+        .map(|feature| {
+            let geometry = match transform {
+                Some(transform) => feature.geometry.try_map_coords(|c| {
+                    transform
+                        .convert((c.x, c.y))
+                        .map(|(lon, lat)| geo::coord! {x: lon, y: lat})
+                })?,
+                None => feature.geometry.clone(),
+            };
+
+            let properties = layer
+                .columns
+                .iter()
+                .zip(&feature.values)
+                .map(|(column, value)| {
+                    let value = match value {
+                        Some(v) if column.numeric => {
+                            v.parse::<f64>().map_or(Value::Null, Value::from)
+                        }
+                        Some(v) => Value::from(v.as_str()),
+                        None => Value::Null,
+                    };
+                    (column.name.clone(), value)
+                })
+                .collect::<Map<_, _>>();
+
+            Ok(json!({
+                "type": "Feature",
+                "properties": properties,
+                "geometry": geometry(&geometry),
+            }))
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    Ok(json!({
+        "type": "FeatureCollection",
+        "name": layer.name,
+        "features": features,
+    }))
+}
+
+fn geometry(geometry: &geo::Geometry) -> Value {
+    let coords =
+        |line: &geo::LineString| line.coords().map(|c| json!([c.x, c.y])).collect::<Vec<_>>();
+
+    match geometry {
+        geo::Geometry::Point(p) => json!({ "type": "Point", "coordinates": [p.x(), p.y()] }),
+        geo::Geometry::LineString(l) => json!({ "type": "LineString", "coordinates": coords(l) }),
+        geo::Geometry::Polygon(p) => json!({
+            "type": "Polygon",
+            "coordinates": std::iter::once(p.exterior())
+                .chain(p.interiors())
+                .map(coords)
+                .collect::<Vec<_>>(),
+        }),
+        _ => Value::Null,
+    }
+}
@@ -0,0 +1,264 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use proj_core::CrsDef;
+use rusqlite::{Connection, params, params_from_iter, types::Value};
+
+use super::{GeometryType, Layer, vector_output_path};
+use crate::map_gen::egui_map::TempMap;
+
+// 'GPKG' and version 1.4.0
+const APPLICATION_ID: u32 = 0x4750_4B47;
+const USER_VERSION: u32 = 10400;
+
+// the srs id the specification reserves for undefined cartesian coordinates
+const UNDEFINED_CARTESIAN_SRS: i32 = -1;
+
+const SCHEMA: &str = "
+CREATE TABLE gpkg_spatial_ref_sys (
+    srs_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL PRIMARY KEY,
+    organization TEXT NOT NULL,
+    organization_coordsys_id INTEGER NOT NULL,
+    definition TEXT NOT NULL,
+    description TEXT
+);
+CREATE TABLE gpkg_contents (
+    table_name TEXT NOT NULL PRIMARY KEY,
+    data_type TEXT NOT NULL,
+    identifier TEXT UNIQUE,
+    description TEXT DEFAULT '',
+    last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    min_x DOUBLE,
+    min_y DOUBLE,
+    max_x DOUBLE,
+    max_y DOUBLE,
+    srs_id INTEGER,
+    CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+CREATE TABLE gpkg_geometry_columns (
+    table_name TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    geometry_type_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL,
+    z TINYINT NOT NULL,
+    m TINYINT NOT NULL,
+    CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+    CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+    CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys (srs_id)
+);
+INSERT INTO gpkg_spatial_ref_sys VALUES
+    ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', NULL),
+    ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', NULL),
+    ('WGS 84 geodetic', 4326, 'EPSG', 4326, 'GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]],AUTHORITY[\"EPSG\",\"4326\"]]', NULL);
+";
+
+/// Writes every symbol as a feature table of a GeoPackage next to the map, returns its path.
+///
+/// The output CRS is stored with its EPSG code and WKT definition
+pub fn write_geopackage(
+    save_location: &Path,
+    map: &TempMap,
+    crs: Option<&CrsDef>,
+) -> crate::Result<PathBuf> {
+    let path = vector_output_path(save_location, ".gpkg");
+    // a GeoPackage is written from scratch, not appended to
+    if path.exists() {
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+    }
+
+    let mut connection = Connection::open(&path)
+        .with_context(|| format!("Failed to create the GeoPackage {}", path.display()))?;
+    connection.execute_batch(&format!(
+        "PRAGMA application_id = {APPLICATION_ID}; PRAGMA user_version = {USER_VERSION};"
+    ))?;
+
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(SCHEMA)?;
+
+    let srs_id = match crs.filter(|crs| crs.epsg() != 0) {
+        Some(crs) if crs.epsg() == 4326 => 4326,
+        Some(crs) => {
+            let epsg = crs.epsg();
+            transaction.execute(
+                "INSERT INTO gpkg_spatial_ref_sys VALUES (?1, ?2, 'EPSG', ?2, ?3, NULL)",
+                params![
+                    format!("EPSG:{epsg}"),
+                    epsg,
+                    crate::parse_crs::crs_wkt(crs)?
+                ],
+            )?;
+            epsg as i32
+        }
+        None => UNDEFINED_CARTESIAN_SRS,
+    };
+
+    for layer in Layer::from_map(map) {
+        write_layer(&transaction, &layer, srs_id)?;
+    }
+    transaction.commit()?;
+
+    Ok(path)
+}
+
+fn write_layer(connection: &Connection, layer: &Layer, srs_id: i32) -> crate::Result<()> {
+    let geometry_type = match layer.geometry_type {
+        GeometryType::Point => "POINT",
+        GeometryType::LineString => "LINESTRING",
+        GeometryType::Polygon => "POLYGON",
+    };
+
+    let table = quote(&layer.name);
+    let columns = layer
+        .columns
+        .iter()
+        .map(|c| {
+            format!(
+                ", {} {}",
+                quote(&c.name),
+                if c.numeric { "REAL" } else { "TEXT" }
+            )
+        })
+        .collect::<String>();
+    connection.execute_batch(&format!(
+        "CREATE TABLE {table} (fid INTEGER PRIMARY KEY AUTOINCREMENT, geom {geometry_type}{columns});"
+    ))?;
+
+    let [min_x, min_y, max_x, max_y] = layer
+        .features
+        .iter()
+        .filter_map(|f| envelope(&f.geometry))
+        .reduce(|a, b| {
+            [
+                a[0].min(b[0]),
+                a[1].min(b[1]),
+                a[2].max(b[2]),
+                a[3].max(b[3]),
+            ]
+        })
+        .unwrap_or_default();
+    connection.execute(
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, min_x, min_y, max_x, max_y, srs_id) \
+        VALUES (?1, 'features', ?1, ?2, ?3, ?4, ?5, ?6)",
+        params![layer.name, min_x, min_y, max_x, max_y, srs_id],
+    )?;
+    connection.execute(
+        "INSERT INTO gpkg_geometry_columns VALUES (?1, 'geom', ?2, ?3, 0, 0)",
+        params![layer.name, geometry_type, srs_id],
+    )?;
+
+    let placeholders = (0..=layer.columns.len())
+        .map(|i| format!("?{}", i + 1))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let names = std::iter::once("geom".to_string())
+        .chain(layer.columns.iter().map(|c| quote(&c.name)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut insert = connection.prepare(&format!(
+        "INSERT INTO {table} ({names}) VALUES ({placeholders})"
+    ))?;
+
+    for feature in &layer.features {
+        let values = std::iter::once(Value::Blob(gpkg_geometry(&feature.geometry, srs_id))).chain(
+            layer
+                .columns
+                .iter()
+                .zip(&feature.values)
+                .map(|(column, value)| match value {
+                    Some(v) if column.numeric => v.parse().map_or(Value::Null, Value::Real),
+                    Some(v) => Value::Text(v.clone()),
+                    None => Value::Null,
+                }),
+        );
+        insert.execute(params_from_iter(values))?;
+    }
+
+    Ok(())
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+// [min x, min y, max x, max y]
+fn envelope(geometry: &geo::Geometry) -> Option<[f64; 4]> {
+    use geo::BoundingRect;
+    geometry
+        .bounding_rect()
+        .map(|r| [r.min().x, r.min().y, r.max().x, r.max().y])
+}
+
+/// The GeoPackage geometry blob: a header with the srs id and the xy envelope,
+/// followed by the little endian WKB of the geometry
+fn gpkg_geometry(geometry: &geo::Geometry, srs_id: i32) -> Vec<u8> {
+    let mut blob = b"GP".to_vec();
+    // version 1, then flags: little endian with an xy envelope
+    blob.push(0);
+    blob.push(0b0000_0011);
+    blob.extend_from_slice(&srs_id.to_le_bytes());
+    let [min_x, min_y, max_x, max_y] = envelope(geometry).unwrap_or_default();
+    for value in [min_x, max_x, min_y, max_y] {
+        blob.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let coords = |blob: &mut Vec<u8>, line: &geo::LineString| {
+        blob.extend_from_slice(&(line.0.len() as u32).to_le_bytes());
+        for c in line.coords() {
+            blob.extend_from_slice(&c.x.to_le_bytes());
+            blob.extend_from_slice(&c.y.to_le_bytes());
+        }
+    };
+
+    blob.push(1);
+    match geometry {
+        geo::Geometry::Point(p) => {
+            blob.extend_from_slice(&1_u32.to_le_bytes());
+            blob.extend_from_slice(&p.x().to_le_bytes());
+            blob.extend_from_slice(&p.y().to_le_bytes());
+        }
+        geo::Geometry::LineString(l) => {
+            blob.extend_from_slice(&2_u32.to_le_bytes());
+            coords(&mut blob, l);
+        }
+        geo::Geometry::Polygon(p) => {
+            blob.extend_from_slice(&3_u32.to_le_bytes());
+            blob.extend_from_slice(&(1 + p.interiors().len() as u32).to_le_bytes());
+            for ring in std::iter::once(p.exterior()).chain(p.interiors()) {
+                coords(&mut blob, ring);
+            }
+        }
+        // the layers only hold points, lines and polygons, anything else is an empty collection
+        _ => {
+            blob.extend_from_slice(&7_u32.to_le_bytes());
+            blob.extend_from_slice(&0_u32.to_le_bytes());
+        }
+    }
+    blob
+}
+
+#[cfg(test)]
+mod tests {
+    use super::gpkg_geometry;
+
+    #[test]
+    fn geometry_blob_has_header_envelope_and_wkb() {
+        let line: geo::Geometry = geo::LineString::from(vec![(1., 2.), (3., -4.)]).into();
+
+        let blob = gpkg_geometry(&line, 25833);
+
+        assert_eq!(&blob[0..4], &[b'G', b'P', 0, 3]);
+        assert_eq!(i32::from_le_bytes(blob[4..8].try_into().unwrap()), 25833);
+        let envelope = blob[8..40]
+            .chunks(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(envelope, [1., 3., -4., 2.]);
+        // little endian linestring of two points
+        assert_eq!(blob[40], 1);
+        assert_eq!(u32::from_le_bytes(blob[41..45].try_into().unwrap()), 2);
+        assert_eq!(u32::from_le_bytes(blob[45..49].try_into().unwrap()), 2);
+        assert_eq!(blob.len(), 49 + 4 * 8);
+    }
+}
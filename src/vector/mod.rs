@@ -0,0 +1,190 @@
+pub mod geojson;
+pub mod geopackage;
+
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use geo::MapCoords;
+
+use crate::map_gen::egui_map::{MapObject, Symbol, TempMap};
+
+/// The objects of one symbol in real world coordinates.
+/// Every tag key becomes a column, numeric if all its values are numbers
+pub struct Layer {
+    pub name: String,
+    pub geometry_type: GeometryType,
+    pub columns: Vec<Column>,
+    pub features: Vec<Feature>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeometryType {
+    Point,
+    LineString,
+    Polygon,
+}
+
+pub struct Column {
+    pub name: String,
+    pub numeric: bool,
+}
+
+pub struct Feature {
+    pub geometry: geo::Geometry,
+    // one value per column, in column order
+    pub values: Vec<Option<String>>,
+}
+
+impl Layer {
+    /// The layers of the map, one per symbol in symbol order.
+    /// Points get a rotation column in degrees, counter clockwise from east
+    pub fn from_map(map: &TempMap) -> Vec<Layer> {
+        let mut symbols = map.objects.keys().copied().collect::<Vec<_>>();
+        symbols.sort();
+
+        symbols
+            .into_iter()
+            .filter_map(|symbol| {
+                let objects = map.objects.get(&symbol)?;
+                (!objects.is_empty()).then(|| Self::from_objects(symbol, objects, map.ref_point))
+            })
+            .collect()
+    }
+
+    fn from_objects(symbol: Symbol, objects: &[MapObject], ref_point: geo::Coord) -> Layer {
+        let geometry_type = match symbol {
+            Symbol::Area(_) => GeometryType::Polygon,
+            Symbol::Line(_) => GeometryType::LineString,
+            Symbol::Point(_) => GeometryType::Point,
+        };
+
+        let tags = |object: &MapObject| match object {
+            MapObject::Area { tags, .. }
+            | MapObject::Line { tags, .. }
+            | MapObject::Point { tags, .. } => tags.clone(),
+        };
+        let keys = objects
+            .iter()
+            .flat_map(|o| tags(o).into_keys())
+            .collect::<BTreeSet<_>>();
+
+        let features = objects
+            .iter()
+            .map(|object| {
+                let tags = tags(object);
+                let (geometry, rotation): (geo::Geometry, _) = match object {
+                    MapObject::Area { object, .. } => (object.clone().into(), None),
+                    MapObject::Line { object, .. } => (object.clone().into(), None),
+                    MapObject::Point {
+                        object, rotation, ..
+                    } => ((*object).into(), Some(rotation.to_degrees())),
+                };
+
+                let mut values = keys
+                    .iter()
+                    .map(|key| tags.get(key).cloned())
+                    .collect::<Vec<_>>();
+                if geometry_type == GeometryType::Point {
+                    values.push(rotation.map(|r| r.to_string()));
+                }
+
+                Feature {
+                    geometry: geometry.map_coords(|c| c + ref_point),
+                    values,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut columns = keys
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| Column {
+                numeric: features
+                    .iter()
+                    .filter_map(|f| f.values[i].as_ref())
+                    .all(|v| v.parse::<f64>().is_ok()),
+                name,
+            })
+            .collect::<Vec<_>>();
+        if geometry_type == GeometryType::Point {
+            columns.push(Column {
+                name: "rotation".to_string(),
+                numeric: true,
+            });
+        }
+
+        Layer {
+            name: symbol.to_string(),
+            geometry_type,
+            columns,
+            features,
+        }
+    }
+}
+
+// <stem><suffix> next to the map file
+fn vector_output_path(save_location: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = save_location
+        .file_stem()
+        .map(|stem| stem.to_os_string())
+        .unwrap_or_else(|| OsString::from("omap"));
+    file_name.push(suffix);
+
+    save_location.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        map_gen::egui_map::{LineSymbol, MapObject, PointSymbol, TempMap},
+        parameters::Scale,
+        vector::{GeometryType, Layer},
+    };
+
+    #[test]
+    fn layers_are_in_world_coordinates_with_typed_columns() {
+        let ref_point = geo::coord! {x: 500_000., y: 6_600_000.};
+        let mut map = TempMap::new(ref_point, Scale::S15_000, None);
+        let mut contour = MapObject::Line {
+            object: geo::LineString::from(vec![(0., 0.), (10., 0.)]),
+            symbol: LineSymbol::Contour,
+            tags: HashMap::new(),
+        };
+        contour.add_elevation_tag(125.);
+        map.add_object(contour);
+        map.add_object(MapObject::Point {
+            object: geo::Point::new(5., 5.),
+            symbol: PointSymbol::DotKnoll,
+            rotation: std::f64::consts::FRAC_PI_2,
+            tags: HashMap::from([("Name".to_string(), "Top".to_string())]),
+        });
+
+        let layers = Layer::from_map(&map);
+
+        assert_eq!(layers.len(), 2);
+        let (contours, knolls) = (&layers[0], &layers[1]);
+        assert_eq!(contours.name, "Contour");
+        assert_eq!(contours.geometry_type, GeometryType::LineString);
+        assert_eq!(contours.columns[0].name, "Elevation");
+        assert!(contours.columns[0].numeric);
+        assert_eq!(
+            contours.features[0].geometry,
+            geo::LineString::from(vec![(500_000., 6_600_000.), (500_010., 6_600_000.)]).into()
+        );
+
+        let names = knolls
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Name", "rotation"]);
+        assert!(!knolls.columns[0].numeric);
+        let rotation = knolls.features[0].values[1].as_ref().unwrap();
+        assert!((rotation.parse::<f64>().unwrap() - 90.).abs() < 1e-9);
+    }
+}